@@ -0,0 +1,44 @@
+//! Embeds the git revision and build time of the bot in a `bomber.meta` custom section, so
+//! the game and the upload server can tell which build of it is live. Bots build fine
+//! without it, so feel free to delete this file.
+
+use std::{
+    env, fs,
+    path::Path,
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const SECTION: &str = "bomber.meta";
+
+fn main() {
+    let git_hash = Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|hash| hash.trim().to_owned());
+    let build_time = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |t| t.as_secs());
+
+    let mut fields = vec![format!("\"build_time\":{}", build_time)];
+    if let Some(hash) = git_hash {
+        fields.push(format!("\"git_hash\":\"{}\"", hash));
+    }
+    let json = ["{", &fields.join(","), "}"].concat();
+
+    // Statics placed in a named link section end up as a custom section of the `.wasm` file.
+    let code = format!(
+        "#[link_section = \"{}\"]\n#[used]\nstatic BOMBER_META: [u8; {}] = *b{:?};\n",
+        SECTION,
+        json.len(),
+        json
+    );
+    let out_dir = env::var("OUT_DIR").expect("Cargo sets OUT_DIR for build scripts");
+    fs::write(Path::new(&out_dir).join("bomber_meta.rs"), code)
+        .expect("Failed to write the build metadata");
+    // Refreshed on new commits and on changes to the bot itself.
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+    println!("cargo:rerun-if-changed=src");
+}
@@ -0,0 +1,30 @@
+use bomber_lib::{
+    world::{Direction, Enemy, Object, Tile, TileOffset},
+    Action, Player,
+};
+use bomber_macro::wasm_export;
+
+// Tells the game which build of the bot this is (see `build.rs`).
+include!(concat!(env!("OUT_DIR"), "/bomber_meta.rs"));
+
+#[derive(Default)]
+struct Bot;
+
+#[wasm_export]
+impl Player for Bot {
+    fn act(
+        &mut self,
+        _surroundings: Vec<(Tile, Option<Object>, Option<Enemy>, TileOffset)>,
+    ) -> Action {
+        // Your strategy goes here! For now, the bot just walks north.
+        Action::Move(Direction::North)
+    }
+
+    fn name(&self) -> String {
+        "{{project-name}}".into()
+    }
+
+    fn team_name() -> String {
+        "{{team_name}}".into()
+    }
+}
@@ -0,0 +1,75 @@
+//! Helper tasks for the bot, run with `cargo xtask <task>`:
+//!
+//! * `build` compiles the bot to WebAssembly.
+//! * `build-and-upload` also submits it to the upload server at `BOMBER_UPLOAD_URL`, with
+//!   the API key in `BOMBER_API_KEY`.
+
+use anyhow::{anyhow, bail, Context, Result};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+const TARGET: &str = "wasm32-unknown-unknown";
+/// Package name, as cargo knows it.
+const BOT_PACKAGE: &str = "{{project-name}}";
+/// Library name, which the `.wasm` file is named after, with hyphens turned into underscores.
+const BOT_CRATE: &str = "{{crate_name}}";
+const DEFAULT_UPLOAD_URL: &str = "http://127.0.0.1:8765";
+
+fn main() -> Result<()> {
+    match env::args().nth(1).as_deref() {
+        Some("build") => {
+            build()?;
+        },
+        Some("build-and-upload") => {
+            let wasm = build()?;
+            upload(&wasm)?;
+        },
+        _ => bail!("Usage: cargo xtask <build|build-and-upload>"),
+    }
+    Ok(())
+}
+
+/// Builds the bot in release mode, returning the path of the `.wasm` file.
+fn build() -> Result<PathBuf> {
+    let cargo = env::var("CARGO").unwrap_or_else(|_| "cargo".to_owned());
+    let status = Command::new(cargo)
+        .current_dir(workspace_root())
+        .args(["build", "--release", "--target", TARGET, "--package", BOT_PACKAGE])
+        .status()
+        .context("running cargo build")?;
+    if !status.success() {
+        bail!("Building the bot failed. Is the target installed? `rustup target add {}`", TARGET);
+    }
+
+    let wasm = workspace_root().join(format!("target/{}/release/{}.wasm", TARGET, BOT_CRATE));
+    println!("Built {}", wasm.display());
+    Ok(wasm)
+}
+
+fn upload(wasm: &Path) -> Result<()> {
+    let api_key = env::var("BOMBER_API_KEY")
+        .map_err(|_| anyhow!("Set BOMBER_API_KEY to the API key your team was given."))?;
+    let url = env::var("BOMBER_UPLOAD_URL").unwrap_or_else(|_| DEFAULT_UPLOAD_URL.to_owned());
+    let bytes = fs::read(wasm).with_context(|| format!("reading {}", wasm.display()))?;
+
+    println!("Uploading {} bytes to {}", bytes.len(), url);
+    match ureq::post(&url).set("Api-Key", &api_key).send_bytes(&bytes) {
+        Ok(response) => {
+            print!("{}", response.into_string()?);
+            Ok(())
+        },
+        // The server explains what went wrong in the body, e.g. an unknown API key.
+        Err(ureq::Error::Status(code, response)) => {
+            let reason = response.into_string().unwrap_or_default();
+            bail!("Upload rejected with status {}: {}", code, reason.trim())
+        },
+        Err(e) => Err(e).with_context(|| format!("connecting to {}", url)),
+    }
+}
+
+fn workspace_root() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).parent().expect("xtask lives in the workspace").into()
+}
@@ -3,7 +3,10 @@ use std::time::Duration;
 use bevy::prelude::*;
 use bomber_lib::world;
 
-use crate::{state::AppState, tick::WHOLE_TURN_PERIOD};
+use crate::{
+    state::AppState,
+    tick::{TimeScale, WHOLE_TURN_PERIOD},
+};
 
 pub struct AnimationPlugin;
 pub struct AnimationTimer(Timer);
@@ -15,11 +18,25 @@ fn animation_period() -> Duration {
 
 #[derive(Component, Debug)]
 pub enum AnimationState {
-    StandingStill,
+    /// Facing the direction the player last walked in.
+    StandingStill(world::Direction),
     Walking(world::Direction, usize),
 }
 
 impl AnimationState {
+    pub fn facing(&self) -> world::Direction {
+        match self {
+            AnimationState::StandingStill(direction) | AnimationState::Walking(direction, _) => {
+                *direction
+            },
+        }
+    }
+
+    /// Stops walking, still facing the same way.
+    pub fn stand_still(&mut self) {
+        *self = AnimationState::StandingStill(self.facing());
+    }
+
     fn next_sprite(&mut self) -> usize {
         let cycle = |direction: world::Direction| match direction {
             world::Direction::West => [17, 18, 19, 18, 17, 16, 15, 16],
@@ -29,7 +46,7 @@ impl AnimationState {
         };
 
         match self {
-            AnimationState::StandingStill => cycle(world::Direction::South)[0],
+            AnimationState::StandingStill(direction) => cycle(*direction)[0],
             AnimationState::Walking(direction, current_cycle_index) => {
                 let cycle = cycle(*direction);
                 *current_cycle_index = (*current_cycle_index + 1) % cycle.len();
@@ -51,9 +68,10 @@ impl Plugin for AnimationPlugin {
 fn animate_bomberman_system(
     mut timer: ResMut<AnimationTimer>,
     time: Res<Time>,
+    time_scale: Res<TimeScale>,
     mut sprite_query: Query<(&mut AnimationState, &mut TextureAtlasSprite)>,
 ) {
-    timer.0.tick(time.delta());
+    timer.0.tick(time_scale.delta(&time));
     if timer.0.just_finished() {
         for (mut state, mut sprite) in sprite_query.iter_mut() {
             sprite.index = state.next_sprite();
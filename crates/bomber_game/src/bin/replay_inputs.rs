@@ -0,0 +1,146 @@
+//! Replays inputs recorded by the game (with `RECORD_INPUTS=1`) against a local `.wasm`
+//! player, printing the action it chooses for each turn. This lets teams reproduce and fix
+//! a decision their bot made during a round.
+//!
+//! Usage: `cargo run -p bomber_game --bin replay_inputs -- <player.wasm> <inputs.bin>`
+//!
+//! Turns get the fuel budget of the round the inputs were recorded in, as written in its
+//! round config, so a bot that ran out of fuel in the game runs out here too. Hill hints, bomb
+//! counts, points scored and death reports are given back as they were recorded, to bots that
+//! take them.
+
+use anyhow::{anyhow, Context, Result};
+use bomber_lib::{
+    wasm_act,
+    world::{Enemy, Object, Tile, TileOffset},
+};
+use bomber_shared::{
+    RoundConfig, BOMB_COUNT_EXPORT, HILL_HINT_EXPORT, LAST_DEATH_BUFFER_EXPORT, LAST_DEATH_EXPORT,
+    ROUND_CONFIG_FILENAME, SCORED_EXPORT,
+};
+use serde::Deserialize;
+use std::{env, fs, path::Path};
+use wasmtime::{Config, Engine, Instance, Linker, Module, Store};
+
+/// Fuel per turn when the round config doesn't say, matching the game's default rules.
+const DEFAULT_FUEL_PER_TICK: u64 = 1_000_000_000;
+/// Matches `FIRST_TURN_FUEL_MULTIPLIER` in the game.
+const FIRST_TURN_FUEL_MULTIPLIER: u64 = 10;
+
+/// Matches `RecordedTurn` in the game.
+#[derive(Deserialize)]
+struct RecordedTurn {
+    surroundings: Vec<(Tile, Option<Object>, Option<Enemy>, TileOffset)>,
+    hill_hint: u32,
+    bomb_count: (u32, u32),
+    scored: u32,
+    last_death: Option<(String, i32, i32)>,
+}
+
+fn main() -> Result<()> {
+    let mut args = env::args().skip(1);
+    let (wasm_path, inputs_path) = match (args.next(), args.next()) {
+        (Some(wasm_path), Some(inputs_path)) => (wasm_path, inputs_path),
+        _ => return Err(anyhow!("Usage: replay_inputs <player.wasm> <inputs.bin>")),
+    };
+
+    let fuel_per_tick = recorded_fuel_per_tick(Path::new(&inputs_path));
+    println!("Replaying with {} fuel per turn.", fuel_per_tick);
+
+    let engine = Engine::new(Config::new().consume_fuel(true))?;
+    let module = Module::from_file(&engine, &wasm_path)
+        .with_context(|| format!("loading {:?}", wasm_path))?;
+    let mut store = Store::new(&engine, ());
+    store.add_fuel(fuel_per_tick * FIRST_TURN_FUEL_MULTIPLIER)?;
+    let mut linker = Linker::new(&engine);
+    // Bots may import the panic report function the game provides. Panics are reported as
+    // errors by `wasm_act` anyway, so the message is ignored here.
+    linker.func_wrap("env", "__wasm_report_panic", |_pointer: u32, _length: u32| {})?;
+    let instance = linker.instantiate(&mut store, &module)?;
+
+    let inputs = fs::read(&inputs_path).with_context(|| format!("reading {:?}", inputs_path))?;
+    let mut remaining = inputs.as_slice();
+    let mut turn = 1;
+    while !remaining.is_empty() {
+        let (length, rest) = remaining.split_at(4.min(remaining.len()));
+        let length = u32::from_le_bytes(
+            length.try_into().map_err(|_| anyhow!("Truncated frame length at turn {}", turn))?,
+        ) as usize;
+        if rest.len() < length {
+            return Err(anyhow!("Truncated frame at turn {}", turn));
+        }
+        let (frame, rest) = rest.split_at(length);
+        let recorded: RecordedTurn =
+            bincode::deserialize(frame).with_context(|| format!("decoding turn {}", turn))?;
+
+        let fuel_before = store.fuel_consumed().unwrap_or_default();
+        if let Some(last_death) = &recorded.last_death {
+            give_last_death(&mut store, instance, last_death)?;
+        }
+        if let Some(give) = instance.get_func(&mut store, HILL_HINT_EXPORT) {
+            give.typed::<u32, (), _>(&store)?.call(&mut store, recorded.hill_hint)?;
+        }
+        if let Some(give) = instance.get_func(&mut store, BOMB_COUNT_EXPORT) {
+            give.typed::<(u32, u32), (), _>(&store)?.call(&mut store, recorded.bomb_count)?;
+        }
+        if let Some(give) = instance.get_func(&mut store, SCORED_EXPORT) {
+            give.typed::<u32, (), _>(&store)?.call(&mut store, recorded.scored)?;
+        }
+        match wasm_act(&mut store, &instance, recorded.surroundings) {
+            Ok(action) => println!("Turn {}: {:?}", turn, action),
+            Err(e) => println!("Turn {}: error ({:?})", turn, e),
+        }
+        // Refill the store to the regular budget for the next turn, like the game does.
+        let consumed = store.fuel_consumed().unwrap_or_default() - fuel_before;
+        if turn == 1 {
+            let left = fuel_per_tick * FIRST_TURN_FUEL_MULTIPLIER - consumed;
+            if left > fuel_per_tick {
+                store.consume_fuel(left - fuel_per_tick)?;
+            } else {
+                store.add_fuel(fuel_per_tick - left)?;
+            }
+        } else {
+            store.add_fuel(consumed)?;
+        }
+        remaining = rest;
+        turn += 1;
+    }
+    Ok(())
+}
+
+/// Gives a recorded death report back to a bot that takes them, like the game does.
+fn give_last_death(
+    store: &mut Store<()>,
+    instance: Instance,
+    (killer_team, x, y): &(String, i32, i32),
+) -> Result<()> {
+    let (buffer, give) = match (
+        instance.get_func(&mut *store, LAST_DEATH_BUFFER_EXPORT),
+        instance.get_func(&mut *store, LAST_DEATH_EXPORT),
+    ) {
+        (Some(buffer), Some(give)) => {
+            (buffer.typed::<u32, u32, _>(&*store)?, give.typed::<(u32, i32, i32), (), _>(&*store)?)
+        },
+        _ => return Ok(()),
+    };
+    let length = killer_team.len() as u32;
+    let pointer = buffer.call(&mut *store, length)?;
+    let memory =
+        instance.get_memory(&mut *store, "memory").ok_or_else(|| anyhow!("no memory exported"))?;
+    memory.write(&mut *store, pointer as usize, killer_team.as_bytes())?;
+    give.call(&mut *store, (length, *x, *y))?;
+    Ok(())
+}
+
+/// Fuel per turn of the round the inputs were recorded in. Recordings live in an `inputs`
+/// folder inside the round folder, next to the round config.
+fn recorded_fuel_per_tick(inputs_path: &Path) -> u64 {
+    let config_path =
+        inputs_path.parent().and_then(Path::parent).map(|round| round.join(ROUND_CONFIG_FILENAME));
+    let from_config = config_path
+        .and_then(|path| fs::read(path).ok())
+        .and_then(|json| serde_json::from_slice::<RoundConfig>(&json).ok())
+        .and_then(|config| config.rules["fuel_per_tick"].as_u64());
+    let from_env = env::var("FUEL_PER_TICK").ok().and_then(|fuel| fuel.parse().ok());
+    from_config.or(from_env).unwrap_or(DEFAULT_FUEL_PER_TICK)
+}
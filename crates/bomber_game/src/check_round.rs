@@ -0,0 +1,113 @@
+//! Checks the `.wasm` players of a round without starting the game, so organizers can catch
+//! broken uploads before going live. Run the game with `check-round N` to load every player
+//! in `rounds/N/`, print its names and how much fuel its first turn takes, and exit with a
+//! non-zero code if any of them fails.
+
+use anyhow::{anyhow, Context, Result};
+use bomber_lib::{wasm_act, wasm_name, wasm_team_name};
+use bomber_shared::{round_folder, REQUIRED_EXPORTS};
+use std::{env, ffi::OsStr, fs, path::Path};
+use wasmtime::{Config, Engine, Module, Store};
+
+use crate::{
+    player::{
+        filter_name, instantiate, PanicReport, DEFAULT_TEAM_NAME, FIRST_TURN_FUEL_MULTIPLIER,
+        MAX_NAME_LENGTH, MAX_TEAM_NAME_LENGTH,
+    },
+    player_hotswap::WASM_EXTENSIONS,
+    reserved_names::{sanitize_and_authorize_name, ReservedNames},
+    settings::Rules,
+};
+
+/// Command line subcommand that checks a round and exits.
+pub const CHECK_ROUND_COMMAND: &str = "check-round";
+
+/// What a player reported about itself during the check.
+struct CheckedPlayer {
+    name: String,
+    team_name: String,
+    first_turn_fuel: u64,
+}
+
+/// Checks every player in the given round, printing a line for each. Returns whether they
+/// all passed.
+pub fn check_round(round: &str) -> Result<bool> {
+    if let Err(e) = dotenv::dotenv() {
+        println!("Not loading a .env file ({}).", e);
+    }
+    let round = round.parse().with_context(|| format!("parsing round {:?}", round))?;
+    let rules = Rules::from_env()?;
+    let reserved_names = ReservedNames::load()?;
+    let folder = round_folder(&env::current_dir()?.join(bomber_shared::rounds_folder()), round);
+
+    let mut paths = fs::read_dir(&folder)
+        .with_context(|| format!("listing {:?}", folder))?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    paths.retain(|p| {
+        p.extension().and_then(OsStr::to_str).map_or(false, |e| WASM_EXTENSIONS.contains(&e))
+    });
+    paths.sort();
+    if paths.is_empty() {
+        println!("No players in {:?}.", folder);
+    }
+
+    let engine = Engine::new(Config::new().consume_fuel(true))?;
+    let first_turn_budget = rules.fuel_per_tick * FIRST_TURN_FUEL_MULTIPLIER;
+    let mut failures = 0;
+    for path in &paths {
+        let file_name = path.file_name().map_or_else(String::new, |n| n.to_string_lossy().into());
+        match check_player(&engine, path, first_turn_budget, &reserved_names) {
+            Ok(CheckedPlayer { name, team_name, first_turn_fuel }) => println!(
+                "{}: ok, {:?} of {:?}, first turn took {} fuel ({:.1}% of its budget)",
+                file_name,
+                name,
+                team_name,
+                first_turn_fuel,
+                first_turn_fuel as f64 * 100.0 / first_turn_budget as f64
+            ),
+            Err(e) => {
+                failures += 1;
+                println!("{}: FAILED, {:#}", file_name, e);
+            },
+        }
+    }
+    println!("{} of {} players passed.", paths.len() - failures, paths.len());
+    Ok(failures == 0)
+}
+
+/// Loads a player the way the game does, and plays a first turn with nothing in sight.
+fn check_player(
+    engine: &Engine,
+    path: &Path,
+    first_turn_budget: u64,
+    reserved_names: &ReservedNames,
+) -> Result<CheckedPlayer> {
+    let module = Module::from_file(engine, path).context("compiling")?;
+    // The ABI version isn't embedded in the modules, so the exports are the best indication
+    // that a player was built against a compatible `bomber_lib`.
+    let missing = REQUIRED_EXPORTS
+        .iter()
+        .filter(|export| module.get_export(export).is_none())
+        .collect::<Vec<_>>();
+    if !missing.is_empty() {
+        return Err(anyhow!("missing exports {:?}, is bomber_lib up to date?", missing));
+    }
+
+    let mut store = Store::new(engine, ());
+    store.add_fuel(first_turn_budget)?;
+    let instance = instantiate(engine, &mut store, &module, &PanicReport::default())
+        .context("instantiating")?;
+    let name = wasm_name(&mut store, &instance).map_err(|e| anyhow!("naming: {:?}", e))?;
+    let team_name =
+        wasm_team_name(&mut store, &instance).map_err(|e| anyhow!("naming the team: {:?}", e))?;
+    let file_stem = path.file_stem().map(|s| s.to_string_lossy().into_owned());
+    let name =
+        sanitize_and_authorize_name(&name, file_stem.as_deref(), MAX_NAME_LENGTH, reserved_names);
+    let team_name = filter_name(&team_name, MAX_TEAM_NAME_LENGTH, DEFAULT_TEAM_NAME);
+
+    let fuel_before = store.fuel_consumed().unwrap_or_default();
+    wasm_act(&mut store, &instance, vec![]).map_err(|e| anyhow!("first turn: {:?}", e))?;
+    let first_turn_fuel = store.fuel_consumed().unwrap_or_default() - fuel_before;
+    Ok(CheckedPlayer { name, team_name, first_turn_fuel })
+}
@@ -1,13 +1,22 @@
-use std::ops::{Add, Sub};
+use std::{
+    fmt,
+    ops::{Add, Sub},
+};
 
-use anyhow::{anyhow, Result};
-use bevy::prelude::*;
+use anyhow::{anyhow, Context, Result};
+use bevy::{
+    asset::{AssetLoader, HandleId, LoadContext, LoadedAsset},
+    prelude::*,
+    reflect::TypeUuid,
+    utils::{BoxedFuture, HashMap, HashSet},
+};
 use bomber_lib::world::{Direction, Object, Tile, TileOffset};
 use rand::Rng;
 
 use crate::{
     log_unrecoverable_error_and_panic,
-    rendering::{GAME_MAP_Z, GAME_OBJECT_Z, TILE_HEIGHT_PX, TILE_WIDTH_PX},
+    object::CrateLoot,
+    rendering::{CRATE_Z, GAME_MAP_Z, TILE_HEIGHT_PX, TILE_WIDTH_PX},
     state::AppState,
     ExternalCrateComponent,
 };
@@ -30,15 +39,170 @@ pub const SHINGEKI: &str = include_str!("../assets/maps/shingeki_no_kyojin.txt")
 pub const SPIRAL: &str = include_str!("../assets/maps/spiral.txt");
 pub const FINLAND: &str = include_str!("../assets/maps/finland.txt");
 
+/// Every available map: its name, its file under `assets/maps`, and the contents it was built
+/// with, which are used until the file is loaded (or if it is edited into an invalid map).
+pub const MAPS: &[(&str, &str, &str)] = &[
+    ("Crate Heavy Cross Arena", "crate_heavy_cross_arena_small.txt", CRATE_HEAVY_CROSS_ARENA_SMALL),
+    ("Castle", "castle.txt", CASTLE),
+    ("Race", "race.txt", RACE),
+    ("Shingeki no Kyojin", "shingeki_no_kyojin.txt", SHINGEKI),
+    ("Spiral", "spiral.txt", SPIRAL),
+    ("Finland", "finland.txt", FINLAND),
+];
+
+/// Order in which maps come up, by name.
+const ROTATION: &[&str] = &[
+    "Crate Heavy Cross Arena",
+    "Castle",
+    "Crate Heavy Cross Arena",
+    "Race",
+    "Crate Heavy Cross Arena",
+    "Shingeki no Kyojin",
+    "Crate Heavy Cross Arena",
+    "Spiral",
+    "Crate Heavy Cross Arena",
+    "Finland",
+];
+
+/// Textual representation of a map, loaded from the `assets/maps` folder.
+#[derive(Debug, TypeUuid)]
+#[uuid = "0b2f7cf4-3f8e-4a43-9a3e-61e9d8f4a6b2"]
+pub struct MapAsset {
+    pub text: String,
+}
+
+#[derive(Default)]
+pub struct MapLoader;
+
+impl AssetLoader for MapLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let map_asset = MapAsset { text: String::from_utf8(bytes.into())? };
+            load_context.set_default_asset(LoadedAsset::new(map_asset));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["txt"]
+    }
+}
+
+/// Latest valid contents of every map, kept up to date with the map files so they can be
+/// edited while the game runs.
+pub struct MapTexts {
+    names: HashMap<HandleId, &'static str>,
+    texts: HashMap<&'static str, String>,
+    /// Keeps the map files loaded, and watched for changes.
+    _handles: Vec<Handle<MapAsset>>,
+}
+
+impl MapTexts {
+    fn text(&self, name: &str) -> Result<&str> {
+        self.texts.get(name).map(String::as_str).ok_or_else(|| anyhow!("Unknown map {}", name))
+    }
+}
+
+/// Sent when a map file changes on disk, with the validation error if the new contents were
+/// rejected.
+pub struct MapReloadEvent {
+    pub name: &'static str,
+    pub result: Result<(), String>,
+}
+
+/// Map to play on the next round instead of the next one in the rotation, if any. It is
+/// cleared once used.
+#[derive(Default)]
+pub struct ForcedNextMap(pub Option<String>);
+
+/// Marks a game map that was forced by an operator rather than picked by the rotation.
+#[derive(Component, Copy, Clone, Debug)]
+pub struct ForcedMap;
+
 /// Activating this plugin automatically spawns a game map on startup.
 pub struct GameMapPlugin;
 
 #[derive(Copy, Clone, Debug, Component)]
 pub struct GameMap {
+    pub name: &'static str,
+    /// Maximum number of players allowed on the map at once, if the map restricts it.
+    pub max_players: Option<usize>,
+    /// Whether players are told which way the nearest hill is while they can't see it.
+    pub hill_hints: bool,
     width: usize,
     height: usize,
 }
 
+/// Reinforced crates (represented with a `k`, or `K` on the hill, in textual form) take
+/// several separate explosions to destroy. Regular crates don't carry this component.
+#[derive(Component, Copy, Clone, Debug)]
+pub struct CrateHp {
+    pub remaining: u32,
+    /// Explosion that last damaged the crate, as its flames linger for a while and must only
+    /// count once.
+    pub last_hit_by: Option<Entity>,
+}
+
+/// Tint of reinforced crates while they have more than one hit left.
+const REINFORCED_CRATE_COLOR: Color = Color::rgb(0.6, 0.6, 0.75);
+const REINFORCED_CRATE_HP: u32 = 2;
+/// Tint of the floor of icy tiles.
+const ICE_COLOR: Color = Color::rgb(0.7, 0.9, 1.0);
+
+/// Locations of the tiles the game rules look up most, cached when the map spawns since tiles
+/// never change during a round. Anything that changes tiles must rebuild it.
+#[derive(Default, Debug)]
+pub struct TileCache {
+    hills: HashSet<TileLocation>,
+    walls: HashSet<TileLocation>,
+    ice: HashSet<TileLocation>,
+}
+
+impl TileCache {
+    /// Caches the tiles of a map, given its rows from the bottom up as `GameMap::parse`
+    /// returns them.
+    pub fn from_rows(rows: &[Vec<&TileSpec>]) -> Self {
+        let mut tile_cache = TileCache::default();
+        for (i, row) in rows.iter().enumerate() {
+            for (j, spec) in row.iter().enumerate() {
+                match spec.tile {
+                    Tile::Hill => {
+                        tile_cache.hills.insert(TileLocation(j, i));
+                    },
+                    Tile::Wall => {
+                        tile_cache.walls.insert(TileLocation(j, i));
+                    },
+                    Tile::Floor => (),
+                }
+                if spec.surface == Surface::Ice {
+                    tile_cache.ice.insert(TileLocation(j, i));
+                }
+            }
+        }
+        tile_cache
+    }
+
+    pub fn is_hill(&self, location: &TileLocation) -> bool {
+        self.hills.contains(location)
+    }
+
+    pub fn is_wall(&self, location: &TileLocation) -> bool {
+        self.walls.contains(location)
+    }
+
+    pub fn is_ice(&self, location: &TileLocation) -> bool {
+        self.ice.contains(location)
+    }
+
+    pub fn hills(&self) -> impl Iterator<Item = TileLocation> + '_ {
+        self.hills.iter().copied()
+    }
+}
+
 /// Spawners (represented with a `s` in textual form) designate the tiles in
 /// which player characters can appear.
 #[derive(Component, Copy, Clone, Debug)]
@@ -53,6 +217,9 @@ pub struct Textures {
 
 impl Plugin for GameMapPlugin {
     fn build(&self, app: &mut App) {
+        check_palette().expect("Invalid map palette");
+        // The map loader has to be registered before any map is loaded.
+        app.add_asset::<MapAsset>().init_asset_loader::<MapLoader>();
         let asset_server =
             app.world.get_resource::<AssetServer>().expect("Failed to retrieve asset server");
         let textures = Textures {
@@ -61,69 +228,93 @@ impl Plugin for GameMapPlugin {
             hill: asset_server.load("graphics/Sprites/Blocks/BackgroundTileColorShifted.png"),
             breakable: asset_server.load("graphics/Sprites/Blocks/ExplodableBlock.png"),
         };
+        let handles = MAPS
+            .iter()
+            .map(|(_, file, _)| asset_server.load(format!("maps/{}", file).as_str()))
+            .collect::<Vec<Handle<MapAsset>>>();
+        let map_texts = MapTexts {
+            names: handles.iter().zip(MAPS).map(|(h, (name, ..))| (h.id, *name)).collect(),
+            texts: MAPS.iter().map(|(name, _, text)| (*name, text.to_string())).collect(),
+            _handles: handles,
+        };
         app.insert_resource(textures)
+            .insert_resource(map_texts)
+            .add_event::<MapReloadEvent>()
+            .add_system(map_reload_system)
+            .init_resource::<ForcedNextMap>()
+            .init_resource::<TileCache>()
             .add_system_set(
                 SystemSet::on_enter(AppState::InGame)
                     .with_system(setup.chain(log_unrecoverable_error_and_panic)),
             )
-            // Keep the game map on the victory screen as the background.
             .add_system_set(
+                // Keep the game map on the victory screen as the background.
                 SystemSet::on_exit(AppState::VictoryScreen)
-                .with_system(cleanup.chain(log_unrecoverable_error_and_panic)));
+                    .with_system(cleanup.chain(log_unrecoverable_error_and_panic)),
+            );
     }
 }
 
 fn setup(
     mut commands: Commands,
     textures: Res<Textures>,
+    map_texts: Res<MapTexts>,
     mut next_map: Local<MapIndex>,
+    mut forced_next_map: ResMut<ForcedNextMap>,
 ) -> Result<()> {
-    match *next_map {
-        MapIndex(0) => {
-            GameMap::spawn_from_text(&mut commands, CRATE_HEAVY_CROSS_ARENA_SMALL, &textures)?;
-            next_map.0 = 1;
-        },
-        MapIndex(1) => {
-            GameMap::spawn_from_text(&mut commands, CASTLE, &textures)?;
-            next_map.0 = 2;
-        },
-        MapIndex(2) => {
-            GameMap::spawn_from_text(&mut commands, CRATE_HEAVY_CROSS_ARENA_SMALL, &textures)?;
-            next_map.0 = 3;
-        },
-        MapIndex(3) => {
-            GameMap::spawn_from_text(&mut commands, RACE, &textures)?;
-            next_map.0 = 4;
-        },
-        MapIndex(4) => {
-            GameMap::spawn_from_text(&mut commands, CRATE_HEAVY_CROSS_ARENA_SMALL, &textures)?;
-            next_map.0 = 5;
-        },
-        MapIndex(5) => {
-            GameMap::spawn_from_text(&mut commands, SHINGEKI, &textures)?;
-            next_map.0 = 6;
-        },
-        MapIndex(6) => {
-            GameMap::spawn_from_text(&mut commands, CRATE_HEAVY_CROSS_ARENA_SMALL, &textures)?;
-            next_map.0 = 7;
-        },
-        MapIndex(7) => {
-            GameMap::spawn_from_text(&mut commands, SPIRAL, &textures)?;
-            next_map.0 = 8;
-        },
-        MapIndex(8) => {
-            GameMap::spawn_from_text(&mut commands, CRATE_HEAVY_CROSS_ARENA_SMALL, &textures)?;
-            next_map.0 = 9;
-        },
-        MapIndex(9) => {
-            GameMap::spawn_from_text(&mut commands, FINLAND, &textures)?;
-            next_map.0 = 0;
-        },
-        _ => return Err(anyhow!("Invalid map index")),
+    // A forced map takes the place of this round in the rotation, which then resumes
+    // where it left off.
+    if let Some(name) = forced_next_map.0.take() {
+        match MAPS.iter().find(|(n, ..)| *n == name) {
+            Some((name, ..)) => {
+                let text = map_texts.text(name)?;
+                let entity = GameMap::spawn_from_text(&mut commands, name, text, &textures)?;
+                commands.entity(entity).insert(ForcedMap);
+                return Ok(());
+            },
+            None => warn!("Can't force unknown map {:?}, playing the rotation instead.", name),
+        }
     }
+
+    let name = ROTATION.get(next_map.0).ok_or_else(|| anyhow!("Invalid map index"))?;
+    GameMap::spawn_from_text(&mut commands, name, map_texts.text(name)?, &textures)?;
+    next_map.0 = (next_map.0 + 1) % ROTATION.len();
     Ok(())
 }
 
+/// Picks up edits to the map files, which take effect the next time the map comes up. Edits
+/// that don't make a valid map are rejected, keeping the previous contents.
+fn map_reload_system(
+    mut asset_events: EventReader<AssetEvent<MapAsset>>,
+    assets: Res<Assets<MapAsset>>,
+    mut map_texts: ResMut<MapTexts>,
+    mut reload_events: EventWriter<MapReloadEvent>,
+) {
+    for event in asset_events.iter() {
+        let handle = match event {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle } => handle,
+            AssetEvent::Removed { .. } => continue,
+        };
+        let (name, asset) = match (map_texts.names.get(&handle.id), assets.get(handle)) {
+            (Some(name), Some(asset)) => (*name, asset),
+            _ => continue,
+        };
+        if map_texts.texts.get(name) == Some(&asset.text) {
+            continue;
+        }
+
+        let result = GameMap::parse(name, &asset.text).map(|_| ()).map_err(|e| format!("{:#}", e));
+        match &result {
+            Ok(()) => {
+                info!("Map {} updated.", name);
+                map_texts.texts.insert(name, asset.text.clone());
+            },
+            Err(e) => warn!("Keeping the previous version of map {}: {}", name, e),
+        }
+        reload_events.send(MapReloadEvent { name, result });
+    }
+}
+
 fn cleanup(game_map_query: Query<Entity, With<GameMap>>, mut commands: Commands) -> Result<()> {
     let entity = game_map_query.single();
     commands.entity(entity).despawn_recursive();
@@ -134,50 +325,135 @@ fn cleanup(game_map_query: Query<Entity, With<GameMap>>, mut commands: Commands)
 impl GameMap {
     /// Initializes a game map and spawns all tiles and tile objects from
     /// its textual representation, under a common entity parent.
-    pub fn spawn_from_text(commands: &mut Commands, text: &str, textures: &Textures) -> Result<()> {
-        let lines: Vec<&str> = text.lines().rev().collect();
-        if lines.windows(2).any(|w| w[0].len() != w[1].len()) {
-            return Err(anyhow!("Mismatched row sizes in the game map"));
-        }
-        if lines.is_empty() || lines[0].is_empty() {
-            return Err(anyhow!("Game map must have at least a row and a column"));
-        }
-        let game_map = GameMap { width: lines[0].len(), height: lines.len() };
+    ///
+    /// The textual representation may start with `key = value` settings lines, before the
+    /// rows of tiles. The supported keys are `max_players` and `hill_hints`. Returns the map
+    /// entity.
+    pub fn spawn_from_text(
+        commands: &mut Commands,
+        name: &'static str,
+        text: &str,
+        textures: &Textures,
+    ) -> Result<Entity> {
+        let (game_map, rows) = Self::parse(name, text)?;
+        commands.insert_resource(TileCache::from_rows(&rows));
 
-        let indexed_characters = lines
+        let indexed_specs = rows
             .iter()
             .enumerate()
-            .flat_map(|(i, l)| l.chars().enumerate().map(move |(j, c)| (i, j, c)));
-
-        commands.spawn().insert(game_map).insert_bundle(SpriteBundle::default()).with_children(
-            |parent| {
-                for (i, j, c) in indexed_characters {
+            .flat_map(|(i, row)| row.iter().enumerate().map(move |(j, spec)| (i, j, *spec)));
+
+        let entity = commands
+            .spawn()
+            .insert(game_map)
+            .insert_bundle(SpriteBundle::default())
+            .with_children(|parent| {
+                for (i, j, spec) in indexed_specs {
                     let location = TileLocation(j, i);
-                    Self::spawn_game_elements_from_character(
-                        parent, &game_map, location, c, textures,
+                    Self::spawn_game_elements_from_spec(
+                        parent, &game_map, location, spec, textures,
                     )
                     .expect("Failed to spawn game elements");
                 }
-            },
-        );
+            })
+            .id();
 
-        Ok(())
+        Ok(entity)
+    }
+
+    /// Parses and validates the textual representation of a map, returning the map and its
+    /// rows of tiles from the bottom up.
+    pub fn parse(name: &'static str, text: &str) -> Result<(GameMap, Vec<Vec<&'static TileSpec>>)> {
+        // Trailing whitespace would count as tiles, so a stray `\r` or space at the end of the
+        // rows would shift the whole map by one.
+        let trimmed_lines = || text.lines().map(str::trim_end);
+        let settings: Vec<&str> = trimmed_lines().take_while(|l| l.contains('=')).collect();
+        let mut lines: Vec<&str> = trimmed_lines().skip(settings.len()).collect();
+        // Blank lines at the end of the file aren't rows.
+        while lines.last().map_or(false, |l| l.is_empty()) {
+            lines.pop();
+        }
+        let mut rows = lines
+            .iter()
+            .enumerate()
+            .map(|(row, line)| {
+                line.chars()
+                    .enumerate()
+                    .map(|(column, character)| {
+                        tile_spec(character).ok_or_else(|| {
+                            anyhow!(
+                                "Unknown character {:?} at line {}, column {} of map {}",
+                                character,
+                                settings.len() + row + 1,
+                                column + 1,
+                                name
+                            )
+                        })
+                    })
+                    .collect::<Result<Vec<_>>>()
+            })
+            .collect::<Result<Vec<_>>>()?;
+        rows.reverse();
+        if rows.windows(2).any(|w| w[0].len() != w[1].len()) {
+            return Err(anyhow!("Mismatched row sizes in map {}", name));
+        }
+        if rows.is_empty() || rows[0].is_empty() {
+            return Err(anyhow!("Map {} must have at least a row and a column", name));
+        }
+        let specs = || rows.iter().flatten();
+        if specs().all(|spec| matches!(spec.tile, Tile::Wall)) {
+            return Err(anyhow!("Map {} has no tiles to walk on", name));
+        }
+
+        let mut max_players = None;
+        let mut hill_hints = true;
+        for setting in settings {
+            match setting.split_once('=').map(|(k, v)| (k.trim(), v.trim())) {
+                Some(("max_players", value)) => {
+                    max_players = Some(value.parse::<usize>().with_context(|| {
+                        format!("parsing max_players {:?} of map {}", value, name)
+                    })?)
+                },
+                Some(("hill_hints", value)) => {
+                    hill_hints = value.parse::<bool>().with_context(|| {
+                        format!("parsing hill_hints {:?} of map {}", value, name)
+                    })?
+                },
+                _ => return Err(anyhow!("Unknown setting {:?} in map {}", setting, name)),
+            }
+        }
+        let spawner_count = specs().filter(|spec| spec.spawner).count();
+        if spawner_count == 0 {
+            return Err(anyhow!("Map {} has no player spawners", name));
+        }
+        if let Some(max_players) = max_players {
+            if max_players == 0 || max_players > spawner_count {
+                return Err(anyhow!(
+                    "Map {} allows {} players, but has {} spawners",
+                    name,
+                    max_players,
+                    spawner_count
+                ));
+            }
+        }
+        let game_map =
+            GameMap { name, max_players, hill_hints, width: rows[0].len(), height: rows.len() };
+        Ok((game_map, rows))
     }
 
-    fn spawn_game_elements_from_character(
+    fn spawn_game_elements_from_spec(
         parent: &mut ChildBuilder,
         game_map: &GameMap,
         location: TileLocation,
-        character: char,
+        spec: &TileSpec,
         textures: &Textures,
     ) -> Result<()> {
-        let tile = tile_from_char(character);
-        Self::spawn_tile(parent, game_map, tile, location, textures);
-        if let Some(object) = object_from_char(character) {
-            Self::spawn_object(parent, game_map, object, location, textures)?;
+        Self::spawn_tile(parent, game_map, spec.tile, spec.surface, location, textures);
+        if let Some(object) = spec.roll_object() {
+            Self::spawn_object(parent, game_map, object, spec.crate_hp(), location, textures)?;
         }
-        if let Some(spawner) = spawner_from_char(character) {
-            parent.spawn().insert(spawner).insert(location);
+        if spec.spawner {
+            parent.spawn().insert(PlayerSpawner).insert(location);
         }
 
         Ok(())
@@ -187,9 +463,14 @@ impl GameMap {
         parent: &mut ChildBuilder,
         game_map: &GameMap,
         tile: Tile,
+        surface: Surface,
         location: TileLocation,
         textures: &Textures,
     ) {
+        let color = match surface {
+            Surface::Plain => Color::WHITE,
+            Surface::Ice => ICE_COLOR,
+        };
         let texture = match tile {
             Tile::Wall => &textures.wall,
             Tile::Floor => &textures.floor,
@@ -203,6 +484,7 @@ impl GameMap {
                     location.as_world_coordinates(game_map).extend(GAME_MAP_Z),
                 ),
                 sprite: Sprite {
+                    color,
                     custom_size: Some(Vec2::splat(TILE_WIDTH_PX)),
                     ..Default::default()
                 },
@@ -211,13 +493,15 @@ impl GameMap {
         );
     }
 
-    fn spawn_object(
+    /// Spawns an object as a child of the map. Only crates can be placed this way.
+    pub fn spawn_object(
         parent: &mut ChildBuilder,
         game_map: &GameMap,
         object: Object,
+        hp: Option<CrateHp>,
         location: TileLocation,
         textures: &Textures,
-    ) -> Result<()> {
+    ) -> Result<Entity> {
         let texture = match object {
             Object::Crate => &textures.breakable,
             _ => {
@@ -225,29 +509,44 @@ impl GameMap {
             },
         }
         .clone();
-        parent.spawn().insert(ExternalCrateComponent(object)).insert(location).insert_bundle(
+        let color = if hp.is_some() { REINFORCED_CRATE_COLOR } else { Color::WHITE };
+        let mut entity = parent.spawn();
+        entity.insert(ExternalCrateComponent(object)).insert(location).insert_bundle(
             SpriteBundle {
                 texture,
                 transform: Transform::from_translation(
-                    location.as_world_coordinates(game_map).extend(GAME_OBJECT_Z),
+                    location.as_world_coordinates(game_map).extend(CRATE_Z),
                 ),
                 sprite: Sprite {
+                    color,
                     custom_size: Some(Vec2::splat(TILE_WIDTH_PX)),
                     ..Default::default()
                 },
                 ..Default::default()
             },
         );
+        if let Some(hp) = hp {
+            entity.insert(hp);
+        }
+        entity.insert(CrateLoot::roll());
 
-        Ok(())
+        Ok(entity.id())
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
     }
 
     pub fn height(&self) -> usize {
         self.height
     }
+
+    pub fn contains(&self, location: TileLocation) -> bool {
+        location.0 < self.width && location.1 < self.height
+    }
 }
 
-#[derive(Component, Copy, Clone, Debug, Eq, PartialEq)]
+#[derive(Component, Copy, Clone, Debug, Eq, PartialEq, Hash)]
 pub struct TileLocation(pub usize, pub usize);
 
 impl TileLocation {
@@ -260,6 +559,11 @@ impl TileLocation {
         )
     }
 
+    /// Adds an offset to the location, if it lands on the map.
+    pub fn try_add(self, offset: TileOffset, game_map: &GameMap) -> Option<TileLocation> {
+        (self + offset).filter(|location| game_map.contains(*location))
+    }
+
     pub fn taxicab_distance_to_closest(
         &self,
         locations: impl Iterator<Item = TileLocation>,
@@ -270,6 +574,13 @@ impl TileLocation {
     }
 }
 
+/// Shows the column and row, counted from the bottom left of the map, as in `(7,3)`.
+impl fmt::Display for TileLocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "({},{})", self.0, self.1)
+    }
+}
+
 impl Add<Direction> for TileLocation {
     type Output = Option<TileLocation>;
 
@@ -285,11 +596,15 @@ impl Add<Direction> for TileLocation {
     }
 }
 
+/// Offsets that would land below column or row zero have no location, rather than being
+/// clamped onto the edge of the map.
 impl Add<TileOffset> for TileLocation {
-    type Output = TileLocation;
+    type Output = Option<TileLocation>;
 
     fn add(self, TileOffset(x, y): TileOffset) -> Self::Output {
-        Self((self.0 as i32 + x).max(0) as usize, (self.1 as i32 + y).max(0) as usize)
+        let x = usize::try_from(self.0 as i32 + x).ok()?;
+        let y = usize::try_from(self.1 as i32 + y).ok()?;
+        Some(Self(x, y))
     }
 }
 
@@ -301,29 +616,287 @@ impl Sub<TileLocation> for TileLocation {
     }
 }
 
-// Implemented as a standalone function to bypass the orphan rule, as the tiles
-// to convert to are defined in the `hero_lib` crate, which must be kept clean for
-// the players
-fn tile_from_char(character: char) -> Tile {
-    match character {
-        '#' => Tile::Wall,
-        '~' | 'C' => Tile::Hill,
-        _ => Tile::Floor,
+/// The order players are handed their surroundings in: row by row from the southmost one, and
+/// west to east within a row. The same view always comes in the same order, so bots can diff
+/// consecutive turns tile by tile, or binary search the surroundings with this key.
+pub fn surroundings_order(TileOffset(x, y): &TileOffset) -> (i32, i32) {
+    (*y, *x)
+}
+
+/// What a character in the textual representation of a map stands for. Maps may only use the
+/// characters of `MAP_PALETTE`.
+#[derive(Copy, Clone, Debug)]
+pub struct TileSpec {
+    pub character: char,
+    pub tile: Tile,
+    pub object: Option<ObjectSpec>,
+    /// Whether players can appear on the tile.
+    pub spawner: bool,
+    pub surface: Surface,
+}
+
+/// What the floor of a tile is like, which bots can't tell apart as it isn't part of the
+/// tiles `bomber_lib` describes.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Surface {
+    Plain,
+    /// Players that move onto ice keep sliding the same way on the following turns, until
+    /// they leave the ice or run into something.
+    Ice,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum ObjectSpec {
+    Crate,
+    /// A crate that takes `REINFORCED_CRATE_HP` explosions to destroy.
+    ReinforcedCrate,
+    /// A crate that only spawns with a chance of this many in ten, rolled every round.
+    CrateChance(u32),
+}
+
+/// Command line flag that prints the map palette as a Markdown legend and exits.
+pub const EXPORT_MAP_PALETTE_FLAG: &str = "--export-map-palette";
+
+/// Every character maps are made of. The palette is defined here rather than as conversions on
+/// the tiles and objects themselves, as those belong to `bomber_lib`, which must be kept clean
+/// for the players.
+pub const MAP_PALETTE: &[TileSpec] = &[
+    TileSpec::new('#', Tile::Wall, None, false),
+    TileSpec::new('.', Tile::Floor, None, false),
+    TileSpec::new('~', Tile::Hill, None, false),
+    TileSpec::new('i', Tile::Floor, None, false).with_surface(Surface::Ice),
+    TileSpec::new('s', Tile::Floor, None, true),
+    TileSpec::new('c', Tile::Floor, Some(ObjectSpec::Crate), false),
+    TileSpec::new('C', Tile::Hill, Some(ObjectSpec::Crate), false),
+    TileSpec::new('k', Tile::Floor, Some(ObjectSpec::ReinforcedCrate), false),
+    TileSpec::new('K', Tile::Hill, Some(ObjectSpec::ReinforcedCrate), false),
+    TileSpec::new('1', Tile::Floor, Some(ObjectSpec::CrateChance(1)), false),
+    TileSpec::new('2', Tile::Floor, Some(ObjectSpec::CrateChance(2)), false),
+    TileSpec::new('3', Tile::Floor, Some(ObjectSpec::CrateChance(3)), false),
+    TileSpec::new('4', Tile::Floor, Some(ObjectSpec::CrateChance(4)), false),
+    TileSpec::new('5', Tile::Floor, Some(ObjectSpec::CrateChance(5)), false),
+    TileSpec::new('6', Tile::Floor, Some(ObjectSpec::CrateChance(6)), false),
+    TileSpec::new('7', Tile::Floor, Some(ObjectSpec::CrateChance(7)), false),
+    TileSpec::new('8', Tile::Floor, Some(ObjectSpec::CrateChance(8)), false),
+    TileSpec::new('9', Tile::Floor, Some(ObjectSpec::CrateChance(9)), false),
+];
+
+impl TileSpec {
+    const fn new(character: char, tile: Tile, object: Option<ObjectSpec>, spawner: bool) -> Self {
+        Self { character, tile, object, spawner, surface: Surface::Plain }
+    }
+
+    const fn with_surface(self, surface: Surface) -> Self {
+        Self { surface, ..self }
+    }
+
+    /// The object to spawn on the tile this round, if any.
+    fn roll_object(&self) -> Option<Object> {
+        match self.object? {
+            ObjectSpec::Crate | ObjectSpec::ReinforcedCrate => Some(Object::Crate),
+            ObjectSpec::CrateChance(chance) => {
+                (chance >= rand::thread_rng().gen_range(1..=10)).then_some(Object::Crate)
+            },
+        }
+    }
+
+    fn crate_hp(&self) -> Option<CrateHp> {
+        matches!(self.object, Some(ObjectSpec::ReinforcedCrate))
+            .then_some(CrateHp { remaining: REINFORCED_CRATE_HP, last_hit_by: None })
     }
 }
 
-// Implemented as a standalone function for the same reason as `tile_from_char`
-fn object_from_char(character: char) -> Option<Object> {
-    match character {
-        'c' | 'C' => Some(Object::Crate),
-        // Numbers in the map text represent a chance for a crate to spawn.
-        p @ '1'..='9' => (p.to_digit(10).unwrap() >= rand::thread_rng().gen_range(1..=10))
-            .then_some(Object::Crate),
-        _ => None,
+fn tile_spec(character: char) -> Option<&'static TileSpec> {
+    MAP_PALETTE.iter().find(|spec| spec.character == character)
+}
+
+/// Makes sure no character of the palette means two things.
+fn check_palette() -> Result<()> {
+    let mut characters = HashSet::default();
+    match MAP_PALETTE.iter().find(|spec| !characters.insert(spec.character)) {
+        Some(spec) => {
+            Err(anyhow!("Character {:?} appears twice in the map palette", spec.character))
+        },
+        None => Ok(()),
     }
 }
 
-// Implemented as a standalone function for the same reason as `tile_from_char`
-fn spawner_from_char(character: char) -> Option<PlayerSpawner> {
-    (character == 's').then_some(PlayerSpawner)
+/// Describes the map palette as a Markdown table, for map authors.
+pub fn map_palette_legend() -> String {
+    let mut legend = format!(
+        "<!-- Generated with `cargo run -p bomber_game -- {}`, don't edit by hand. -->\n\n\
+         # Map palette\n\n\
+         | Character | Tile | Object | Spawner |\n\
+         |-----------|------|--------|---------|\n",
+        EXPORT_MAP_PALETTE_FLAG
+    );
+    for spec in MAP_PALETTE {
+        let tile = match (spec.tile, spec.surface) {
+            (Tile::Wall, _) => "wall",
+            (Tile::Floor, Surface::Plain) => "floor",
+            (Tile::Floor, Surface::Ice) => "floor, icy (players slide)",
+            (Tile::Hill, Surface::Plain) => "hill",
+            (Tile::Hill, Surface::Ice) => "hill, icy (players slide)",
+        };
+        let object = match spec.object {
+            None => String::new(),
+            Some(ObjectSpec::Crate) => "crate".to_owned(),
+            Some(ObjectSpec::ReinforcedCrate) => {
+                format!("reinforced crate ({} hits)", REINFORCED_CRATE_HP)
+            },
+            Some(ObjectSpec::CrateChance(chance)) => format!("crate, {} in 10 chance", chance),
+        };
+        let spawner = if spec.spawner { "yes" } else { "" };
+        legend
+            .push_str(&format!("| `{}` | {} | {} | {} |\n", spec.character, tile, object, spawner));
+    }
+    legend
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offsets_add_up_within_the_first_quadrant() {
+        assert_eq!(TileLocation(2, 3) + TileOffset(1, -2), Some(TileLocation(3, 1)));
+        assert_eq!(TileLocation(2, 3) + TileOffset(-2, -3), Some(TileLocation(0, 0)));
+        assert_eq!(TileLocation(0, 0) + TileOffset(0, 0), Some(TileLocation(0, 0)));
+    }
+
+    #[test]
+    fn offsets_past_the_origin_are_out_of_bounds() {
+        // These used to be clamped onto column and row 0.
+        assert_eq!(TileLocation(1, 3) + TileOffset(-2, 0), None);
+        assert_eq!(TileLocation(3, 1) + TileOffset(0, -2), None);
+        assert_eq!(TileLocation(0, 0) + TileOffset(-1, -1), None);
+    }
+
+    fn test_map() -> GameMap {
+        GameMap::parse("test", "s......\n.......\n.......").unwrap().0
+    }
+
+    #[test]
+    fn map_contains_locations_up_to_its_far_corner() {
+        let game_map = test_map();
+        assert_eq!((game_map.width(), game_map.height()), (7, 3));
+        assert!(game_map.contains(TileLocation(0, 0)));
+        assert!(game_map.contains(TileLocation(6, 2)));
+        assert!(!game_map.contains(TileLocation(7, 2)));
+        assert!(!game_map.contains(TileLocation(6, 3)));
+    }
+
+    #[test]
+    fn checked_offsets_stay_on_the_map() {
+        let game_map = test_map();
+        let corner = TileLocation(6, 2);
+        assert_eq!(corner.try_add(TileOffset(-6, -2), &game_map), Some(TileLocation(0, 0)));
+        assert_eq!(corner.try_add(TileOffset(1, 0), &game_map), None);
+        assert_eq!(corner.try_add(TileOffset(0, 1), &game_map), None);
+        assert_eq!(corner.try_add(TileOffset(-7, 0), &game_map), None);
+        assert_eq!(corner.try_add(TileOffset(0, -3), &game_map), None);
+    }
+
+    #[test]
+    fn locations_display_as_coordinates() {
+        assert_eq!(TileLocation(7, 3).to_string(), "(7,3)");
+    }
+
+    /// Tile, crate chance in ten, reinforcement and spawner as maps were parsed before the
+    /// palette, one character at a time.
+    fn legacy_meaning(character: char) -> (Tile, Option<u32>, bool, bool) {
+        let tile = match character {
+            '#' => Tile::Wall,
+            '~' | 'C' | 'K' => Tile::Hill,
+            _ => Tile::Floor,
+        };
+        let crate_chance = match character {
+            'c' | 'C' | 'k' | 'K' => Some(10),
+            p @ '1'..='9' => p.to_digit(10),
+            _ => None,
+        };
+        (tile, crate_chance, matches!(character, 'k' | 'K'), character == 's')
+    }
+
+    #[test]
+    fn palette_characters_are_unique() {
+        assert!(check_palette().is_ok());
+    }
+
+    #[test]
+    fn bundled_maps_parse_as_they_did_before_the_palette() {
+        for (name, _, text) in MAPS {
+            let (game_map, rows) = GameMap::parse(name, text).unwrap();
+            assert_eq!((game_map.width(), game_map.height()), (rows[0].len(), rows.len()));
+            for spec in rows.iter().flatten() {
+                let crate_chance = match spec.object {
+                    Some(ObjectSpec::Crate | ObjectSpec::ReinforcedCrate) => Some(10),
+                    Some(ObjectSpec::CrateChance(chance)) => Some(chance),
+                    None => None,
+                };
+                let (tile, legacy_chance, reinforced, spawner) = legacy_meaning(spec.character);
+                assert_eq!(format!("{:?}", spec.tile), format!("{:?}", tile), "in {}", name);
+                assert_eq!(crate_chance, legacy_chance, "in {}", name);
+                assert_eq!(spec.crate_hp().is_some(), reinforced, "in {}", name);
+                assert_eq!(spec.spawner, spawner, "in {}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_characters_are_reported_where_they_are() {
+        let error = GameMap::parse("test", "max_players = 2\ns..\n.x.").unwrap_err();
+        assert_eq!(error.to_string(), "Unknown character 'x' at line 3, column 2 of map test");
+    }
+
+    /// Characters of the parsed rows of a map, from the top down as written.
+    fn characters(text: &str) -> Vec<String> {
+        let (_, rows) = GameMap::parse("test", text).unwrap();
+        rows.iter().rev().map(|row| row.iter().map(|spec| spec.character).collect()).collect()
+    }
+
+    #[test]
+    fn windows_line_endings_are_ignored() {
+        let text = "max_players = 2\r\nhill_hints = false\r\ns.#\r\n~cs\r\n";
+        let (game_map, _) = GameMap::parse("test", text).unwrap();
+        assert_eq!((game_map.width(), game_map.height()), (3, 2));
+        assert_eq!((game_map.max_players, game_map.hill_hints), (Some(2), false));
+        assert_eq!(characters(text), vec!["s.#", "~cs"]);
+        for (name, _, text) in MAPS {
+            assert_eq!(characters(&text.replace('\n', "\r\n")), characters(text), "in {}", name);
+        }
+    }
+
+    #[test]
+    fn trailing_whitespace_is_not_part_of_the_map() {
+        assert_eq!(characters("s.# \n~cs\t\n\n  \n"), vec!["s.#", "~cs"]);
+    }
+
+    #[test]
+    fn maps_of_only_walls_are_rejected() {
+        let error = GameMap::parse("test", "###\n###").unwrap_err();
+        assert_eq!(error.to_string(), "Map test has no tiles to walk on");
+    }
+
+    #[test]
+    fn maps_without_spawners_are_rejected() {
+        let error = GameMap::parse("test", "...\n.#.").unwrap_err();
+        assert_eq!(error.to_string(), "Map test has no player spawners");
+        let error = GameMap::parse("test", "max_players = 0\ns..").unwrap_err();
+        assert_eq!(error.to_string(), "Map test allows 0 players, but has 1 spawners");
+    }
+
+    #[test]
+    fn empty_maps_are_rejected() {
+        let error = GameMap::parse("test", "max_players = 2\r\n\r\n").unwrap_err();
+        assert_eq!(error.to_string(), "Map test must have at least a row and a column");
+    }
+
+    #[test]
+    fn legend_lists_every_character() {
+        let legend = map_palette_legend();
+        for spec in MAP_PALETTE {
+            assert!(legend.contains(&format!("| `{}` |", spec.character)));
+        }
+    }
 }
@@ -1,37 +1,84 @@
-use std::time::Duration;
+use std::{hash::Hash, time::Duration};
 
-use bevy::prelude::*;
+use bevy::{asset::HandleId, prelude::*};
 use bevy_egui::{
-    egui::{self, epaint::Shadow, style::Widgets, Color32, RichText, Stroke},
+    egui::{
+        self,
+        epaint::Shadow,
+        plot::{Line, Plot, Value, Values},
+        style::Widgets,
+        Color32, RichText, Stroke,
+    },
     EguiContext, EguiPlugin,
 };
+use bomber_shared::BuildMeta;
 
 use crate::{
+    game_map::{ForcedNextMap, GameMap, MapReloadEvent, MAPS},
     object,
-    player_behaviour::{Player, PlayerDespawnedEvent, PlayerName, SpawnPlayerEvent},
+    player::{
+        arena_is_full, HideNames, Player, PlayerDespawnedEvent, PlayerName, SpawnPlayerEvent,
+        SpawnStarvedEvent, Team,
+    },
+    player_hotswap::{PlayerHandle, PlayerHandles, PlayerReloadEvent, WasmPlayerAsset},
     rendering::TILE_HEIGHT_PX,
-    score::Score,
+    score::{Score, Throttled},
+    settings::GameSettings,
     state::{AppState, Round, RoundTimer},
+    stats::{RoundStats, ScoreHistory, SCORE_HISTORY_LENGTH},
+    tick::RoundPhase,
 };
 
 pub struct GameUiPlugin;
 
 const DESPAWNED_MARKER_DURATION: Duration = Duration::from_secs(10);
+/// Dead players shown at most, so players banned over and over can't push the live scores
+/// off the panel.
+const MAX_DESPAWNED_MARKERS: usize = 5;
+const TOAST_DURATION: Duration = Duration::from_secs(5);
+const SPARKLINE_WIDTH_PX: f32 = 60.0;
+/// Shown in place of dead players' names when names are hidden.
+pub const HIDDEN_NAME: &str = "(hidden)";
+const TOGGLE_SCORE_PANEL_KEY: KeyCode = KeyCode::Tab;
+
+/// Whether the score panel is shown next to the arena. It can be hidden to make room for
+/// the map, such as when the scores are up in the scoreboard window instead.
+pub struct ScorePanelVisible(pub bool);
+
+impl Default for ScorePanelVisible {
+    fn default() -> Self {
+        Self(true)
+    }
+}
 
 /// Marker component that identifies a score/name pair as belonging to a dead
-/// (despawned) player, so their last score is visible until they respawn.
+/// (despawned) player, so their last score is visible until they respawn. Players that
+/// aren't coming back are only shown for a while.
 #[derive(Component)]
 struct DespawnedPlayerMarker {
     reason: String,
+    handle: Option<HandleId>,
+    /// Time since startup at which the player died, to show the most recent deaths first.
+    despawned_at: Duration,
     timer: Timer,
 }
 
 impl Plugin for GameUiPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugin(EguiPlugin);
+        app.init_resource::<ScorePanelVisible>();
+        app.add_system(toggle_score_panel_system);
         app.add_system(dead_player_score_system);
         app.add_system(dead_player_score_cleanup_system);
-        app.add_system_set(SystemSet::on_update(AppState::InGame).with_system(score_panel_system));
+        app.add_system(reload_toast_system);
+        app.add_system_set(
+            SystemSet::on_update(AppState::InGame)
+                .with_system(score_panel_system)
+                .with_system(get_ready_overlay_system),
+        );
+        // Operators can steer the game from a debug window, which is left out of release builds.
+        #[cfg(debug_assertions)]
+        app.add_system(operator_window_system);
         app.add_startup_system(configure_visuals);
     }
 }
@@ -39,27 +86,97 @@ impl Plugin for GameUiPlugin {
 fn dead_player_score_cleanup_system(
     mut commands: Commands,
     time: Res<Time>,
+    handles: Res<PlayerHandles>,
     mut despawned_query: Query<(Entity, &mut DespawnedPlayerMarker)>,
 ) {
     for (entity, mut despawned) in despawned_query.iter_mut() {
+        if respawn_countdown(&handles, despawned.handle).is_some() {
+            // Removed once the player is back instead.
+            continue;
+        }
         despawned.timer.tick(time.delta());
         if despawned.timer.just_finished() {
             commands.entity(entity).despawn_recursive();
         }
     }
+
+    let mut markers = despawned_query
+        .iter()
+        .filter(|(_, d)| !d.timer.just_finished())
+        .map(|(e, d)| (e, d.despawned_at))
+        .collect::<Vec<_>>();
+    markers.sort_by(|(_, a), (_, b)| b.cmp(a));
+    for (entity, _) in markers.into_iter().skip(MAX_DESPAWNED_MARKERS) {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn toggle_score_panel_system(
+    keys: Res<Input<KeyCode>>,
+    mut panel_visible: ResMut<ScorePanelVisible>,
+) {
+    if keys.just_pressed(TOGGLE_SCORE_PANEL_KEY) {
+        panel_visible.0 = !panel_visible.0;
+    }
 }
 
 fn score_panel_system(
+    panel_visible: Res<ScorePanelVisible>,
     mut egui_context: ResMut<EguiContext>,
-    player_query: Query<(&Player, &PlayerName, &Score)>,
+    player_query: Query<(
+        &Player,
+        &PlayerName,
+        &Team,
+        &Score,
+        Option<&Handle<WasmPlayerAsset>>,
+        Option<&Throttled>,
+    )>,
     dead_query: Query<(&PlayerName, &Score, &DespawnedPlayerMarker)>,
     round_timer_query: Query<&RoundTimer>,
     round: Res<Round>,
+    stats: Res<RoundStats>,
     textures: Res<object::Textures>,
+    handles: Res<PlayerHandles>,
+    game_map_query: Query<&GameMap>,
+    // Grouped to stay within the lint on system parameters.
+    (asset_server, assets): (Res<AssetServer>, Res<Assets<WasmPlayerAsset>>),
+    hide_names: Res<HideNames>,
+    mut starved_events: EventReader<SpawnStarvedEvent>,
+    settings: Res<GameSettings>,
 ) {
+    if !panel_visible.0 {
+        return;
+    }
     let mut score_entries = player_query.iter().collect::<Vec<_>>();
     // Sort by descending score
-    score_entries.sort_by(|(_, _, Score(a)), (_, _, Score(b))| b.cmp(a));
+    score_entries.sort_by(|(_, _, _, Score(a), ..), (_, _, _, Score(b), ..)| b.cmp(a));
+    let mut dead_entries = dead_query.iter().collect::<Vec<_>>();
+    // Most recent deaths first
+    dead_entries.sort_by(|(_, _, a), (_, _, b)| b.despawned_at.cmp(&a.despawned_at));
+    // Players that are ready but can't join, because the map is at capacity or because there
+    // aren't enough safe spawners for everyone at the moment.
+    let max_players = game_map_query.get_single().ok().and_then(|m| m.max_players);
+    let map_full = arena_is_full(player_query.iter().count(), &handles.0, max_players);
+    let starved = starved_events.iter().last().map(|SpawnStarvedEvent(s)| s.clone());
+    let queued_entries = if map_full {
+        handles
+            .0
+            .iter()
+            .filter(|h| h.is_ready_to_spawn())
+            .filter(|h| {
+                player_query.iter().all(|(.., p, _)| p.map_or(true, |p| p.id != h.inner().id))
+            })
+            .filter_map(|h| PlayerHandle::file_stem(h.inner(), &asset_server))
+            .map(|name| (name, "waiting — map full"))
+            .collect::<Vec<_>>()
+    } else {
+        starved
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|id| PlayerHandle::file_stem(id, &asset_server))
+            .map(|name| (name, "waiting for a free spawner"))
+            .collect::<Vec<_>>()
+    };
     let timer = round_timer_query.single();
     let remaining = timer.0.duration() - timer.0.elapsed();
     let (minutes, seconds) = (remaining.as_secs() / 60, remaining.as_secs() % 60);
@@ -75,18 +192,52 @@ fn score_panel_system(
                 RichText::new(format!("Round {} ends in {minutes}:{seconds:02}", round.0))
                     .size(25.0);
             ui.label(label_text);
+            ui.label(RichText::new(round.name()).italics().size(20.0));
+            if let Some(rules_override) = &settings.rules_override {
+                ui.colored_label(
+                    tonari_color::STRAWBERRY_LETTER_23,
+                    RichText::new(format!("Gimmick round: {}", rules_override.describe())).strong(),
+                );
+            }
+            if !settings.rules.friendly_fire {
+                ui.colored_label(
+                    tonari_color::STRAWBERRY_LETTER_23,
+                    RichText::new("FF off").strong(),
+                )
+                .on_hover_text("Bombs don't kill their owner's teammates this round.");
+            }
             ui.separator();
             ui.heading(RichText::new("Player Score").strong());
             egui::Grid::new("Score Grid").striped(true).show(ui, |ui| {
-                for (Player { power_ups, .. }, PlayerName(name), score) in score_entries.iter() {
-                    ui.colored_label(
-                        tonari_color::MIDNIGHT,
-                        RichText::new(name).text_style(egui::TextStyle::Heading),
-                    );
-                    ui.label(
-                        RichText::new(format!(" {: >3} points", score.0,))
-                            .text_style(egui::TextStyle::Heading),
-                    );
+                for (Player { power_ups, .. }, PlayerName(name), team, score, handle, throttled) in
+                    score_entries.iter()
+                {
+                    let name = if hide_names.0 { &team.name } else { name };
+                    let build = handle.and_then(|h| assets.get(h)).and_then(|a| a.build.as_ref());
+                    ui.horizontal(|ui| {
+                        ui.colored_label(
+                            tonari_color::MIDNIGHT,
+                            RichText::new(name).text_style(egui::TextStyle::Heading),
+                        );
+                        // Lets teams check which build of their bot is live.
+                        if let Some(hash) = build.and_then(BuildMeta::short_hash) {
+                            ui.label(RichText::new(hash).monospace().weak());
+                        }
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label(
+                            RichText::new(format!(" {: >3} points", score.0,))
+                                .text_style(egui::TextStyle::Heading),
+                        );
+                        // Same marker as on the scoreboard window.
+                        if throttled.is_some() {
+                            ui.colored_label(
+                                tonari_color::STRAWBERRY_LETTER_23,
+                                RichText::new("↓").text_style(egui::TextStyle::Heading),
+                            )
+                            .on_hover_text("Hill points halved until the others catch up.");
+                        }
+                    });
                     ui.end_row();
                     ui.horizontal(|ui| {
                         ui.image(bomb_range_power_up, egui::Vec2::splat(TILE_HEIGHT_PX / 2.0));
@@ -116,25 +267,62 @@ fn score_panel_system(
                                 .copied()
                                 .unwrap_or_default()
                         ));
+                        if let Some(player_stats) = handle.and_then(|h| stats.0.get(&h.id)) {
+                            ui.label(format!(
+                                "{:.1}M fuel/turn",
+                                player_stats.mean_fuel() as f64 / 1_000_000.0
+                            ));
+                            score_sparkline(ui, handle.map(|h| h.id), &player_stats.score_history);
+                        }
                     });
                     ui.end_row();
                 }
-                for (PlayerName(name), score, DespawnedPlayerMarker { reason, .. }) in
-                    dead_query.iter()
-                {
-                    ui.colored_label(
-                        tonari_color::STRAWBERRY_LETTER_23,
-                        RichText::new(name).strikethrough().text_style(egui::TextStyle::Heading),
-                    );
-                    ui.label(
-                        RichText::new(format!(" {: >3} (Dead)", score.0,))
-                            .text_style(egui::TextStyle::Heading),
-                    );
-                    ui.end_row();
+            });
+            if !dead_entries.is_empty() {
+                egui::CollapsingHeader::new(format!("Dead ({})", dead_entries.len()))
+                    .default_open(true)
+                    .show(ui, |ui| {
+                        egui::Grid::new("Dead Grid").striped(true).show(ui, |ui| {
+                            for (
+                                PlayerName(name),
+                                score,
+                                DespawnedPlayerMarker { reason, handle, .. },
+                            ) in dead_entries.iter()
+                            {
+                                let name = if hide_names.0 { HIDDEN_NAME } else { name.as_str() };
+                                ui.colored_label(
+                                    tonari_color::STRAWBERRY_LETTER_23,
+                                    RichText::new(name)
+                                        .strikethrough()
+                                        .text_style(egui::TextStyle::Heading),
+                                );
+                                ui.label(
+                                    RichText::new(format!(" {: >3} (Dead)", score.0,))
+                                        .text_style(egui::TextStyle::Heading),
+                                );
+                                ui.end_row();
+                                ui.colored_label(
+                                    tonari_color::STRAWBERRY_LETTER_23,
+                                    RichText::new(reason).strong(),
+                                );
+                                if let Some(remaining) = respawn_countdown(&handles, *handle) {
+                                    ui.label(
+                                        RichText::new(format!("respawning in {remaining}…"))
+                                            .italics(),
+                                    );
+                                }
+                                ui.end_row();
+                            }
+                        });
+                    });
+            }
+            egui::Grid::new("Queue Grid").striped(true).show(ui, |ui| {
+                for (name, reason) in queued_entries.iter().filter(|_| !hide_names.0) {
                     ui.colored_label(
-                        tonari_color::STRAWBERRY_LETTER_23,
-                        RichText::new(reason).strong(),
+                        tonari_color::MIDNIGHT,
+                        RichText::new(name).weak().text_style(egui::TextStyle::Heading),
                     );
+                    ui.label(RichText::new(*reason).italics());
                     ui.end_row();
                 }
                 ui.allocate_space(ui.available_size());
@@ -143,29 +331,142 @@ fn score_panel_system(
     });
 }
 
+/// Tiny plot of a player's recent score, to show who has momentum.
+fn score_sparkline(ui: &mut egui::Ui, id: impl Hash, history: &ScoreHistory) {
+    let values = history.iter().enumerate().map(|(tick, score)| Value::new(tick as f64, score));
+    Plot::new(("Score history", id))
+        .width(SPARKLINE_WIDTH_PX)
+        .height(TILE_HEIGHT_PX / 2.0)
+        .include_x(SCORE_HISTORY_LENGTH as f64)
+        .include_y(0.0)
+        .allow_drag(false)
+        .allow_zoom(false)
+        .show_x(false)
+        .show_y(false)
+        .show(ui, |plot_ui| {
+            plot_ui.line(Line::new(Values::from_values_iter(values)).color(tonari_color::MIDNIGHT))
+        });
+}
+
+#[cfg(debug_assertions)]
+fn operator_window_system(
+    mut egui_context: ResMut<EguiContext>,
+    mut forced_next_map: ResMut<ForcedNextMap>,
+) {
+    egui::Window::new("Operator").resizable(false).show(egui_context.ctx_mut(), |ui| {
+        let selected = forced_next_map.0.clone().unwrap_or_else(|| "Rotation".to_owned());
+        egui::ComboBox::from_label("Next map").selected_text(selected).show_ui(ui, |ui| {
+            ui.selectable_value(&mut forced_next_map.0, None, "Rotation");
+            for (name, ..) in MAPS {
+                ui.selectable_value(&mut forced_next_map.0, Some(name.to_string()), *name);
+            }
+        });
+    });
+}
+
+/// Briefly notifies operators of map and player files being edited, and of edits being
+/// rejected.
+fn reload_toast_system(
+    mut egui_context: ResMut<EguiContext>,
+    mut map_reload_events: EventReader<MapReloadEvent>,
+    mut player_reload_events: EventReader<PlayerReloadEvent>,
+    hide_names: Res<HideNames>,
+    mut toasts: Local<Vec<(String, Timer)>>,
+    time: Res<Time>,
+) {
+    let map_messages =
+        map_reload_events.iter().map(|MapReloadEvent { name, result }| match result {
+            Ok(()) => format!("Map '{}' updated", name),
+            Err(e) => format!("Map '{}' not updated: {}", name, e),
+        });
+    let player_messages = player_reload_events.iter().map(|PlayerReloadEvent { name, result }| {
+        let name = if hide_names.0 { HIDDEN_NAME } else { name.as_str() };
+        match result {
+            Ok(()) => format!("{} reloaded", name),
+            Err(e) => format!("{} reload rejected: {}", name, e),
+        }
+    });
+    for message in map_messages.chain(player_messages) {
+        toasts.push((message, Timer::new(TOAST_DURATION, false)));
+    }
+    for (_, timer) in toasts.iter_mut() {
+        timer.tick(time.delta());
+    }
+    toasts.retain(|(_, timer)| !timer.finished());
+    if toasts.is_empty() {
+        return;
+    }
+
+    egui::Area::new("Toasts")
+        .anchor(egui::Align2::RIGHT_BOTTOM, egui::Vec2::new(-10.0, -10.0))
+        .show(egui_context.ctx_mut(), |ui| {
+            for (message, _) in toasts.iter() {
+                ui.label(RichText::new(message).strong().size(20.0));
+            }
+        });
+}
+
+fn get_ready_overlay_system(mut egui_context: ResMut<EguiContext>, phase: Res<RoundPhase>) {
+    if let RoundPhase::GetReady(timer) = &*phase {
+        let remaining = (timer.duration() - timer.elapsed()).as_secs_f32().ceil();
+        egui::Area::new("Get Ready").anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO).show(
+            egui_context.ctx_mut(),
+            |ui| {
+                ui.vertical_centered(|ui| {
+                    ui.label(RichText::new("Get ready!").strong().size(60.0));
+                    ui.label(RichText::new(format!("{remaining}")).strong().size(90.0));
+                });
+            },
+        );
+    }
+}
+
 fn dead_player_score_system(
     mut spawn_events: EventReader<SpawnPlayerEvent>,
     mut despawn_events: EventReader<PlayerDespawnedEvent>,
     mut commands: Commands,
+    time: Res<Time>,
     dead_player_scores: Query<(Entity, &DespawnedPlayerMarker, &PlayerName)>,
 ) {
-    for SpawnPlayerEvent(name) in spawn_events.iter() {
+    for SpawnPlayerEvent(name, handle, _) in spawn_events.iter() {
+        // Names may repeat across teams, so `.wasm` players are matched by handle instead.
+        let returning = |marker: &DespawnedPlayerMarker, marker_name: &PlayerName| match handle {
+            Some(handle) => marker.handle == Some(*handle),
+            None => marker.handle.is_none() && marker_name.0 == name.0,
+        };
         if let Some(entity) =
-            dead_player_scores.iter().find_map(|(e, _, n)| (n.0 == name.0).then_some(e))
+            dead_player_scores.iter().find_map(|(e, d, n)| returning(d, n).then_some(e))
         {
             commands.entity(entity).despawn_recursive();
         }
     }
-    for PlayerDespawnedEvent(name, score, reason) in despawn_events.iter() {
+    for PlayerDespawnedEvent(name, score, reason, handle) in despawn_events.iter() {
+        // A `.wasm` player dying again, such as one re-uploaded and banned over and over,
+        // replaces its previous row rather than adding another.
+        let previous = handle.and_then(|handle| {
+            dead_player_scores.iter().find_map(|(e, d, _)| (d.handle == Some(handle)).then_some(e))
+        });
         // The player themselves will be despawned this frame, but we instead insert a score marker that will persist
         // until they despawn.
-        commands.spawn().insert(name.clone()).insert(*score).insert(DespawnedPlayerMarker {
+        let mut entity = match previous {
+            Some(entity) => commands.entity(entity),
+            None => commands.spawn(),
+        };
+        entity.insert(name.clone()).insert(*score).insert(DespawnedPlayerMarker {
             reason: reason.clone(),
+            handle: *handle,
+            despawned_at: time.time_since_startup(),
             timer: Timer::new(DESPAWNED_MARKER_DURATION, false),
         });
     }
 }
 
+/// World ticks until a despawned `.wasm` player is back, if it's coming back at all.
+fn respawn_countdown(handles: &PlayerHandles, handle: Option<HandleId>) -> Option<u32> {
+    let id = handle?;
+    handles.0.iter().find(|h| h.inner().id == id).and_then(PlayerHandle::respawn_countdown)
+}
+
 fn configure_visuals(mut egui_ctx: ResMut<EguiContext>) {
     let faded_little_dragon = Color32::from_rgb(102, 178, 162);
     let mut widgets = Widgets::light();
@@ -0,0 +1,54 @@
+//! Records everything handed to each `.wasm` player on its turns, so teams can replay the
+//! exact inputs of a round against their bot offline with the `replay_inputs` binary.
+//!
+//! Recordings live in `inputs/<file stem>.bin` under the round folder, as a sequence of
+//! frames. Each frame is the bincode encoding of a [`RecordedTurn`], prefixed by its length
+//! as a little endian `u32`.
+
+use anyhow::{Context, Result};
+use bomber_lib::world::{Enemy, Object, Tile, TileOffset};
+use serde::Serialize;
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+const INPUTS_FOLDER: &str = "inputs";
+
+/// Where the inputs of a player are recorded, given its round folder.
+pub fn recording_path(round_folder: &Path, file_stem: &str) -> PathBuf {
+    round_folder.join(INPUTS_FOLDER).join(file_stem).with_extension("bin")
+}
+
+/// The inputs of a single turn, as the player was given them.
+#[derive(Serialize)]
+pub struct RecordedTurn<'a> {
+    /// Surroundings after truncation to the player's buffer.
+    pub surroundings: &'a [(Tile, Option<Object>, Option<Enemy>, TileOffset)],
+    /// The value passed to `HILL_HINT_EXPORT`.
+    pub hill_hint: u32,
+    /// Bombs on the map and the most allowed at once, as passed to `BOMB_COUNT_EXPORT`.
+    pub bomb_count: (u32, u32),
+    /// Points scored since the previous turn, as passed to `SCORED_EXPORT`.
+    pub scored: u32,
+    /// The killer team (empty for the player's own bomb) and bomb offset passed to
+    /// `LAST_DEATH_EXPORT`, on the first turn after a respawn.
+    pub last_death: Option<(&'a str, i32, i32)>,
+}
+
+/// Appends a frame to the recording at `path`.
+pub fn record_frame(path: &Path, turn: &RecordedTurn) -> Result<()> {
+    if let Some(folder) = path.parent() {
+        fs::create_dir_all(folder).with_context(|| format!("creating {:?}", folder))?;
+    }
+    let frame = bincode::serialize(turn)?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("opening {:?}", path))?;
+    file.write_all(&(frame.len() as u32).to_le_bytes())?;
+    file.write_all(&frame)?;
+    Ok(())
+}
@@ -0,0 +1,93 @@
+//! Defines a Bevy plugin that adds a bit of punch to kills: the camera shakes, and the game
+//! briefly slows down. Both can be turned off with `REDUCED_MOTION=1`.
+
+use std::time::Duration;
+
+use bevy::{prelude::*, utils::HashSet};
+use rand::{thread_rng, Rng};
+
+use crate::{
+    player::KillPlayerEvent, scoreboard_window::ScoreboardCamera, settings::GameSettings,
+    tick::TimeScale,
+};
+
+pub struct JuicePlugin;
+
+const SHAKE_DURATION: Duration = Duration::from_millis(100);
+/// Shake amplitude per player killed on the same frame.
+const SHAKE_AMPLITUDE_PX: f32 = 4.0;
+const SLOW_MOTION_DURATION: Duration = Duration::from_millis(50);
+const SLOW_MOTION_TIME_SCALE: f32 = 0.25;
+
+/// Camera shake in progress, fading out as its timer runs down.
+struct ScreenShake {
+    timer: Timer,
+    amplitude: f32,
+}
+/// Slow motion in progress, measured in real time so it isn't slowed down itself.
+struct SlowMotion(Timer);
+
+impl Plugin for JuicePlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(ScreenShake { timer: finished_timer(SHAKE_DURATION), amplitude: 0.0 })
+            .insert_resource(SlowMotion(finished_timer(SLOW_MOTION_DURATION)))
+            .add_system(kill_juice_system)
+            .add_system(screen_shake_system)
+            .add_system(slow_motion_system);
+    }
+}
+
+fn finished_timer(duration: Duration) -> Timer {
+    let mut timer = Timer::new(duration, false);
+    timer.tick(duration);
+    timer
+}
+
+fn kill_juice_system(
+    mut kill_events: EventReader<KillPlayerEvent>,
+    mut shake: ResMut<ScreenShake>,
+    mut slow_motion: ResMut<SlowMotion>,
+    settings: Res<GameSettings>,
+) {
+    // The same player can be reported more than once before it's despawned.
+    let killed: HashSet<Entity> =
+        kill_events.iter().map(|KillPlayerEvent(entity, ..)| *entity).collect();
+    if killed.is_empty() || settings.reduced_motion {
+        return;
+    }
+
+    *shake = ScreenShake {
+        timer: Timer::new(SHAKE_DURATION, false),
+        amplitude: SHAKE_AMPLITUDE_PX * killed.len() as f32,
+    };
+    slow_motion.0.reset();
+}
+
+fn screen_shake_system(
+    time: Res<Time>,
+    mut shake: ResMut<ScreenShake>,
+    mut camera_query: Query<&mut Transform, (With<Camera2d>, Without<ScoreboardCamera>)>,
+) {
+    if shake.timer.finished() {
+        return;
+    }
+
+    shake.timer.tick(time.delta());
+    let amplitude = shake.amplitude * shake.timer.percent_left();
+    let mut rng = thread_rng();
+    for mut transform in camera_query.iter_mut() {
+        // Back to rest once the shake is over, as `percent_left` is zero then.
+        transform.translation.x = rng.gen_range(-1.0..=1.0) * amplitude;
+        transform.translation.y = rng.gen_range(-1.0..=1.0) * amplitude;
+    }
+}
+
+fn slow_motion_system(
+    time: Res<Time>,
+    mut slow_motion: ResMut<SlowMotion>,
+    mut time_scale: ResMut<TimeScale>,
+) {
+    let SlowMotion(ref mut timer) = *slow_motion;
+    timer.tick(time.delta());
+    time_scale.0 = if timer.finished() { 1.0 } else { SLOW_MOTION_TIME_SCALE };
+}
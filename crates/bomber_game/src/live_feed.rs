@@ -0,0 +1,193 @@
+//! Defines a Bevy plugin that streams the state of the game as JSON over WebSocket, for
+//! stream overlays such as lower thirds with the scores. Clients connect to
+//! `ws://LIVE_FEED_ADDRESS/live` and get a message on every world tick, kill and change of
+//! round. Only built with the `live-feed` feature.
+
+use std::{
+    net::{TcpListener, TcpStream},
+    sync::{
+        mpsc::{self, Receiver, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+use anyhow::{Context, Result};
+use bevy::prelude::*;
+use serde::Serialize;
+use tungstenite::{
+    handshake::server::{ErrorResponse, Request, Response},
+    http::StatusCode,
+    Message,
+};
+
+use crate::{
+    game_ui::HIDDEN_NAME,
+    log_recoverable_error,
+    player::{HideNames, KillPlayerEvent, Player, PlayerName, Team},
+    score::Score,
+    settings::GameSettings,
+    state::{AppState, Round, RoundTimer},
+    tick::{RoundPhase, TurnPhase, WorldTurnStep},
+};
+
+pub struct LiveFeedPlugin;
+
+const LIVE_FEED_PATH: &str = "/live";
+/// Messages queued for a client before further ones are dropped, so a slow client never
+/// holds up the game or the other clients.
+const CLIENT_QUEUE_LENGTH: usize = 8;
+
+/// Queues of the connected clients, fed by the game and drained by a thread per client.
+#[derive(Clone, Default)]
+struct LiveFeedClients(Arc<Mutex<Vec<SyncSender<Arc<str>>>>>);
+
+#[derive(Serialize, Debug)]
+struct LiveState<'a> {
+    /// What prompted the message: `tick`, `kill`, `round_start` or `round_end`.
+    event: &'static str,
+    round: u32,
+    /// `get_ready`, `playing` or `victory_screen`.
+    phase: &'static str,
+    seconds_left: Option<u64>,
+    /// Players in the arena, best score first.
+    players: Vec<LivePlayer<'a>>,
+    /// Players killed since the previous message.
+    killed: Vec<&'a str>,
+}
+
+#[derive(Serialize, Debug)]
+struct LivePlayer<'a> {
+    name: &'a str,
+    team: &'a str,
+    score: u32,
+}
+
+impl Plugin for LiveFeedPlugin {
+    fn build(&self, app: &mut App) {
+        let address = app.world.resource::<GameSettings>().live_feed_address.clone();
+        let address = if let Some(address) = address { address } else { return };
+        let clients = LiveFeedClients::default();
+        match TcpListener::bind(&address) {
+            Ok(listener) => {
+                info!("Serving the live feed on ws://{}{}.", address, LIVE_FEED_PATH);
+                let accepting_clients = clients.clone();
+                thread::spawn(move || accept_clients(listener, accepting_clients));
+            },
+            Err(e) => {
+                error!("Not serving the live feed, binding {} failed: {}", address, e);
+                return;
+            },
+        }
+
+        app.insert_resource(clients).add_system(
+            live_feed_system.chain(log_recoverable_error).after(WorldTurnStep::Scoring),
+        );
+    }
+}
+
+fn accept_clients(listener: TcpListener, clients: LiveFeedClients) {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("Accepting a live feed client: {}", e);
+                continue;
+            },
+        };
+        let (sender, receiver) = mpsc::sync_channel(CLIENT_QUEUE_LENGTH);
+        let clients = clients.clone();
+        thread::spawn(move || {
+            let peer = stream.peer_addr().map_or_else(|_| "unknown".to_owned(), |a| a.to_string());
+            clients.0.lock().unwrap().push(sender);
+            match serve_client(stream, receiver) {
+                Ok(()) => info!("Live feed client {} left.", peer),
+                Err(e) => info!("Live feed client {} dropped: {:#}", peer, e),
+            }
+        });
+    }
+}
+
+/// Sends the queued messages to a client until it hangs up.
+fn serve_client(stream: TcpStream, receiver: Receiver<Arc<str>>) -> Result<()> {
+    let check_path = |request: &Request, response: Response| {
+        if request.uri().path() == LIVE_FEED_PATH {
+            Ok(response)
+        } else {
+            let mut not_found = ErrorResponse::new(Some(format!("Try {}\n", LIVE_FEED_PATH)));
+            *not_found.status_mut() = StatusCode::NOT_FOUND;
+            Err(not_found)
+        }
+    };
+    let mut websocket = tungstenite::accept_hdr(stream, check_path).context("handshake")?;
+    // Clients hanging up are only noticed on the next message sent to them.
+    for message in receiver {
+        websocket.write_message(Message::Text(message.to_string())).context("sending")?;
+    }
+    Ok(())
+}
+
+fn live_feed_system(
+    clients: Res<LiveFeedClients>,
+    state: Res<State<AppState>>,
+    turn_phase: Res<TurnPhase>,
+    round_phase: Res<RoundPhase>,
+    round: Res<Round>,
+    hide_names: Res<HideNames>,
+    timer_query: Query<&RoundTimer>,
+    player_query: Query<(&PlayerName, &Team, &Score), With<Player>>,
+    mut kill_events: EventReader<KillPlayerEvent>,
+    mut last_state: Local<Option<AppState>>,
+) -> Result<()> {
+    let killed = kill_events.iter().map(|KillPlayerEvent(_, name, _)| name).collect::<Vec<_>>();
+    let current_state = state.current().clone();
+    let state_changed = last_state.replace(current_state.clone()) != Some(current_state);
+    let event = match (state.current(), state_changed) {
+        (AppState::InGame, true) => "round_start",
+        (AppState::VictoryScreen, true) => "round_end",
+        _ if !killed.is_empty() => "kill",
+        (AppState::InGame, false) if *turn_phase == TurnPhase::World => "tick",
+        _ => return Ok(()),
+    };
+
+    let mut players = player_query
+        .iter()
+        .map(|(player_name, team, score)| LivePlayer {
+            name: shown_name(player_name, hide_names.0),
+            team: &team.name,
+            score: score.0,
+        })
+        .collect::<Vec<_>>();
+    players.sort_by(|a, b| b.score.cmp(&a.score));
+    let phase = match (state.current(), &*round_phase) {
+        (AppState::VictoryScreen, _) => "victory_screen",
+        (_, RoundPhase::GetReady(_)) => "get_ready",
+        (_, RoundPhase::Playing) => "playing",
+    };
+    let message: Arc<str> = serde_json::to_string(&LiveState {
+        event,
+        round: round.0,
+        phase,
+        seconds_left: timer_query
+            .get_single()
+            .ok()
+            .map(|RoundTimer(timer)| (timer.duration() - timer.elapsed()).as_secs()),
+        players,
+        killed: killed.into_iter().map(|name| shown_name(name, hide_names.0)).collect(),
+    })?
+    .into();
+
+    clients.0.lock().unwrap().retain(|client| match client.try_send(Arc::clone(&message)) {
+        Ok(()) | Err(TrySendError::Full(_)) => true,
+        Err(TrySendError::Disconnected(_)) => false,
+    });
+    Ok(())
+}
+
+fn shown_name(PlayerName(name): &PlayerName, hide_names: bool) -> &str {
+    if hide_names {
+        HIDDEN_NAME
+    } else {
+        name
+    }
+}
@@ -1,35 +1,82 @@
-use std::ops::{Deref, DerefMut};
+use std::{
+    env,
+    ops::{Deref, DerefMut},
+    process,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
 
 use animation::AnimationPlugin;
-use anyhow::Result;
-use bevy::prelude::*;
+use anyhow::{anyhow, Result};
+use bevy::{
+    app::{ScheduleRunnerPlugin, ScheduleRunnerSettings},
+    prelude::*,
+    render::settings::WgpuSettings,
+    winit::{WinitPlugin, WinitSettings},
+};
 use bevy_tweening::TweeningPlugin;
 
 use object::ObjectPlugin;
+use particles::ParticlesPlugin;
 
 use game_map::GameMapPlugin;
 use game_ui::GameUiPlugin;
-use player_behaviour::PlayerBehaviourPlugin;
+use juice::JuicePlugin;
+use native_player::NativePlayerPlugin;
+use player::PlayerBehaviourPlugin;
 use player_hotswap::PlayerHotswapPlugin;
+use reserved_names::ReservedNames;
+use results::ResultsPlugin;
+use round_summary::RoundSummaryPlugin;
 use score::ScorePlugin;
+use scoreboard_window::ScoreboardWindowPlugin;
+use settings::GameSettings;
+use stagnation::StagnationPlugin;
 use state::AppStatePlugin;
+use stats::StatsPlugin;
 use tick::TickPlugin;
+use tournament::TournamentPlugin;
 use victory_screen::VictoryScreenPlugin;
+use webhook::WebhookPlugin;
 
 use crate::audio::GameAudioPlugin;
 
 mod animation;
 mod audio;
+mod check_round;
 mod game_map;
 mod game_ui;
+mod input_recording;
+mod juice;
+#[cfg(feature = "live-feed")]
+mod live_feed;
+mod native_player;
 mod object;
-mod player_behaviour;
+mod offenses;
+mod particles;
+mod player;
 mod player_hotswap;
 mod rendering;
+mod reserved_names;
+mod results;
+mod round_summary;
+mod schema;
 mod score;
+mod scoreboard_window;
+mod settings;
+mod stagnation;
 mod state;
+mod stats;
 mod tick;
+mod tournament;
 mod victory_screen;
+mod webhook;
+
+/// Time between frames when running headless, matching a 60 Hz display.
+const HEADLESS_FRAME_DURATION: Duration = Duration::from_micros(16_667);
+
+/// Set whenever an error is logged, so unattended runs can report failure on exit.
+static ERROR_LOGGED: AtomicBool = AtomicBool::new(false);
 
 // Newtype wrapper to work around orphan rule (for the bevy `Component` trait)
 #[derive(Component)]
@@ -50,22 +97,84 @@ impl<T> DerefMut for ExternalCrateComponent<T> {
 }
 
 fn main() -> Result<()> {
-    App::new()
-        .add_plugins(DefaultPlugins)
-        .add_plugin(AppStatePlugin)
+    if env::args().any(|arg| arg == schema::EXPORT_SCHEMA_FLAG) {
+        println!("{}", schema::export_schema()?);
+        return Ok(());
+    }
+    if env::args().any(|arg| arg == game_map::EXPORT_MAP_PALETTE_FLAG) {
+        print!("{}", game_map::map_palette_legend());
+        return Ok(());
+    }
+    if env::args().nth(1).as_deref() == Some(check_round::CHECK_ROUND_COMMAND) {
+        let round = env::args().nth(2).ok_or_else(|| {
+            anyhow!("Usage: bomber_game {} <round>", check_round::CHECK_ROUND_COMMAND)
+        })?;
+        if !check_round::check_round(&round)? {
+            process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let settings = GameSettings::load()?;
+    let mut app = App::new();
+    if settings.headless {
+        // Without winit there is no window, and the schedule runner drives the frames instead,
+        // so the game runs on machines without a display.
+        app.insert_resource(WgpuSettings { backends: None, ..default() })
+            .insert_resource(ScheduleRunnerSettings::run_loop(HEADLESS_FRAME_DURATION))
+            .add_plugins_with(DefaultPlugins, |group| group.disable::<WinitPlugin>())
+            .add_plugin(ScheduleRunnerPlugin);
+    } else {
+        if settings.exit_when_done {
+            app.insert_resource(WinitSettings { return_from_run: true, ..default() });
+        }
+        app.add_plugins(DefaultPlugins);
+    }
+    app.insert_resource(settings.clone())
+        .insert_resource(ReservedNames::load()?)
+        .add_plugin(AppStatePlugin {
+            // Absolute, as the asset server would otherwise resolve it from the assets folder.
+            rounds_folder: env::current_dir()?.join(bomber_shared::rounds_folder()),
+            create_missing_folders: true,
+        })
         .add_plugin(GameMapPlugin)
         .add_plugin(GameAudioPlugin)
         .add_plugin(TickPlugin)
         .add_plugin(ScorePlugin)
+        .add_plugin(StatsPlugin)
+        .add_plugin(ResultsPlugin)
+        .add_plugin(WebhookPlugin)
         .add_plugin(PlayerBehaviourPlugin)
         .add_plugin(PlayerHotswapPlugin)
+        .add_plugin(NativePlayerPlugin)
+        .add_plugin(TournamentPlugin)
         .add_plugin(ObjectPlugin)
+        .add_plugin(StagnationPlugin)
         .add_plugin(VictoryScreenPlugin)
-        .add_plugin(GameUiPlugin)
         .add_plugin(AnimationPlugin)
         .add_plugin(TweeningPlugin)
         .add_startup_system(setup)
-        .run();
+        .add_startup_system(settings::log_rules_system);
+    if !settings.headless {
+        app.add_plugin(GameUiPlugin)
+            .add_plugin(JuicePlugin)
+            .add_plugin(ParticlesPlugin)
+            .add_plugin(RoundSummaryPlugin);
+        if settings.scoreboard_window {
+            app.add_plugin(ScoreboardWindowPlugin);
+        }
+    }
+    #[cfg(feature = "live-feed")]
+    app.add_plugin(live_feed::LiveFeedPlugin);
+    #[cfg(not(feature = "live-feed"))]
+    if settings.live_feed_address.is_some() {
+        warn!("LIVE_FEED_ADDRESS is set, but the game was built without the live-feed feature.");
+    }
+    app.run();
+
+    if settings.exit_when_done && ERROR_LOGGED.load(Ordering::Relaxed) {
+        process::exit(1);
+    }
     Ok(())
 }
 
@@ -76,12 +185,14 @@ fn setup(mut commands: Commands) {
 fn log_recoverable_error(In(result): In<Result<()>>) {
     if let Err(e) = result {
         error!("Unhandled error: {}", e);
+        ERROR_LOGGED.store(true, Ordering::Relaxed);
     }
 }
 
 fn log_unrecoverable_error_and_panic(In(result): In<Result<()>>) {
     if let Err(e) = result {
         error!("Unrecoverable error: {}", e);
+        ERROR_LOGGED.store(true, Ordering::Relaxed);
         panic!("{}", e);
     }
 }
@@ -0,0 +1,402 @@
+//! Defines a Bevy plugin that lets the operator summon players whose logic is compiled
+//! natively into the game, rather than loaded from a `.wasm` file. They share the whole
+//! player pipeline (surroundings, actions, scoring and UI) with regular players.
+//!
+//! House bots are native players too, filling the arena while few teams have uploaded.
+
+use bevy::prelude::*;
+use bomber_lib::{
+    world::{Direction, Enemy, Object, Tile, TileOffset},
+    Action, Player,
+};
+
+use crate::{
+    audio::SoundEffects,
+    game_map::{GameMap, PlayerSpawner, TileCache, TileLocation},
+    object::FlameMarker,
+    player::{
+        self, available_spawn_locations, spawn_player_entity, team_for, PlayerName,
+        SpawnPlayerEvent, Team,
+    },
+    player_hotswap::{PlayerHandle, PlayerHandles},
+    score::Score,
+    settings::GameSettings,
+    state::AppState,
+    ExternalCrateComponent,
+};
+
+/// Key the operator presses to summon the boss into the arena.
+const SUMMON_BOSS_KEY: KeyCode = KeyCode::F1;
+const HOUSE_TEAM_NAME: &str = "House Bots";
+/// Neutral, so house bots don't take a color teams would recognize as theirs.
+const HOUSE_TEAM_COLOR: Color = Color::GRAY;
+
+pub struct NativePlayerPlugin;
+
+/// Object safe counterpart of `bomber_lib::Player`, so natively compiled players
+/// can be stored as trait objects.
+pub trait NativeBrain {
+    fn act(
+        &mut self,
+        surroundings: Vec<(Tile, Option<Object>, Option<Enemy>, TileOffset)>,
+    ) -> Action;
+    fn name(&self) -> String;
+    fn team_name(&self) -> String;
+    /// Which way the nearest hill is, told before each turn on maps that give hints. `None`
+    /// while a hill is in sight.
+    fn hill_hint(&mut self, _hint: Option<Direction>) {}
+}
+
+impl<P: Player> NativeBrain for P {
+    fn act(
+        &mut self,
+        surroundings: Vec<(Tile, Option<Object>, Option<Enemy>, TileOffset)>,
+    ) -> Action {
+        Player::act(self, surroundings)
+    }
+
+    fn name(&self) -> String {
+        Player::name(self)
+    }
+
+    fn team_name(&self) -> String {
+        P::team_name()
+    }
+}
+
+/// Replaces the `.wasm` instance and store for players implemented natively. Their
+/// actions are requested directly, without any fuel accounting.
+#[derive(Component)]
+pub struct NativePlayer(pub Box<dyn NativeBrain + Send + Sync>);
+
+/// Marks the native players standing in for teams that haven't uploaded yet. They are left
+/// out of the leaderboard and the podium.
+#[derive(Component)]
+pub struct HouseBot;
+
+impl Plugin for NativePlayerPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(
+            SystemSet::on_update(AppState::InGame)
+                .with_system(summon_boss_system)
+                .with_system(house_bot_system),
+        );
+    }
+}
+
+/// Spawns the boss on the best available spawner when the operator presses the summon key.
+/// There is at most one boss in the arena, and it stays dead until summoned again.
+fn summon_boss_system(
+    keys: Res<Input<KeyCode>>,
+    mut commands: Commands,
+    game_map_query: Query<&GameMap>,
+    player_query: Query<
+        (&TileLocation, Option<&NativePlayer>, Option<&HouseBot>),
+        With<player::Player>,
+    >,
+    spawner_query: Query<&TileLocation, With<PlayerSpawner>>,
+    object_query: Query<(&TileLocation, &ExternalCrateComponent<Object>)>,
+    tile_cache: Res<TileCache>,
+    flame_query: Query<&TileLocation, With<FlameMarker>>,
+    team_query: Query<&Team>,
+    asset_server: Res<AssetServer>,
+    mut spawn_event: EventWriter<SpawnPlayerEvent>,
+    mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+    audio: Res<Audio>,
+    sound_effects: Res<SoundEffects>,
+) {
+    if !keys.just_pressed(SUMMON_BOSS_KEY) {
+        return;
+    }
+    if player_query.iter().any(|(_, native, house)| native.is_some() && house.is_none()) {
+        info!("The boss is already in the arena.");
+        return;
+    }
+
+    let game_map = game_map_query.single();
+    let player_locations = player_query.iter().map(|(l, ..)| *l).collect::<Vec<_>>();
+    let location = if let Some(location) = available_spawn_locations(
+        &spawner_query,
+        &object_query,
+        &tile_cache,
+        game_map,
+        &flame_query,
+        &player_locations,
+    )
+    .pop()
+    {
+        location
+    } else {
+        warn!("No free spawner to summon the boss on.");
+        return;
+    };
+
+    let boss = Boss::default();
+    let team = team_for(&NativeBrain::team_name(&boss), &team_query);
+    audio.play(sound_effects.spawn.clone());
+    let entity = spawn_player_entity(
+        NativeBrain::name(&boss),
+        None,
+        team,
+        location,
+        Score(0),
+        game_map,
+        &asset_server,
+        &mut spawn_event,
+        &mut texture_atlases,
+        &mut commands,
+    );
+    commands.entity(entity).insert(NativePlayer(Box::new(boss)));
+}
+
+/// Keeps the arena at the minimum number of players set by `FILL_WITH_HOUSE_BOTS`, counting
+/// every uploaded player whether or not it's in the arena right now. House bots are added
+/// one per frame as spawners free up, and leave one per frame as uploads arrive, the lowest
+/// scoring first.
+fn house_bot_system(
+    settings: Res<GameSettings>,
+    handles: Res<PlayerHandles>,
+    mut commands: Commands,
+    game_map_query: Query<&GameMap>,
+    player_query: Query<
+        (Entity, &TileLocation, &PlayerName, &Score, Option<&HouseBot>),
+        With<player::Player>,
+    >,
+    spawner_query: Query<&TileLocation, With<PlayerSpawner>>,
+    object_query: Query<(&TileLocation, &ExternalCrateComponent<Object>)>,
+    tile_cache: Res<TileCache>,
+    flame_query: Query<&TileLocation, With<FlameMarker>>,
+    asset_server: Res<AssetServer>,
+    mut spawn_event: EventWriter<SpawnPlayerEvent>,
+    mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+    // Grouped to stay within the lint on system parameters.
+    (audio, sound_effects): (Res<Audio>, Res<SoundEffects>),
+) {
+    if settings.house_bots == 0 {
+        return;
+    }
+    let game_map = game_map_query.single();
+    let uploaded = handles.0.iter().filter(|h| !matches!(h, PlayerHandle::Misbehaved(..))).count();
+    let minimum =
+        game_map.max_players.map_or(settings.house_bots, |max| max.min(settings.house_bots));
+    let wanted = minimum.saturating_sub(uploaded);
+    let house_bots = player_query
+        .iter()
+        .filter(|(.., house)| house.is_some())
+        .map(|(entity, _, name, score, _)| (entity, name, score))
+        .collect::<Vec<_>>();
+
+    if house_bots.len() > wanted {
+        if let Some((entity, PlayerName(name), _)) =
+            house_bots.iter().min_by_key(|(.., Score(score))| *score)
+        {
+            info!("{} makes room for an uploaded player.", name);
+            commands.entity(*entity).despawn_recursive();
+        }
+        return;
+    }
+    if house_bots.len() == wanted {
+        return;
+    }
+
+    let player_locations = player_query.iter().map(|(_, l, ..)| *l).collect::<Vec<_>>();
+    let location = match available_spawn_locations(
+        &spawner_query,
+        &object_query,
+        &tile_cache,
+        game_map,
+        &flame_query,
+        &player_locations,
+    )
+    .pop()
+    {
+        Some(location) => location,
+        // Tried again next frame.
+        None => return,
+    };
+    let number = (1..)
+        .find(|n| {
+            house_bots.iter().all(|(_, PlayerName(name), _)| *name != HouseBrain::name_for(*n))
+        })
+        .expect("There are fewer house bots than numbers");
+    let brain = HouseBrain { number, boss: Boss::default() };
+    let team = Team { name: HOUSE_TEAM_NAME.into(), color: HOUSE_TEAM_COLOR };
+    audio.play(sound_effects.spawn.clone());
+    let entity = spawn_player_entity(
+        NativeBrain::name(&brain),
+        None,
+        team,
+        location,
+        Score(0),
+        game_map,
+        &asset_server,
+        &mut spawn_event,
+        &mut texture_atlases,
+        &mut commands,
+    );
+    commands.entity(entity).insert(NativePlayer(Box::new(brain))).insert(HouseBot);
+}
+
+/// Plays like the boss, under a numbered name.
+#[derive(Default)]
+struct HouseBrain {
+    number: usize,
+    boss: Boss,
+}
+
+impl HouseBrain {
+    fn name_for(number: usize) -> String {
+        format!("House Bot {}", number)
+    }
+}
+
+impl Player for HouseBrain {
+    fn act(
+        &mut self,
+        surroundings: Vec<(Tile, Option<Object>, Option<Enemy>, TileOffset)>,
+    ) -> Action {
+        self.boss.act(surroundings)
+    }
+
+    fn name(&self) -> String {
+        Self::name_for(self.number)
+    }
+
+    fn team_name() -> String {
+        HOUSE_TEAM_NAME.into()
+    }
+}
+
+/// House bot that heads for the hill, bombs whoever gets close and runs away from blasts.
+struct Boss {
+    preferred_direction: Direction,
+}
+
+impl Default for Boss {
+    fn default() -> Self {
+        Self { preferred_direction: Direction::North }
+    }
+}
+
+impl Boss {
+    /// Whether a tile in view is within reach of a visible bomb's flames.
+    fn is_in_blast_range(
+        offset: TileOffset,
+        surroundings: &[(Tile, Option<Object>, Option<Enemy>, TileOffset)],
+    ) -> bool {
+        surroundings.iter().any(|(_, object, _, bomb_offset)| match object {
+            Some(Object::Bomb { range, .. }) => {
+                let (dx, dy) = (offset.0 - bomb_offset.0, offset.1 - bomb_offset.1);
+                (dx == 0 || dy == 0) && (dx.abs() + dy.abs()) as u32 <= *range
+            },
+            _ => false,
+        })
+    }
+
+    /// Directions leading to adjacent tiles the boss can walk into.
+    fn free_directions(
+        surroundings: &[(Tile, Option<Object>, Option<Enemy>, TileOffset)],
+    ) -> Vec<(Direction, TileOffset)> {
+        Direction::all()
+            .into_iter()
+            .filter_map(|direction| {
+                let target = direction.extend(1);
+                surroundings
+                    .iter()
+                    .any(|(tile, object, enemy, offset)| {
+                        *offset == target
+                            && matches!(tile, Tile::Floor | Tile::Hill)
+                            && !object.map(|o| o.is_solid()).unwrap_or(false)
+                            && enemy.is_none()
+                    })
+                    .then_some((direction, target))
+            })
+            .collect()
+    }
+
+    fn distance_to_closest_hill(
+        from: TileOffset,
+        surroundings: &[(Tile, Option<Object>, Option<Enemy>, TileOffset)],
+    ) -> Option<u32> {
+        surroundings
+            .iter()
+            .filter(|(tile, ..)| matches!(tile, Tile::Hill))
+            .map(|(.., offset)| TileOffset(offset.0 - from.0, offset.1 - from.1).taxicab_distance())
+            .min()
+    }
+}
+
+impl Player for Boss {
+    fn act(
+        &mut self,
+        surroundings: Vec<(Tile, Option<Object>, Option<Enemy>, TileOffset)>,
+    ) -> Action {
+        let here = TileOffset(0, 0);
+        let free_directions = Self::free_directions(&surroundings);
+        let safe_directions = free_directions
+            .iter()
+            .filter(|(_, offset)| !Self::is_in_blast_range(*offset, &surroundings))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        // Running away from a bomb trumps everything else.
+        if Self::is_in_blast_range(here, &surroundings) {
+            return safe_directions
+                .first()
+                .or_else(|| free_directions.first())
+                .map(|(direction, _)| Action::Move(*direction))
+                .unwrap_or(Action::StayStill);
+        }
+
+        // Bomb enemies that get too close, as long as there is a way out.
+        let enemy_nearby = surroundings
+            .iter()
+            .any(|(_, _, enemy, offset)| enemy.is_some() && offset.taxicab_distance() <= 2);
+        if enemy_nearby {
+            if let Some((direction, _)) = safe_directions.first() {
+                return Action::DropBombAndMove(*direction);
+            }
+        }
+
+        // Head for the hill if we can see it, and hold it once we're there.
+        if matches!(surroundings.iter().find(|(.., o)| *o == here), Some((Tile::Hill, ..))) {
+            return Action::StayStill;
+        }
+        if let Some(distance) = Self::distance_to_closest_hill(here, &surroundings) {
+            let closer_to_hill = safe_directions.iter().find(|(_, offset)| {
+                Self::distance_to_closest_hill(*offset, &surroundings)
+                    .map_or(false, |d| d < distance)
+            });
+            if let Some((direction, _)) = closer_to_hill {
+                return Action::Move(*direction);
+            }
+        }
+
+        // Otherwise blast through crates in the way, or wander around looking for the hill.
+        let crate_adjacent = surroundings.iter().any(|(_, object, _, offset)| {
+            matches!(object, Some(Object::Crate)) && offset.is_adjacent()
+        });
+        if crate_adjacent {
+            if let Some((direction, _)) = safe_directions.first() {
+                return Action::DropBombAndMove(*direction);
+            }
+        }
+        let preferred_offset = self.preferred_direction.extend(1);
+        if safe_directions.iter().any(|(_, offset)| *offset == preferred_offset) {
+            return Action::Move(self.preferred_direction);
+        }
+        if let Some((direction, _)) = safe_directions.first() {
+            self.preferred_direction = *direction;
+            return Action::Move(*direction);
+        }
+        Action::StayStill
+    }
+
+    fn name(&self) -> String {
+        "The Boss".into()
+    }
+
+    fn team_name() -> String {
+        "House".into()
+    }
+}
@@ -1,24 +1,37 @@
 //! Defines a Bevy plugin that governs spawning, exploding and despawning of the bombs and flames.
 
-use bevy::prelude::*;
-use bomber_lib::world::{Direction, Object, PowerUp, Ticks, Tile};
+use bevy::{prelude::*, utils::HashSet};
+use bomber_lib::world::{Direction, Object, PowerUp, Ticks};
 use rand::{thread_rng, Rng};
 
 use crate::{
     audio::SoundEffects,
-    game_map::{GameMap, TileLocation},
-    player_behaviour::{KillPlayerEvent, Owner, Player, PlayerName},
-    rendering::{FLAME_Z, GAME_OBJECT_Z, TILE_WIDTH_PX},
+    game_map::{CrateHp, GameMap, TileCache, TileLocation},
+    particles::CrateDestroyedEvent,
+    player::{
+        DeathReport, DeathReports, KillPlayerEvent, Owner, PendingDeaths, Player, PlayerName, Team,
+    },
+    player_hotswap::WasmPlayerAsset,
+    rendering::{BOMB_Z, CRATE_Z, FLAME_Z, OVERLAY_Z, POWER_UP_Z, TILE_WIDTH_PX},
     score::Score,
+    settings::GameSettings,
     state::AppState,
-    tick::Tick,
+    stats::RoundStats,
+    tick::{world_turn, TickStep, WorldTurnStep},
     ExternalCrateComponent,
 };
 
-// A bomb explodes after this number of ticks since it's placed on the map.
-const BOMB_FUSE_LENGTH: Ticks = Ticks(2);
-const BASE_BOMB_RANGE: u32 = 2;
+// Explosions are spawned on world ticks and disappear this number of world ticks later,
+// so their flames are always live for exactly one player tick.
+const EXPLOSION_LIFETIME: Ticks = Ticks(1);
 const CHANCE_OF_POWERUP_ON_CRATE: f32 = 0.3;
+/// Points awarded to the owner of the bomb whose flames destroy a crate.
+const CRATE_DESTRUCTION_SCORE: u32 = 1;
+/// How strongly bombs and flames are tinted with their owner's team color. Kept partial so
+/// they still read as bombs and flames.
+const OWNER_TINT_STRENGTH: f32 = 0.6;
+const TOGGLE_OVERLAY_KEY: KeyCode = KeyCode::O;
+const DROP_CHANCE_BADGE_FONT_SIZE: f32 = 14.0;
 
 pub struct ObjectPlugin;
 pub struct BombExplodeEvent {
@@ -30,19 +43,68 @@ pub struct BombExplodeEvent {
 pub struct SpawnBombEvent {
     pub location: TileLocation,
     pub owner: Entity,
+    pub snapshot: OwnerSnapshot,
+}
+
+/// What a bomb needs to know about its owner, recorded when it's dropped. The owner may die
+/// in the same tick, before the bomb is spawned.
+#[derive(Clone, Debug)]
+pub struct OwnerSnapshot {
+    pub range: u32,
+    pub max_bombs: u32,
+    pub team: Team,
 }
 /// Marks a bomb placed on the game map.
 #[derive(Component)]
 struct BombMarker;
-/// Marks the center of an explosion with flames in each direction.
+/// Tint of a bomb, taken from its owner's team when placed. Kept on the bomb so its flames
+/// share it even if the owner is gone by the time it explodes.
+#[derive(Component, Clone, Copy)]
+struct OwnerTint(Color);
+/// Name of the team that owned a bomb when placed, carried on to its explosion and flames
+/// to tell friendly fire apart.
+#[derive(Component, Clone)]
+struct OwnerTeam(String);
+/// Marks the center of an explosion with flames in each direction. Both the explosion
+/// and its flames carry the `Owner` of the bomb that caused them.
+#[derive(Component)]
+struct ExplosionMarker {
+    lifetime_remaining: Ticks,
+}
+/// Where the bomb behind an explosion was, to tell players killed by it.
 #[derive(Component)]
-struct ExplosionMarker;
+struct BombOrigin(TileLocation);
 /// Marks a flame placed on the game map.
 #[derive(Component)]
 pub struct FlameMarker;
 /// Marks a powerup placed on the game map.
 #[derive(Component)]
 struct PowerUpMarker;
+/// What a crate drops when blown up. It's rolled as the crate is placed, rather than as it
+/// breaks, so the content of every crate is settled from the start.
+#[derive(Component, Clone, Copy)]
+pub struct CrateLoot(pub Option<PowerUp>);
+
+impl CrateLoot {
+    /// A power up, with the usual chance of a crate holding one.
+    pub fn roll() -> Self {
+        Self((thread_rng().gen::<f32>() < CHANCE_OF_POWERUP_ON_CRATE).then(random_power_up))
+    }
+}
+/// Marks the power up drop chance shown on a crate about to be blown up. It's a child of
+/// the crate, so it goes away along with it.
+#[derive(Component)]
+struct DropChanceBadge;
+
+/// Whether spectator overlays (hints that help the audience follow the game, but are never
+/// visible to bots) are shown on the map.
+pub struct SpectatorOverlay(pub bool);
+
+impl Default for SpectatorOverlay {
+    fn default() -> Self {
+        Self(true)
+    }
+}
 
 pub struct Textures {
     pub bomb: Handle<Image>,
@@ -68,16 +130,57 @@ impl Plugin for ObjectPlugin {
             .add_event::<KillPlayerEvent>()
             .add_event::<BombExplodeEvent>()
             .add_event::<SpawnBombEvent>()
+            .add_event::<CrateDestroyedEvent>()
+            .init_resource::<SpectatorOverlay>()
+            .add_system(toggle_overlay_system)
             .add_system_set(
                 SystemSet::on_update(AppState::InGame)
-                    .with_system(bomb_spawn_system)
-                    .with_system(fuse_remaining_system)
-                    .with_system(pick_up_power_up_system)
-                    .with_system(bomb_explosion_system)
-                    .with_system(objects_on_fire_system)
-                    .with_system(explosion_despawn_system),
+                    .with_system(
+                        bomb_spawn_system.label(TickStep::SpawnBombs).after(TickStep::ApplyMoves),
+                    )
+                    .with_system(
+                        bomb_explosion_system
+                            .label(TickStep::ResolveExplosions)
+                            .after(TickStep::SpawnBombs)
+                            .after(fuse_remaining_system),
+                    )
+                    .with_system(
+                        objects_on_fire_system
+                            .label(TickStep::ResolveExplosions)
+                            .after(bomb_explosion_system),
+                    )
+                    .with_system(
+                        players_on_fire_system
+                            .label(TickStep::ResolveExplosions)
+                            .after(bomb_explosion_system),
+                    )
+                    .with_system(
+                        // Badges must be attached before a crate can be blown up that frame.
+                        drop_chance_badge_system.before(objects_on_fire_system),
+                    ),
+            )
+            .add_system_set(
+                SystemSet::new()
+                    .with_run_criteria(world_turn)
+                    .label(WorldTurnStep::Effects)
+                    .with_system(
+                        fuse_remaining_system
+                            .label(TickStep::ResolveExplosions)
+                            .after(TickStep::SpawnBombs),
+                    )
+                    .with_system(pick_up_power_up_system.after(TickStep::ResolveExplosions))
+                    .with_system(
+                        explosion_despawn_system
+                            .label(TickStep::ResolveExplosions)
+                            .after(TickStep::SpawnBombs),
+                    ),
             )
             .add_system_set(SystemSet::on_exit(AppState::InGame).with_system(cleanup));
+        // Catches sprites that would flicker on stream and broken invariants between the
+        // steps of a tick, which is left out of release builds.
+        #[cfg(debug_assertions)]
+        app.add_system(draw_order_audit_system)
+            .add_system(tick_invariants_system.after(TickStep::Cleanup));
     }
 }
 
@@ -85,23 +188,32 @@ fn bomb_spawn_system(
     mut spawn_event_reader: EventReader<SpawnBombEvent>,
     game_map_query: Query<&GameMap>,
     bomb_query: Query<&Owner, With<BombMarker>>,
-    player_query: Query<&Player>,
     textures: Res<Textures>,
     audio: Res<Audio>,
     sound_effects: Res<SoundEffects>,
+    settings: Res<GameSettings>,
     mut commands: Commands,
 ) {
     let game_map = game_map_query.single();
+    let fuse = Ticks(settings.rules.bomb_fuse_ticks);
 
     let mut any_bomb_spawned = false;
-    for SpawnBombEvent { location, owner } in spawn_event_reader.iter() {
-        let player = player_query.get(*owner).expect("Bomb has an invalid owner");
-        let range = BASE_BOMB_RANGE
-            + player.power_ups.get(&PowerUp::BombRange).copied().unwrap_or_default();
-        let maximum_bombs =
-            1 + player.power_ups.get(&PowerUp::SimultaneousBombs).copied().unwrap_or_default();
-        if bomb_query.iter().filter(|Owner(o)| owner == o).count() < maximum_bombs as usize {
-            spawn_bomb(location, *owner, range, game_map, &textures, &mut commands);
+    for SpawnBombEvent { location, owner, snapshot } in spawn_event_reader.iter() {
+        let placed = bomb_query.iter().filter(|Owner(o)| owner == o).count();
+        if placed < snapshot.max_bombs as usize {
+            let tint = OwnerTint(owner_tint(snapshot.team.color));
+            let team = OwnerTeam(snapshot.team.name.clone());
+            spawn_bomb(
+                location,
+                *owner,
+                tint,
+                team,
+                snapshot.range,
+                fuse,
+                game_map,
+                &textures,
+                &mut commands,
+            );
             any_bomb_spawned = true;
         } else {
             info!("Failed to spawn bomb: User is at maximum bomb count");
@@ -113,10 +225,20 @@ fn bomb_spawn_system(
     }
 }
 
+/// Blends a team color with white, as sprite colors multiply the texture.
+fn owner_tint(team_color: Color) -> Color {
+    let [r, g, b, _] = team_color.as_rgba_f32();
+    let blend = |channel: f32| 1.0 - OWNER_TINT_STRENGTH * (1.0 - channel);
+    Color::rgb(blend(r), blend(g), blend(b))
+}
+
 fn spawn_bomb(
     location: &TileLocation,
     owner: Entity,
+    tint: OwnerTint,
+    team: OwnerTeam,
     range: u32,
+    fuse: Ticks,
     game_map: &GameMap,
     textures: &Textures,
     commands: &mut Commands,
@@ -125,54 +247,58 @@ fn spawn_bomb(
         .spawn()
         .insert(BombMarker)
         .insert(Owner(owner))
-        .insert(ExternalCrateComponent(Object::Bomb { fuse_remaining: BOMB_FUSE_LENGTH, range }))
+        .insert(tint)
+        .insert(team)
+        .insert(ExternalCrateComponent(Object::Bomb { fuse_remaining: fuse, range }))
         .insert(*location)
         .insert_bundle(SpriteBundle {
             texture: textures.bomb.clone(),
             transform: Transform::from_translation(
-                location.as_world_coordinates(game_map).extend(GAME_OBJECT_Z),
+                location.as_world_coordinates(game_map).extend(BOMB_Z),
             ),
-            sprite: Sprite { custom_size: Some(Vec2::splat(TILE_WIDTH_PX)), ..Default::default() },
+            sprite: Sprite {
+                color: tint.0,
+                custom_size: Some(Vec2::splat(TILE_WIDTH_PX)),
+                ..Default::default()
+            },
             ..Default::default()
         });
 }
 
 fn fuse_remaining_system(
-    mut ticks: EventReader<Tick>,
     mut bomb_query: Query<
         (Entity, &TileLocation, &mut ExternalCrateComponent<Object>),
         With<BombMarker>,
     >,
     mut explode_events: EventWriter<BombExplodeEvent>,
 ) {
-    for _ in ticks.iter().filter(|t| matches!(t, Tick::World)) {
-        for (bomb, &location, mut object) in bomb_query.iter_mut() {
-            let should_explode = match **object {
-                Object::Bomb { ref mut fuse_remaining, .. } => {
-                    let should_explode = fuse_remaining.0 == 0;
-                    fuse_remaining.0 = fuse_remaining.0.saturating_sub(1);
-                    should_explode
-                },
-                _ => false,
-            };
+    for (bomb, &location, mut object) in bomb_query.iter_mut() {
+        let should_explode = match **object {
+            Object::Bomb { ref mut fuse_remaining, .. } => {
+                let should_explode = fuse_remaining.0 == 0;
+                fuse_remaining.0 = fuse_remaining.0.saturating_sub(1);
+                should_explode
+            },
+            _ => false,
+        };
 
-            if should_explode {
-                explode_events.send(BombExplodeEvent { bomb, location });
-            }
+        if should_explode {
+            explode_events.send(BombExplodeEvent { bomb, location });
         }
     }
 }
 
 fn bomb_explosion_system(
     mut exploded_bombs: EventReader<BombExplodeEvent>,
-    tile_query: Query<(&TileLocation, &ExternalCrateComponent<Tile>)>,
+    tile_cache: Res<TileCache>,
     object_query: Query<
         (&TileLocation, &ExternalCrateComponent<Object>),
         (Without<BombMarker>, Without<Player>),
     >,
-    bomb_query: Query<&ExternalCrateComponent<Object>, With<BombMarker>>,
-    player_query: Query<(&Player, &TileLocation, Entity, &PlayerName, &Score)>,
-    mut kill_events: EventWriter<KillPlayerEvent>,
+    bomb_query: Query<
+        (&ExternalCrateComponent<Object>, &Owner, &OwnerTint, &OwnerTeam),
+        With<BombMarker>,
+    >,
     game_map_query: Query<&GameMap>,
     textures: Res<Textures>,
     audio: Res<Audio>,
@@ -181,38 +307,45 @@ fn bomb_explosion_system(
 ) {
     let game_map = game_map_query.single();
 
-    let mut any_bomb_exploded = false;
+    let mut exploded = HashSet::new();
     for BombExplodeEvent { bomb, location } in exploded_bombs.iter() {
-        let range =
-            if let Ok(ExternalCrateComponent(Object::Bomb { range, .. })) = bomb_query.get(*bomb) {
-                range
-            } else {
-                // Duplicate bomb explode events are possible during chain reactions depending on system order
-                continue;
-            };
+        let (range, owner, tint, team) = match bomb_query.get(*bomb) {
+            // Duplicate bomb explode events are possible during chain reactions, both within a
+            // frame (the despawn command isn't applied yet) and across frames (flames persist for
+            // a while). The latter find the bomb already despawned.
+            Ok((ExternalCrateComponent(Object::Bomb { range, .. }), Owner(owner), tint, team))
+                if exploded.insert(*bomb) =>
+            {
+                (range, *owner, *tint, team)
+            },
+            _ => continue,
+        };
 
         commands.entity(*bomb).despawn_recursive();
         commands
             .spawn()
-            .insert(ExplosionMarker)
+            .insert(ExplosionMarker { lifetime_remaining: EXPLOSION_LIFETIME })
+            .insert(BombOrigin(*location))
+            .insert(Owner(owner))
+            .insert(team.clone())
             .insert_bundle(SpriteBundle::default())
             .with_children(|parent| {
                 spawn_flames(
                     parent,
                     location,
-                    &tile_query,
+                    &tile_cache,
                     &object_query,
-                    &player_query,
-                    &mut kill_events,
                     *range,
+                    owner,
+                    tint,
+                    team,
                     game_map,
                     &textures,
                 );
             });
-        any_bomb_exploded = true;
     }
 
-    if any_bomb_exploded {
+    if !exploded.is_empty() {
         audio.play(sound_effects.explosion.clone());
     }
 }
@@ -220,90 +353,141 @@ fn bomb_explosion_system(
 fn spawn_flames(
     parent: &mut ChildBuilder,
     bomb_location: &TileLocation,
-    tile_query: &Query<(&TileLocation, &ExternalCrateComponent<Tile>)>,
+    tile_cache: &TileCache,
     object_query: &Query<
         (&TileLocation, &ExternalCrateComponent<Object>),
         (Without<BombMarker>, Without<Player>),
     >,
-    player_query: &Query<(&Player, &TileLocation, Entity, &PlayerName, &Score)>,
-    kill_events: &mut EventWriter<KillPlayerEvent>,
     range: u32,
+    owner: Entity,
+    tint: OwnerTint,
+    team: &OwnerTeam,
     game_map: &GameMap,
     textures: &Textures,
 ) {
-    // Spawn a flame at the bomb location.
-    spawn_flame(parent, bomb_location, game_map, textures);
-
-    if let Some((entity, name, score)) =
-        player_query
-            .iter()
-            .find_map(|(_, l, e, n, s)| if *l == *bomb_location { Some((e, n, s)) } else { None })
-    {
-        kill_events.send(KillPlayerEvent(entity, name.clone(), *score));
+    let blast = blast_locations(*bomb_location, range, tile_cache, game_map, |location| {
+        object_query.iter().find_map(|(l, o)| (*l == location).then_some(**o))
+    });
+    for location in &blast {
+        spawn_flame(parent, location, owner, tint, team, game_map, textures);
     }
+}
 
-    // Spawn flames in each direction.
+/// Locations a bomb's flames reach when it explodes: the bomb location itself, and up to
+/// `range` tiles in each direction. Flames don't spawn on walls, nor extend beyond crates.
+pub fn blast_locations(
+    bomb_location: TileLocation,
+    range: u32,
+    tile_cache: &TileCache,
+    game_map: &GameMap,
+    object_at: impl Fn(TileLocation) -> Option<Object>,
+) -> Vec<TileLocation> {
+    let mut locations = vec![bomb_location];
     for direction in &Direction::all() {
         for reach in 1..=(range as i32) {
-            let location = *bomb_location + direction.extend(reach);
-            let tile =
-                tile_query.iter().find_map(|(l, t)| if *l == location { Some(t) } else { None });
-            let object =
-                object_query.iter().find_map(|(l, o)| if *l == location { Some(o) } else { None });
-            // Flame can not spawn on the walls.
-            if matches!(tile, Some(ExternalCrateComponent(Tile::Wall))) {
-                break;
-            }
-            spawn_flame(parent, &location, game_map, textures);
-            if matches!(object, Some(ExternalCrateComponent(Object::Crate))) {
-                // Flame does not extend beyond a crate.
+            let location = match bomb_location.try_add(direction.extend(reach), game_map) {
+                Some(location) if !tile_cache.is_wall(&location) => location,
+                // Flames stop at walls and at the edge of the map.
+                _ => break,
+            };
+            locations.push(location);
+            if matches!(object_at(location), Some(Object::Crate)) {
                 break;
             }
-
-            if let Some((entity, name, score)) =
-                player_query
-                    .iter()
-                    .find_map(|(_, l, e, n, s)| if *l == location { Some((e, n, s)) } else { None })
-            {
-                kill_events.send(KillPlayerEvent(entity, name.clone(), *score));
-            }
         }
     }
+    locations
 }
 
 fn spawn_flame(
     parent: &mut ChildBuilder,
     location: &TileLocation,
+    owner: Entity,
+    tint: OwnerTint,
+    team: &OwnerTeam,
     game_map: &GameMap,
     textures: &Textures,
 ) {
-    parent.spawn().insert(FlameMarker).insert(*location).insert_bundle(SpriteBundle {
-        texture: textures.flame.clone(),
-        transform: Transform::from_translation(
-            location.as_world_coordinates(game_map).extend(FLAME_Z),
-        ),
-        sprite: Sprite { custom_size: Some(Vec2::splat(TILE_WIDTH_PX)), ..Default::default() },
-        ..Default::default()
-    });
+    parent
+        .spawn()
+        .insert(FlameMarker)
+        .insert(Owner(owner))
+        .insert(team.clone())
+        .insert(*location)
+        .insert_bundle(SpriteBundle {
+            texture: textures.flame.clone(),
+            transform: Transform::from_translation(
+                location.as_world_coordinates(game_map).extend(FLAME_Z),
+            ),
+            sprite: Sprite {
+                color: tint.0,
+                custom_size: Some(Vec2::splat(TILE_WIDTH_PX)),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
 }
 
 /// Handle objects being blasted by bomb's explosion.
 fn objects_on_fire_system(
-    flame_query: Query<&TileLocation, With<FlameMarker>>,
-    object_query: Query<(Entity, &TileLocation, &ExternalCrateComponent<Object>)>,
+    flame_query: Query<(&TileLocation, &Owner, &Parent), With<FlameMarker>>,
+    mut object_query: Query<(
+        Entity,
+        &TileLocation,
+        &ExternalCrateComponent<Object>,
+        Option<&mut CrateHp>,
+        Option<&mut Sprite>,
+        Option<&CrateLoot>,
+    )>,
+    mut owner_query: Query<(&mut Score, Option<&Handle<WasmPlayerAsset>>), With<Player>>,
+    mut stats: ResMut<RoundStats>,
     mut explode_events: EventWriter<BombExplodeEvent>,
+    mut crate_destroyed_events: EventWriter<CrateDestroyedEvent>,
     mut commands: Commands,
     game_map_query: Query<&GameMap>,
     textures: Res<Textures>,
 ) {
-    let on_fire = |&(_, location, _): &(_, _, _)| flame_query.iter().any(|l| l == location);
-    for (entity, location, object) in object_query.iter().filter(on_fire) {
+    let flame_at = |location: &TileLocation| {
+        flame_query.iter().find_map(|(l, o, p)| (l == location).then_some((o.0, p.get())))
+    };
+    for (entity, location, object, hp, sprite, loot) in object_query.iter_mut() {
+        let (owner, explosion) =
+            if let Some(flame) = flame_at(location) { flame } else { continue };
         match **object {
             Object::Bomb { .. } => {
                 explode_events.send(BombExplodeEvent { bomb: entity, location: *location })
             },
             Object::Crate => {
-                blow_up_crate(&mut commands, entity, *location, game_map_query.single(), &textures)
+                if let Some(mut hp) = hp {
+                    if hp.last_hit_by == Some(explosion) {
+                        continue;
+                    }
+                    hp.last_hit_by = Some(explosion);
+                    hp.remaining = hp.remaining.saturating_sub(1);
+                    if hp.remaining > 0 {
+                        // One hit away from breaking, it looks like any other crate.
+                        if let Some(mut sprite) = sprite.filter(|_| hp.remaining == 1) {
+                            sprite.color = Color::WHITE;
+                        }
+                        continue;
+                    }
+                }
+                blow_up_crate(
+                    &mut commands,
+                    entity,
+                    *location,
+                    loot.and_then(|l| l.0),
+                    game_map_query.single(),
+                    &textures,
+                );
+                crate_destroyed_events.send(CrateDestroyedEvent(*location));
+                // The bomb owner may have died in the meantime, in which case nobody is credited.
+                if let Ok((mut score, handle)) = owner_query.get_mut(owner) {
+                    score.0 += CRATE_DESTRUCTION_SCORE;
+                    if let Some(player_stats) = handle.and_then(|h| stats.0.get_mut(&h.id)) {
+                        player_stats.crates_destroyed += 1;
+                    }
+                }
             },
             Object::PowerUp(_) => (),
         }
@@ -314,22 +498,26 @@ fn blow_up_crate(
     commands: &mut Commands,
     entity: Entity,
     location: TileLocation,
+    loot: Option<PowerUp>,
     game_map: &GameMap,
     textures: &Textures,
 ) {
     commands.entity(entity).despawn_recursive();
-    let mut rng = thread_rng();
-    if rng.gen::<f32>() < CHANCE_OF_POWERUP_ON_CRATE {
-        let power_up = match rng.gen_range(0..=2) as u32 {
-            0 => PowerUp::BombRange,
-            1 => PowerUp::SimultaneousBombs,
-            2 => PowerUp::VisionRange,
-            _ => unreachable!(),
-        };
+    if let Some(power_up) = loot {
         spawn_power_up(power_up, commands, location, game_map, textures);
     }
 }
 
+/// Any power up, with equal chances.
+pub fn random_power_up() -> PowerUp {
+    match thread_rng().gen_range(0..=2) as u32 {
+        0 => PowerUp::BombRange,
+        1 => PowerUp::SimultaneousBombs,
+        2 => PowerUp::VisionRange,
+        _ => unreachable!(),
+    }
+}
+
 fn spawn_power_up(
     power_up: PowerUp,
     commands: &mut Commands,
@@ -349,7 +537,7 @@ fn spawn_power_up(
                 PowerUp::VisionRange => textures.vision_range_power_up.clone(),
             },
             transform: Transform::from_translation(
-                location.as_world_coordinates(game_map).extend(GAME_OBJECT_Z),
+                location.as_world_coordinates(game_map).extend(POWER_UP_Z),
             ),
             sprite: Sprite {
                 custom_size: Some(Vec2::splat(TILE_WIDTH_PX * 3.0 / 4.0)),
@@ -359,51 +547,192 @@ fn spawn_power_up(
         });
 }
 
+/// Kills players standing on live flames, whether they were there when the bomb went
+/// off or walked into the flames afterwards. `.wasm` players get told how they died once
+/// they respawn.
+fn players_on_fire_system(
+    flame_query: Query<(&TileLocation, &Owner, &OwnerTeam, &Parent), With<FlameMarker>>,
+    explosion_query: Query<&BombOrigin>,
+    player_query: Query<
+        (Entity, &TileLocation, &PlayerName, &Score, &Team, Option<&Handle<WasmPlayerAsset>>),
+        With<Player>,
+    >,
+    owner_query: Query<&Handle<WasmPlayerAsset>, With<Player>>,
+    mut stats: ResMut<RoundStats>,
+    settings: Res<GameSettings>,
+    mut kill_events: EventWriter<KillPlayerEvent>,
+    mut pending_deaths: ResMut<PendingDeaths>,
+    mut death_reports: ResMut<DeathReports>,
+    mut reported: Local<HashSet<Entity>>,
+    mut teammates_hit: Local<HashSet<(Entity, Entity)>>,
+) {
+    // Killed players linger until their despawn is applied, which can be a frame later, so
+    // each of them is only reported once.
+    reported.retain(|entity| player_query.get(*entity).is_ok());
+    // Likewise, teammates spared by friendly fire stand in the flames for several frames, so
+    // they're counted once per explosion.
+    teammates_hit.retain(|(explosion, _)| flame_query.iter().any(|(.., p)| p.get() == *explosion));
+    for (entity, location, name, score, team, handle) in player_query.iter() {
+        let mut lethal = None;
+        for (_, Owner(owner), OwnerTeam(owner_team), explosion) in
+            flame_query.iter().filter(|(l, ..)| *l == location)
+        {
+            // Players are never immune to their own bombs.
+            let friendly = *owner != entity && *owner_team == team.name;
+            if friendly && teammates_hit.insert((explosion.get(), entity)) {
+                let handle = owner_query.get(*owner).ok();
+                if let Some(player_stats) = handle.and_then(|h| stats.0.get_mut(&h.id)) {
+                    player_stats.team_kills += 1;
+                }
+            }
+            if !friendly || settings.rules.friendly_fire {
+                lethal = Some((*owner, owner_team, explosion.get()));
+            }
+        }
+        if let Some((owner, owner_team, explosion)) = lethal.filter(|_| reported.insert(entity)) {
+            kill_events.send(KillPlayerEvent(entity, name.clone(), *score));
+            pending_deaths.0.insert(entity);
+            if let (Some(handle), Ok(BombOrigin(bomb_location))) =
+                (handle, explosion_query.get(explosion))
+            {
+                let killer_team = (owner != entity).then(|| owner_team.clone());
+                let report = DeathReport { killer_team, bomb_location: *bomb_location };
+                death_reports.0.insert(handle.id, report);
+            }
+        }
+    }
+}
+
 fn explosion_despawn_system(
-    mut ticks: EventReader<Tick>,
-    explosion_query: Query<Entity, With<ExplosionMarker>>,
+    mut explosion_query: Query<(Entity, &mut ExplosionMarker)>,
     mut commands: Commands,
 ) {
-    // We despawn explosions during player ticks as they're just a visual
-    // indication; the damage has already been done when spawning the flames.
-    for _ in ticks.iter().filter(|t| matches!(t, Tick::Player)) {
-        for entity in explosion_query.iter() {
+    for (entity, mut explosion) in explosion_query.iter_mut() {
+        let remaining = &mut explosion.lifetime_remaining;
+        remaining.0 = remaining.0.saturating_sub(1);
+        if remaining.0 == 0 {
             commands.entity(entity).despawn_recursive();
         }
     }
 }
 
+/// Awards each power up to a player standing on it. Should several players reach it on the
+/// same tick, it goes to the one with the lowest score, or to the earliest to spawn among
+/// them. Players killed this tick don't collect anything on their way out.
 fn pick_up_power_up_system(
-    mut ticks: EventReader<Tick>,
-    mut player_query: Query<(&mut Player, &TileLocation)>,
+    mut player_query: Query<(Entity, &mut Player, &TileLocation, &Score)>,
     power_up_query: Query<
         (Entity, &ExternalCrateComponent<Object>, &TileLocation),
         (With<PowerUpMarker>, Without<Player>),
     >,
+    pending_deaths: Res<PendingDeaths>,
     mut commands: Commands,
     audio: Res<Audio>,
     sound_effects: Res<SoundEffects>,
 ) {
-    for _ in ticks.iter().filter(|t| matches!(t, Tick::World)) {
-        for (mut player, player_location) in player_query.iter_mut() {
-            if let Some((entity, power_up)) =
-                power_up_query.iter().find_map(|(entity, power_up, location)| {
-                    (location == player_location).then_some((entity, power_up))
-                })
-            {
-                let power_up = if let Object::PowerUp(power_up) = **power_up {
-                    power_up
-                } else {
-                    panic!("Object incorrectly marked as a powerup");
-                };
-                let power_up_count = player.power_ups.entry(power_up).or_insert(0);
-                *power_up_count = (*power_up_count + 1).min(power_up.max_count_per_player());
-
-                audio.play(sound_effects.powerup.clone());
-                commands.entity(entity).despawn_recursive();
+    for (entity, power_up, location) in power_up_query.iter() {
+        let power_up = if let Object::PowerUp(power_up) = **power_up {
+            power_up
+        } else {
+            panic!("Object incorrectly marked as a powerup");
+        };
+        let candidates = player_query
+            .iter()
+            .filter(|(player, _, player_location, _)| {
+                *player_location == location && !pending_deaths.0.contains(player)
+            })
+            .map(|(player, Player { spawn_order, .. }, _, score)| (player, *score, *spawn_order));
+        let winner = if let Some(winner) = power_up_winner(candidates) { winner } else { continue };
+        let (_, mut player, ..) = player_query.get_mut(winner).expect("Winner is a player");
+        let power_up_count = player.power_ups.entry(power_up).or_insert(0);
+        *power_up_count = (*power_up_count + 1).min(power_up.max_count_per_player());
+
+        audio.play(sound_effects.powerup.clone());
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+/// Picks who gets a power up out of the players that reached it, given as their entity,
+/// score and spawn order.
+fn power_up_winner(candidates: impl Iterator<Item = (Entity, Score, u64)>) -> Option<Entity> {
+    candidates.min_by_key(|(_, Score(score), spawn_order)| (*score, *spawn_order)).map(|(e, ..)| e)
+}
+
+fn toggle_overlay_system(keys: Res<Input<KeyCode>>, mut overlay: ResMut<SpectatorOverlay>) {
+    if keys.just_pressed(TOGGLE_OVERLAY_KEY) {
+        overlay.0 = !overlay.0;
+    }
+}
+
+/// Shows the chance of a power up dropping on each crate that will be destroyed when the
+/// next world tick sets off the bombs around it. Reinforced crates with hits to spare can't
+/// drop anything yet, so they get no badge.
+fn drop_chance_badge_system(
+    overlay: Res<SpectatorOverlay>,
+    tile_cache: Res<TileCache>,
+    game_map_query: Query<&GameMap>,
+    object_query: Query<(Entity, &TileLocation, &ExternalCrateComponent<Object>, Option<&CrateHp>)>,
+    badge_query: Query<(Entity, &Parent), With<DropChanceBadge>>,
+    asset_server: Res<AssetServer>,
+    mut commands: Commands,
+) {
+    let mut threatened_crates = HashSet::new();
+    if let (true, Ok(game_map)) = (overlay.0, game_map_query.get_single()) {
+        let object_at = |location: TileLocation| {
+            object_query.iter().find_map(|(_, l, o, _)| (*l == location).then_some(**o))
+        };
+        let about_to_explode =
+            object_query.iter().filter_map(|(_, &location, object, _)| match **object {
+                Object::Bomb { fuse_remaining: Ticks(0), range } => Some((location, range)),
+                _ => None,
+            });
+        for (bomb_location, range) in about_to_explode {
+            let blast = blast_locations(bomb_location, range, &tile_cache, game_map, object_at);
+            for location in blast {
+                threatened_crates.extend(object_query.iter().filter_map(
+                    |(entity, l, object, hp)| {
+                        let breaks = hp.map_or(true, |hp| hp.remaining <= 1);
+                        (*l == location && matches!(**object, Object::Crate) && breaks)
+                            .then_some(entity)
+                    },
+                ));
             }
         }
     }
+
+    for (badge, parent) in badge_query.iter() {
+        // Crates that already have a badge keep it, the rest lost it because the threat passed.
+        if !threatened_crates.remove(&parent.get()) {
+            commands.entity(badge).despawn_recursive();
+        }
+    }
+    for crate_entity in threatened_crates {
+        commands.entity(crate_entity).with_children(|parent| {
+            parent
+                .spawn()
+                .insert_bundle(Text2dBundle {
+                    text: Text::from_section(
+                        format!("?{:.0}%", CHANCE_OF_POWERUP_ON_CRATE * 100.0),
+                        TextStyle {
+                            font: asset_server.load("fonts/space_mono_400.ttf"),
+                            font_size: DROP_CHANCE_BADGE_FONT_SIZE,
+                            color: Color::WHITE,
+                        },
+                    )
+                    .with_alignment(TextAlignment {
+                        vertical: VerticalAlign::Center,
+                        horizontal: HorizontalAlign::Center,
+                    }),
+                    transform: Transform::from_translation(Vec3::new(
+                        0.0,
+                        0.0,
+                        OVERLAY_Z - CRATE_Z,
+                    )),
+                    ..Default::default()
+                })
+                .insert(DropChanceBadge);
+        });
+    }
 }
 
 fn cleanup(
@@ -417,3 +746,325 @@ fn cleanup(
         commands.entity(entity).despawn_recursive();
     }
 }
+
+/// Logs different sprites drawn on the same tile at the same depth, as their draw order is
+/// undefined and they flicker. Sprites sharing a texture (e.g. overlapping flames) look the
+/// same either way, so they're left alone. Only sprites that appeared, moved or changed
+/// texture since the last frame are checked, and each pair is only reported once a round.
+#[cfg(debug_assertions)]
+fn draw_order_audit_system(
+    changed_query: Query<
+        Entity,
+        (
+            With<Sprite>,
+            Or<(Changed<GlobalTransform>, Changed<TileLocation>, Changed<Handle<Image>>)>,
+        ),
+    >,
+    sprite_query: Query<(Entity, &TileLocation, &GlobalTransform, &Handle<Image>), With<Sprite>>,
+    round: Res<crate::state::Round>,
+    mut reported: Local<HashSet<(Entity, Entity)>>,
+) {
+    if round.is_changed() {
+        reported.clear();
+    }
+    for (a, location, transform, texture) in sprite_query.iter_many(changed_query.iter()) {
+        for (b, other_location, other_transform, other_texture) in sprite_query.iter() {
+            if a != b
+                && location == other_location
+                && transform.translation().z == other_transform.translation().z
+                && texture != other_texture
+                && reported.insert((a.min(b), a.max(b)))
+            {
+                warn!("Sprites {:?} and {:?} share depth on tile {}.", a, b, location);
+            }
+        }
+    }
+}
+
+/// Logs state that shouldn't survive a frame whose steps ran in order: flames outliving
+/// their explosion, and bombs whose owner is gone without its team having been recorded.
+/// Each entity is only reported once.
+#[cfg(debug_assertions)]
+fn tick_invariants_system(
+    flame_query: Query<(Entity, &Parent), With<FlameMarker>>,
+    explosion_query: Query<(), With<ExplosionMarker>>,
+    bomb_query: Query<(Entity, &Owner, Option<&OwnerTeam>), With<BombMarker>>,
+    player_query: Query<(), With<Player>>,
+    mut reported: Local<HashSet<Entity>>,
+) {
+    for (flame, explosion) in flame_query.iter() {
+        if explosion_query.get(explosion.get()).is_err() && reported.insert(flame) {
+            error!("Flame {:?} outlived its explosion {:?}.", flame, explosion.get());
+        }
+    }
+    for (bomb, Owner(owner), team) in bomb_query.iter() {
+        if player_query.get(*owner).is_err() && team.is_none() && reported.insert(bomb) {
+            error!("Bomb {:?} lost its owner {:?} without a record of its team.", bomb, owner);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::schedule::Stage;
+
+    use super::*;
+    use crate::tick::TurnPhase;
+
+    /// A 7 by 3 map with nothing on it, and the resources the object systems use.
+    fn test_world() -> World {
+        let mut world = World::new();
+        let (game_map, _) = GameMap::parse("test", "s......\n.......\n.......").unwrap();
+        world.spawn().insert(game_map);
+        world.insert_resource(TileCache::default());
+        world.insert_resource(Textures {
+            bomb: Handle::default(),
+            flame: Handle::default(),
+            bomb_range_power_up: Handle::default(),
+            simultaneous_bombs_power_up: Handle::default(),
+            vision_range_power_up: Handle::default(),
+        });
+        world.insert_resource(SoundEffects {
+            explosion: Handle::default(),
+            drop: Handle::default(),
+            spawn: Handle::default(),
+            death: Handle::default(),
+            powerup: Handle::default(),
+            win: Handle::default(),
+        });
+        world.insert_resource(Audio::default());
+        world.insert_resource(RoundStats::default());
+        world.insert_resource(GameSettings::default());
+        world.insert_resource(PendingDeaths::default());
+        world.insert_resource(DeathReports::default());
+        world.insert_resource(Events::<BombExplodeEvent>::default());
+        world.insert_resource(Events::<CrateDestroyedEvent>::default());
+        world.insert_resource(Events::<KillPlayerEvent>::default());
+        world.insert_resource(Events::<SpawnBombEvent>::default());
+        world
+    }
+
+    fn bomb(world: &mut World, location: TileLocation, owner: Entity) -> Entity {
+        world
+            .spawn()
+            .insert(BombMarker)
+            .insert(location)
+            .insert(ExternalCrateComponent(Object::Bomb { fuse_remaining: Ticks(3), range: 2 }))
+            .insert(Owner(owner))
+            .insert(OwnerTint(Color::WHITE))
+            .insert(OwnerTeam("team".into()))
+            .id()
+    }
+
+    /// An explosion with a single flame, as `bomb_explosion_system` leaves them.
+    fn explosion(world: &mut World, location: TileLocation, owner: Entity) -> Entity {
+        let flame = world
+            .spawn()
+            .insert(FlameMarker)
+            .insert(location)
+            .insert(Owner(owner))
+            .insert(OwnerTeam("bombers".into()))
+            .id();
+        world
+            .spawn()
+            .insert(ExplosionMarker { lifetime_remaining: EXPLOSION_LIFETIME })
+            .insert(BombOrigin(location))
+            .insert(Owner(owner))
+            .insert(OwnerTeam("bombers".into()))
+            .push_children(&[flame])
+            .id()
+    }
+
+    fn player(world: &mut World, location: TileLocation) -> Entity {
+        world
+            .spawn()
+            .insert(Player::default())
+            .insert(location)
+            .insert(PlayerName("victim".into()))
+            .insert(Score(10))
+            .insert(Team { name: "victims".into(), color: Color::WHITE })
+            .id()
+    }
+
+    fn kills(world: &World) -> Vec<Entity> {
+        let events = world.resource::<Events<KillPlayerEvent>>();
+        events.get_reader().iter(events).map(|KillPlayerEvent(entity, ..)| *entity).collect()
+    }
+
+    #[test]
+    fn chain_reaction_explodes_each_bomb_once() {
+        let mut world = test_world();
+        // Each bomb is in range of the next one, but the first can't reach the last.
+        let owner = world.spawn().id();
+        let first = bomb(&mut world, TileLocation(0, 1), owner);
+        bomb(&mut world, TileLocation(2, 1), owner);
+        bomb(&mut world, TileLocation(4, 1), owner);
+        world
+            .resource_mut::<Events<BombExplodeEvent>>()
+            .send(BombExplodeEvent { bomb: first, location: TileLocation(0, 1) });
+
+        let mut stage = SystemStage::single_threaded()
+            .with_system(bomb_explosion_system)
+            .with_system(objects_on_fire_system.after(bomb_explosion_system));
+        // Flames only show up once commands are applied at the end of a frame, so each link
+        // of the chain takes two frames. The extra frames let duplicate events play out.
+        for _ in 0..10 {
+            stage.run(&mut world);
+        }
+
+        let bombs_left = world.query_filtered::<(), With<BombMarker>>().iter(&world).count();
+        let explosions = world.query_filtered::<(), With<ExplosionMarker>>().iter(&world).count();
+        assert_eq!(bombs_left, 0);
+        assert_eq!(explosions, 3);
+    }
+
+    #[test]
+    fn player_standing_in_flames_is_killed_once() {
+        let mut world = test_world();
+        let owner = world.spawn().id();
+        explosion(&mut world, TileLocation(1, 1), owner);
+        let victim = player(&mut world, TileLocation(1, 1));
+
+        // The killed player lingers until its despawn is applied, so it's still in the flames
+        // on the following frames.
+        let mut stage = SystemStage::single_threaded().with_system(players_on_fire_system);
+        for _ in 0..3 {
+            stage.run(&mut world);
+        }
+
+        assert_eq!(kills(&world), vec![victim]);
+        assert!(world.resource::<PendingDeaths>().0.contains(&victim));
+    }
+
+    #[test]
+    fn player_walking_into_live_flames_dies() {
+        let mut world = test_world();
+        let owner = world.spawn().id();
+        explosion(&mut world, TileLocation(1, 1), owner);
+        let victim = player(&mut world, TileLocation(2, 1));
+
+        let mut stage = SystemStage::single_threaded().with_system(players_on_fire_system);
+        stage.run(&mut world);
+        assert!(kills(&world).is_empty());
+
+        *world.get_mut::<TileLocation>(victim).unwrap() = TileLocation(1, 1);
+        stage.run(&mut world);
+        assert_eq!(kills(&world), vec![victim]);
+    }
+
+    #[test]
+    fn flames_last_one_world_tick() {
+        let mut world = test_world();
+        let owner = world.spawn().id();
+        explosion(&mut world, TileLocation(1, 1), owner);
+
+        // Scheduled the way the plugin does, so flames only burn down on world turns.
+        let mut stage = SystemStage::single_threaded().with_system_set(
+            SystemSet::new().with_run_criteria(world_turn).with_system(explosion_despawn_system),
+        );
+        let mut frame = |world: &mut World, turn_phase| {
+            world.insert_resource(turn_phase);
+            stage.run(world);
+            let flames = world.query_filtered::<(), With<FlameMarker>>().iter(world).count();
+            let explosions =
+                world.query_filtered::<(), With<ExplosionMarker>>().iter(world).count();
+            (flames, explosions)
+        };
+
+        // Players walking into the flames on their turn still find them there.
+        assert_eq!(frame(&mut world, TurnPhase::Player), (1, 1));
+        assert_eq!(frame(&mut world, TurnPhase::Idle), (1, 1));
+        assert_eq!(frame(&mut world, TurnPhase::World), (0, 0));
+    }
+
+    #[test]
+    fn bombs_dropped_by_dying_players_are_still_placed() {
+        let mut world = test_world();
+        let victim = player(&mut world, TileLocation(1, 1));
+        let snapshot = OwnerSnapshot {
+            range: 3,
+            max_bombs: 1,
+            team: Team { name: "bombers".into(), color: Color::RED },
+        };
+        world.resource_mut::<Events<SpawnBombEvent>>().send(SpawnBombEvent {
+            location: TileLocation(1, 1),
+            owner: victim,
+            snapshot,
+        });
+        // The player is killed in the same tick, so it's gone by the time the bomb spawns.
+        world.despawn(victim);
+
+        let mut stage = SystemStage::single_threaded().with_system(bomb_spawn_system);
+        stage.run(&mut world);
+
+        // Only bombs and their explosions carry the team of their owner.
+        let mut bombs = world.query::<(&Owner, &OwnerTeam, &ExternalCrateComponent<Object>)>();
+        let bombs = bombs
+            .iter(&world)
+            .map(|(Owner(owner), OwnerTeam(team), object)| match **object {
+                Object::Bomb { range, .. } => (*owner, team.clone(), range),
+                _ => unreachable!("only bombs are marked as bombs"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(bombs, vec![(victim, "bombers".to_string(), 3)]);
+    }
+
+    fn blast(bomb_location: TileLocation) -> HashSet<(usize, usize)> {
+        let (game_map, rows) = GameMap::parse("test", "s.#....\n.......\n.......").unwrap();
+        let tile_cache = TileCache::from_rows(&rows);
+        blast_locations(bomb_location, 2, &tile_cache, &game_map, |_| None)
+            .into_iter()
+            .map(|TileLocation(x, y)| (x, y))
+            .collect()
+    }
+
+    #[test]
+    fn blasts_stop_at_the_edge_of_the_map() {
+        let expected = [(0, 0), (1, 0), (2, 0), (0, 1), (0, 2)];
+        assert_eq!(blast(TileLocation(0, 0)), expected.into_iter().collect());
+        // The wall east of the bomb stops the flames too.
+        let expected = [(1, 2), (0, 2), (1, 1), (1, 0)];
+        assert_eq!(blast(TileLocation(1, 2)), expected.into_iter().collect());
+    }
+
+    #[test]
+    fn lowest_score_wins_the_power_up() {
+        let [first, second, third] = [0, 1, 2].map(Entity::from_raw);
+        let candidates = [(first, Score(30), 0), (second, Score(10), 1), (third, Score(20), 2)];
+        assert_eq!(power_up_winner(candidates.into_iter()), Some(second));
+    }
+
+    #[test]
+    fn power_up_tie_goes_to_the_earlier_spawn() {
+        let [first, second, third] = [0, 1, 2].map(Entity::from_raw);
+        let candidates = [(first, Score(10), 5), (second, Score(10), 3), (third, Score(20), 1)];
+        assert_eq!(power_up_winner(candidates.into_iter()), Some(second));
+        assert_eq!(power_up_winner(std::iter::empty()), None);
+    }
+
+    #[test]
+    fn dying_players_collect_nothing() {
+        let mut world = test_world();
+        let power_up = world
+            .spawn()
+            .insert(PowerUpMarker)
+            .insert(ExternalCrateComponent(Object::PowerUp(PowerUp::BombRange)))
+            .insert(TileLocation(1, 1))
+            .id();
+        let dying = player(&mut world, TileLocation(1, 1));
+        world.resource_mut::<PendingDeaths>().0.insert(dying);
+
+        let mut stage = SystemStage::single_threaded().with_system(pick_up_power_up_system);
+        stage.run(&mut world);
+        assert!(world.get_entity(power_up).is_some());
+        assert!(world.get::<Player>(dying).unwrap().power_ups.is_empty());
+
+        let survivor = player(&mut world, TileLocation(1, 1));
+        stage.run(&mut world);
+        assert!(world.get_entity(power_up).is_none());
+        assert_eq!(
+            world.get::<Player>(survivor).unwrap().power_ups.get(&PowerUp::BombRange),
+            Some(&1)
+        );
+    }
+}
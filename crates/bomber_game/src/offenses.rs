@@ -0,0 +1,188 @@
+//! Keeps track of how often each uploaded bot got banned, across rounds, so bans escalate.
+//! A first offense only bans a bot for the rest of the round, as failures are often down to
+//! the environment (such as spending the first turn's fuel budget). A second offense in the
+//! same round, or a third one overall, bans it until a new version is uploaded, which carries
+//! over to the following rounds for as long as the same file is uploaded again.
+//!
+//! The record lives in `offenses.json` under the rounds folder, keyed by API key (the stem of
+//! the `.wasm` files), so it survives restarts.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+const OFFENSES_FILENAME: &str = "offenses.json";
+/// Offenses within a single round that ban a bot until a new upload.
+const MAX_OFFENSES_PER_ROUND: u32 = 2;
+/// Offenses across rounds that ban a bot until a new upload.
+const MAX_OFFENSES: u32 = 3;
+
+/// How long a bot is banned for after an offense.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BanLength {
+    /// Until the end of the round, or until a new version is uploaded, whichever comes first.
+    RestOfRound,
+    /// Until a new version is uploaded, even across rounds.
+    UntilReupload,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+struct OffenseRecord {
+    offenses: u32,
+    last_round: u32,
+    offenses_last_round: u32,
+    /// Fingerprint of the file that is banned until a new upload, if any.
+    banned_file: Option<u64>,
+}
+
+/// Offenses of each API key, persisted under the rounds folder.
+#[derive(Debug)]
+pub struct OffenseLog {
+    path: PathBuf,
+    records: BTreeMap<String, OffenseRecord>,
+}
+
+impl OffenseLog {
+    /// Reads the offenses recorded under the rounds folder, or starts afresh if there are none.
+    pub fn load(rounds_folder: &Path) -> Result<Self> {
+        let path = rounds_folder.join(OFFENSES_FILENAME);
+        let records = match fs::read_to_string(&path) {
+            Ok(text) => {
+                serde_json::from_str(&text).with_context(|| format!("parsing {:?}", path))?
+            },
+            Err(e) if e.kind() == ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e).with_context(|| format!("reading {:?}", path)),
+        };
+        Ok(Self { path, records })
+    }
+
+    /// Records an offense of the bot uploaded with `api_key`, whose file is `bytes`, and
+    /// returns how long it's banned for.
+    pub fn record(&mut self, api_key: &str, round: u32, bytes: &[u8]) -> Result<BanLength> {
+        let record = self.records.entry(api_key.to_owned()).or_default();
+        if record.last_round != round {
+            record.last_round = round;
+            record.offenses_last_round = 0;
+        }
+        record.offenses += 1;
+        record.offenses_last_round += 1;
+        let length = if record.offenses_last_round >= MAX_OFFENSES_PER_ROUND
+            || record.offenses >= MAX_OFFENSES
+        {
+            record.banned_file = Some(fingerprint(bytes));
+            BanLength::UntilReupload
+        } else {
+            BanLength::RestOfRound
+        };
+        self.save()?;
+        Ok(length)
+    }
+
+    /// Whether the bot uploaded with `api_key` is banned until a new upload.
+    pub fn is_banned_until_reupload(&self, api_key: &str) -> bool {
+        self.records.get(api_key).map_or(false, |record| record.banned_file.is_some())
+    }
+
+    /// Whether `bytes` are the file the bot uploaded with `api_key` is banned for. Any other
+    /// file counts as a new upload, which lifts the ban and wipes the slate clean.
+    pub fn is_banned_file(&mut self, api_key: &str, bytes: &[u8]) -> Result<bool> {
+        match self.records.get(api_key).and_then(|record| record.banned_file) {
+            Some(banned) if banned == fingerprint(bytes) => Ok(true),
+            Some(_) => self.lift(api_key).map(|()| false),
+            None => Ok(false),
+        }
+    }
+
+    /// Lifts a ban until a new upload, as one just came in. Offenses are forgotten along with
+    /// it, so the new version starts from a first offense again.
+    pub fn lift(&mut self, api_key: &str) -> Result<()> {
+        if self.is_banned_until_reupload(api_key) {
+            self.records.remove(api_key);
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    fn save(&self) -> Result<()> {
+        let json = serde_json::to_string_pretty(&self.records)?;
+        fs::write(&self.path, json).with_context(|| format!("writing {:?}", self.path))
+    }
+}
+
+/// FNV-1a hash of a file, which unlike the standard library hashers is stable across builds
+/// of the game.
+fn fingerprint(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    /// Empty folder for a test to play in, named after it so tests don't step on each other.
+    fn test_folder(name: &str) -> PathBuf {
+        let folder =
+            env::temp_dir().join(format!("bomber_offenses_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&folder);
+        fs::create_dir_all(&folder).unwrap();
+        folder
+    }
+
+    #[test]
+    fn first_offense_bans_for_the_rest_of_the_round() {
+        let folder = test_folder("first_offense");
+        let mut log = OffenseLog::load(&folder).unwrap();
+        assert_eq!(log.record("key", 1, b"bot").unwrap(), BanLength::RestOfRound);
+        assert!(!log.is_banned_until_reupload("key"));
+        assert!(!log.is_banned_file("key", b"bot").unwrap());
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn second_offense_in_a_round_bans_until_reupload() {
+        let folder = test_folder("second_offense");
+        let mut log = OffenseLog::load(&folder).unwrap();
+        log.record("key", 1, b"bot").unwrap();
+        assert_eq!(log.record("key", 1, b"bot").unwrap(), BanLength::UntilReupload);
+        assert!(log.is_banned_file("key", b"bot").unwrap());
+        // Other bots are left alone.
+        assert!(!log.is_banned_until_reupload("other key"));
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn third_offense_across_rounds_bans_until_reupload() {
+        let folder = test_folder("third_offense");
+        let mut log = OffenseLog::load(&folder).unwrap();
+        assert_eq!(log.record("key", 1, b"bot").unwrap(), BanLength::RestOfRound);
+        assert_eq!(log.record("key", 2, b"bot").unwrap(), BanLength::RestOfRound);
+        assert_eq!(log.record("key", 3, b"bot").unwrap(), BanLength::UntilReupload);
+        // The ban carries over restarts, and to the following rounds.
+        let mut log = OffenseLog::load(&folder).unwrap();
+        assert!(log.is_banned_file("key", b"bot").unwrap());
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn a_different_file_lifts_the_ban() {
+        let folder = test_folder("different_file");
+        let mut log = OffenseLog::load(&folder).unwrap();
+        log.record("key", 1, b"bot").unwrap();
+        log.record("key", 1, b"bot").unwrap();
+        assert!(!log.is_banned_file("key", b"fixed bot").unwrap());
+        assert!(!log.is_banned_until_reupload("key"));
+        assert!(!OffenseLog::load(&folder).unwrap().is_banned_until_reupload("key"));
+        // The slate is wiped clean, so the new version starts from a first offense.
+        assert_eq!(log.record("key", 1, b"fixed bot").unwrap(), BanLength::RestOfRound);
+        fs::remove_dir_all(&folder).unwrap();
+    }
+}
@@ -0,0 +1,197 @@
+//! Defines a Bevy plugin for purely visual effects: debris flying off destroyed crates, and
+//! a sparkle over hill tiles so viewers new to the game can tell where the objective is.
+//! None of it has any effect on the game.
+
+use std::{f32::consts::TAU, ops::RangeInclusive, time::Duration};
+
+use bevy::prelude::*;
+use bevy_tweening::{
+    lens::{SpriteColorLens, TransformPositionLens},
+    *,
+};
+use bomber_lib::world::Tile;
+use rand::{thread_rng, Rng};
+
+use crate::{
+    game_map::{GameMap, TileLocation},
+    rendering::{GAME_MAP_Z, HILL_SPARKLE_Z, PARTICLE_Z, TILE_WIDTH_PX},
+    state::AppState,
+    ExternalCrateComponent,
+};
+
+pub struct ParticlesPlugin;
+
+/// Sent when a crate is destroyed, with the location it stood on.
+pub struct CrateDestroyedEvent(pub TileLocation);
+
+/// Debris sprites are spawned once and reused, so enough of them are needed for a few
+/// crates breaking at once.
+const DEBRIS_POOL_SIZE: usize = 96;
+const DEBRIS_PER_CRATE: RangeInclusive<usize> = 4..=6;
+const DEBRIS_DURATION: Duration = Duration::from_millis(400);
+const DEBRIS_DISTANCE_PX: f32 = TILE_WIDTH_PX * 0.75;
+const DEBRIS_SIZE_PX: f32 = TILE_WIDTH_PX / 5.0;
+const SPARKLE_PERIOD_SECONDS: f32 = 2.0;
+const SPARKLE_SIZE_PX: f32 = TILE_WIDTH_PX / 6.0;
+const SPARKLE_MAX_ALPHA: f32 = 0.7;
+
+/// Debris sprites, shown while they fly and hidden once their lifetime is over.
+struct DebrisPool(Vec<Entity>);
+
+#[derive(Component)]
+struct Debris {
+    lifetime: Timer,
+}
+
+/// Twinkles over a hill tile, out of step with the others.
+#[derive(Component)]
+struct HillSparkle {
+    phase: f32,
+}
+
+impl Plugin for ParticlesPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_startup_system(setup)
+            .add_system(debris_spawn_system)
+            .add_system(debris_lifetime_system)
+            .add_system(hill_sparkle_spawn_system)
+            .add_system(hill_sparkle_system)
+            .add_system_set(SystemSet::on_exit(AppState::InGame).with_system(cleanup));
+    }
+}
+
+fn setup(asset_server: Res<AssetServer>, mut commands: Commands) {
+    let texture = asset_server.load("graphics/Sprites/Blocks/ExplodableBlock.png");
+    let pool = (0..DEBRIS_POOL_SIZE)
+        .map(|_| {
+            let mut lifetime = Timer::new(DEBRIS_DURATION, false);
+            // Finished timers mark debris that is free to use.
+            lifetime.tick(DEBRIS_DURATION);
+            commands
+                .spawn_bundle(SpriteBundle {
+                    texture: texture.clone(),
+                    sprite: Sprite { custom_size: Some(Vec2::splat(DEBRIS_SIZE_PX)), ..default() },
+                    visibility: Visibility { is_visible: false },
+                    ..default()
+                })
+                .insert(Debris { lifetime })
+                .id()
+        })
+        .collect();
+    commands.insert_resource(DebrisPool(pool));
+}
+
+fn debris_spawn_system(
+    mut crate_destroyed_events: EventReader<CrateDestroyedEvent>,
+    game_map_query: Query<&GameMap>,
+    pool: Res<DebrisPool>,
+    mut debris_query: Query<(&mut Debris, &mut Visibility)>,
+    mut commands: Commands,
+) {
+    let game_map = if let Ok(game_map) = game_map_query.get_single() { game_map } else { return };
+    let mut rng = thread_rng();
+    let mut free_debris = pool.0.iter().copied();
+    for CrateDestroyedEvent(location) in crate_destroyed_events.iter() {
+        let center = location.as_world_coordinates(game_map);
+        let count = rng.gen_range(DEBRIS_PER_CRATE);
+        let first_angle = rng.gen_range(0.0..TAU);
+        for i in 0..count {
+            // When the pool runs dry, the remaining crates simply go without debris.
+            let entity = match free_debris
+                .find(|e| debris_query.get(*e).map_or(false, |(d, _)| d.lifetime.finished()))
+            {
+                Some(entity) => entity,
+                None => break,
+            };
+            let (mut debris, mut visibility) = debris_query.get_mut(entity).unwrap();
+            debris.lifetime.reset();
+            visibility.is_visible = true;
+
+            let angle = first_angle + TAU * i as f32 / count as f32 + rng.gen_range(-0.3..0.3);
+            let end = center + Vec2::new(angle.cos(), angle.sin()) * DEBRIS_DISTANCE_PX;
+            commands
+                .entity(entity)
+                .insert(Animator::new(Tween::new(
+                    EaseFunction::QuadraticOut,
+                    TweeningType::Once,
+                    DEBRIS_DURATION,
+                    TransformPositionLens {
+                        start: center.extend(PARTICLE_Z),
+                        end: end.extend(PARTICLE_Z),
+                    },
+                )))
+                .insert(Animator::new(Tween::new(
+                    EaseFunction::QuadraticIn,
+                    TweeningType::Once,
+                    DEBRIS_DURATION,
+                    SpriteColorLens { start: Color::WHITE, end: Color::rgba(1.0, 1.0, 1.0, 0.0) },
+                )));
+        }
+    }
+}
+
+fn debris_lifetime_system(
+    time: Res<Time>,
+    mut debris_query: Query<(&mut Debris, &mut Visibility)>,
+) {
+    for (mut debris, mut visibility) in debris_query.iter_mut() {
+        if debris.lifetime.tick(time.delta()).just_finished() {
+            visibility.is_visible = false;
+        }
+    }
+}
+
+/// Adds a sparkle to hill tiles as the map is spawned. Sparkles are children of their tile,
+/// so they go away along with the map.
+fn hill_sparkle_spawn_system(
+    tile_query: Query<(Entity, &ExternalCrateComponent<Tile>), Added<ExternalCrateComponent<Tile>>>,
+    mut commands: Commands,
+) {
+    let mut rng = thread_rng();
+    for (entity, _) in tile_query.iter().filter(|(_, tile)| matches!(***tile, Tile::Hill)) {
+        let offset = Vec2::new(rng.gen_range(-0.3..0.3), rng.gen_range(-0.3..0.3)) * TILE_WIDTH_PX;
+        commands.entity(entity).with_children(|parent| {
+            parent
+                .spawn_bundle(SpriteBundle {
+                    sprite: Sprite {
+                        color: Color::rgba(1.0, 1.0, 0.85, 0.0),
+                        custom_size: Some(Vec2::splat(SPARKLE_SIZE_PX)),
+                        ..default()
+                    },
+                    // Relative to the tile, and turned to look like a diamond.
+                    transform: Transform::from_translation(
+                        offset.extend(HILL_SPARKLE_Z - GAME_MAP_Z),
+                    )
+                    .with_rotation(Quat::from_rotation_z(TAU / 8.0)),
+                    ..default()
+                })
+                .insert(HillSparkle { phase: rng.gen_range(0.0..1.0) });
+        });
+    }
+}
+
+fn hill_sparkle_system(
+    time: Res<Time>,
+    mut sparkle_query: Query<(&HillSparkle, &mut Sprite, &mut Transform)>,
+) {
+    let cycle = time.seconds_since_startup() as f32 / SPARKLE_PERIOD_SECONDS;
+    for (HillSparkle { phase }, mut sprite, mut transform) in sparkle_query.iter_mut() {
+        // Brief flashes with long pauses in between, rather than a constant pulse.
+        let brightness = (TAU * (cycle + phase)).sin().max(0.0).powi(4);
+        sprite.color.set_a(brightness * SPARKLE_MAX_ALPHA);
+        transform.scale = Vec3::splat(0.5 + brightness * 0.5);
+    }
+}
+
+/// Hides any debris still in flight when the round ends.
+fn cleanup(
+    mut debris_query: Query<(Entity, &mut Debris, &mut Visibility)>,
+    mut commands: Commands,
+) {
+    for (entity, mut debris, mut visibility) in debris_query.iter_mut() {
+        let remaining = debris.lifetime.duration() - debris.lifetime.elapsed();
+        debris.lifetime.tick(remaining);
+        visibility.is_visible = false;
+        commands.entity(entity).remove::<Animator<Transform>>().remove::<Animator<Sprite>>();
+    }
+}
@@ -0,0 +1,1257 @@
+//! Runs the turns of players: asks every brain for an action, keeping `.wasm` players to
+//! their fuel budget, and applies the actions to the world.
+
+use std::{mem, time::Instant};
+
+use anyhow::{anyhow, Result};
+use bevy::{prelude::*, utils::HashMap};
+use bomber_lib::{
+    wasm_act,
+    world::{Direction, Enemy, Object, PowerUp, Tile, TileOffset},
+    Action,
+};
+use bomber_shared::{
+    round_folder, BOMB_COUNT_EXPORT, BUFFER_SIZE_EXPORT, HILL_HINT_EXPORT,
+    LAST_DEATH_BUFFER_EXPORT, LAST_DEATH_EXPORT, LOAD_STATE_BUFFER_EXPORT, LOAD_STATE_EXPORT,
+    SAVE_STATE_EXPORT, SCORED_EXPORT,
+};
+use wasmtime::{Caller, Extern, Instance, Linker, Module, Store};
+
+use super::{
+    DeathReport, DeathReports, Owner, PanicReport, PendingDeaths, Player, PlayerMovedEvent,
+    PlayerName, Team,
+};
+use crate::{
+    animation::AnimationState,
+    game_map::{surroundings_order, GameMap, TileCache, TileLocation},
+    input_recording::{record_frame, recording_path, RecordedTurn},
+    native_player::NativePlayer,
+    object::{OwnerSnapshot, SpawnBombEvent},
+    player_hotswap::{PlayerHandle, PlayerHandles, WasmPlayerAsset},
+    score::Score,
+    settings::{GameSettings, Rules},
+    state::{Round, RoundsFolder},
+    stats::RoundStats,
+    tick::TurnPhase,
+    ExternalCrateComponent,
+};
+
+/// Optional import through which `.wasm` players report the message and location of a panic,
+/// as a pointer and length into their memory.
+const PANIC_REPORT_IMPORT: (&str, &str) = ("env", "__wasm_report_panic");
+const MAX_PANIC_MESSAGE_LENGTH: u32 = 256;
+/// How many players decide their action each frame following a player tick.
+const PLAYERS_PER_FRAME: usize = 3;
+/// Largest state handed over between versions of a player.
+const MAX_SAVED_STATE_SIZE: u32 = 64 * 1024;
+/// Consecutive turns a `.wasm` player may fail to act on (standing still instead) before it's
+/// banned, so a one-off hiccup doesn't end its round.
+const MAX_FAILED_TURNS: u32 = 3;
+
+/// Binds a module to a store, providing the imports players may use. Modules that don't use
+/// some of the imports instantiate just fine, as the linker only hands out what's requested.
+pub fn instantiate(
+    engine: &wasmtime::Engine,
+    store: &mut Store<()>,
+    module: &Module,
+    panic_report: &PanicReport,
+) -> Result<Instance> {
+    let mut linker = Linker::new(engine);
+    let report = panic_report.0.clone();
+    let (import_module, import_name) = PANIC_REPORT_IMPORT;
+    linker.func_wrap(
+        import_module,
+        import_name,
+        move |mut caller: Caller<'_, ()>, pointer: u32, length: u32| {
+            let memory = caller.get_export("memory").and_then(Extern::into_memory);
+            let mut bytes = vec![0; length.min(MAX_PANIC_MESSAGE_LENGTH) as usize];
+            if let Some(Ok(())) = memory.map(|m| m.read(&caller, pointer as usize, &mut bytes)) {
+                *report.lock().expect("Panic report lock poisoned") =
+                    Some(String::from_utf8_lossy(&bytes).into_owned());
+            }
+        },
+    )?;
+    linker.instantiate(store, module)
+}
+
+/// Asks a player for the state to carry over to its next version, if it supports handing it
+/// over.
+pub fn save_state(store: &mut Store<()>, instance: Instance) -> Result<Option<Vec<u8>>> {
+    let save = match instance.get_func(&mut *store, SAVE_STATE_EXPORT) {
+        Some(save) => save.typed::<(), u64, _>(&*store)?,
+        None => return Ok(None),
+    };
+    let location = save.call(&mut *store, ())?;
+    let (pointer, length) = ((location >> 32) as u32, location as u32);
+    if length > MAX_SAVED_STATE_SIZE {
+        return Err(anyhow!("state of {} bytes exceeds {}", length, MAX_SAVED_STATE_SIZE));
+    }
+    let memory =
+        instance.get_memory(&mut *store, "memory").ok_or_else(|| anyhow!("no memory exported"))?;
+    let mut state = vec![0; length as usize];
+    memory.read(&*store, pointer as usize, &mut state)?;
+    Ok(Some(state))
+}
+
+/// Hands the state saved by the previous version of a player to the new one. Returns whether
+/// the new version takes state at all.
+pub fn load_state(store: &mut Store<()>, instance: Instance, state: &[u8]) -> Result<bool> {
+    let (buffer, load) = match (
+        instance.get_func(&mut *store, LOAD_STATE_BUFFER_EXPORT),
+        instance.get_func(&mut *store, LOAD_STATE_EXPORT),
+    ) {
+        (Some(buffer), Some(load)) => {
+            (buffer.typed::<u32, u32, _>(&*store)?, load.typed::<u32, (), _>(&*store)?)
+        },
+        _ => return Ok(false),
+    };
+    let length = state.len() as u32;
+    let pointer = buffer.call(&mut *store, length)?;
+    let memory =
+        instance.get_memory(&mut *store, "memory").ok_or_else(|| anyhow!("no memory exported"))?;
+    memory.write(&mut *store, pointer as usize, state)?;
+    load.call(&mut *store, length)?;
+    Ok(true)
+}
+
+/// How a hill hint is passed to `HILL_HINT_EXPORT`.
+fn hill_hint_code(hint: Option<Direction>) -> u32 {
+    match hint {
+        None => 0,
+        Some(Direction::North) => 1,
+        Some(Direction::East) => 2,
+        Some(Direction::South) => 3,
+        Some(Direction::West) => 4,
+    }
+}
+
+/// Tells a player which way the nearest hill is, if it takes hints.
+fn give_hill_hint(
+    store: &mut Store<()>,
+    instance: Instance,
+    hint: Option<Direction>,
+) -> Result<()> {
+    let give = match instance.get_func(&mut *store, HILL_HINT_EXPORT) {
+        Some(give) => give.typed::<u32, (), _>(&*store)?,
+        None => return Ok(()),
+    };
+    give.call(&mut *store, hill_hint_code(hint))?;
+    Ok(())
+}
+
+/// Tells a player how many of its bombs are on the map and how many it may have there at
+/// once, if it takes bomb counts.
+fn give_bomb_count(
+    store: &mut Store<()>,
+    instance: Instance,
+    (active, max): (u32, u32),
+) -> Result<()> {
+    let give = match instance.get_func(&mut *store, BOMB_COUNT_EXPORT) {
+        Some(give) => give.typed::<(u32, u32), (), _>(&*store)?,
+        None => return Ok(()),
+    };
+    give.call(&mut *store, (active, max))?;
+    Ok(())
+}
+
+/// Tells a player how many points it scored since its previous turn, if it takes them.
+fn give_scored(store: &mut Store<()>, instance: Instance, points: u32) -> Result<()> {
+    let give = match instance.get_func(&mut *store, SCORED_EXPORT) {
+        Some(give) => give.typed::<u32, (), _>(&*store)?,
+        None => return Ok(()),
+    };
+    give.call(&mut *store, points)?;
+    Ok(())
+}
+
+/// How a death report is passed to `LAST_DEATH_EXPORT`: the killer team, empty for the
+/// player's own bomb, and the bomb's offset from `location`.
+fn last_death(report: &DeathReport, location: TileLocation) -> (&str, i32, i32) {
+    let TileOffset(x, y) = report.bomb_location - location;
+    (report.killer_team.as_deref().unwrap_or_default(), x, y)
+}
+
+/// Tells a respawned player how it last died, if it takes death reports. `location` is where
+/// it respawned, which the location of the bomb is given relative to.
+fn give_death_report(
+    store: &mut Store<()>,
+    instance: Instance,
+    report: &DeathReport,
+    location: TileLocation,
+) -> Result<()> {
+    let (buffer, give) = match (
+        instance.get_func(&mut *store, LAST_DEATH_BUFFER_EXPORT),
+        instance.get_func(&mut *store, LAST_DEATH_EXPORT),
+    ) {
+        (Some(buffer), Some(give)) => {
+            (buffer.typed::<u32, u32, _>(&*store)?, give.typed::<(u32, i32, i32), (), _>(&*store)?)
+        },
+        _ => return Ok(()),
+    };
+    let (killer_team, x, y) = last_death(report, location);
+    let length = killer_team.len() as u32;
+    let pointer = buffer.call(&mut *store, length)?;
+    let memory =
+        instance.get_memory(&mut *store, "memory").ok_or_else(|| anyhow!("no memory exported"))?;
+    memory.write(&mut *store, pointer as usize, killer_team.as_bytes())?;
+    give.call(&mut *store, (length, x, y))?;
+    Ok(())
+}
+
+/// Progress through the player tick being processed.
+#[derive(Default)]
+pub(super) enum TurnProgress {
+    /// Waiting for the next player tick.
+    #[default]
+    Idle,
+    /// Players decide their actions a few per frame. Actions are buffered rather than applied,
+    /// so every player decides based on the same state of the world.
+    Collecting {
+        pending: Vec<Entity>,
+        actions: Vec<(Entity, Action)>,
+        /// How every player looked to their enemies at the player tick. Scores change while
+        /// players decide, so it's kept for the whole turn for all of them to see the same.
+        enemies: HashMap<Entity, Enemy>,
+    },
+    /// Every player has decided, and all actions are applied at once.
+    Applying(Vec<(Entity, Action)>),
+}
+
+impl TurnProgress {
+    /// Moves on to a new frame, taking a snapshot of the `players` (as their enemies see them)
+    /// if a player turn starts. Returns whether every player still to decide has to do so
+    /// right away, because another turn came along before they all did.
+    fn start_frame(
+        &mut self,
+        turn_phase: TurnPhase,
+        players: impl Iterator<Item = (Entity, Enemy)>,
+    ) -> bool {
+        if turn_phase == TurnPhase::Idle {
+            return false;
+        }
+        if matches!(self, TurnProgress::Collecting { .. }) {
+            return true;
+        }
+        if turn_phase == TurnPhase::Player {
+            let (pending, enemies) =
+                players.map(|(entity, enemy)| (entity, (entity, enemy))).unzip();
+            *self = TurnProgress::Collecting { pending, actions: vec![], enemies };
+        }
+        false
+    }
+
+    /// Takes the players that decide their action this frame.
+    fn take_deciding(&mut self, finish_now: bool) -> Vec<Entity> {
+        if let TurnProgress::Collecting { pending, .. } = self {
+            let count =
+                if finish_now { pending.len() } else { pending.len().min(PLAYERS_PER_FRAME) };
+            pending.drain(..count).collect()
+        } else {
+            vec![]
+        }
+    }
+
+    /// How a player looked to their enemies at the start of the turn being decided.
+    fn enemy(&self, entity: Entity) -> Option<&Enemy> {
+        match self {
+            TurnProgress::Collecting { enemies, .. } => enemies.get(&entity),
+            _ => None,
+        }
+    }
+
+    fn decided(&mut self, player_entity: Entity, action: Action) {
+        if let TurnProgress::Collecting { actions, .. } = self {
+            actions.push((player_entity, action));
+        }
+    }
+
+    /// Takes the actions to apply this frame, which are all of them once every player has
+    /// decided, and none until then.
+    fn take_actions(&mut self) -> Vec<(Entity, Action)> {
+        if let TurnProgress::Collecting { pending, actions, .. } = self {
+            if pending.is_empty() {
+                *self = TurnProgress::Applying(mem::take(actions));
+            }
+        }
+        match mem::take(self) {
+            TurnProgress::Applying(actions) => actions,
+            progress => {
+                *self = progress;
+                vec![]
+            },
+        }
+    }
+}
+
+/// Every universal tick, queries all players for their desired action and applies
+/// it. At the moment this only results in movement but will likely expand into more
+/// complex actions.
+///
+/// Running every `.wasm` player in the same frame causes a noticeable frame time spike, so
+/// players decide over the frames following the player tick, `PLAYERS_PER_FRAME` at a time.
+/// The buffered actions are then applied together, well before the following world tick.
+pub(super) fn player_action_system(
+    mut player_query: Query<(
+        Entity,
+        &mut TileLocation,
+        &mut AnimationState,
+        Option<&mut ExternalCrateComponent<wasmtime::Store<()>>>,
+        Option<&ExternalCrateComponent<wasmtime::Instance>>,
+        Option<&PanicReport>,
+        Option<&mut NativePlayer>,
+        &PlayerName,
+        &Team,
+        &Score,
+        &mut Player,
+        Option<&Handle<WasmPlayerAsset>>,
+    )>,
+    tile_query: Query<
+        (&TileLocation, &ExternalCrateComponent<Tile>),
+        (Without<Player>, Without<ExternalCrateComponent<Object>>),
+    >,
+    object_query: Query<
+        (&TileLocation, &ExternalCrateComponent<Object>),
+        (Without<Player>, Without<ExternalCrateComponent<Tile>>),
+    >,
+    bomb_owner_query: Query<&Owner, With<ExternalCrateComponent<Object>>>,
+    mut spawn_bomb_event: EventWriter<SpawnBombEvent>,
+    turn_phase: Res<TurnPhase>,
+    // Grouped to stay within the lint on system parameters.
+    (mut handles, pending_deaths, mut death_reports): (
+        ResMut<PlayerHandles>,
+        Res<PendingDeaths>,
+        ResMut<DeathReports>,
+    ),
+    mut stats: ResMut<RoundStats>,
+    mut event_writer: EventWriter<PlayerMovedEvent>,
+    mut progress: Local<TurnProgress>,
+    settings: Res<GameSettings>,
+    asset_server: Res<AssetServer>,
+    (round, rounds_folder): (Res<Round>, Res<RoundsFolder>),
+    (tile_cache, map_query): (Res<TileCache>, Query<&GameMap>),
+) -> Result<()> {
+    let Rules { fuel_per_tick, base_vision, .. } = settings.rules;
+    // If another turn comes along before every player has decided, the game can't keep up
+    // and the remaining players decide right away, so actions never spill into the next turn.
+    let finish_now = progress.start_frame(
+        *turn_phase,
+        player_query.iter().map(|(e, _, _, _, _, _, _, n, t, s, _, _)| (e, as_enemy(n, t, s))),
+    );
+    let deciding = progress.take_deciding(finish_now);
+
+    if !deciding.is_empty() {
+        let locations = player_query.iter().map(|(e, l, ..)| (e, *l)).collect::<Vec<_>>();
+        let hill_hints = map_query.get_single().map_or(false, |game_map| game_map.hill_hints);
+
+        for player_entity in deciding {
+            // The player may have died or been despawned since the tick. Dying players aren't
+            // run at all, so they aren't charged fuel either.
+            if pending_deaths.0.contains(&player_entity) {
+                continue;
+            }
+            let (
+                _,
+                location,
+                _,
+                store,
+                instance,
+                panic_report,
+                native_player,
+                player_name,
+                team,
+                _,
+                mut player,
+                handle_inner,
+            ) = if let Ok(player) = player_query.get_mut(player_entity) {
+                player
+            } else {
+                continue;
+            };
+
+            let enemies = locations
+                .iter()
+                .filter(|(e, l)| *e != player_entity && *l != *location)
+                .filter_map(|(e, l)| progress.enemy(*e).map(|enemy| (enemy, *l)))
+                .collect::<Vec<_>>();
+            let mut surroundings = player_surroundings(
+                &location,
+                &tile_query,
+                &object_query,
+                &enemies,
+                &player,
+                base_vision,
+            );
+            let hill_hint = hill_hints
+                .then(|| hill_hint(*location, &tile_cache, view_distance(&player, base_vision)))
+                .flatten();
+            // Taken from every player, so points don't pile up for those that aren't told.
+            let scored = player.take_points_scored();
+
+            let action = if let Some(mut native_player) = native_player {
+                // Native players are compiled in with the game, so they are trusted
+                // and exempt from fuel accounting.
+                native_player.0.hill_hint(hill_hint);
+                native_player.0.act(surroundings)
+            } else if let (Some(mut store), Some(instance), Some(handle_inner)) =
+                (store, instance, handle_inner)
+            {
+                let buffer_size = *player.buffer_size.get_or_insert_with(|| {
+                    instance
+                        .get_typed_func::<(), u32, _>(&mut **store, BUFFER_SIZE_EXPORT)
+                        .and_then(|size| size.call(&mut **store, ()))
+                        .ok()
+                        .map(u64::from)
+                });
+                if let Some(buffer_size) = buffer_size {
+                    let clipped = truncate_surroundings(&mut surroundings, buffer_size)?;
+                    if clipped > 0 {
+                        info!(
+                            "Left {clipped} tiles out of the view of {}, to fit its buffer.",
+                            player_name.0
+                        );
+                        stats.0.entry(handle_inner.id).or_default().clipped_turns += 1;
+                    }
+                }
+
+                let death_report = (!player.first_turn_done)
+                    .then(|| death_reports.0.remove(&handle_inner.id))
+                    .flatten();
+                // Counted the way `bomb_spawn_system` does, so bots can tell whether a drop
+                // would go through.
+                let bombs_placed =
+                    bomb_owner_query.iter().filter(|Owner(owner)| *owner == player_entity).count();
+                let bomb_count = (bombs_placed as u32, player.max_bombs());
+                if settings.record_inputs {
+                    if let Some(file_stem) = PlayerHandle::file_stem(handle_inner, &asset_server) {
+                        let path =
+                            recording_path(&round_folder(&rounds_folder.0, round.0), &file_stem);
+                        let turn = RecordedTurn {
+                            surroundings: &surroundings,
+                            hill_hint: hill_hint_code(hill_hint),
+                            bomb_count,
+                            scored,
+                            last_death: death_report
+                                .as_ref()
+                                .map(|report| last_death(report, *location)),
+                        };
+                        if let Err(e) = record_frame(&path, &turn) {
+                            warn!("Failed to record the inputs of {}: {:#}", player_name.0, e);
+                        }
+                    }
+                }
+
+                let started = Instant::now();
+                if let Some(report) = death_report {
+                    if let Err(e) = give_death_report(&mut **store, instance, &report, *location) {
+                        warn!("Failed to tell {} how it died: {:#}", player_name.0, e);
+                    }
+                }
+                if let Err(e) = give_hill_hint(&mut **store, instance, hill_hint) {
+                    warn!("Failed to tell {} where the hill is: {:#}", player_name.0, e);
+                }
+                if let Err(e) = give_bomb_count(&mut **store, instance, bomb_count) {
+                    warn!("Failed to tell {} how many bombs it has: {:#}", player_name.0, e);
+                }
+                if let Err(e) = give_scored(&mut **store, instance, scored) {
+                    warn!("Failed to tell {} how many points it scored: {:#}", player_name.0, e);
+                }
+                let result = wasm_act(&mut **store, instance, surroundings);
+                let total_fuel_consumed =
+                    store.fuel_consumed().expect("Fuel consumption should be enabled");
+                let fuel_consumed_this_turn = total_fuel_consumed
+                    .checked_sub(player.total_fuel_consumed)
+                    .expect("Invalid fuel count");
+                let action = match result {
+                    Ok(action) => {
+                        player.failed_turns = 0;
+                        info!("{} spent {fuel_consumed_this_turn} fuel this turn.", player_name.0);
+                        let player_stats = stats.0.entry(handle_inner.id).or_default();
+                        player_stats.name = player_name.0.clone();
+                        player_stats.team_name = team.name.clone();
+                        player_stats.record_turn(fuel_consumed_this_turn, started.elapsed());
+                        action
+                    },
+                    Err(error) => {
+                        player.failed_turns += 1;
+                        let panic_message = panic_report.and_then(PanicReport::take);
+                        let error = format!("{error:?}");
+                        if let Some(reason) = ban_reason(
+                            &error,
+                            panic_message,
+                            &player,
+                            fuel_consumed_this_turn,
+                            fuel_per_tick,
+                        ) {
+                            error!(
+                                "Player {} triggered an unrecoverable error ({}). \
+                                 Invalidating handle.",
+                                player_name.0, error
+                            );
+                            if let Some(handle) = handles
+                                .0
+                                .iter_mut()
+                                .find(|handle| handle.inner().id == handle_inner.id)
+                            {
+                                handle.invalidate(reason);
+                            }
+                            continue;
+                        }
+                        warn!(
+                            "{} failed to act ({error}), standing still instead (strike {} of {}).",
+                            player_name.0, player.failed_turns, MAX_FAILED_TURNS
+                        );
+                        Action::StayStill
+                    },
+                };
+
+                if player.first_turn_done {
+                    store.add_fuel(fuel_consumed_this_turn)?;
+                } else {
+                    // Bring the store down to the regular budget now that the first turn is over.
+                    let remaining = player.fuel_budget(fuel_per_tick) - fuel_consumed_this_turn;
+                    if remaining > fuel_per_tick {
+                        store.consume_fuel(remaining - fuel_per_tick)?;
+                    } else {
+                        store.add_fuel(fuel_per_tick - remaining)?;
+                    }
+                    player.first_turn_done = true;
+                }
+                // Synthetic fuel consumption counts as consumed, so query the store again.
+                player.total_fuel_consumed =
+                    store.fuel_consumed().expect("Fuel consumption should be enabled");
+                action
+            } else {
+                error!("Player {} has neither a native nor a WASM brain.", player_name.0);
+                continue;
+            };
+
+            progress.decided(player_entity, action);
+        }
+    }
+
+    // Locations are refreshed between actions so players don't end up incorrectly occupying
+    // the same spot.
+    for (player_entity, action) in progress.take_actions() {
+        if pending_deaths.0.contains(&player_entity) {
+            continue;
+        }
+        let locations = player_query.iter().map(|(e, l, ..)| (e, *l)).collect::<Vec<_>>();
+        let (
+            _,
+            mut location,
+            mut animation,
+            ..,
+            player_name,
+            team,
+            score,
+            mut player,
+            handle_inner,
+        ) = if let Ok(player) = player_query.get_mut(player_entity) {
+            player
+        } else {
+            continue;
+        };
+
+        let bombs_placed =
+            bomb_owner_query.iter().filter(|Owner(owner)| *owner == player_entity).count();
+        let action = match validate_action(action, player.max_bombs(), bombs_placed) {
+            Ok(action) => action,
+            Err(e) => {
+                info!("{} chose an invalid action, standing still instead: {}", player_name.0, e);
+                if let Some(player_stats) = handle_inner.and_then(|h| stats.0.get_mut(&h.id)) {
+                    player_stats.invalid_actions += 1;
+                }
+                Action::StayStill
+            },
+        };
+
+        // Players sliding on ice can't steer or stop, though they may still drop bombs on
+        // their way.
+        let action = match (player.sliding.take(), action) {
+            (Some(direction), Action::DropBomb | Action::DropBombAndMove(_)) => {
+                Action::DropBombAndMove(direction)
+            },
+            (Some(direction), _) => Action::Move(direction),
+            (None, action) => action,
+        };
+
+        // Only `.wasm` players can be kicked for idling, so only they keep count.
+        if handle_inner.is_some() {
+            player.record_activity(&action, score.0);
+        }
+
+        let snapshot = OwnerSnapshot {
+            range: player.bomb_range(settings.rules.base_bomb_range),
+            max_bombs: player.max_bombs(),
+            team: team.clone(),
+        };
+        let moved_from = *location;
+        let result = apply_action(
+            action,
+            player_name,
+            player_entity,
+            snapshot,
+            locations.iter().map(|(_, l)| *l),
+            &tile_query,
+            &object_query,
+            &mut spawn_bomb_event,
+            &mut location,
+            &mut animation,
+            &mut event_writer,
+        );
+        player.sliding = keeps_sliding(action, moved_from, *location, &tile_cache);
+        if let Err(e) = result {
+            // We downgrade this error to informative as the player is allowed
+            // to attempt impossible things like walking into a wall (We can later
+            // animate these).
+            info!("{}", e);
+            if let Some(player_stats) = handle_inner.and_then(|h| stats.0.get_mut(&h.id)) {
+                player_stats.failed_actions += 1;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Decides whether a failed turn gets a player banned, returning the reason if so. Panics,
+/// traps and running out of fuel are fatal right away, while other errors (such as the
+/// surroundings failing to fit the bot's buffers) only are after `MAX_FAILED_TURNS` turns in
+/// a row. `bomber_lib` only lets errors be told apart by their debug output.
+fn ban_reason(
+    error: &str,
+    panic_message: Option<String>,
+    player: &Player,
+    fuel_consumed_this_turn: u64,
+    fuel_per_tick: u64,
+) -> Option<String> {
+    if let Some(message) = panic_message {
+        Some(format!("Panicked: {}", message))
+    } else if fuel_consumed_this_turn >= player.fuel_budget(fuel_per_tick) {
+        if player.first_turn_done {
+            Some(format!("Ran out of WASM fuel ({} per turn)", fuel_per_tick))
+        } else {
+            Some(format!(
+                "Ran out of WASM fuel ({} on the first turn)",
+                player.fuel_budget(fuel_per_tick)
+            ))
+        }
+    } else if error.to_lowercase().contains("trap") {
+        Some(String::from("Triggered a WASM trap"))
+    } else if player.failed_turns >= MAX_FAILED_TURNS {
+        Some(format!("Triggered a WASM error {} turns in a row", MAX_FAILED_TURNS))
+    } else {
+        None
+    }
+}
+
+/// Checks the action chosen by a player is one it can take at all, as opposed to one that
+/// merely fails, like walking into a wall. Directions need no checking, as anything other
+/// than the four cardinal directions fails to decode and is reported as a WASM error.
+/// Dropping a bomb and moving with no bomb left still moves.
+fn validate_action(action: Action, max_bombs: u32, bombs_placed: usize) -> Result<Action> {
+    let out_of_bombs = bombs_placed >= max_bombs as usize;
+    match action {
+        Action::DropBomb if out_of_bombs => {
+            Err(anyhow!("dropped a bomb with all {} bombs already placed", max_bombs))
+        },
+        Action::DropBombAndMove(direction) if out_of_bombs => {
+            warn!("Dropped a bomb with all {} bombs already placed, only moving.", max_bombs);
+            Ok(Action::Move(direction))
+        },
+        action => Ok(action),
+    }
+}
+
+/// Applies the action chosen by a player, causing an impact on the world or itself.
+#[allow(clippy::too_many_arguments)]
+fn apply_action(
+    action: Action,
+    player_name: &PlayerName,
+    player_entity: Entity,
+    snapshot: OwnerSnapshot,
+    player_locations: impl Iterator<Item = TileLocation>,
+    tile_query: &Query<
+        (&TileLocation, &ExternalCrateComponent<Tile>),
+        (Without<Player>, Without<ExternalCrateComponent<Object>>),
+    >,
+    object_query: &Query<
+        (&TileLocation, &ExternalCrateComponent<Object>),
+        (Without<Player>, Without<ExternalCrateComponent<Tile>>),
+    >,
+    spawn_bomb_event: &mut EventWriter<SpawnBombEvent>,
+    player_location: &mut TileLocation,
+    player_animation: &mut AnimationState,
+    event_writer: &mut EventWriter<PlayerMovedEvent>,
+) -> Result<()> {
+    match action {
+        Action::Move(direction) => {
+            *player_animation = AnimationState::Walking(direction, 0);
+            move_player(
+                player_entity,
+                player_name,
+                player_location,
+                player_locations,
+                direction,
+                tile_query,
+                object_query,
+                event_writer,
+            )?;
+        },
+        Action::StayStill => player_animation.stand_still(),
+        Action::DropBomb => {
+            spawn_bomb_event.send(SpawnBombEvent {
+                location: *player_location,
+                owner: player_entity,
+                snapshot,
+            });
+            player_animation.stand_still();
+        },
+        Action::DropBombAndMove(direction) => {
+            let bomb_location = *player_location;
+            *player_animation = AnimationState::Walking(direction, 0);
+            move_player(
+                player_entity,
+                player_name,
+                player_location,
+                player_locations,
+                direction,
+                tile_query,
+                object_query,
+                event_writer,
+            )?;
+            spawn_bomb_event.send(SpawnBombEvent {
+                location: bomb_location,
+                owner: player_entity,
+                snapshot,
+            });
+        },
+    }
+    Ok(())
+}
+
+/// Direction a player slides in on its next turn, after `action` took it from `moved_from` to
+/// `location`. Players moving onto ice keep going the same way until they leave the ice or
+/// run into something, which leaves them where they were.
+fn keeps_sliding(
+    action: Action,
+    moved_from: TileLocation,
+    location: TileLocation,
+    tile_cache: &TileCache,
+) -> Option<Direction> {
+    if location == moved_from || !tile_cache.is_ice(&location) {
+        return None;
+    }
+    match action {
+        Action::Move(direction) | Action::DropBombAndMove(direction) => Some(direction),
+        Action::StayStill | Action::DropBomb => None,
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn move_player(
+    player_entity: Entity,
+    player_name: &PlayerName,
+    player_location: &mut TileLocation,
+    player_locations: impl Iterator<Item = TileLocation>,
+    direction: Direction,
+    tile_query: &Query<
+        (&TileLocation, &ExternalCrateComponent<Tile>),
+        (Without<Player>, Without<ExternalCrateComponent<Object>>),
+    >,
+    object_query: &Query<
+        (&TileLocation, &ExternalCrateComponent<Object>),
+        (Without<Player>, Without<ExternalCrateComponent<Tile>>),
+    >,
+    event_writer: &mut EventWriter<PlayerMovedEvent>,
+) -> Result<()> {
+    let PlayerName(player_name) = player_name;
+
+    let target_location = (*player_location + direction)
+        .ok_or_else(|| anyhow!("Invalid target location ({})", player_name))?;
+    let target_tile = tile_query
+        .iter()
+        .find_map(|(l, t)| (*l == target_location).then_some(t))
+        .ok_or_else(|| anyhow!("No tile at target location ({})", player_name))?;
+    let solid_objects_on_tile =
+        object_query.iter().filter(|(l, o)| (*l == &target_location && o.is_solid())).count();
+    let players_on_target_tile = player_locations.filter(|l| *l == target_location).count();
+
+    match **target_tile {
+        Tile::Floor | Tile::Hill if solid_objects_on_tile + players_on_target_tile == 0 => {
+            info!("{} moves to {}", player_name, target_location);
+            event_writer.send(PlayerMovedEvent {
+                entity: player_entity,
+                from: *player_location,
+                to: target_location,
+            });
+            *player_location = target_location;
+            Ok(())
+        },
+        _ => Err(anyhow!("Can't move to target tile ({})", player_name)),
+    }
+}
+
+/// Drops the farthest tiles of the surroundings until they fit in `buffer_size` bytes once
+/// encoded, rather than failing the turn. The player's own tile is always kept, and the rest
+/// stay in [`surroundings_order`]. Returns how many tiles were dropped.
+fn truncate_surroundings(
+    surroundings: &mut Vec<(Tile, Option<Object>, Option<Enemy>, TileOffset)>,
+    buffer_size: u64,
+) -> Result<usize> {
+    let mut size = bincode::serialized_size(&*surroundings)?;
+    if size <= buffer_size {
+        return Ok(0);
+    }
+    let original_length = surroundings.len();
+    surroundings.sort_by_key(|(.., offset)| offset.taxicab_distance());
+    while size > buffer_size && surroundings.len() > 1 {
+        if let Some(farthest) = surroundings.pop() {
+            size -= bincode::serialized_size(&farthest)?;
+        }
+    }
+    surroundings.sort_by_key(|(.., offset)| surroundings_order(offset));
+    Ok(original_length - surroundings.len())
+}
+
+/// How a player looks to their enemies, with its registered team.
+fn as_enemy(name: &PlayerName, team: &Team, score: &Score) -> Enemy {
+    Enemy { name: name.0.clone(), team_name: team.name.clone(), score: score.0 }
+}
+
+/// Builds the view of the world a player is given to decide its next action. This is shared
+/// by `.wasm` and native players alike. Tiles are in [`surroundings_order`] rather than the
+/// order of the query, which can change from one tick to the next.
+fn player_surroundings(
+    player_location: &TileLocation,
+    tile_query: &Query<
+        (&TileLocation, &ExternalCrateComponent<Tile>),
+        (Without<Player>, Without<ExternalCrateComponent<Object>>),
+    >,
+    object_query: &Query<
+        (&TileLocation, &ExternalCrateComponent<Object>),
+        (Without<Player>, Without<ExternalCrateComponent<Tile>>),
+    >,
+    enemies: &[(&Enemy, TileLocation)],
+    player: &Player,
+    base_vision: u32,
+) -> Vec<(Tile, Option<Object>, Option<Enemy>, TileOffset)> {
+    let view_distance = view_distance(player, base_vision);
+    let mut surroundings = tile_query
+        .iter()
+        .filter_map(|(location, tile)| {
+            let object_on_tile =
+                object_query.iter().find_map(|(l, o)| (l == location).then_some(o));
+            let enemy_on_tile = enemies.iter().find_map(|(e, l)| (l == location).then_some(*e));
+            ((*location - *player_location).taxicab_distance() <= view_distance).then_some({
+                (
+                    **tile,
+                    object_on_tile.map(|o| **o),
+                    enemy_on_tile.cloned(),
+                    (*location - *player_location),
+                )
+            })
+        })
+        .collect::<Vec<_>>();
+    surroundings.sort_by_key(|(.., offset)| surroundings_order(offset));
+    surroundings
+}
+
+/// How far a player sees, in taxicab distance.
+fn view_distance(player: &Player, base_vision: u32) -> u32 {
+    base_vision + player.power_ups.get(&PowerUp::VisionRange).copied().unwrap_or_default()
+}
+
+/// Which way the nearest hill is from a player that can't see any, along the axis it's
+/// farthest on (north or south on a tie). Hills in sight tell players more than this would.
+fn hill_hint(
+    location: TileLocation,
+    tile_cache: &TileCache,
+    view_distance: u32,
+) -> Option<Direction> {
+    let TileOffset(x, y) = tile_cache
+        .hills()
+        .map(|hill| hill - location)
+        .min_by_key(|offset| (offset.taxicab_distance(), surroundings_order(offset)))
+        .filter(|offset| offset.taxicab_distance() > view_distance)?;
+    Some(match (y.abs() >= x.abs(), x > 0, y > 0) {
+        (true, _, true) => Direction::North,
+        (true, _, false) => Direction::South,
+        (false, true, _) => Direction::East,
+        (false, false, _) => Direction::West,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::SystemState;
+
+    use super::*;
+
+    #[test]
+    fn dropping_a_bomb_without_bombs_left_is_invalid() {
+        assert!(validate_action(Action::DropBomb, 1, 1).is_err());
+        assert!(matches!(validate_action(Action::DropBomb, 2, 1), Ok(Action::DropBomb)));
+    }
+
+    #[test]
+    fn dropping_a_bomb_and_moving_without_bombs_left_still_moves() {
+        assert!(matches!(
+            validate_action(Action::DropBombAndMove(Direction::West), 1, 1),
+            Ok(Action::Move(Direction::West))
+        ));
+        assert!(matches!(
+            validate_action(Action::DropBombAndMove(Direction::West), 2, 1),
+            Ok(Action::DropBombAndMove(Direction::West))
+        ));
+    }
+
+    #[test]
+    fn other_actions_are_valid() {
+        assert!(matches!(validate_action(Action::StayStill, 1, 1), Ok(Action::StayStill)));
+        assert!(matches!(
+            validate_action(Action::Move(Direction::North), 1, 1),
+            Ok(Action::Move(Direction::North))
+        ));
+    }
+
+    fn entities(count: u32) -> Vec<Entity> {
+        (0..count).map(Entity::from_raw).collect()
+    }
+
+    /// The players as their enemies see them, all with the given score.
+    fn as_players(entities: &[Entity], score: u32) -> impl Iterator<Item = (Entity, Enemy)> + '_ {
+        entities.iter().map(move |&entity| {
+            let name = PlayerName(format!("player {}", entity.id()));
+            let team = Team { name: "team".into(), color: Color::WHITE };
+            (entity, as_enemy(&name, &team, &Score(score)))
+        })
+    }
+
+    /// Runs frames with no tick until the actions are applied, returning who decided on each
+    /// frame and the actions applied.
+    fn run_turn(progress: &mut TurnProgress) -> (Vec<Vec<Entity>>, Vec<(Entity, Action)>) {
+        let mut deciding_per_frame = vec![];
+        loop {
+            let finish_now = progress.start_frame(TurnPhase::Idle, std::iter::empty());
+            let deciding = progress.take_deciding(finish_now);
+            for &player_entity in &deciding {
+                progress.decided(player_entity, Action::StayStill);
+            }
+            deciding_per_frame.push(deciding);
+            let actions = progress.take_actions();
+            if !actions.is_empty() {
+                return (deciding_per_frame, actions);
+            }
+            assert!(deciding_per_frame.len() < 10, "the turn never finished");
+        }
+    }
+
+    #[test]
+    fn every_player_decides_once_per_player_tick() {
+        let players = entities(7);
+        let mut progress = TurnProgress::default();
+        assert!(!progress.start_frame(TurnPhase::Player, as_players(&players, 0)));
+        for _ in 0..2 {
+            let (deciding_per_frame, actions) = run_turn(&mut progress);
+            assert_eq!(deciding_per_frame.iter().map(Vec::len).collect::<Vec<_>>(), [3, 3, 1]);
+            assert_eq!(deciding_per_frame.concat(), players);
+            assert_eq!(actions.iter().map(|(e, _)| *e).collect::<Vec<_>>(), players);
+            assert!(matches!(progress, TurnProgress::Idle));
+
+            assert!(!progress.start_frame(TurnPhase::World, as_players(&players, 0)));
+            assert!(progress.take_deciding(false).is_empty());
+            assert!(progress.take_actions().is_empty());
+            assert!(!progress.start_frame(TurnPhase::Player, as_players(&players, 0)));
+        }
+    }
+
+    #[test]
+    fn actions_are_applied_together_once_everyone_decided() {
+        let players = entities(4);
+        let mut progress = TurnProgress::default();
+        progress.start_frame(TurnPhase::Player, as_players(&players, 0));
+        for player_entity in progress.take_deciding(false) {
+            progress.decided(player_entity, Action::StayStill);
+        }
+        assert!(progress.take_actions().is_empty());
+        for player_entity in progress.take_deciding(false) {
+            progress.decided(player_entity, Action::StayStill);
+        }
+        assert_eq!(progress.take_actions().len(), 4);
+        assert!(progress.take_actions().is_empty());
+    }
+
+    #[test]
+    fn next_tick_makes_remaining_players_decide_right_away() {
+        let players = entities(7);
+        let mut progress = TurnProgress::default();
+        progress.start_frame(TurnPhase::Player, as_players(&players, 0));
+        let first = progress.take_deciding(false);
+        assert_eq!(first.len(), 3);
+
+        assert!(progress.start_frame(TurnPhase::World, as_players(&players, 0)));
+        let rest = progress.take_deciding(true);
+        assert_eq!([first, rest].concat(), players);
+    }
+
+    #[test]
+    fn enemies_are_seen_as_they_were_at_the_player_tick() {
+        let players = entities(4);
+        let mut progress = TurnProgress::default();
+        progress.start_frame(TurnPhase::Player, as_players(&players, 5));
+        for _ in 0..2 {
+            // Scores go up while players decide, such as from crates destroyed.
+            let finish_now = progress.start_frame(TurnPhase::Idle, as_players(&players, 9));
+            assert!(!progress.take_deciding(finish_now).is_empty());
+            for &entity in &players {
+                let enemy = progress.enemy(entity).unwrap();
+                assert_eq!(
+                    (enemy.name.as_str(), enemy.score),
+                    (format!("player {}", entity.id()).as_str(), 5)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn enemies_show_their_registered_team() {
+        let name = PlayerName("alice".into());
+        let team = Team { name: "Registered".into(), color: Color::RED };
+        let enemy = as_enemy(&name, &team, &Score(12));
+        assert_eq!(
+            (enemy.name.as_str(), enemy.team_name.as_str(), enemy.score),
+            ("alice", "Registered", 12)
+        );
+    }
+
+    #[test]
+    fn surroundings_that_fit_are_unchanged() {
+        let mut truncated = surroundings();
+        let size = bincode::serialized_size(&truncated).unwrap();
+        assert_eq!(truncate_surroundings(&mut truncated, size).unwrap(), 0);
+        assert_eq!(offsets(&truncated), offsets(&surroundings()));
+    }
+
+    #[test]
+    fn surroundings_are_truncated_to_the_closest_tiles_in_order() {
+        let mut truncated = surroundings();
+        assert_eq!(truncate_surroundings(&mut truncated, size_of(5)).unwrap(), 8);
+        assert_eq!(offsets(&truncated), vec![(0, -1), (-1, 0), (0, 0), (1, 0), (0, 1)]);
+    }
+
+    #[test]
+    fn own_tile_is_kept_however_small_the_buffer() {
+        let mut truncated = surroundings();
+        assert_eq!(truncate_surroundings(&mut truncated, 0).unwrap(), 12);
+        assert_eq!(offsets(&truncated), vec![(0, 0)]);
+    }
+
+    /// Hint given to a player at `location` on the map, seeing `view_distance` tiles away.
+    fn hint(map: &'static str, location: TileLocation, view_distance: u32) -> Option<Direction> {
+        let (_, rows) = GameMap::parse("test", map).unwrap();
+        hill_hint(location, &TileCache::from_rows(&rows), view_distance)
+    }
+
+    #[test]
+    fn hill_hint_points_along_the_farthest_axis() {
+        let map = "s......\n.......\n......~";
+        assert!(matches!(hint(map, TileLocation(0, 2), 4), Some(Direction::East)));
+        let map = "~......\n.......\n.......\n.......\n.......\n.......\ns......";
+        assert!(matches!(hint(map, TileLocation(1, 0), 4), Some(Direction::North)));
+        assert!(matches!(hint(map, TileLocation(6, 5), 4), Some(Direction::West)));
+    }
+
+    #[test]
+    fn hill_hint_ties_go_north_or_south() {
+        let map = "...~\n....\n....\ns...";
+        assert!(matches!(hint(map, TileLocation(0, 0), 4), Some(Direction::North)));
+        let map = "...s\n....\n....\n~...";
+        assert!(matches!(hint(map, TileLocation(3, 3), 4), Some(Direction::South)));
+    }
+
+    #[test]
+    fn hill_hint_points_to_the_nearest_hill() {
+        let map = "~.....s.~";
+        assert!(matches!(hint(map, TileLocation(6, 0), 1), Some(Direction::East)));
+        assert!(matches!(hint(map, TileLocation(2, 0), 1), Some(Direction::West)));
+    }
+
+    #[test]
+    fn no_hill_hint_with_a_hill_in_sight() {
+        let map = "...~\n....\n....\ns...";
+        assert!(hint(map, TileLocation(0, 0), 6).is_none());
+        assert!(hint(map, TileLocation(0, 0), 5).is_some());
+        assert!(hint("s......", TileLocation(0, 0), 4).is_none());
+    }
+
+    type TileQuery<'w, 's> = Query<
+        'w,
+        's,
+        (&'static TileLocation, &'static ExternalCrateComponent<Tile>),
+        (Without<Player>, Without<ExternalCrateComponent<Object>>),
+    >;
+    type ObjectQuery<'w, 's> = Query<
+        'w,
+        's,
+        (&'static TileLocation, &'static ExternalCrateComponent<Object>),
+        (Without<Player>, Without<ExternalCrateComponent<Tile>>),
+    >;
+
+    /// A 5 by 5 floor with a crate, its tiles spawned in the given order.
+    fn floor_world(locations: impl Iterator<Item = TileLocation>) -> World {
+        let mut world = World::new();
+        for location in locations {
+            world.spawn().insert(location).insert(ExternalCrateComponent(Tile::Floor));
+        }
+        world.spawn().insert(TileLocation(3, 2)).insert(ExternalCrateComponent(Object::Crate));
+        world
+    }
+
+    /// Surroundings of a player in the middle of the world, as they're handed to bots.
+    fn serialized_surroundings(world: &mut World) -> Vec<u8> {
+        let enemy = Enemy { name: "enemy".into(), team_name: "team".into(), score: 3 };
+        let mut state = SystemState::<(TileQuery, ObjectQuery)>::new(world);
+        let (tile_query, object_query) = state.get(world);
+        let surroundings = player_surroundings(
+            &TileLocation(2, 2),
+            &tile_query,
+            &object_query,
+            &[(&enemy, TileLocation(1, 2))],
+            &Player::default(),
+            2,
+        );
+        let offsets = surroundings.iter().map(|(.., offset)| *offset).collect::<Vec<_>>();
+        assert_eq!(offsets.len(), 13);
+        assert!(offsets.windows(2).all(|w| surroundings_order(&w[0]) < surroundings_order(&w[1])));
+        bincode::serialize(&surroundings).unwrap()
+    }
+
+    #[test]
+    fn identical_worlds_give_identical_surroundings() {
+        let locations = (0..5).flat_map(|y| (0..5).map(move |x| TileLocation(x, y)));
+        let mut in_order = floor_world(locations.clone());
+        let mut reversed = floor_world(locations.clone().collect::<Vec<_>>().into_iter().rev());
+        // Despawning and respawning a row of tiles moves it within the query iteration too.
+        let mut shuffled = floor_world(locations);
+        let row = shuffled
+            .query_filtered::<(Entity, &TileLocation), With<ExternalCrateComponent<Tile>>>()
+            .iter(&shuffled)
+            .filter_map(|(entity, location)| (location.1 == 2).then_some(entity))
+            .collect::<Vec<_>>();
+        for entity in row {
+            shuffled.despawn(entity);
+        }
+        for x in (0..5).rev() {
+            shuffled.spawn().insert(TileLocation(x, 2)).insert(ExternalCrateComponent(Tile::Floor));
+        }
+
+        let expected = serialized_surroundings(&mut in_order);
+        assert_eq!(serialized_surroundings(&mut in_order), expected);
+        assert_eq!(serialized_surroundings(&mut reversed), expected);
+        assert_eq!(serialized_surroundings(&mut shuffled), expected);
+    }
+
+    /// Where a player moving from `start` ends up after each turn, as it slides along the ice
+    /// of `map` with other players standing at `others`.
+    fn slide(
+        map: &str,
+        start: TileLocation,
+        direction: Direction,
+        others: &[TileLocation],
+    ) -> Vec<TileLocation> {
+        let (_, rows) = GameMap::parse("test", map).unwrap();
+        let tile_cache = TileCache::from_rows(&rows);
+        let mut world = World::new();
+        for (y, row) in rows.iter().enumerate() {
+            for (x, spec) in row.iter().enumerate() {
+                world.spawn().insert(TileLocation(x, y)).insert(ExternalCrateComponent(spec.tile));
+                if spec.object.is_some() {
+                    world
+                        .spawn()
+                        .insert(TileLocation(x, y))
+                        .insert(ExternalCrateComponent(Object::Crate));
+                }
+            }
+        }
+        world.insert_resource(Events::<PlayerMovedEvent>::default());
+        let mut state =
+            SystemState::<(TileQuery, ObjectQuery, EventWriter<PlayerMovedEvent>)>::new(&mut world);
+        let (tile_query, object_query, mut event_writer) = state.get_mut(&mut world);
+
+        let (mut location, mut direction) = (start, direction);
+        let mut path = vec![];
+        loop {
+            let moved_from = location;
+            let _ = move_player(
+                Entity::from_raw(0),
+                &PlayerName("slider".into()),
+                &mut location,
+                others.iter().copied(),
+                direction,
+                &tile_query,
+                &object_query,
+                &mut event_writer,
+            );
+            path.push(location);
+            match keeps_sliding(Action::Move(direction), moved_from, location, &tile_cache) {
+                Some(next) => direction = next,
+                None => return path,
+            }
+            assert!(path.len() < 10, "slid forever");
+        }
+    }
+
+    #[test]
+    fn sliding_ends_off_the_ice() {
+        let path = slide("s.iii..", TileLocation(1, 0), Direction::East, &[]);
+        assert_eq!(
+            path,
+            vec![TileLocation(2, 0), TileLocation(3, 0), TileLocation(4, 0), TileLocation(5, 0)]
+        );
+    }
+
+    #[test]
+    fn sliding_ends_against_a_wall() {
+        let path = slide("s.iii#.", TileLocation(1, 0), Direction::East, &[]);
+        assert_eq!(
+            path,
+            vec![TileLocation(2, 0), TileLocation(3, 0), TileLocation(4, 0), TileLocation(4, 0)]
+        );
+    }
+
+    #[test]
+    fn sliding_ends_against_a_crate() {
+        let path = slide("s.iiic.", TileLocation(1, 0), Direction::East, &[]);
+        assert_eq!(
+            path,
+            vec![TileLocation(2, 0), TileLocation(3, 0), TileLocation(4, 0), TileLocation(4, 0)]
+        );
+    }
+
+    #[test]
+    fn sliding_ends_against_another_player() {
+        let path = slide("s.iiii.", TileLocation(1, 0), Direction::East, &[TileLocation(5, 0)]);
+        assert_eq!(
+            path,
+            vec![TileLocation(2, 0), TileLocation(3, 0), TileLocation(4, 0), TileLocation(4, 0)]
+        );
+    }
+
+    #[test]
+    fn standing_still_on_ice_does_not_slide() {
+        let (_, rows) = GameMap::parse("test", "s.iii..").unwrap();
+        let tile_cache = TileCache::from_rows(&rows);
+        let on_ice = TileLocation(3, 0);
+        assert_eq!(keeps_sliding(Action::StayStill, on_ice, on_ice, &tile_cache), None);
+        assert_eq!(keeps_sliding(Action::DropBomb, on_ice, on_ice, &tile_cache), None);
+        assert_eq!(
+            keeps_sliding(
+                Action::DropBombAndMove(Direction::East),
+                TileLocation(2, 0),
+                on_ice,
+                &tile_cache
+            ),
+            Some(Direction::East)
+        );
+    }
+}
@@ -0,0 +1,123 @@
+//! Limits on what bots may call themselves and how much they may compute, shared by the
+//! game and the `check-round` command.
+
+use unicode_segmentation::UnicodeSegmentation;
+
+pub const MAX_NAME_LENGTH: usize = 10;
+pub const MAX_TEAM_NAME_LENGTH: usize = 20;
+pub const DEFAULT_PLAYER_NAME: &str = "Trickster";
+pub const DEFAULT_TEAM_NAME: &str = "Team ???";
+/// The first turn after a `.wasm` module is instantiated (on spawn or live reload) gets this
+/// many times the regular fuel budget, so bots can afford expensive lazy initialization such
+/// as building lookup tables. From the second turn onwards the regular budget applies.
+pub const FIRST_TURN_FUEL_MULTIPLIER: u64 = 10;
+
+/// Cleans up a name chosen by a bot so it displays well: control and format characters (such
+/// as bidi overrides, which could reverse how a name reads) are dropped, whitespace runs
+/// (newlines included) collapse into single spaces, and the name is truncated to `length`
+/// graphemes so multi codepoint emoji stay whole. Falls back to `default` if nothing is left.
+pub fn filter_name(name: &str, length: usize, default: &str) -> String {
+    let cleaned = name
+        .split_whitespace()
+        .map(|word| word.chars().filter(|c| !c.is_control() && !is_format(*c)).collect::<String>())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ");
+    let truncated = cleaned.graphemes(true).take(length).collect::<String>();
+    match truncated.trim_end() {
+        "" => default.to_owned(),
+        name => name.to_owned(),
+    }
+}
+
+/// Whether `c` is in the Unicode `Cf` (format) category, except for the zero width joiner that
+/// holds emoji sequences together.
+fn is_format(c: char) -> bool {
+    matches!(c,
+        '\u{AD}'
+        | '\u{600}'..='\u{605}'
+        | '\u{61C}'
+        | '\u{6DD}'
+        | '\u{70F}'
+        | '\u{890}'..='\u{891}'
+        | '\u{8E2}'
+        | '\u{180E}'
+        | '\u{200B}'..='\u{200C}'
+        | '\u{200E}'..='\u{200F}'
+        | '\u{202A}'..='\u{202E}'
+        | '\u{2060}'..='\u{2064}'
+        | '\u{2066}'..='\u{206F}'
+        | '\u{FEFF}'
+        | '\u{FFF9}'..='\u{FFFB}'
+        | '\u{110BD}'
+        | '\u{110CD}'
+        | '\u{13430}'..='\u{1343F}'
+        | '\u{1BCA0}'..='\u{1BCA3}'
+        | '\u{1D173}'..='\u{1D17A}'
+        | '\u{E0001}'
+        | '\u{E0020}'..='\u{E007F}'
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bidi_characters_are_dropped() {
+        assert_eq!(filter_name("\u{202E}olleh", MAX_NAME_LENGTH, DEFAULT_PLAYER_NAME), "olleh");
+        assert_eq!(filter_name("a\u{2066}b\u{2069}c", MAX_NAME_LENGTH, DEFAULT_PLAYER_NAME), "abc");
+        assert_eq!(
+            filter_name("\u{200F}\u{FEFF}", MAX_NAME_LENGTH, DEFAULT_PLAYER_NAME),
+            "Trickster"
+        );
+    }
+
+    #[test]
+    fn control_characters_and_whitespace_are_cleaned_up() {
+        assert_eq!(
+            filter_name("a\u{7}b\n\n  c\td", MAX_NAME_LENGTH, DEFAULT_PLAYER_NAME),
+            "ab c d"
+        );
+        assert_eq!(filter_name("\u{1B}[31m", MAX_NAME_LENGTH, DEFAULT_PLAYER_NAME), "[31m");
+        assert_eq!(filter_name("  ", MAX_NAME_LENGTH, DEFAULT_PLAYER_NAME), "Trickster");
+    }
+
+    #[test]
+    fn emoji_sequences_stay_whole() {
+        let family = "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}";
+        assert_eq!(
+            filter_name(&format!("{family}abc"), 2, DEFAULT_PLAYER_NAME),
+            format!("{family}a")
+        );
+    }
+
+    #[test]
+    fn right_to_left_names_keep_their_letters() {
+        // Vowel marks are part of the letter they sit on, so they count as one grapheme and
+        // are kept or dropped along with it.
+        let arabic = "مُحَمَّد بن عبد الله الخوارزمي";
+        let truncated = filter_name(arabic, MAX_NAME_LENGTH, DEFAULT_PLAYER_NAME);
+        assert_eq!(truncated, "مُحَمَّد بن عب");
+        assert_eq!(truncated.graphemes(true).count(), MAX_NAME_LENGTH);
+        assert!(arabic.starts_with(&truncated));
+
+        let hebrew = "שָׁלוֹם עֲלֵיכֶם חֲבֵרִים";
+        let truncated = filter_name(hebrew, MAX_NAME_LENGTH, DEFAULT_PLAYER_NAME);
+        assert_eq!(truncated, "שָׁלוֹם עֲלֵיכֶם");
+        assert_eq!(truncated.graphemes(true).count(), MAX_NAME_LENGTH);
+    }
+
+    #[test]
+    fn very_long_names_are_truncated() {
+        let long = "a".repeat(1000);
+        assert_eq!(
+            filter_name(&long, MAX_NAME_LENGTH, DEFAULT_PLAYER_NAME),
+            "a".repeat(MAX_NAME_LENGTH)
+        );
+        let long = "ab \n".repeat(250);
+        assert_eq!(filter_name(&long, MAX_NAME_LENGTH, DEFAULT_PLAYER_NAME), "ab ab ab a");
+        let long = "\u{202E}".repeat(1000);
+        assert_eq!(filter_name(&long, MAX_TEAM_NAME_LENGTH, DEFAULT_TEAM_NAME), "Team ???");
+    }
+}
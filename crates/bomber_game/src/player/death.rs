@@ -0,0 +1,455 @@
+//! Takes players out of the arena when they die or get banned, leaving a fading sign
+//! behind, and shows a ghost where dead players are expected to respawn.
+
+use std::time::Duration;
+
+use anyhow::Result;
+use bevy::{asset::HandleId, prelude::*, utils::HashSet};
+use bomber_lib::world::{Object, Ticks};
+
+use super::{
+    spawn::{available_spawn_locations, choose_spawner},
+    KillPlayerEvent, PendingDeaths, Player, PlayerDespawnedEvent, PlayerName, RecentSpawners,
+    SpawnPlayerEvent, Team,
+};
+use crate::{
+    audio::SoundEffects,
+    game_map::{GameMap, PlayerSpawner, TileCache, TileLocation},
+    object::FlameMarker,
+    player_hotswap::{PlayerHandle, PlayerHandles, WasmPlayerAsset},
+    rendering::{
+        PLAYER_HEIGHT_PX, PLAYER_VERTICAL_OFFSET_PX, PLAYER_WIDTH_PX, PLAYER_Z, SKELETON_HEIGHT_PX,
+        SKELETON_WIDTH_PX,
+    },
+    score::Score,
+    settings::GameSettings,
+    state::MatchRng,
+    ExternalCrateComponent,
+};
+
+/// Visual representation of a dead player
+#[derive(Component)]
+pub(super) struct Skeleton(pub Timer);
+/// Translucent preview of where a dead player is expected to respawn, with a countdown.
+#[derive(Component)]
+pub(super) struct RespawnGhost(HandleId);
+#[derive(Component)]
+pub(super) struct RespawnCountdownMarker;
+/// Visual representation of a banned player
+#[derive(Component)]
+pub(super) struct BanSign(pub Timer);
+/// It's OK to use seconds rather than ticks for the skeleton and ban sign as it's just a
+/// visual representation for fun.
+const SKELETON_DURATION: Duration = Duration::from_secs(3);
+const BAN_SIGN_DURATION: Duration = Duration::from_secs(3);
+const RESPAWN_GHOST_ALPHA: f32 = 0.35;
+
+/// Spawns a translucent copy of a dead player, which `respawn_ghost_system` keeps on the
+/// spawner the player is expected to come back from.
+fn spawn_respawn_ghost(
+    handle: HandleId,
+    respawn_ticks: u32,
+    team: &Team,
+    transform: Transform,
+    asset_server: &AssetServer,
+    texture_atlases: &mut Assets<TextureAtlas>,
+    commands: &mut Commands,
+) {
+    let texture_handle = asset_server.load("graphics/Sprites/Bomberman/sheet.png");
+    let texture_atlas = TextureAtlas::from_grid(texture_handle, Vec2::new(21.0, 32.0), 5, 4);
+    let texture_atlas_handle = texture_atlases.add(texture_atlas);
+    let mut color = team.color;
+    color.set_a(RESPAWN_GHOST_ALPHA);
+
+    commands
+        .spawn()
+        .insert(RespawnGhost(handle))
+        .insert_bundle(SpriteSheetBundle {
+            sprite: TextureAtlasSprite {
+                index: 2,
+                color,
+                custom_size: Some(Vec2::new(PLAYER_WIDTH_PX, PLAYER_HEIGHT_PX)),
+                ..Default::default()
+            },
+            texture_atlas: texture_atlas_handle,
+            transform,
+            ..default()
+        })
+        .with_children(|p| {
+            p.spawn()
+                .insert_bundle(Text2dBundle {
+                    text: Text::from_section(
+                        respawn_ticks.to_string(),
+                        TextStyle {
+                            font: asset_server.load("fonts/space_mono_400.ttf"),
+                            font_size: 32.0,
+                            color: Color::WHITE,
+                        },
+                    )
+                    .with_alignment(TextAlignment {
+                        vertical: VerticalAlign::Center,
+                        horizontal: HorizontalAlign::Center,
+                    }),
+                    transform: Transform::from_translation(Vec3::new(0.0, 0.0, 1.0)),
+                    ..Default::default()
+                })
+                .insert(RespawnCountdownMarker);
+        });
+}
+
+/// If a player "misbehaves" at any point after being spawned (such as by reserving too
+/// much memory or spending too much wasm fuel) they will be removed from the game with
+/// a visual to represent it, so that the team are made aware there is an issue they
+/// need to fix.
+pub(super) fn player_ban_system(
+    mut commands: Commands,
+    player_query: Query<
+        (Entity, &Transform, &PlayerName, &Score, &Handle<WasmPlayerAsset>),
+        With<Player>,
+    >,
+    asset_server: Res<AssetServer>,
+    mut handles: ResMut<PlayerHandles>,
+    mut despawn_event: EventWriter<PlayerDespawnedEvent>,
+    mut pending_deaths: ResMut<PendingDeaths>,
+) {
+    for (entity, transform, name, score, handle_inner) in player_query.iter() {
+        if let Some(PlayerHandle::Misbehaved(_, reason)) =
+            handles.0.iter_mut().find(|h| h.inner().id == handle_inner.id)
+        {
+            info!("{} has been forciby despawned (banned)!", name.0);
+            pending_deaths.0.insert(entity);
+            despawn_event.send(PlayerDespawnedEvent(
+                name.clone(),
+                *score,
+                reason.clone(),
+                Some(handle_inner.id),
+            ));
+
+            commands.entity(entity).despawn_recursive();
+            let texture_handle = asset_server.load("graphics/Sprites/Bomberman/Front/Cross.png");
+            commands
+                .spawn()
+                .insert_bundle(SpriteBundle {
+                    texture: texture_handle,
+                    transform: *transform,
+                    sprite: Sprite {
+                        custom_size: Some(Vec2::new(SKELETON_WIDTH_PX, SKELETON_HEIGHT_PX)),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                })
+                .insert(BanSign(Timer::new(BAN_SIGN_DURATION, false)));
+        }
+    }
+}
+
+/// Takes `.wasm` players that stood still without scoring for too long out of the arena
+/// (`IDLE_KICK_TICKS`). Unlike a ban, their handle goes back to the end of the queue, so
+/// players waiting for a spot spawn first and the idle player returns once there's room.
+pub(super) fn player_idle_kick_system(
+    mut commands: Commands,
+    player_query: Query<(Entity, &Player, &PlayerName, &Score, &Handle<WasmPlayerAsset>)>,
+    settings: Res<GameSettings>,
+    mut handles: ResMut<PlayerHandles>,
+    mut despawn_event: EventWriter<PlayerDespawnedEvent>,
+    mut pending_deaths: ResMut<PendingDeaths>,
+) {
+    let idle_kick_ticks = settings.rules.idle_kick_ticks;
+    if idle_kick_ticks == 0 {
+        return;
+    }
+    for (entity, player, name, score, handle_inner) in player_query.iter() {
+        if player.idle_turns < idle_kick_ticks {
+            continue;
+        }
+        let position = match handles.0.iter().position(|h| h.inner().id == handle_inner.id) {
+            Some(position) if handles.0[position].is_ready_to_spawn() => position,
+            // Banned players are taken care of by `player_ban_system`.
+            _ => continue,
+        };
+        info!(
+            "{} has been idle for {} turns, sending it back to the queue.",
+            name.0, idle_kick_ticks
+        );
+        let handle = handles.0.remove(position);
+        handles.0.push(handle);
+        pending_deaths.0.insert(entity);
+        despawn_event.send(PlayerDespawnedEvent(
+            name.clone(),
+            *score,
+            format!("Idle for {} turns", idle_kick_ticks),
+            Some(handle_inner.id),
+        ));
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+pub(super) fn player_death_system(
+    mut kill_events: EventReader<KillPlayerEvent>,
+    mut despawn_event: EventWriter<PlayerDespawnedEvent>,
+    mut commands: Commands,
+    player_query: Query<(&Transform, &Team, Option<&Handle<WasmPlayerAsset>>), With<Player>>,
+    asset_server: Res<AssetServer>,
+    mut handles: ResMut<PlayerHandles>,
+    mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+    audio: Res<Audio>,
+    sound_effects: Res<SoundEffects>,
+    settings: Res<GameSettings>,
+    mut pending_deaths: ResMut<PendingDeaths>,
+) {
+    // Players stay pending until their despawn has been applied, as they could otherwise
+    // still act on the frame they are despawned in.
+    pending_deaths.0.retain(|entity| player_query.get(*entity).is_ok());
+    // Despawning is deferred, so a player can be reported more than once before it's gone,
+    // e.g. when standing in the flames of two bombs. Each player only dies once.
+    let mut killed = HashSet::default();
+    for KillPlayerEvent(entity, name, score) in kill_events.iter() {
+        if !killed.insert(*entity) {
+            continue;
+        }
+        let (transform, team, handle) = match player_query.get(*entity) {
+            Ok(player) => player,
+            Err(_) => {
+                debug!("{} was killed, but had already been despawned.", name.0);
+                continue;
+            },
+        };
+        // The handle will be picked up and the player will be automatically respawned with
+        // fresh `wasm` state.
+        info!("{} has died!", name.0);
+
+        audio.play(sound_effects.death.clone());
+        despawn_event.send(PlayerDespawnedEvent(
+            name.clone(),
+            *score,
+            "Killed by a bomb".into(),
+            handle.map(|h| h.id),
+        ));
+        commands.entity(*entity).despawn_recursive();
+        let texture_handle = asset_server.load("graphics/Sprites/Bomberman/Front/Dead.png");
+        commands
+            .spawn()
+            .insert_bundle(SpriteBundle {
+                texture: texture_handle,
+                transform: *transform,
+                sprite: Sprite {
+                    custom_size: Some(Vec2::new(SKELETON_WIDTH_PX, SKELETON_HEIGHT_PX)),
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .insert(Skeleton(Timer::new(SKELETON_DURATION, false)));
+
+        // Native players have no handle, so they stay dead until summoned again.
+        if let Some(handle) =
+            handle.and_then(|handle| handles.0.iter_mut().find(|h| h.inner().id == handle.id))
+        {
+            let respawn_ticks = settings.rules.respawn_ticks;
+            *handle = PlayerHandle::Respawning(handle.inner().clone(), Ticks(respawn_ticks));
+            spawn_respawn_ghost(
+                handle.inner().id,
+                respawn_ticks,
+                team,
+                *transform,
+                &asset_server,
+                &mut texture_atlases,
+                &mut commands,
+            );
+        }
+    }
+}
+
+/// Keeps each respawn ghost on the spawner its player is expected to come back from, using
+/// the same spawner order as `player_spawn_system`, and despawns it once the player is back
+/// (or won't be coming back at all). Players pick at random among the best few spawners, so
+/// they may come back on a neighbouring one.
+pub(super) fn respawn_ghost_system(
+    mut commands: Commands,
+    mut spawn_events: EventReader<SpawnPlayerEvent>,
+    handles: Res<PlayerHandles>,
+    game_map_query: Query<&GameMap>,
+    mut ghost_query: Query<(Entity, &RespawnGhost, &mut Transform, &Children)>,
+    mut countdown_query: Query<&mut Text, With<RespawnCountdownMarker>>,
+    player_query: Query<&TileLocation, With<Player>>,
+    spawner_query: Query<&TileLocation, With<PlayerSpawner>>,
+    object_query: Query<(&TileLocation, &ExternalCrateComponent<Object>)>,
+    tile_cache: Res<TileCache>,
+    flame_query: Query<&TileLocation, With<FlameMarker>>,
+    match_rng: Res<MatchRng>,
+    recent_spawners: Res<RecentSpawners>,
+) {
+    let game_map = game_map_query.single();
+    let spawned =
+        spawn_events.iter().filter_map(|SpawnPlayerEvent(_, h, _)| *h).collect::<Vec<_>>();
+
+    let player_locations = player_query.iter().copied().collect::<Vec<_>>();
+    let mut available = available_spawn_locations(
+        &spawner_query,
+        &object_query,
+        &tile_cache,
+        game_map,
+        &flame_query,
+        &player_locations,
+    );
+    // Spawners are chosen the way `player_spawn_system` does, with a copy of the match RNG so
+    // the prediction doesn't change where players actually spawn.
+    let mut rng = match_rng.0.clone();
+
+    // Ghosts are matched to spawners in handle order, like the players they stand for.
+    for handle in handles.0.iter() {
+        let remaining = match handle.respawn_countdown() {
+            Some(remaining) => remaining,
+            None => continue,
+        };
+        let id = handle.inner().id;
+        let ghost = ghost_query.iter_mut().find(|(_, RespawnGhost(h), ..)| *h == id);
+        if let Some((_, _, mut transform, children)) = ghost.filter(|_| !spawned.contains(&id)) {
+            let recent = recent_spawners.0.get(&id).map_or(&[][..], Vec::as_slice);
+            if let Some(location) = choose_spawner(&available, recent, &mut rng) {
+                available.retain(|l| *l != location);
+                transform.translation = location.as_world_coordinates(game_map).extend(PLAYER_Z)
+                    + Vec3::new(0.0, PLAYER_VERTICAL_OFFSET_PX, 0.0);
+            }
+            for child in children.iter() {
+                if let Ok(mut text) = countdown_query.get_mut(*child) {
+                    text.sections[0].value = remaining.to_string();
+                }
+            }
+        }
+    }
+
+    for (entity, RespawnGhost(id), ..) in ghost_query.iter() {
+        let waiting = handles
+            .0
+            .iter()
+            .any(|h| h.inner().id == *id && !matches!(h, PlayerHandle::Misbehaved(..)));
+        if !waiting || spawned.contains(id) {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+pub(super) fn skeleton_cleanup_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut skeleton_query: Query<(Entity, &mut Sprite, &mut Skeleton)>,
+) -> Result<()> {
+    for (entity, mut sprite, mut skeleton) in skeleton_query.iter_mut() {
+        let Skeleton(ref mut timer) = *skeleton;
+        timer.tick(time.delta());
+        // Slowly fade the skeleton
+        sprite.color.set_a(timer.percent_left());
+        if timer.just_finished() {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+
+    Ok(())
+}
+
+pub(super) fn ban_sign_cleanup_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut ban_sign_query: Query<(Entity, &mut Sprite, &mut BanSign)>,
+) -> Result<()> {
+    for (entity, mut sprite, mut ban_sign) in ban_sign_query.iter_mut() {
+        let BanSign(ref mut timer) = *ban_sign;
+        timer.tick(time.delta());
+        // Slowly fade the ban_sign
+        sprite.color.set_a(timer.percent_left());
+        if timer.just_finished() {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{asset::AssetPlugin, ecs::schedule::Stage};
+
+    use super::*;
+
+    /// A world with the resources `player_death_system` uses. The asset server comes from the
+    /// asset plugin, as `Assets` can't be created without one.
+    fn test_world() -> World {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins).add_plugin(AssetPlugin).add_asset::<TextureAtlas>();
+        let mut world = std::mem::take(&mut app.world);
+        world.insert_resource(SoundEffects {
+            explosion: Handle::default(),
+            drop: Handle::default(),
+            spawn: Handle::default(),
+            death: Handle::default(),
+            powerup: Handle::default(),
+            win: Handle::default(),
+        });
+        world.insert_resource(Audio::default());
+        world.insert_resource(GameSettings::default());
+        world.insert_resource(PendingDeaths::default());
+        world.insert_resource(PlayerHandles(vec![]));
+        world.insert_resource(Events::<KillPlayerEvent>::default());
+        world.insert_resource(Events::<PlayerDespawnedEvent>::default());
+        world
+    }
+
+    fn wasm_player(world: &mut World, name: &str) -> (Entity, HandleId) {
+        let handle = Handle::<WasmPlayerAsset>::weak(HandleId::random::<WasmPlayerAsset>());
+        let id = handle.id;
+        world.resource_mut::<PlayerHandles>().0.push(PlayerHandle::ReadyToSpawn(handle.clone()));
+        let entity = world
+            .spawn()
+            .insert(Player::default())
+            .insert(Transform::default())
+            .insert(Team { name: name.into(), color: Color::WHITE })
+            .insert(handle)
+            .id();
+        (entity, id)
+    }
+
+    #[test]
+    fn players_killing_each_other_both_die_once() {
+        let mut world = test_world();
+        let (first, first_handle) = wasm_player(&mut world, "first");
+        let (second, second_handle) = wasm_player(&mut world, "second");
+        // Each player stands in the flames of both bombs, so each is reported once per bomb.
+        for bomb in 0..2 {
+            for (entity, name) in [(first, "first"), (second, "second")] {
+                world.resource_mut::<Events<KillPlayerEvent>>().send(KillPlayerEvent(
+                    entity,
+                    PlayerName(format!("{name} {bomb}")),
+                    Score(bomb),
+                ));
+            }
+        }
+
+        let mut stage = SystemStage::single_threaded().with_system(player_death_system);
+        stage.run(&mut world);
+        // The kills were all read, so running again must not kill anyone twice.
+        stage.run(&mut world);
+
+        assert!(world.get_entity(first).is_none());
+        assert!(world.get_entity(second).is_none());
+        let skeletons = world.query_filtered::<(), With<Skeleton>>().iter(&world).count();
+        assert_eq!(skeletons, 2);
+        let ghosts =
+            world.query::<&RespawnGhost>().iter(&world).map(|g| g.0).collect::<HashSet<_>>();
+        assert_eq!(ghosts, HashSet::from_iter([first_handle, second_handle]));
+        let respawning = world
+            .resource::<PlayerHandles>()
+            .0
+            .iter()
+            .filter(|h| matches!(h, PlayerHandle::Respawning(..)))
+            .map(|h| h.inner().id)
+            .collect::<Vec<_>>();
+        assert_eq!(respawning, vec![first_handle, second_handle]);
+        let events = world.resource::<Events<PlayerDespawnedEvent>>();
+        let despawned = events
+            .get_reader()
+            .iter(events)
+            .map(|PlayerDespawnedEvent(name, ..)| name.0.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(despawned, vec!["first 0", "second 0"]);
+    }
+}
@@ -0,0 +1,300 @@
+// Warranted since bevy queries tend to have very complex types.
+#![allow(clippy::type_complexity)]
+//! Defines a Bevy plugin that governs spawning and despawning players from .wasm handles,
+//! as well as the continuous behaviour of players as they exist in the game world.
+//!
+//! The systems are split by concern: `spawn` brings players into the arena, `act` runs
+//! their turns, `death` takes them out of it and `visuals` keeps them looking right. The
+//! tunables shared with the rest of the game live in `config`.
+
+use std::sync::{atomic::AtomicU64, Arc, Mutex};
+
+use anyhow::Result;
+use bevy::{
+    asset::HandleId,
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+use bomber_lib::{
+    world::{Direction, PowerUp},
+    Action,
+};
+use wasmtime::Store;
+
+use crate::{
+    game_map::TileLocation,
+    log_recoverable_error, log_unrecoverable_error_and_panic,
+    score::Score,
+    settings::GameSettings,
+    state::AppState,
+    tick::{world_turn, TickStep, WorldTurnStep},
+};
+
+mod act;
+mod config;
+mod death;
+mod spawn;
+mod visuals;
+
+pub use act::{instantiate, load_state, save_state};
+pub use config::{
+    filter_name, DEFAULT_PLAYER_NAME, DEFAULT_TEAM_NAME, FIRST_TURN_FUEL_MULTIPLIER,
+    MAX_NAME_LENGTH, MAX_TEAM_NAME_LENGTH,
+};
+pub use spawn::{arena_is_full, available_spawn_locations, spawn_player_entity, team_for};
+
+use act::player_action_system;
+use death::{
+    ban_sign_cleanup_system, player_ban_system, player_death_system, player_idle_kick_system,
+    respawn_ghost_system, skeleton_cleanup_system, RespawnGhost,
+};
+use spawn::{
+    player_respawn_system, player_spawn_system, respawn_fallback_system,
+    spawn_starvation_log_system,
+};
+use visuals::{
+    name_tag_placement_system, name_visibility_system, player_positioning_system,
+    toggle_names_system,
+};
+
+pub struct PlayerBehaviourPlugin;
+
+#[derive(Component, Clone)]
+pub struct PlayerName(pub String);
+/// Marks a player
+#[derive(Component, Default)]
+pub struct Player {
+    // The wasm fuel is internally tracked by the store, but it can't be accessed
+    // through the `wasmtime` API, so we keep a separate count associated to the player.
+    total_fuel_consumed: u64,
+    /// Whether the player already acted once since its `.wasm` module was instantiated,
+    /// which means it no longer benefits from the first turn fuel grace.
+    first_turn_done: bool,
+    /// Turns in a row the `.wasm` player failed to act on, without being banned yet.
+    failed_turns: u32,
+    /// Size of the input buffer of the `.wasm` module, once asked for. `Some(None)` means
+    /// the module doesn't say, in which case its surroundings are never truncated.
+    buffer_size: Option<Option<u64>>,
+    /// Turns in a row the player stood still without scoring.
+    idle_turns: u32,
+    /// Score as of the player's last turn, to tell whether it scored since.
+    last_turn_score: u32,
+    /// Points scored on the hill since the player was last told about them.
+    points_since_last_turn: u32,
+    /// Direction the player is sliding in on ice, which its next move is forced into.
+    sliding: Option<Direction>,
+    pub power_ups: HashMap<PowerUp, u32>,
+    /// Increases with every player spawned, so players that entered the game earlier can
+    /// be told apart from later ones.
+    pub spawn_order: u64,
+}
+
+static NEXT_SPAWN_ORDER: AtomicU64 = AtomicU64::new(0);
+
+impl Player {
+    /// Fuel the player is allowed to spend on their upcoming turn, given the regular
+    /// per turn budget.
+    pub fn fuel_budget(&self, fuel_per_tick: u64) -> u64 {
+        if self.first_turn_done {
+            fuel_per_tick
+        } else {
+            fuel_per_tick * FIRST_TURN_FUEL_MULTIPLIER
+        }
+    }
+
+    /// Grants the first turn fuel grace again, after the `.wasm` module has been swapped
+    /// for a freshly instantiated one. Whatever was learnt about the previous module, such as
+    /// its buffer size, is forgotten too.
+    pub fn restart_first_turn(&mut self, store: &mut Store<()>, fuel_per_tick: u64) -> Result<()> {
+        self.buffer_size = None;
+        if self.first_turn_done {
+            store.add_fuel(fuel_per_tick * (FIRST_TURN_FUEL_MULTIPLIER - 1))?;
+            self.first_turn_done = false;
+        }
+        Ok(())
+    }
+
+    /// Counts the turns the player spends idle, standing still without scoring, given the
+    /// action it ended up taking this turn.
+    fn record_activity(&mut self, action: &Action, score: u32) {
+        if matches!(action, Action::StayStill) && score <= self.last_turn_score {
+            self.idle_turns += 1;
+        } else {
+            self.idle_turns = 0;
+        }
+        self.last_turn_score = score;
+    }
+
+    /// Adds to the points the player will be told it scored on its next turn.
+    pub fn record_points(&mut self, points: u32) {
+        self.points_since_last_turn += points;
+    }
+
+    /// Points scored since the last time this was called, once per turn.
+    pub fn take_points_scored(&mut self) -> u32 {
+        std::mem::take(&mut self.points_since_last_turn)
+    }
+
+    /// Number of bombs the player can have on the map at once.
+    pub fn max_bombs(&self) -> u32 {
+        1 + self.power_ups.get(&PowerUp::SimultaneousBombs).copied().unwrap_or_default()
+    }
+
+    /// Tiles the flames of the player's bombs reach in each direction.
+    pub fn bomb_range(&self, base_bomb_range: u32) -> u32 {
+        base_bomb_range + self.power_ups.get(&PowerUp::BombRange).copied().unwrap_or_default()
+    }
+}
+
+#[derive(Component, Clone, Debug)]
+pub struct Team {
+    pub name: String,
+    pub color: Color,
+}
+
+#[derive(Component, Clone, Copy, Debug)]
+pub struct TeamNameMarker;
+#[derive(Component, Clone, Copy, Debug)]
+pub struct PlayerNameMarker;
+
+/// Whether player names are hidden everywhere in favour of team names, for events where
+/// participants don't want their names photographed.
+pub struct HideNames(pub bool);
+
+impl FromWorld for HideNames {
+    fn from_world(world: &mut World) -> Self {
+        Self(world.get_resource::<GameSettings>().map_or(false, |s| s.hide_names))
+    }
+}
+
+pub struct KillPlayerEvent(pub Entity, pub PlayerName, pub Score);
+/// Players a `KillPlayerEvent` was sent for, or that were banned or kicked, that haven't been
+/// despawned yet. They don't get to act or score, so they can't collect anything on their way
+/// out.
+#[derive(Default)]
+pub struct PendingDeaths(pub HashSet<Entity>);
+/// How a `.wasm` player was last killed, to tell it on its first turn after respawning.
+pub struct DeathReport {
+    /// Team of the bomb's owner, unless the player was killed by its own bomb.
+    pub killer_team: Option<String>,
+    pub bomb_location: TileLocation,
+}
+/// Deaths not yet reported to the players they happened to, by the handle of their `.wasm`
+/// file, so they carry over respawns. They are dropped when the round ends.
+#[derive(Default)]
+pub struct DeathReports(pub HashMap<HandleId, DeathReport>);
+/// Spawners each `.wasm` player came back on last this round, most recent first.
+#[derive(Default)]
+pub struct RecentSpawners(pub HashMap<HandleId, Vec<TileLocation>>);
+/// Sent when a player enters the arena, along with the handle of its `.wasm` file (native
+/// players have none) and the score it starts with.
+pub struct SpawnPlayerEvent(pub PlayerName, pub Option<HandleId>, pub Score);
+/// Sent when a player leaves the arena, with the reason and the handle of its `.wasm` file
+/// (native players have none).
+pub struct PlayerDespawnedEvent(pub PlayerName, pub Score, pub String, pub Option<HandleId>);
+/// Sent every frame in which players are ready to spawn but there aren't enough safe spawners
+/// for all of them, with the handles of those left waiting.
+pub struct SpawnStarvedEvent(pub Vec<HandleId>);
+pub struct PlayerMovedEvent {
+    pub entity: Entity,
+    pub from: TileLocation,
+    pub to: TileLocation,
+}
+
+/// Last words of a `.wasm` player, reported through the panic import right before the
+/// module traps. Shared with the import function bound to the player's instance.
+#[derive(Component, Clone, Default)]
+pub struct PanicReport(Arc<Mutex<Option<String>>>);
+
+impl PanicReport {
+    pub fn take(&self) -> Option<String> {
+        self.0.lock().expect("Panic report lock poisoned").take()
+    }
+}
+
+/// Used to mark objects owned by a player entity, such as placed bombs
+#[derive(Component)]
+pub struct Owner(pub Entity);
+
+impl Plugin for PlayerBehaviourPlugin {
+    fn build(&self, app: &mut App) {
+        let wasm_engine = wasmtime::Engine::new(wasmtime::Config::new().consume_fuel(true))
+            .expect("Failed to build wasm engine");
+        app.insert_resource(wasm_engine)
+            .init_resource::<HideNames>()
+            .init_resource::<PendingDeaths>()
+            .init_resource::<DeathReports>()
+            .init_resource::<RecentSpawners>()
+            .add_event::<SpawnPlayerEvent>()
+            .add_event::<PlayerMovedEvent>()
+            .add_event::<PlayerDespawnedEvent>()
+            .add_event::<SpawnStarvedEvent>()
+            .add_system(toggle_names_system)
+            .add_system(name_visibility_system)
+            .add_system_set(
+                SystemSet::on_update(AppState::InGame)
+                    .with_system(player_spawn_system)
+                    .with_system(spawn_starvation_log_system)
+                    .with_system(name_tag_placement_system)
+                    .with_system(player_positioning_system.chain(log_unrecoverable_error_and_panic))
+                    .with_system(
+                        player_death_system
+                            .label(TickStep::ApplyKills)
+                            .after(TickStep::ResolveExplosions),
+                    )
+                    .with_system(
+                        player_ban_system
+                            .label(TickStep::ApplyKills)
+                            .after(TickStep::ResolveExplosions),
+                    )
+                    .with_system(
+                        player_idle_kick_system
+                            .label(TickStep::ApplyKills)
+                            .after(TickStep::ResolveExplosions),
+                    )
+                    .with_system(respawn_ghost_system)
+                    .with_system(respawn_fallback_system)
+                    .with_system(
+                        skeleton_cleanup_system
+                            .chain(log_recoverable_error)
+                            .label(TickStep::Cleanup)
+                            .after(TickStep::Score),
+                    )
+                    .with_system(
+                        ban_sign_cleanup_system
+                            .chain(log_recoverable_error)
+                            .label(TickStep::Cleanup)
+                            .after(TickStep::Score),
+                    )
+                    .with_system(
+                        player_action_system
+                            .chain(log_recoverable_error)
+                            .label(TickStep::DecideActions)
+                            .label(TickStep::ApplyMoves),
+                    ),
+            )
+            .add_system_set(
+                SystemSet::new()
+                    .with_run_criteria(world_turn)
+                    .label(WorldTurnStep::Effects)
+                    .with_system(player_respawn_system),
+            )
+            .add_system_set(
+                // Keep the players on the victory screen as the background.
+                SystemSet::on_exit(AppState::VictoryScreen).with_system(cleanup),
+            );
+    }
+}
+
+fn cleanup(
+    player_query: Query<Entity, Or<(With<Player>, With<RespawnGhost>)>>,
+    mut recent_spawners: ResMut<RecentSpawners>,
+    mut death_reports: ResMut<DeathReports>,
+    mut commands: Commands,
+) {
+    for entity in player_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    recent_spawners.0.clear();
+    death_reports.0.clear();
+}
@@ -0,0 +1,541 @@
+//! Brings players into the arena, from `.wasm` handles or native implementations, and
+//! counts down the respawns of dead ones.
+
+use std::{sync::atomic::Ordering, time::Duration};
+
+use anyhow::{anyhow, Result};
+use bevy::{asset::HandleId, prelude::*};
+use bomber_lib::{
+    wasm_name, wasm_team_name,
+    world::{Direction, Object, Ticks},
+};
+use rand::{prelude::SliceRandom, thread_rng, Rng};
+use wasmtime::Store;
+
+use super::{
+    act::instantiate,
+    config::{
+        filter_name, DEFAULT_TEAM_NAME, FIRST_TURN_FUEL_MULTIPLIER, MAX_NAME_LENGTH,
+        MAX_TEAM_NAME_LENGTH,
+    },
+    visuals::spawn_player_text,
+    PanicReport, Player, PlayerName, RecentSpawners, SpawnPlayerEvent, SpawnStarvedEvent, Team,
+    NEXT_SPAWN_ORDER,
+};
+use crate::{
+    animation::AnimationState,
+    audio::SoundEffects,
+    game_map::{GameMap, PlayerSpawner, TileCache, TileLocation},
+    game_ui::tonari_color,
+    object::{blast_locations, FlameMarker},
+    player_hotswap::{PlayerHandle, PlayerHandles, WasmPlayerAsset},
+    rendering::{PLAYER_HEIGHT_PX, PLAYER_VERTICAL_OFFSET_PX, PLAYER_WIDTH_PX, PLAYER_Z},
+    reserved_names::{sanitize_and_authorize_name, ReservedNames},
+    score::{late_joiner_handicap, Score},
+    settings::GameSettings,
+    state::MatchRng,
+    stats::RoundStats,
+    tick::{LastWorldTurn, WHOLE_TURN_PERIOD},
+    ExternalCrateComponent,
+};
+
+/// Bombs with this many ticks or fewer left on their fuse make their surroundings unsafe to
+/// spawn on.
+const IMMINENT_FUSE: Ticks = Ticks(1);
+/// Number of best spawners a player spawns on at random, so opponents can't tell which one it
+/// will be.
+const SPAWNER_CHOICES: usize = 3;
+/// Number of a player's previous spawners it avoids coming back on while there are others.
+const RECENT_SPAWNERS: usize = 2;
+
+/// Ensures the number of active live players matches the `.wasm` files under `assets/players`
+/// at all times, by recursively spawning and despawning players.
+#[allow(clippy::too_many_arguments)]
+pub(super) fn player_spawn_system(
+    mut commands: Commands,
+    mut handles: ResMut<PlayerHandles>,
+    game_map_query: Query<&GameMap>,
+    player_query: Query<(Entity, Option<&Handle<WasmPlayerAsset>>, &TileLocation), With<Player>>,
+    spawner_query: Query<&TileLocation, With<PlayerSpawner>>,
+    object_query: Query<(&TileLocation, &ExternalCrateComponent<Object>)>,
+    tile_cache: Res<TileCache>,
+    flame_query: Query<&TileLocation, With<FlameMarker>>,
+    team_query: Query<&Team>,
+    engine: Res<wasmtime::Engine>,
+    asset_server: Res<AssetServer>,
+    assets: Res<Assets<WasmPlayerAsset>>,
+    mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+    // Grouped to stay within Bevy's limit on system parameters.
+    (mut spawn_event, mut starved_events): (
+        EventWriter<SpawnPlayerEvent>,
+        EventWriter<SpawnStarvedEvent>,
+    ),
+    (audio, sound_effects): (Res<Audio>, Res<SoundEffects>),
+    (reserved_names, settings, mut stats, score_query, mut match_rng, mut recent_spawners): (
+        Res<ReservedNames>,
+        Res<GameSettings>,
+        ResMut<RoundStats>,
+        Query<&Score, With<Player>>,
+        ResMut<MatchRng>,
+        ResMut<RecentSpawners>,
+    ),
+) {
+    let game_map = game_map_query.single();
+    // Despawn all excess players (if the wasm file was unloaded). Native players
+    // have no handle and are never despawned this way.
+    for (entity, handle, _) in player_query.iter() {
+        if let Some(handle) = handle {
+            if handles.0.iter().all(|h| h.inner().id != handle.id) {
+                commands.entity(entity).despawn_recursive();
+            }
+        }
+    }
+
+    // Players beyond the map's limit stay queued until a spot frees up, or the next round
+    // takes place on a bigger map.
+    if arena_is_full(player_query.iter().count(), &handles.0, game_map.max_players) {
+        return;
+    }
+
+    let player_locations = player_query.iter().map(|(.., l)| *l).collect::<Vec<_>>();
+    let available_spawn_locations = available_spawn_locations(
+        &spawner_query,
+        &object_query,
+        &tile_cache,
+        game_map,
+        &flame_query,
+        &player_locations,
+    );
+
+    // Players take spawners in handle order, one per frame. Spawners aren't held for players
+    // still respawning, so their ghosts' predicted spawners are free to take meanwhile.
+    let waiting = handles
+        .0
+        .iter()
+        .filter(|handle| handle.is_ready_to_spawn())
+        .filter(|handle| {
+            player_query.iter().all(|(_, h, _)| h.map_or(true, |h| h.id != handle.inner().id))
+        })
+        .map(|handle| handle.inner().id)
+        .collect::<Vec<_>>();
+    if waiting.len() > available_spawn_locations.len() {
+        starved_events.send(SpawnStarvedEvent(waiting[available_spawn_locations.len()..].to_vec()));
+    }
+
+    // Spawn all missing players (if the wasm file was just loaded)
+    let handle = handles.0.iter_mut().find(|handle| waiting.contains(&handle.inner().id));
+    let location = handle.as_ref().and_then(|handle| {
+        let recent = recent_spawners.0.get(&handle.inner().id).map_or(&[][..], Vec::as_slice);
+        choose_spawner(&available_spawn_locations, recent, &mut match_rng.0)
+    });
+    if let (Some(handle), Some(location)) = (handle, location) {
+        let id = handle.inner().id;
+
+        // Respawns come back with a blank score, but players joining the round late may be
+        // given a head start.
+        let first_spawn = !stats.0.contains_key(&id);
+        let head_start = if first_spawn && settings.rules.late_joiner_handicap {
+            late_joiner_handicap(score_query.iter().map(|s| s.0))
+        } else {
+            0
+        };
+
+        let spawned = spawn_player(
+            handle,
+            location,
+            Score(head_start),
+            game_map,
+            &engine,
+            &asset_server,
+            &mut spawn_event,
+            &assets,
+            &mut texture_atlases,
+            &team_query,
+            &reserved_names,
+            settings.rules.fuel_per_tick,
+            &mut commands,
+        );
+        // Only players that made it into the arena count as having joined the round.
+        if spawned.is_ok() {
+            let recent = recent_spawners.0.entry(id).or_default();
+            recent.insert(0, location);
+            recent.truncate(RECENT_SPAWNERS);
+            stats.0.entry(id).or_default().handicap += head_start;
+            audio.play(sound_effects.spawn.clone());
+        }
+    }
+}
+
+/// Whether the map's player limit is reached by the players in the arena and those respawning,
+/// who keep their spot while they wait so queued players can't take it from under them.
+pub fn arena_is_full(
+    live_players: usize,
+    handles: &[PlayerHandle],
+    max_players: Option<usize>,
+) -> bool {
+    let respawning = handles.iter().filter(|h| matches!(h, PlayerHandle::Respawning(..))).count();
+    max_players.map_or(false, |max| live_players + respawning >= max)
+}
+
+/// Logs when players start waiting for a free spawner, rather than on every frame they wait.
+pub(super) fn spawn_starvation_log_system(
+    mut starved_events: EventReader<SpawnStarvedEvent>,
+    asset_server: Res<AssetServer>,
+    mut last_starved: Local<Vec<HandleId>>,
+) {
+    let starved = starved_events.iter().last().map(|SpawnStarvedEvent(s)| s.clone());
+    let starved = starved.unwrap_or_default();
+    if starved.iter().any(|id| !last_starved.contains(id)) {
+        let files = starved
+            .iter()
+            .map(|id| PlayerHandle::file_stem(*id, &asset_server).unwrap_or_else(|| "?".into()))
+            .collect::<Vec<_>>();
+        warn!("Not enough free spawners, waiting to spawn: {}", files.join(", "));
+    }
+    *last_starved = starved;
+}
+
+/// Retrieves all spawner locations that are safe to spawn on, sorted in ascending order of
+/// distance to other players. Spawners are unsafe if they are occupied by an object or another
+/// player, on fire, or about to be caught in a bomb's blast.
+pub fn available_spawn_locations(
+    spawner_query: &Query<&TileLocation, With<PlayerSpawner>>,
+    object_query: &Query<(&TileLocation, &ExternalCrateComponent<Object>)>,
+    tile_cache: &TileCache,
+    game_map: &GameMap,
+    flame_query: &Query<&TileLocation, With<FlameMarker>>,
+    player_locations: &[TileLocation],
+) -> Vec<TileLocation> {
+    let object_at = |location: TileLocation| {
+        object_query.iter().find_map(|(l, o)| (*l == location).then_some(**o))
+    };
+    let endangered = object_query
+        .iter()
+        .filter_map(|(location, object)| match **object {
+            Object::Bomb { fuse_remaining, range } if fuse_remaining.0 <= IMMINENT_FUSE.0 => {
+                Some(blast_locations(*location, range, tile_cache, game_map, object_at))
+            },
+            _ => None,
+        })
+        .flatten()
+        .chain(flame_query.iter().cloned())
+        .collect::<Vec<_>>();
+
+    let mut available_spawn_locations: Vec<_> = spawner_query
+        .iter()
+        .cloned()
+        .filter(|spawner_location| {
+            object_query.iter().all(|(object_location, _)| object_location != spawner_location)
+                && player_locations
+                    .iter()
+                    .all(|player_location| player_location != spawner_location)
+                && !endangered.contains(spawner_location)
+        })
+        .collect();
+
+    available_spawn_locations.sort_by_key(|spawner| {
+        spawner.taxicab_distance_to_closest(player_locations.iter().cloned())
+    });
+    available_spawn_locations
+}
+
+/// Picks where a player spawns among the `SPAWNER_CHOICES` spawners farthest from other
+/// players, given the available spawners sorted closest first. Spawners the player came back
+/// on recently are left out unless there is nowhere else to go.
+pub(super) fn choose_spawner(
+    available: &[TileLocation],
+    recent: &[TileLocation],
+    rng: &mut impl Rng,
+) -> Option<TileLocation> {
+    let fresh = available.iter().rev().filter(|l| !recent.contains(l)).copied();
+    let mut candidates = fresh.take(SPAWNER_CHOICES).collect::<Vec<_>>();
+    if candidates.is_empty() {
+        candidates = available.iter().rev().take(SPAWNER_CHOICES).copied().collect();
+    }
+    candidates.choose(rng).copied()
+}
+
+/// Loads the `.wasm` bytes, JIT compiles them and stores all player-related state
+/// in an entity. The import functions binding is done here, which means players effectively
+/// get a "callback" into the world to use as they remain alive.
+fn spawn_player(
+    handle: &mut PlayerHandle,
+    location: TileLocation,
+    score: Score,
+    game_map: &GameMap,
+    engine: &wasmtime::Engine,
+    asset_server: &AssetServer,
+    spawn_event: &mut EventWriter<SpawnPlayerEvent>,
+    assets: &Assets<WasmPlayerAsset>,
+    texture_atlases: &mut ResMut<Assets<TextureAtlas>>,
+    team_query: &Query<&Team>,
+    reserved_names: &ReservedNames,
+    fuel_per_tick: u64,
+    commands: &mut Commands,
+) -> Result<(), anyhow::Error> {
+    // The Store owns all player-adjacent data internal to the wasm module
+    let mut store = Store::new(engine, ());
+    store.add_fuel(fuel_per_tick * FIRST_TURN_FUEL_MULTIPLIER)?;
+    let wasm_bytes = assets
+        .get(handle.inner())
+        .ok_or_else(|| anyhow!("Wasm asset not found at runtime"))?
+        .bytes
+        .clone();
+
+    // Here the raw `wasm` is JIT compiled into a stateless module.
+    let module = wasmtime::Module::new(engine, wasm_bytes)?;
+    // Here the module is bound to a store.
+    let panic_report = PanicReport::default();
+    let instance = instantiate(engine, &mut store, &module, &panic_report)?;
+
+    let name = if let Ok(name) = wasm_name(&mut store, &instance) {
+        let file_stem = PlayerHandle::file_stem(handle.inner(), asset_server);
+        sanitize_and_authorize_name(&name, file_stem.as_deref(), MAX_NAME_LENGTH, reserved_names)
+    } else {
+        *handle =
+            PlayerHandle::Misbehaved(handle.inner().clone(), "Failed to provide a name".into());
+        return Err(anyhow!("Wasm failed to return name, invalidating handle."));
+    };
+    let team_name = if let Ok(team_name) = wasm_team_name(&mut store, &instance) {
+        filter_name(&team_name, MAX_TEAM_NAME_LENGTH, DEFAULT_TEAM_NAME)
+    } else {
+        *handle = PlayerHandle::Misbehaved(
+            handle.inner().clone(),
+            "Failed to provide a team name".into(),
+        );
+        return Err(anyhow!("Wasm failed to return team name, invalidating handle."));
+    };
+
+    let team = team_for(&team_name, team_query);
+    let entity = spawn_player_entity(
+        name,
+        Some(handle.inner().id),
+        team,
+        location,
+        score,
+        game_map,
+        asset_server,
+        spawn_event,
+        texture_atlases,
+        commands,
+    );
+    commands
+        .entity(entity)
+        .insert(ExternalCrateComponent(instance))
+        .insert(ExternalCrateComponent(store))
+        .insert(panic_report)
+        .insert(handle.inner().clone());
+    Ok(())
+}
+
+/// Finds the already registered team with the given name, or creates a new one with
+/// a color no other team is using.
+pub fn team_for(team_name: &str, team_query: &Query<&Team>) -> Team {
+    let team = team_query.iter().cloned().find(|Team { name, .. }| name == team_name);
+
+    team.unwrap_or_else(|| {
+        let mut available_colors = tonari_color::team_colors_bevy()
+            .filter(|c| !team_query.iter().any(|Team { color, .. }| color == c))
+            .collect::<Vec<_>>();
+        available_colors.shuffle(&mut thread_rng());
+
+        let color = available_colors.into_iter().next().unwrap_or_default();
+        Team { name: team_name.to_owned(), color }
+    })
+}
+
+/// Spawns the parts of a player entity that don't depend on where its brain comes from
+/// (a `.wasm` module or a native implementation), returning the new entity.
+pub fn spawn_player_entity(
+    name: String,
+    handle: Option<HandleId>,
+    team: Team,
+    location: TileLocation,
+    score: Score,
+    game_map: &GameMap,
+    asset_server: &AssetServer,
+    spawn_event: &mut EventWriter<SpawnPlayerEvent>,
+    texture_atlases: &mut Assets<TextureAtlas>,
+    commands: &mut Commands,
+) -> Entity {
+    let texture_handle = asset_server.load("graphics/Sprites/Bomberman/sheet.png");
+    let texture_atlas = TextureAtlas::from_grid(texture_handle, Vec2::new(21.0, 32.0), 5, 4);
+    let texture_atlas_handle = texture_atlases.add(texture_atlas);
+
+    info!("{} from team {} has entered the game!", name, team.name);
+    spawn_event.send(SpawnPlayerEvent(PlayerName(name.clone()), handle, score));
+    commands
+        .spawn()
+        .insert(Player {
+            spawn_order: NEXT_SPAWN_ORDER.fetch_add(1, Ordering::Relaxed),
+            ..default()
+        })
+        .insert(location)
+        .insert(PlayerName(name.clone()))
+        .insert(score)
+        .insert(AnimationState::StandingStill(Direction::South))
+        .insert_bundle(SpriteSheetBundle {
+            sprite: TextureAtlasSprite {
+                index: 2,
+                color: team.color,
+                custom_size: Some(Vec2::new(PLAYER_WIDTH_PX, PLAYER_HEIGHT_PX)),
+                ..Default::default()
+            },
+            texture_atlas: texture_atlas_handle,
+            transform: Transform::from_translation(
+                location.as_world_coordinates(game_map).extend(PLAYER_Z)
+                    + Vec3::new(0.0, PLAYER_VERTICAL_OFFSET_PX, 0.0),
+            ),
+            ..default()
+        })
+        .with_children(|p| {
+            // Text needs to be a child in order to be offset from the player
+            // location but still move with the player.
+            spawn_player_text(p, asset_server, name, &team);
+        })
+        .insert(team)
+        .id()
+}
+
+pub(super) fn player_respawn_system(mut handles: ResMut<PlayerHandles>) {
+    count_down_respawns(&mut handles);
+}
+
+/// Counts respawns down once per whole turn of real time while world turns are stalled, so
+/// dead players still come back.
+pub(super) fn respawn_fallback_system(
+    time: Res<Time>,
+    last_world_turn: Res<LastWorldTurn>,
+    mut handles: ResMut<PlayerHandles>,
+    mut stalled_for: Local<Duration>,
+) {
+    if !last_world_turn.stalled(&time) {
+        *stalled_for = Duration::ZERO;
+        return;
+    }
+    *stalled_for += time.delta();
+    while *stalled_for >= WHOLE_TURN_PERIOD {
+        *stalled_for -= WHOLE_TURN_PERIOD;
+        count_down_respawns(&mut handles);
+    }
+}
+
+fn count_down_respawns(handles: &mut PlayerHandles) {
+    for handle in handles.0.iter_mut() {
+        match handle {
+            PlayerHandle::ReadyToSpawn(_) => (),
+            PlayerHandle::Misbehaved(..) => (),
+            PlayerHandle::Respawning(_, Ticks(t)) if *t > 0 => *t -= 1,
+            PlayerHandle::Respawning(h, _) => {
+                *handle = PlayerHandle::ReadyToSpawn(h.clone());
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::schedule::Stage;
+    use rand::rngs::mock::StepRng;
+
+    use super::*;
+
+    fn handle() -> Handle<WasmPlayerAsset> {
+        Handle::weak(HandleId::random::<WasmPlayerAsset>())
+    }
+
+    #[test]
+    fn respawning_players_keep_their_spot() {
+        let handles = vec![
+            PlayerHandle::Respawning(handle(), Ticks(2)),
+            PlayerHandle::ReadyToSpawn(handle()),
+        ];
+        // One player in the arena and one respawning fill a map for two, so the queued one
+        // has to wait.
+        assert!(arena_is_full(1, &handles, Some(2)));
+        assert!(!arena_is_full(1, &handles, Some(3)));
+        assert!(!arena_is_full(1, &handles, None));
+    }
+
+    #[test]
+    fn banned_players_give_up_their_spot() {
+        let handles = vec![
+            PlayerHandle::Misbehaved(handle(), "trapped".into()),
+            PlayerHandle::ReadyToSpawn(handle()),
+        ];
+        assert!(!arena_is_full(1, &handles, Some(2)));
+    }
+
+    /// Spawners sorted closest to other players first, as `available_spawn_locations` does.
+    fn spawners(count: usize) -> Vec<TileLocation> {
+        (0..count).map(|i| TileLocation(i, 0)).collect()
+    }
+
+    #[test]
+    fn spawner_is_one_of_the_farthest() {
+        let available = spawners(6);
+        // The mock always picks the first candidate, which is the farthest spawner.
+        let mut rng = StepRng::new(0, 0);
+        assert_eq!(choose_spawner(&available, &[], &mut rng), Some(TileLocation(5, 0)));
+
+        let mut rng = StepRng::new(0, u64::MAX / 7);
+        for _ in 0..20 {
+            let chosen = choose_spawner(&available, &[], &mut rng).unwrap();
+            assert!(available[available.len() - SPAWNER_CHOICES..].contains(&chosen));
+        }
+    }
+
+    #[test]
+    fn recent_spawners_are_avoided_while_there_are_others() {
+        let available = spawners(6);
+        let recent = [TileLocation(5, 0), TileLocation(4, 0)];
+        let mut rng = StepRng::new(0, 0);
+        assert_eq!(choose_spawner(&available, &recent, &mut rng), Some(TileLocation(3, 0)));
+
+        let mut rng = StepRng::new(0, u64::MAX / 7);
+        for _ in 0..20 {
+            let chosen = choose_spawner(&available, &recent, &mut rng).unwrap();
+            assert!([TileLocation(3, 0), TileLocation(2, 0), TileLocation(1, 0)].contains(&chosen));
+        }
+    }
+
+    #[test]
+    fn recent_spawners_are_used_when_there_is_nowhere_else() {
+        let available = spawners(2);
+        let recent = [TileLocation(1, 0), TileLocation(0, 0)];
+        let mut rng = StepRng::new(0, 0);
+        assert_eq!(choose_spawner(&available, &recent, &mut rng), Some(TileLocation(1, 0)));
+        assert_eq!(choose_spawner(&[], &recent, &mut rng), None);
+    }
+
+    #[test]
+    fn respawns_count_down_in_real_time_while_world_turns_are_stalled() {
+        let ms = Duration::from_millis;
+        let mut world = World::new();
+        world.insert_resource(Time::default());
+        world.insert_resource(LastWorldTurn(Duration::ZERO));
+        world.insert_resource(PlayerHandles(vec![PlayerHandle::Respawning(handle(), Ticks(1))]));
+        let mut stage = SystemStage::single_threaded().with_system(respawn_fallback_system);
+        // Runs a frame starting at `since_startup`, and returns the ticks left to respawn, or
+        // `None` once the player is ready to spawn.
+        let mut frame_at = |world: &mut World, since_startup: Duration| {
+            let mut time = world.resource_mut::<Time>();
+            let startup = time.startup();
+            time.update_with_instant(startup + since_startup);
+            stage.run(world);
+            match world.resource::<PlayerHandles>().0[0] {
+                PlayerHandle::Respawning(_, Ticks(t)) => Some(t),
+                _ => None,
+            }
+        };
+
+        // World turns are on time, so respawns wait for them.
+        assert_eq!(frame_at(&mut world, ms(2000)), Some(1));
+        assert_eq!(frame_at(&mut world, ms(3000)), Some(1));
+        // Stalled from here on, so each whole turn of real time counts down a tick.
+        assert_eq!(frame_at(&mut world, ms(3500)), Some(1));
+        assert_eq!(frame_at(&mut world, ms(4000)), Some(0));
+        assert_eq!(frame_at(&mut world, ms(4500)), Some(0));
+        assert_eq!(frame_at(&mut world, ms(5000)), None);
+    }
+}
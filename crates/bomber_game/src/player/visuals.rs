@@ -0,0 +1,196 @@
+//! Keeps players looking right: their name tags, and their sprites following their moves.
+
+use anyhow::Result;
+use bevy::{prelude::*, utils::HashSet};
+use bevy_tweening::{lens::TransformPositionLens, *};
+use bomber_lib::world::Direction;
+
+use super::{HideNames, Player, PlayerMovedEvent, PlayerNameMarker, Team, TeamNameMarker};
+use crate::{
+    animation::AnimationState,
+    game_map::{GameMap, TileLocation},
+    rendering::{PLAYER_VERTICAL_OFFSET_PX, PLAYER_Z},
+    tick::WHOLE_TURN_PERIOD,
+};
+
+const HIDE_NAMES_KEY: KeyCode = KeyCode::H;
+/// Distance of the name tags from the middle of the player, above or below it.
+const NAME_OFFSET_PX: f32 = 48.0;
+const TEAM_NAME_OFFSET_PX: f32 = 32.0;
+/// How far name tags lean away from the direction the player faces.
+const NAME_TAG_SHIFT_PX: f32 = 8.0;
+/// How quickly name tags glide to a new placement, as a fraction of the way per second.
+const NAME_TAG_SPEED: f32 = 10.0;
+
+pub(super) fn spawn_player_text(
+    parent: &mut ChildBuilder,
+    asset_server: &AssetServer,
+    name: String,
+    team: &Team,
+) {
+    parent
+        .spawn()
+        .insert_bundle(Text2dBundle {
+            text: Text::from_section(
+                name,
+                TextStyle {
+                    font: asset_server.load("fonts/space_mono_400.ttf"),
+                    font_size: 24.0,
+                    color: Color::WHITE,
+                },
+            )
+            .with_alignment(TextAlignment {
+                vertical: VerticalAlign::Center,
+                horizontal: HorizontalAlign::Center,
+            }),
+            transform: Transform::from_translation(Vec3::new(0.0, NAME_OFFSET_PX, 0.0)),
+            ..Default::default()
+        })
+        .insert(PlayerNameMarker);
+    parent
+        .spawn()
+        .insert_bundle(Text2dBundle {
+            text: Text::from_section(
+                &team.name,
+                TextStyle {
+                    font: asset_server.load("fonts/space_mono_400.ttf"),
+                    font_size: 16.0,
+                    color: team.color,
+                },
+            )
+            .with_alignment(TextAlignment {
+                vertical: VerticalAlign::Center,
+                horizontal: HorizontalAlign::Center,
+            }),
+            transform: Transform::from_translation(Vec3::new(0.0, TEAM_NAME_OFFSET_PX, 0.0)),
+            ..Default::default()
+        })
+        .insert(TeamNameMarker);
+}
+
+pub(super) fn toggle_names_system(keys: Res<Input<KeyCode>>, mut hide_names: ResMut<HideNames>) {
+    if keys.just_pressed(HIDE_NAMES_KEY) {
+        hide_names.0 = !hide_names.0;
+    }
+}
+
+pub(super) fn name_visibility_system(
+    hide_names: Res<HideNames>,
+    mut name_query: Query<&mut Visibility, With<PlayerNameMarker>>,
+) {
+    for mut visibility in name_query.iter_mut() {
+        if visibility.is_visible == hide_names.0 {
+            visibility.is_visible = !hide_names.0;
+        }
+    }
+}
+
+/// Where the name tags of a player go: how far they shift sideways, and whether they sit above
+/// (1.0) or below (-1.0) the sprite.
+fn name_tag_placement(facing: Direction, above_taken: bool, below_taken: bool) -> (f32, f32) {
+    let shift = match facing {
+        Direction::East => -NAME_TAG_SHIFT_PX,
+        Direction::West => NAME_TAG_SHIFT_PX,
+        Direction::North | Direction::South => 0.0,
+    };
+    // Tags make way for a player right next to them. Otherwise they go under players facing
+    // south, so they don't cover their face.
+    let side = match (above_taken, below_taken, facing) {
+        (true, false, _) => -1.0,
+        (false, true, _) => 1.0,
+        (_, _, Direction::South) => -1.0,
+        _ => 1.0,
+    };
+    (shift, side)
+}
+
+/// Moves name tags around players so they stay clear of the sprite's face and of neighbours.
+pub(super) fn name_tag_placement_system(
+    time: Res<Time>,
+    player_query: Query<(&AnimationState, &TileLocation, &Children), With<Player>>,
+    mut team_name_query: Query<&mut Transform, (With<TeamNameMarker>, Without<PlayerNameMarker>)>,
+    mut name_query: Query<&mut Transform, (With<PlayerNameMarker>, Without<TeamNameMarker>)>,
+) {
+    let taken = player_query.iter().map(|(_, location, _)| *location).collect::<HashSet<_>>();
+    let is_taken = |location: Option<TileLocation>| location.map_or(false, |l| taken.contains(&l));
+    let step = (time.delta_seconds() * NAME_TAG_SPEED).min(1.0);
+    for (animation, &location, children) in player_query.iter() {
+        let (shift, side) = name_tag_placement(
+            animation.facing(),
+            is_taken(location + Direction::North),
+            is_taken(location + Direction::South),
+        );
+        for &child in children.iter() {
+            let (mut transform, offset) = if let Ok(transform) = team_name_query.get_mut(child) {
+                (transform, TEAM_NAME_OFFSET_PX)
+            } else if let Ok(transform) = name_query.get_mut(child) {
+                (transform, NAME_OFFSET_PX)
+            } else {
+                continue;
+            };
+            let target = Vec3::new(shift, side * offset, transform.translation.z);
+            if transform.translation.distance(target) < 0.5 {
+                if transform.translation != target {
+                    transform.translation = target;
+                }
+            } else {
+                transform.translation = transform.translation.lerp(target, step);
+            }
+        }
+    }
+}
+
+/// Each frame, matches the player world coordinates to their abstract position
+/// in the game world.
+pub(super) fn player_positioning_system(
+    game_map_query: Query<&GameMap>,
+    mut events: EventReader<PlayerMovedEvent>,
+    mut commands: Commands,
+) -> Result<()> {
+    for PlayerMovedEvent { entity, from, to } in events.iter() {
+        let game_map = game_map_query.single();
+        // Players render over objects (only flames go above them), and in particular over other
+        // things above them (in the Y direction) so we achieve this by having a Z offset dependant on Y
+        let z_offset = 0.001 * (game_map.height() - to.1) as f32;
+        let start = from.as_world_coordinates(game_map).extend(PLAYER_Z + z_offset)
+            + Vec3::new(0.0, PLAYER_VERTICAL_OFFSET_PX, 0.0);
+        let end = to.as_world_coordinates(game_map).extend(PLAYER_Z + z_offset)
+            + Vec3::new(0.0, PLAYER_VERTICAL_OFFSET_PX, 0.0);
+        commands.entity(*entity).insert(Animator::new(Tween::new(
+            EaseMethod::Linear,
+            TweeningType::Once,
+            WHOLE_TURN_PERIOD,
+            TransformPositionLens { start, end },
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn name_tags_shift_away_from_the_face() {
+        assert_eq!(name_tag_placement(Direction::East, false, false), (-NAME_TAG_SHIFT_PX, 1.0));
+        assert_eq!(name_tag_placement(Direction::West, false, false), (NAME_TAG_SHIFT_PX, 1.0));
+        assert_eq!(name_tag_placement(Direction::North, false, false), (0.0, 1.0));
+        // Players facing south show their face at the top of the sprite.
+        assert_eq!(name_tag_placement(Direction::South, false, false), (0.0, -1.0));
+    }
+
+    #[test]
+    fn name_tags_make_way_for_neighbours() {
+        for facing in Direction::all() {
+            assert_eq!(name_tag_placement(facing, true, false).1, -1.0);
+            assert_eq!(name_tag_placement(facing, false, true).1, 1.0);
+        }
+    }
+
+    #[test]
+    fn name_tags_between_two_neighbours_fall_back_to_facing() {
+        assert_eq!(name_tag_placement(Direction::South, true, true).1, -1.0);
+        assert_eq!(name_tag_placement(Direction::North, true, true).1, 1.0);
+        assert_eq!(name_tag_placement(Direction::East, true, true), (-NAME_TAG_SHIFT_PX, 1.0));
+    }
+}
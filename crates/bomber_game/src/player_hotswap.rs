@@ -1,21 +1,53 @@
 use crate::{
     log_recoverable_error,
-    player_behaviour::{filter_name, Player, PlayerName, PlayerNameMarker, MAX_NAME_LENGTH},
-    state::Round,
+    offenses::{BanLength, OffenseLog},
+    player::{
+        instantiate, load_state, save_state, PanicReport, Player, PlayerName, PlayerNameMarker,
+        MAX_NAME_LENGTH,
+    },
+    reserved_names::{sanitize_and_authorize_name, ReservedNames},
+    settings::GameSettings,
+    state::{Round, RoundsFolder},
     ExternalCrateComponent,
 };
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
 use bevy::{
-    asset::{AssetLoader, AssetServerSettings, LoadContext, LoadedAsset},
+    asset::{AssetLoader, AssetServerSettings, FileAssetIo, HandleId, LoadContext, LoadedAsset},
     prelude::*,
     reflect::TypeUuid,
-    utils::BoxedFuture,
+    utils::{BoxedFuture, HashMap, HashSet},
 };
 use bomber_lib::{wasm_name, world::Ticks};
+use bomber_shared::{round_folder, BuildMeta};
+use std::{ffi::OsStr, fs, path::Path, time::Duration};
 use wasmtime::{Instance, Store};
 
 pub struct PlayerHotswapPlugin;
 pub const MAX_PLAYERS: usize = 12;
+pub const WASM_EXTENSIONS: &[&str] = &["wasm", "wat"];
+/// How often the rounds folder is listed when it can't be watched.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+/// How long a `.wasm` file must go unmodified before the player is reloaded from it.
+const RELOAD_DEBOUNCE: Duration = Duration::from_millis(500);
+/// Binary modules start with the magic number, followed by a 4 byte version.
+const WASM_MAGIC: &[u8] = b"\0asm";
+const WASM_HEADER_LENGTH: usize = 8;
+const REUPLOAD_REQUIRED: &str = "Banned until a new upload, after failing repeatedly";
+
+/// Sent when a player in the arena is reloaded from its edited file, with the reason if the
+/// new file was rejected and the previous version kept.
+pub struct PlayerReloadEvent {
+    pub name: String,
+    pub result: Result<(), String>,
+}
+
+/// How changes to the rounds folder are noticed. Bevy's file watcher crashes on changes to
+/// files outside the asset folder, so a rounds folder that resolves elsewhere (typically
+/// through a symlink) is polled instead.
+enum HotswapMode {
+    Watching,
+    Polling(Timer),
+}
 
 /// Handle into a .wasm file, classified by whether or not it misbehaved.
 #[derive(Clone, Debug)]
@@ -30,6 +62,17 @@ impl PlayerHandle {
         matches!(self, PlayerHandle::ReadyToSpawn(_))
     }
 
+    /// World ticks until the player behind the handle is back in the arena, as shown to
+    /// spectators, or `None` if it isn't coming back. Players that are ready to spawn count
+    /// as one tick away, as they are only waiting for a free spawner.
+    pub fn respawn_countdown(&self) -> Option<u32> {
+        match self {
+            PlayerHandle::Respawning(_, Ticks(t)) => Some((*t).max(1)),
+            PlayerHandle::ReadyToSpawn(_) => Some(1),
+            PlayerHandle::Misbehaved(..) => None,
+        }
+    }
+
     pub fn inner(&self) -> &Handle<WasmPlayerAsset> {
         match self {
             PlayerHandle::ReadyToSpawn(h) => h,
@@ -38,6 +81,13 @@ impl PlayerHandle {
         }
     }
 
+    /// Stem of the `.wasm` file behind the handle, which is the API key it was uploaded with.
+    pub fn file_stem(handle: impl Into<HandleId>, asset_server: &AssetServer) -> Option<String> {
+        asset_server
+            .get_handle_path(handle)
+            .and_then(|p| p.path().file_stem().map(|s| s.to_string_lossy().into_owned()))
+    }
+
     pub fn invalidate(&mut self, reason: String) {
         *self = PlayerHandle::Misbehaved(self.inner().clone(), reason);
     }
@@ -55,18 +105,46 @@ pub struct WasmPlayerAsset {
     /// Raw `wasm` bytes, whether in binary precompiled `.wasm` format or textual
     /// `.wat` representation (wasmtime can process both).
     pub bytes: Vec<u8>,
+    /// Which build of the bot this is, if it says.
+    pub build: Option<BuildMeta>,
 }
 
 impl Plugin for PlayerHotswapPlugin {
     fn build(&self, app: &mut App) {
+        let rounds_folder = &app.world.resource::<RoundsFolder>().0;
+        let asset_folder = &app.world.resource::<AssetServerSettings>().asset_folder;
+        let asset_root = FileAssetIo::get_root_path().join(asset_folder);
+        let mode = if resolves_inside(rounds_folder, &asset_root) {
+            HotswapMode::Watching
+        } else {
+            error!(
+                "The rounds folder {:?} resolves outside the asset folder {:?}, which crashes \
+                 Bevy's file watcher. Move it under the asset folder, or point ${} or the \
+                 `rounds` symlink there. Until then, the folder is polled every {:?}: new \
+                 players are picked up, but edited players and maps are not reloaded.",
+                rounds_folder,
+                asset_root,
+                bomber_shared::ROUNDS_FOLDER_ENV,
+                POLL_INTERVAL
+            );
+            HotswapMode::Polling(Timer::new(POLL_INTERVAL, true))
+        };
+        let watch_for_changes = matches!(mode, HotswapMode::Watching);
+        let offense_log =
+            OffenseLog::load(rounds_folder).expect("Failed to read the offenses of players");
+
         app.insert_resource(PlayerHandles(vec![]))
-            .insert_resource(AssetServerSettings { watch_for_changes: true, ..default() })
+            .insert_resource(offense_log)
+            .insert_resource(mode)
+            .insert_resource(AssetServerSettings { watch_for_changes, ..default() })
             .add_asset::<WasmPlayerAsset>()
             .init_asset_loader::<WasmPlayerLoader>()
+            .add_event::<PlayerReloadEvent>()
             .add_system(live_brain_reload_system.chain(log_recoverable_error))
-            .add_system(unban_system)
+            .add_system(offense_system.chain(log_recoverable_error))
+            .add_system(unban_system.chain(log_recoverable_error))
             .add_startup_system(setup)
-            .add_system(hotswap_system);
+            .add_system(hotswap_system.chain(log_recoverable_error));
     }
 }
 
@@ -80,97 +158,282 @@ impl AssetLoader for WasmPlayerLoader {
         load_context: &'a mut LoadContext,
     ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
         Box::pin(async move {
-            let wasm_player_asset = WasmPlayerAsset { bytes: bytes.into() };
+            // Build metadata is optional, so a malformed one doesn't keep the bot out.
+            let build = BuildMeta::read(bytes).unwrap_or_else(|e| {
+                warn!("Ignoring the build metadata of {:?}: {:#}", load_context.path(), e);
+                None
+            });
+            let wasm_player_asset = WasmPlayerAsset { bytes: bytes.into(), build };
             load_context.set_default_asset(LoadedAsset::new(wasm_player_asset));
             Ok(())
         })
     }
 
     fn extensions(&self) -> &[&str] {
-        &["wasm", "wat"]
+        WASM_EXTENSIONS
     }
 }
 
-fn setup(asset_server: Res<AssetServer>) {
-    asset_server.watch_for_changes().unwrap()
+fn setup(asset_server: Res<AssetServer>, mode: Res<HotswapMode>) {
+    if matches!(*mode, HotswapMode::Watching) {
+        asset_server.watch_for_changes().unwrap()
+    }
+}
+
+/// Whether `path` is inside `folder` once symlinks are resolved.
+fn resolves_inside(path: &Path, folder: &Path) -> bool {
+    match (path.canonicalize(), folder.canonicalize()) {
+        (Ok(path), Ok(folder)) => path.starts_with(folder),
+        _ => false,
+    }
 }
 
-/// Maintains the `PlayerHandles` resource in sync with the files in the hotswap folder.
+/// Maintains the `PlayerHandles` resource in sync with the files in the hotswap folder. Files
+/// banned until a new upload are picked up as misbehaving.
 fn hotswap_system(
     asset_server: Res<AssetServer>,
     mut handles: ResMut<PlayerHandles>,
+    mut offense_log: ResMut<OffenseLog>,
     round: Res<Round>,
-) {
-    let mut new_handles = asset_server.load_folder(format!("rounds/{}", round.0)).unwrap();
+    rounds_folder: Res<RoundsFolder>,
+    mut mode: ResMut<HotswapMode>,
+    time: Res<Time>,
+) -> Result<()> {
+    if let HotswapMode::Polling(timer) = &mut *mode {
+        if !timer.tick(time.delta()).just_finished() {
+            return Ok(());
+        }
+    }
+
+    // The rounds folder may live outside the assets folder, so it is listed directly rather
+    // than through `AssetServer::load_folder`. Only bot files are loaded, skipping anything
+    // else in there such as upload temp files.
+    let round_folder = round_folder(&rounds_folder.0, round.0);
+    let mut new_handles = fs::read_dir(&round_folder)
+        .with_context(|| format!("reading {:?}", round_folder))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| {
+            path.extension().and_then(OsStr::to_str).map_or(false, |e| WASM_EXTENSIONS.contains(&e))
+        })
+        .map(|path| (asset_server.load::<WasmPlayerAsset, _>(path.as_path()), path))
+        .collect::<Vec<_>>();
     // Remove any handles associated to files that have disappeared from the folder
-    handles.0.retain(|h| new_handles.iter().any(|new| new.id == h.inner().id));
+    handles.0.retain(|h| new_handles.iter().any(|(new, _)| new.id == h.inner().id));
     // Add any handles that aren't already present and misbehaving
-    new_handles.retain(|h| handles.0.iter().all(|old| old.inner().id != h.id));
-    handles.0.extend(new_handles.into_iter().map(|new| PlayerHandle::ReadyToSpawn(new.typed())));
+    new_handles.retain(|(h, _)| handles.0.iter().all(|old| old.inner().id != h.id));
+    for (handle, path) in new_handles {
+        handles.0.push(match banned_until_reupload(&path, &mut offense_log) {
+            Ok(true) => PlayerHandle::Misbehaved(handle, REUPLOAD_REQUIRED.into()),
+            Ok(false) => PlayerHandle::ReadyToSpawn(handle),
+            Err(e) => {
+                warn!("Letting {:?} in, as checking for a ban failed: {:#}", path, e);
+                PlayerHandle::ReadyToSpawn(handle)
+            },
+        });
+    }
     handles.0.truncate(MAX_PLAYERS);
+    Ok(())
 }
 
 /// Keeps characters up to date with their most recent WASM AI.
 ///
 /// Note that this supports changing name live, but not teams, out of fairness. Teams can be changed
 /// on death/respawn though.
+///
+/// Files are often written in several chunks, each firing its own event, so a player is only
+/// reloaded once its file has been left alone for `RELOAD_DEBOUNCE`.
 fn live_brain_reload_system(
     assets: Res<Assets<WasmPlayerAsset>>,
     wasm_engine: Res<wasmtime::Engine>,
-    mut players: Query<
-        (
-            Entity,
-            &mut ExternalCrateComponent<Instance>,
-            &mut ExternalCrateComponent<Store<()>>,
-            &mut PlayerName,
-            &Handle<WasmPlayerAsset>,
-        ),
-        With<Player>,
-    >,
+    asset_server: Res<AssetServer>,
+    reserved_names: Res<ReservedNames>,
+    settings: Res<GameSettings>,
+    time: Res<Time>,
+    mut players: Query<(
+        Entity,
+        &mut ExternalCrateComponent<Instance>,
+        &mut ExternalCrateComponent<Store<()>>,
+        &mut PlayerName,
+        &mut Player,
+        &PanicReport,
+        &Handle<WasmPlayerAsset>,
+    )>,
     mut player_name_text: Query<(&mut Text, &Parent), With<PlayerNameMarker>>,
     mut events: EventReader<AssetEvent<WasmPlayerAsset>>,
+    mut reload_events: EventWriter<PlayerReloadEvent>,
+    mut last_modified: Local<HashMap<Handle<WasmPlayerAsset>, Duration>>,
 ) -> Result<()> {
-    let changed_handles = events.iter().filter_map(|e| match e {
-        AssetEvent::Modified { handle } => Some(handle),
-        _ => None,
-    });
+    let settled_handles =
+        take_settled(&mut last_modified, events.iter(), time.time_since_startup());
 
-    for handle in changed_handles {
-        for (entity, mut instance, mut store, mut player_name, player_handle) in players.iter_mut()
+    for handle in settled_handles {
+        for (
+            entity,
+            mut instance,
+            mut store,
+            mut player_name,
+            mut player,
+            panic_report,
+            player_handle,
+        ) in players.iter_mut()
         {
-            if handle.id == player_handle.id {
-                let wasm_bytes = assets
-                    .get(handle)
-                    .ok_or_else(|| anyhow!("Wasm asset not found at runtime"))?
-                    .bytes
-                    .clone();
-                let module = wasmtime::Module::new(&wasm_engine, wasm_bytes)?;
-                let mut store = &mut **store;
-                **instance = wasmtime::Instance::new(&mut store, &module, &[])?;
-
-                if let Ok(name) = wasm_name(store, &instance) {
-                    let name = filter_name(&name, MAX_NAME_LENGTH);
-                    player_name.0 = name.clone();
-                    for mut text in player_name_text
-                        .iter_mut()
-                        .filter_map(|(text, p)| (p.get() == entity).then_some(text))
-                    {
-                        text.sections[0].value = name.clone();
-                    }
+            if handle.id != player_handle.id {
+                continue;
+            }
+            let asset =
+                assets.get(&handle).ok_or_else(|| anyhow!("Wasm asset not found at runtime"))?;
+            // The previous instance keeps running if the new one can't be set up.
+            let store = &mut **store;
+            let is_binary = asset_server
+                .get_handle_path(&handle)
+                .map_or(true, |path| path.path().extension() == Some(OsStr::new("wasm")));
+            let reloaded = check_complete(&asset.bytes, is_binary)
+                .and_then(|()| wasmtime::Module::new(&wasm_engine, &asset.bytes))
+                .and_then(|module| instantiate(&wasm_engine, store, &module, panic_report));
+            let new_instance = match reloaded {
+                Ok(new_instance) => new_instance,
+                Err(e) => {
+                    warn!("Not reloading {}: {:#}", player_name.0, e);
+                    reload_events.send(PlayerReloadEvent {
+                        name: player_name.0.clone(),
+                        result: Err(format!("{:#}", e)),
+                    });
+                    continue;
+                },
+            };
+            // Players that support it carry their state over, anything going wrong with the
+            // handoff only loses it.
+            let saved_state = save_state(store, **instance).unwrap_or_else(|e| {
+                warn!("Not carrying over the state of {}: {:#}", player_name.0, e);
+                None
+            });
+            **instance = new_instance;
+            player.restart_first_turn(store, settings.rules.fuel_per_tick)?;
+            if let Some(state) = saved_state {
+                match load_state(store, **instance, &state) {
+                    Ok(true) => info!("{} kept {} bytes of state.", player_name.0, state.len()),
+                    Ok(false) => {},
+                    Err(e) => warn!("{} failed to load its state: {:#}", player_name.0, e),
                 }
             }
+            // A trap during the handoff isn't a reason to ban the player later on.
+            panic_report.take();
+
+            if let Ok(name) = wasm_name(store, &instance) {
+                let file_stem = PlayerHandle::file_stem(&handle, &asset_server);
+                let name = sanitize_and_authorize_name(
+                    &name,
+                    file_stem.as_deref(),
+                    MAX_NAME_LENGTH,
+                    &reserved_names,
+                );
+                player_name.0 = name.clone();
+                for mut text in player_name_text
+                    .iter_mut()
+                    .filter_map(|(text, p)| (p.get() == entity).then_some(text))
+                {
+                    text.sections[0].value = name.clone();
+                }
+            }
+            reload_events.send(PlayerReloadEvent { name: player_name.0.clone(), result: Ok(()) });
         }
     }
 
     Ok(())
 }
 
+/// Notes when files were modified, and takes the handles of those that have now been left
+/// alone for `RELOAD_DEBOUNCE`.
+fn take_settled<'a>(
+    last_modified: &mut HashMap<Handle<WasmPlayerAsset>, Duration>,
+    events: impl Iterator<Item = &'a AssetEvent<WasmPlayerAsset>>,
+    now: Duration,
+) -> Vec<Handle<WasmPlayerAsset>> {
+    for event in events {
+        if let AssetEvent::Modified { handle } = event {
+            last_modified.insert(handle.clone_weak(), now);
+        }
+    }
+    let settled = last_modified
+        .iter()
+        .filter(|(_, modified)| now - **modified >= RELOAD_DEBOUNCE)
+        .map(|(handle, _)| handle.clone_weak())
+        .collect::<Vec<_>>();
+    for handle in &settled {
+        last_modified.remove(handle);
+    }
+    settled
+}
+
+/// Rejects binary `.wasm` files that don't start like one, which is typically a file still
+/// being written. Textual `.wat` files have no such header, and are left to the compiler.
+fn check_complete(bytes: &[u8], is_binary: bool) -> Result<()> {
+    if is_binary && (bytes.len() < WASM_HEADER_LENGTH || !bytes.starts_with(WASM_MAGIC)) {
+        return Err(anyhow!("file incomplete"));
+    }
+    Ok(())
+}
+
+/// Whether the file at `path` is one its uploader is banned until a new upload for.
+fn banned_until_reupload(path: &Path, offense_log: &mut OffenseLog) -> Result<bool> {
+    let api_key = match path.file_stem() {
+        Some(stem) => stem.to_string_lossy(),
+        None => return Ok(false),
+    };
+    if !offense_log.is_banned_until_reupload(&api_key) {
+        return Ok(false);
+    }
+    let bytes = fs::read(path).with_context(|| format!("reading {:?}", path))?;
+    offense_log.is_banned_file(&api_key, &bytes)
+}
+
+/// Records the offense of every player newly banned, and extends its ban until a new upload
+/// when it's a repeat offense.
+fn offense_system(
+    mut handles: ResMut<PlayerHandles>,
+    assets: Res<Assets<WasmPlayerAsset>>,
+    asset_server: Res<AssetServer>,
+    round: Res<Round>,
+    mut offense_log: ResMut<OffenseLog>,
+    mut banned: Local<HashSet<HandleId>>,
+) -> Result<()> {
+    let misbehaving = handles
+        .0
+        .iter()
+        .filter(|h| matches!(h, PlayerHandle::Misbehaved(..)))
+        .map(|h| h.inner().id)
+        .collect::<HashSet<_>>();
+    for id in misbehaving.iter().filter(|id| !banned.contains(*id)) {
+        let api_key = match PlayerHandle::file_stem(*id, &asset_server) {
+            Some(api_key) => api_key,
+            None => continue,
+        };
+        // Files picked up while banned until a new upload aren't a new offense.
+        if offense_log.is_banned_until_reupload(&api_key) {
+            continue;
+        }
+        let bytes = assets.get(*id).map(|a| a.bytes.as_slice()).unwrap_or_default();
+        if offense_log.record(&api_key, round.0, bytes)? == BanLength::UntilReupload {
+            info!("{} is banned until a new upload, after failing repeatedly.", api_key);
+            if let Some(PlayerHandle::Misbehaved(_, reason)) =
+                handles.0.iter_mut().find(|h| h.inner().id == *id)
+            {
+                *reason = format!("{} ({})", reason, REUPLOAD_REQUIRED.to_lowercase());
+            }
+        }
+    }
+    *banned = misbehaving;
+    Ok(())
+}
+
 /// Returns "banned" (misbehaving) players to the arena when a new AI is uploaded for them,
-/// assuming that the upload fixes the issue.
+/// assuming that the upload fixes the issue. This also lifts bans until a new upload.
 fn unban_system(
     mut handles: ResMut<PlayerHandles>,
     mut events: EventReader<AssetEvent<WasmPlayerAsset>>,
-) {
+    asset_server: Res<AssetServer>,
+    mut offense_log: ResMut<OffenseLog>,
+) -> Result<()> {
     let changed_handles = events.iter().filter_map(|e| match e {
         AssetEvent::Modified { handle } => Some(handle),
         _ => None,
@@ -178,8 +441,60 @@ fn unban_system(
     for changed_handle in changed_handles {
         if let Some(handle) = handles.0.iter_mut().find(|h| h.inner() == changed_handle) {
             if matches!(handle, PlayerHandle::Misbehaved(..)) {
-                *handle = PlayerHandle::ReadyToSpawn(changed_handle.clone())
+                *handle = PlayerHandle::ReadyToSpawn(changed_handle.clone());
+                if let Some(api_key) = PlayerHandle::file_stem(changed_handle, &asset_server) {
+                    offense_log.lift(&api_key)?;
+                }
             }
         }
     }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::asset::HandleId;
+
+    use super::*;
+
+    fn modified(handle: &Handle<WasmPlayerAsset>) -> AssetEvent<WasmPlayerAsset> {
+        AssetEvent::Modified { handle: handle.clone_weak() }
+    }
+
+    #[test]
+    fn bursts_of_modifications_reload_once_settled() {
+        let handle = Handle::weak(HandleId::random::<WasmPlayerAsset>());
+        let mut last_modified = HashMap::default();
+        let ms = Duration::from_millis;
+
+        assert!(take_settled(&mut last_modified, [modified(&handle)].iter(), ms(0)).is_empty());
+        assert!(take_settled(&mut last_modified, [modified(&handle)].iter(), ms(100)).is_empty());
+        assert!(take_settled(&mut last_modified, [].iter(), ms(300)).is_empty());
+        assert!(take_settled(&mut last_modified, [].iter(), ms(599)).is_empty());
+        assert_eq!(take_settled(&mut last_modified, [].iter(), ms(600)), vec![handle]);
+        assert!(take_settled(&mut last_modified, [].iter(), ms(1200)).is_empty());
+    }
+
+    #[test]
+    fn files_settle_independently() {
+        let first = Handle::weak(HandleId::random::<WasmPlayerAsset>());
+        let second = Handle::weak(HandleId::random::<WasmPlayerAsset>());
+        let mut last_modified = HashMap::default();
+        let ms = Duration::from_millis;
+
+        take_settled(&mut last_modified, [modified(&first)].iter(), ms(0));
+        take_settled(&mut last_modified, [modified(&second)].iter(), ms(200));
+        assert_eq!(take_settled(&mut last_modified, [].iter(), ms(500)), vec![first]);
+        assert_eq!(take_settled(&mut last_modified, [].iter(), ms(700)), vec![second]);
+    }
+
+    #[test]
+    fn incomplete_wasm_files_are_rejected() {
+        let header = b"\0asm\x01\0\0\0";
+        assert!(check_complete(header, true).is_ok());
+        assert!(check_complete(&header[..4], true).is_err());
+        assert!(check_complete(b"", true).is_err());
+        assert!(check_complete(b"(module)", true).is_err());
+        assert!(check_complete(b"(module)", false).is_ok());
+    }
 }
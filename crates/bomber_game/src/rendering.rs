@@ -4,10 +4,19 @@ pub const TILE_WIDTH_PX: f32 = 64.0 * SCALE_PX;
 pub const TILE_HEIGHT_PX: f32 = 64.0 * SCALE_PX;
 
 pub const GAME_MAP_Z: f32 = 0.0;
-pub const GAME_OBJECT_Z: f32 = GAME_MAP_Z + 1.0;
-pub const FLAME_Z: f32 = GAME_OBJECT_Z + 1.0;
-pub const PLAYER_Z: f32 = FLAME_Z + 1.0;
-pub const VICTORY_SCREEN_Z: f32 = PLAYER_Z + 1.0;
+// Decorations drawn over the floor, but under anything standing on it.
+pub const HILL_SPARKLE_Z: f32 = GAME_MAP_Z + 0.5;
+// Each kind of game object has its own layer, so sprites sharing a tile (e.g. a bomb dropped
+// on a power up) don't swap draw order from frame to frame.
+pub const CRATE_Z: f32 = GAME_MAP_Z + 1.0;
+pub const POWER_UP_Z: f32 = CRATE_Z + 1.0;
+pub const BOMB_Z: f32 = POWER_UP_Z + 1.0;
+pub const PLAYER_Z: f32 = BOMB_Z + 1.0;
+pub const FLAME_Z: f32 = PLAYER_Z + 1.0;
+pub const PARTICLE_Z: f32 = FLAME_Z + 1.0;
+// Spectator overlays go above everything on the map, but under the victory screen.
+pub const OVERLAY_Z: f32 = PARTICLE_Z + 1.0;
+pub const VICTORY_SCREEN_Z: f32 = OVERLAY_Z + 1.0;
 pub const VICTORY_SCREEN_ITEMS_Z: f32 = VICTORY_SCREEN_Z + 1.0;
 
 pub const PLAYER_WIDTH_PX: f32 = 64.0 * SCALE_PX;
@@ -0,0 +1,122 @@
+//! Keeps players from impersonating each other (or the organizers) on the projector.
+//! Names can be reserved in an optional `reserved_names.toml` file, which maps each
+//! reserved name to the API key of the only team allowed to use it:
+//!
+//! ```toml
+//! "tonari staff" = "<api key>"
+//! ```
+//!
+//! Uploaded `.wasm` files are named after the API key they were uploaded with, which is
+//! how the game tells who is claiming a name.
+
+use anyhow::{Context, Result};
+use bevy::prelude::*;
+use std::{collections::HashMap, fs, io::ErrorKind};
+
+use crate::player::{filter_name, DEFAULT_PLAYER_NAME, MAX_NAME_LENGTH};
+
+pub const RESERVED_NAMES_FILE: &str = "reserved_names.toml";
+
+/// Reserved player names, mapped to the API key allowed to use them. Names are keyed the way
+/// they would show after `filter_name`, lowercased, so neither a name too long to display
+/// whole nor a change of case gets around the reservation.
+#[derive(Default, Clone, Debug)]
+pub struct ReservedNames(HashMap<String, ReservedName>);
+
+#[derive(Clone, Debug)]
+struct ReservedName {
+    /// The name as it displays.
+    name: String,
+    api_key: String,
+}
+
+impl ReservedNames {
+    /// Reads the reserved names, or reserves none if the file doesn't exist.
+    pub fn load() -> Result<Self> {
+        let text = match fs::read_to_string(RESERVED_NAMES_FILE) {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e).with_context(|| format!("reading {}", RESERVED_NAMES_FILE)),
+        };
+        let names: HashMap<String, String> =
+            toml::from_str(&text).with_context(|| format!("parsing {}", RESERVED_NAMES_FILE))?;
+        info!("Loaded {} reserved names from {}.", names.len(), RESERVED_NAMES_FILE);
+        Ok(Self::new(names))
+    }
+
+    fn new(names: HashMap<String, String>) -> Self {
+        let mut reserved = HashMap::new();
+        for (name, api_key) in names {
+            let name = filter_name(&name, MAX_NAME_LENGTH, DEFAULT_PLAYER_NAME);
+            if let Some(other) =
+                reserved.insert(name.to_lowercase(), ReservedName { name, api_key })
+            {
+                warn!("{:?} is reserved more than once after shortening, keeping one.", other.name);
+            }
+        }
+        Self(reserved)
+    }
+}
+
+/// Filters a player name like `filter_name`, then makes sure a reserved name is only used
+/// by the `.wasm` file uploaded with the matching API key (`file_stem`). Anyone else claiming
+/// it is shown under their own reserved name if they have one, or with an asterisk otherwise.
+pub fn sanitize_and_authorize_name(
+    name: &str,
+    file_stem: Option<&str>,
+    length: usize,
+    reserved_names: &ReservedNames,
+) -> String {
+    let name = filter_name(name, length, DEFAULT_PLAYER_NAME);
+    match reserved_names.0.get(&name.to_lowercase()) {
+        Some(reserved) if Some(reserved.api_key.as_str()) != file_stem => {
+            let own_name = reserved_names
+                .0
+                .values()
+                .find_map(|r| (Some(r.api_key.as_str()) == file_stem).then(|| r.name.clone()));
+            own_name.unwrap_or_else(|| {
+                warn!("{:?} is reserved, but was claimed by {:?}.", name, file_stem);
+                format!("{}*", filter_name(&name, length.saturating_sub(1), DEFAULT_PLAYER_NAME))
+            })
+        },
+        _ => name,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reserved(names: &[(&str, &str)]) -> ReservedNames {
+        ReservedNames::new(names.iter().map(|(n, k)| (n.to_string(), k.to_string())).collect())
+    }
+
+    #[test]
+    fn long_reserved_name_is_protected() {
+        let names = reserved(&[("tonari staff", "staff-key")]);
+        let claimed =
+            sanitize_and_authorize_name("tonari staff", Some("other-key"), MAX_NAME_LENGTH, &names);
+        assert_eq!(claimed, "tonari st*");
+        let owned =
+            sanitize_and_authorize_name("tonari staff", Some("staff-key"), MAX_NAME_LENGTH, &names);
+        assert_eq!(owned, "tonari sta");
+    }
+
+    #[test]
+    fn reserved_name_is_protected_regardless_of_case() {
+        let names = reserved(&[("Crab", "crab-key")]);
+        let claimed =
+            sanitize_and_authorize_name("cRAB", Some("other-key"), MAX_NAME_LENGTH, &names);
+        assert_eq!(claimed, "cRAB*");
+        let owned = sanitize_and_authorize_name("CRAB", Some("crab-key"), MAX_NAME_LENGTH, &names);
+        assert_eq!(owned, "CRAB");
+    }
+
+    #[test]
+    fn impostor_with_own_reserved_name_gets_it() {
+        let names = reserved(&[("Crab", "crab-key"), ("Lobster", "lobster-key")]);
+        let claimed =
+            sanitize_and_authorize_name("crab", Some("lobster-key"), MAX_NAME_LENGTH, &names);
+        assert_eq!(claimed, "Lobster");
+    }
+}
@@ -0,0 +1,206 @@
+//! Defines a Bevy plugin that writes the results of each round to a `results.json`
+//! file in the round folder, for teams and organizers to inspect after the fact. A
+//! leaderboard accumulating the scores of every round since the game started is kept
+//! up to date next to the round folders.
+
+use anyhow::{Context, Result};
+use bevy::{prelude::*, utils::HashMap};
+use bomber_shared::{round_folder, BuildMeta};
+use serde::Serialize;
+use std::fs;
+
+use crate::{
+    game_map::{ForcedMap, GameMap},
+    log_recoverable_error,
+    player::Player,
+    player_hotswap::WasmPlayerAsset,
+    score::Score,
+    settings::{GameSettings, Rules, RulesOverride},
+    stagnation::StagnationWatcher,
+    state::{AppState, Round, RoundsFolder},
+    stats::RoundStats,
+};
+
+const RESULTS_FILENAME: &str = "results.json";
+const LEADERBOARD_FILENAME: &str = "leaderboard.json";
+
+pub struct ResultsPlugin;
+
+#[derive(Serialize, Debug)]
+pub struct RoundResults {
+    pub round: u32,
+    pub name: String,
+    pub map: String,
+    /// Whether an operator forced the map, rather than it coming up in the rotation.
+    pub map_forced: bool,
+    pub rules: Rules,
+    /// Rules changed for this round only, which are already reflected in `rules`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rules_override: Option<RulesOverride>,
+    /// Times loot crates were dropped near the hill because nothing happened for a while.
+    pub stalemate_nudges: u32,
+    /// Sorted by descending score.
+    pub players: Vec<PlayerResults>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PlayerResults {
+    pub name: String,
+    pub team_name: String,
+    /// Score at the end of the round, or `None` if the player wasn't alive by then.
+    pub score: Option<u32>,
+    pub turns: u32,
+    pub failed_actions: u32,
+    pub invalid_actions: u32,
+    pub crates_destroyed: u32,
+    /// Teammates caught in the player's blasts, whether or not friendly fire was on.
+    pub team_kills: u32,
+    /// Points given as a head start for joining the round late, included in `score`.
+    pub handicap: u32,
+    /// World ticks the player led by so much that its hill points were halved.
+    pub throttled_ticks: u32,
+    /// Turns on which the farthest tiles were left out of the view of the bot, to fit the
+    /// buffer it reports.
+    pub clipped_turns: u32,
+    pub min_fuel: u64,
+    pub mean_fuel: u64,
+    pub max_fuel: u64,
+    pub mean_latency_us: u64,
+    pub max_latency_us: u64,
+    /// Build of the bot that was live at the end of the round, if it embeds its metadata.
+    pub build: Option<BuildMeta>,
+    /// Moves that ended on each tile, as `heatmap[y][x]` with the bottom row first.
+    pub heatmap: Vec<Vec<u32>>,
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct LeaderboardEntry {
+    pub name: String,
+    pub team_name: String,
+    pub rounds: u32,
+    pub total_score: u32,
+}
+
+/// Cumulative scores since the game started, keyed by player and team name.
+#[derive(Default)]
+pub struct Leaderboard(HashMap<(String, String), LeaderboardEntry>);
+
+impl Leaderboard {
+    fn record(&mut self, results: &RoundResults) {
+        for player in &results.players {
+            let key = (player.name.clone(), player.team_name.clone());
+            let entry = self.0.entry(key).or_insert_with(|| LeaderboardEntry {
+                name: player.name.clone(),
+                team_name: player.team_name.clone(),
+                ..Default::default()
+            });
+            entry.rounds += 1;
+            entry.total_score += player.score.unwrap_or_default();
+        }
+    }
+
+    /// Entries sorted by descending total score.
+    pub fn ranking(&self) -> Vec<LeaderboardEntry> {
+        let mut entries = self.0.values().cloned().collect::<Vec<_>>();
+        entries.sort_by(|a, b| b.total_score.cmp(&a.total_score));
+        entries
+    }
+}
+
+impl Plugin for ResultsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Leaderboard>().add_system_set(
+            SystemSet::on_enter(AppState::VictoryScreen)
+                .with_system(write_results_system.chain(log_recoverable_error)),
+        );
+    }
+}
+
+impl RoundResults {
+    /// Gathers the results of the round that just finished. Meant to be called on entering
+    /// the victory screen, while the players and game map are still around.
+    pub fn collect(
+        player_query: &Query<(&Handle<WasmPlayerAsset>, &Score), With<Player>>,
+        assets: &Assets<WasmPlayerAsset>,
+        stats: &RoundStats,
+        round: &Round,
+        game_map: &GameMap,
+        map_forced: bool,
+        settings: &GameSettings,
+        stalemate_nudges: u32,
+    ) -> Self {
+        // The round counter is advanced as soon as a round finishes.
+        let finished_round = Round(round.0 - 1);
+
+        let mut players = stats
+            .0
+            .iter()
+            .map(|(handle_id, stats)| PlayerResults {
+                name: stats.name.clone(),
+                team_name: stats.team_name.clone(),
+                score: player_query.iter().find_map(|(h, s)| (h.id == *handle_id).then_some(s.0)),
+                turns: stats.turns,
+                failed_actions: stats.failed_actions,
+                invalid_actions: stats.invalid_actions,
+                crates_destroyed: stats.crates_destroyed,
+                team_kills: stats.team_kills,
+                handicap: stats.handicap,
+                throttled_ticks: stats.throttled_ticks,
+                clipped_turns: stats.clipped_turns,
+                min_fuel: stats.min_fuel,
+                mean_fuel: stats.mean_fuel(),
+                max_fuel: stats.max_fuel,
+                mean_latency_us: stats.mean_latency().as_micros() as u64,
+                max_latency_us: stats.max_latency.as_micros() as u64,
+                build: assets.get(*handle_id).and_then(|asset| asset.build.clone()),
+                heatmap: stats.heatmap.rows().to_vec(),
+            })
+            .collect::<Vec<_>>();
+        players.sort_by(|a, b| b.score.cmp(&a.score));
+
+        Self {
+            round: finished_round.0,
+            name: finished_round.name(),
+            map: game_map.name.to_owned(),
+            map_forced,
+            rules: settings.rules,
+            rules_override: settings.rules_override.clone(),
+            stalemate_nudges,
+            players,
+        }
+    }
+}
+
+fn write_results_system(
+    player_query: Query<(&Handle<WasmPlayerAsset>, &Score), With<Player>>,
+    assets: Res<Assets<WasmPlayerAsset>>,
+    stats: Res<RoundStats>,
+    round: Res<Round>,
+    rounds_folder: Res<RoundsFolder>,
+    mut leaderboard: ResMut<Leaderboard>,
+    game_map_query: Query<(&GameMap, Option<&ForcedMap>)>,
+    watcher: Res<StagnationWatcher>,
+    settings: Res<GameSettings>,
+) -> Result<()> {
+    let (game_map, forced) = game_map_query.single();
+    let results = RoundResults::collect(
+        &player_query,
+        &assets,
+        &stats,
+        &round,
+        game_map,
+        forced.is_some(),
+        &settings,
+        watcher.nudges,
+    );
+    let path = round_folder(&rounds_folder.0, results.round).join(RESULTS_FILENAME);
+    fs::write(&path, serde_json::to_string_pretty(&results)?)
+        .with_context(|| format!("writing {:?}", path))?;
+    info!("Round {} results written to {:?}.", results.round, path);
+
+    leaderboard.record(&results);
+    let path = rounds_folder.0.join(LEADERBOARD_FILENAME);
+    fs::write(&path, serde_json::to_string_pretty(&leaderboard.ranking())?)
+        .with_context(|| format!("writing {:?}", path))?;
+    Ok(())
+}
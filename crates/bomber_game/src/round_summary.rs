@@ -0,0 +1,270 @@
+//! Defines a Bevy plugin that saves a picture of the victory screen to `rounds/N/summary.png`
+//! at the end of every round, for sharing on social media.
+//!
+//! Bevy can't screenshot a window, so a second camera renders the victory screen into an
+//! image, which the render graph copies into a buffer for a few frames while the podium shows
+//! up. The buffer is then read back from the GPU, which completes asynchronously.
+
+use std::{
+    num::NonZeroU32,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use anyhow::{Context, Result};
+use bevy::{
+    prelude::*,
+    render::{
+        camera::RenderTarget,
+        main_graph::node::CAMERA_DRIVER,
+        render_asset::RenderAssets,
+        render_graph::{self, NodeRunError, RenderGraph, RenderGraphContext},
+        render_resource::{
+            Buffer, BufferDescriptor, BufferUsages, Extent3d, ImageCopyBuffer, ImageDataLayout,
+            MapMode, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages,
+        },
+        renderer::{RenderContext, RenderDevice},
+        texture::BevyDefault,
+        RenderApp, RenderStage,
+    },
+};
+use bomber_shared::round_folder;
+
+use crate::state::{AppState, Round, RoundsFolder};
+
+pub struct RoundSummaryPlugin;
+
+const SUMMARY_FILENAME: &str = "summary.png";
+const SUMMARY_CAPTURE_NODE: &str = "round_summary_capture";
+/// Frames rendered before reading the picture back, so the podium is in place.
+const CAPTURE_FRAMES: u32 = 5;
+/// Rows copied out of a texture must start at multiples of this many bytes.
+const COPY_BYTES_PER_ROW_ALIGNMENT: u32 = 256;
+const BYTES_PER_PIXEL: u32 = 4;
+
+/// Image the capture camera renders into, and the buffer the render graph copies it to.
+#[derive(Clone)]
+struct CopyRequest {
+    image: Handle<Image>,
+    buffer: Buffer,
+    size: Extent3d,
+    padded_bytes_per_row: u32,
+}
+
+/// Progress of the summary picture of the round that just finished.
+enum SummaryCapture {
+    Idle,
+    /// The capture camera is rendering the victory screen, which is copied out every frame.
+    Rendering {
+        path: PathBuf,
+        camera: Entity,
+        copy: CopyRequest,
+        frames_left: u32,
+    },
+    /// Waiting for the GPU to map the buffer, which happens once bevy submits more work.
+    Reading {
+        path: PathBuf,
+        copy: CopyRequest,
+        mapped: Arc<Mutex<Option<bool>>>,
+    },
+}
+
+/// Render graph node copying the capture image into its buffer, while a capture is rendering.
+struct SummaryCaptureNode;
+
+impl Plugin for RoundSummaryPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(SummaryCapture::Idle)
+            .add_system_set(
+                SystemSet::on_enter(AppState::VictoryScreen).with_system(start_capture_system),
+            )
+            .add_system(capture_system);
+
+        let render_app = match app.get_sub_app_mut(RenderApp) {
+            Ok(render_app) => render_app,
+            Err(_) => return,
+        };
+        render_app.add_system_to_stage(RenderStage::Extract, extract_copy_request);
+        let mut graph = render_app.world.resource_mut::<RenderGraph>();
+        graph.add_node(SUMMARY_CAPTURE_NODE, SummaryCaptureNode);
+        graph
+            .add_node_edge(CAMERA_DRIVER, SUMMARY_CAPTURE_NODE)
+            .expect("Failed to schedule the round summary capture");
+    }
+}
+
+fn start_capture_system(
+    mut capture: ResMut<SummaryCapture>,
+    windows: Res<Windows>,
+    round: Res<Round>,
+    rounds_folder: Res<RoundsFolder>,
+    render_device: Res<RenderDevice>,
+    mut images: ResMut<Assets<Image>>,
+    mut commands: Commands,
+) {
+    if !matches!(*capture, SummaryCapture::Idle) {
+        warn!("Skipping the round summary, as the previous one isn't saved yet.");
+        return;
+    }
+    let window = if let Some(window) = windows.get_primary() { window } else { return };
+
+    // Logical size, so the capture frames the victory screen exactly like the window does.
+    let size = Extent3d {
+        width: (window.width() as u32).max(1),
+        height: (window.height() as u32).max(1),
+        depth_or_array_layers: 1,
+    };
+    let mut image = Image {
+        texture_descriptor: TextureDescriptor {
+            label: Some("round_summary"),
+            size,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::bevy_default(),
+            mip_level_count: 1,
+            sample_count: 1,
+            usage: TextureUsages::TEXTURE_BINDING
+                | TextureUsages::COPY_SRC
+                | TextureUsages::RENDER_ATTACHMENT,
+        },
+        ..default()
+    };
+    image.resize(size);
+    let image = images.add(image);
+
+    let padded_bytes_per_row = (size.width * BYTES_PER_PIXEL + COPY_BYTES_PER_ROW_ALIGNMENT - 1)
+        / COPY_BYTES_PER_ROW_ALIGNMENT
+        * COPY_BYTES_PER_ROW_ALIGNMENT;
+    let buffer = render_device.create_buffer(&BufferDescriptor {
+        label: Some("round_summary"),
+        size: (padded_bytes_per_row * size.height) as u64,
+        usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+
+    let camera = commands
+        .spawn_bundle(Camera2dBundle {
+            camera: Camera { target: RenderTarget::Image(image.clone()), ..default() },
+            ..default()
+        })
+        .insert(UiCameraConfig { show_ui: false })
+        .id();
+
+    // The round counter is advanced as soon as a round finishes.
+    let path = round_folder(&rounds_folder.0, round.0 - 1).join(SUMMARY_FILENAME);
+    *capture = SummaryCapture::Rendering {
+        path,
+        camera,
+        copy: CopyRequest { image, buffer, size, padded_bytes_per_row },
+        frames_left: CAPTURE_FRAMES,
+    };
+}
+
+fn capture_system(
+    mut capture: ResMut<SummaryCapture>,
+    render_device: Res<RenderDevice>,
+    mut commands: Commands,
+) {
+    *capture = match std::mem::replace(&mut *capture, SummaryCapture::Idle) {
+        SummaryCapture::Idle => SummaryCapture::Idle,
+        SummaryCapture::Rendering { path, camera, copy, frames_left } if frames_left > 0 => {
+            SummaryCapture::Rendering { path, camera, copy, frames_left: frames_left - 1 }
+        },
+        SummaryCapture::Rendering { path, camera, copy, .. } => {
+            // Leaving the rendering state also stops the copies, as the buffer can't be
+            // written to while it's mapped.
+            commands.entity(camera).despawn_recursive();
+            let mapped = Arc::new(Mutex::new(None));
+            let callback_mapped = mapped.clone();
+            render_device.map_buffer(&copy.buffer.slice(..), MapMode::Read, move |result| {
+                *callback_mapped.lock().unwrap() = Some(result.is_ok());
+            });
+            SummaryCapture::Reading { path, copy, mapped }
+        },
+        SummaryCapture::Reading { path, copy, mapped } => {
+            let result = *mapped.lock().unwrap();
+            match result {
+                None => SummaryCapture::Reading { path, copy, mapped },
+                Some(false) => {
+                    warn!("Failed to read back the round summary for {:?}.", path);
+                    SummaryCapture::Idle
+                },
+                Some(true) => {
+                    let pixels = unpadded_rgba_pixels(&copy);
+                    copy.buffer.unmap();
+                    let Extent3d { width, height, .. } = copy.size;
+                    // Encoding a PNG takes a while, so it's kept off the frame.
+                    thread::spawn(move || match save_png(&path, &pixels, width, height) {
+                        Ok(()) => info!("Saved the round summary to {:?}.", path),
+                        Err(e) => warn!("Failed to save the round summary: {:#}", e),
+                    });
+                    SummaryCapture::Idle
+                },
+            }
+        },
+    };
+}
+
+/// Reads the mapped buffer back as tightly packed RGBA rows.
+fn unpadded_rgba_pixels(copy: &CopyRequest) -> Vec<u8> {
+    let bytes_per_row = (copy.size.width * BYTES_PER_PIXEL) as usize;
+    let data = copy.buffer.slice(..).get_mapped_range();
+    let mut pixels = data
+        .chunks(copy.padded_bytes_per_row as usize)
+        .take(copy.size.height as usize)
+        .flat_map(|row| &row[..bytes_per_row])
+        .copied()
+        .collect::<Vec<_>>();
+    if TextureFormat::bevy_default() == TextureFormat::Bgra8UnormSrgb {
+        for pixel in pixels.chunks_exact_mut(BYTES_PER_PIXEL as usize) {
+            pixel.swap(0, 2);
+        }
+    }
+    pixels
+}
+
+fn save_png(path: &Path, pixels: &[u8], width: u32, height: u32) -> Result<()> {
+    image::save_buffer(path, pixels, width, height, image::ColorType::Rgba8)
+        .with_context(|| format!("writing {:?}", path))
+}
+
+/// Makes the capture in progress available to `SummaryCaptureNode`, for as long as it's
+/// rendering.
+fn extract_copy_request(capture: Res<SummaryCapture>, mut commands: Commands) {
+    match &*capture {
+        SummaryCapture::Rendering { copy, .. } => commands.insert_resource(copy.clone()),
+        _ => commands.remove_resource::<CopyRequest>(),
+    }
+}
+
+impl render_graph::Node for SummaryCaptureNode {
+    fn run(
+        &self,
+        _graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        let copy = match world.get_resource::<CopyRequest>() {
+            Some(copy) => copy,
+            None => return Ok(()),
+        };
+        // The image may take a frame to reach the GPU.
+        let gpu_image = match world.resource::<RenderAssets<Image>>().get(&copy.image) {
+            Some(gpu_image) => gpu_image,
+            None => return Ok(()),
+        };
+        render_context.command_encoder.copy_texture_to_buffer(
+            gpu_image.texture.as_image_copy(),
+            ImageCopyBuffer {
+                buffer: &copy.buffer,
+                layout: ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(copy.padded_bytes_per_row),
+                    rows_per_image: None,
+                },
+            },
+            copy.size,
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,204 @@
+//! Describes the interface between the game and `.wasm` players in a machine readable way,
+//! so participants can write bots in languages other than Rust. Run the game with
+//! `--export-schema` to print it as JSON instead of starting a round.
+
+use anyhow::{anyhow, Result};
+use bomber_lib::{
+    world::{Direction, Object, PowerUp, Ticks, Tile},
+    Action,
+};
+use bomber_shared::{ABI_VERSION, OPTIONAL_EXPORTS, REQUIRED_EXPORTS};
+use serde::Serialize;
+
+/// Command line flag that prints the schema and exits.
+pub const EXPORT_SCHEMA_FLAG: &str = "--export-schema";
+
+#[derive(Serialize, Debug)]
+struct Schema {
+    /// Bumped whenever the interface changes in a way that breaks existing players.
+    abi_version: u32,
+    /// Functions the host expects the guest module to export, as generated by the
+    /// `bomber_macro::wasm_export` attribute for Rust bots.
+    exports: Vec<&'static str>,
+    /// Functions the host calls only if the guest module exports them.
+    optional_exports: Vec<&'static str>,
+    /// How arguments and return values cross the host/guest boundary.
+    encoding: &'static str,
+    enums: Vec<EnumSchema>,
+}
+
+#[derive(Serialize, Debug)]
+struct EnumSchema {
+    name: &'static str,
+    variants: Vec<VariantSchema>,
+}
+
+#[derive(Serialize, Debug)]
+struct VariantSchema {
+    name: &'static str,
+    discriminant: u32,
+}
+
+/// Lists a sample value of each variant. The discriminants are not written down by hand but
+/// read back from the encoded samples, so they can't drift from what the host sends.
+fn enum_schema<T: Serialize>(
+    name: &'static str,
+    samples: &[(&'static str, T)],
+) -> Result<EnumSchema> {
+    let variants = samples
+        .iter()
+        .map(|(variant, sample)| {
+            let bytes = bincode::serialize(sample)?;
+            let discriminant = bytes
+                .get(..4)
+                .and_then(|b| b.try_into().ok())
+                .map(u32::from_le_bytes)
+                .ok_or_else(|| anyhow!("{}::{} encodes to too few bytes", name, variant))?;
+            Ok(VariantSchema { name: variant, discriminant })
+        })
+        .collect::<Result<_>>()?;
+    Ok(EnumSchema { name, variants })
+}
+
+// The matches below have no purpose other than failing to compile when a variant is added
+// upstream, as a reminder to list it in the schema.
+fn action_samples() -> Vec<(&'static str, Action)> {
+    match Action::StayStill {
+        Action::Move(_) | Action::StayStill | Action::DropBomb | Action::DropBombAndMove(_) => (),
+    }
+    vec![
+        ("Move", Action::Move(Direction::North)),
+        ("StayStill", Action::StayStill),
+        ("DropBomb", Action::DropBomb),
+        ("DropBombAndMove", Action::DropBombAndMove(Direction::North)),
+    ]
+}
+
+fn tile_samples() -> Vec<(&'static str, Tile)> {
+    match Tile::Floor {
+        Tile::Wall | Tile::Floor | Tile::Hill => (),
+    }
+    vec![("Wall", Tile::Wall), ("Floor", Tile::Floor), ("Hill", Tile::Hill)]
+}
+
+fn object_samples() -> Vec<(&'static str, Object)> {
+    match Object::Crate {
+        Object::Bomb { .. } | Object::Crate | Object::PowerUp(_) => (),
+    }
+    vec![
+        ("Bomb", Object::Bomb { fuse_remaining: Ticks(0), range: 0 }),
+        ("Crate", Object::Crate),
+        ("PowerUp", Object::PowerUp(PowerUp::BombRange)),
+    ]
+}
+
+fn power_up_samples() -> Vec<(&'static str, PowerUp)> {
+    match PowerUp::BombRange {
+        PowerUp::BombRange | PowerUp::SimultaneousBombs | PowerUp::VisionRange => (),
+    }
+    vec![
+        ("BombRange", PowerUp::BombRange),
+        ("SimultaneousBombs", PowerUp::SimultaneousBombs),
+        ("VisionRange", PowerUp::VisionRange),
+    ]
+}
+
+fn direction_samples() -> Vec<(&'static str, Direction)> {
+    match Direction::North {
+        Direction::West | Direction::North | Direction::East | Direction::South => (),
+    }
+    vec![
+        ("West", Direction::West),
+        ("North", Direction::North),
+        ("East", Direction::East),
+        ("South", Direction::South),
+    ]
+}
+
+/// Renders the schema as pretty printed JSON.
+pub fn export_schema() -> Result<String> {
+    let schema = Schema {
+        abi_version: ABI_VERSION,
+        exports: REQUIRED_EXPORTS.to_vec(),
+        optional_exports: OPTIONAL_EXPORTS.to_vec(),
+        encoding: "bincode (little endian, fixed width integers, u32 enum discriminants)",
+        enums: vec![
+            enum_schema("Action", &action_samples())?,
+            enum_schema("Tile", &tile_samples())?,
+            enum_schema("Object", &object_samples())?,
+            enum_schema("PowerUp", &power_up_samples())?,
+            enum_schema("Direction", &direction_samples())?,
+        ],
+    };
+    Ok(serde_json::to_string_pretty(&schema)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::de::DeserializeOwned;
+    use serde_json::Value;
+
+    use super::*;
+
+    /// Encodes each sample with the discriminant the schema lists for it, and checks the host
+    /// decodes it back into the same value.
+    fn assert_round_trips<T: Serialize + DeserializeOwned + std::fmt::Debug>(
+        schema: &Value,
+        name: &str,
+        samples: &[(&'static str, T)],
+    ) {
+        let enum_schema = schema["enums"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|e| e["name"] == name)
+            .unwrap_or_else(|| panic!("{} is missing from the schema", name));
+        let variants = enum_schema["variants"].as_array().unwrap();
+        assert_eq!(variants.len(), samples.len());
+        for (variant, sample) in samples {
+            let discriminant = variants
+                .iter()
+                .find(|v| v["name"] == *variant)
+                .and_then(|v| v["discriminant"].as_u64())
+                .unwrap_or_else(|| panic!("{}::{} is missing from the schema", name, variant));
+            let mut bytes = (discriminant as u32).to_le_bytes().to_vec();
+            bytes.extend_from_slice(&bincode::serialize(sample).unwrap()[4..]);
+            let decoded: T = bincode::deserialize(&bytes).unwrap();
+            assert_eq!(format!("{:?}", decoded), format!("{:?}", sample));
+        }
+    }
+
+    #[test]
+    fn schema_round_trips_every_enum() {
+        let schema: Value = serde_json::from_str(&export_schema().unwrap()).unwrap();
+        assert_round_trips(&schema, "Action", &action_samples());
+        assert_round_trips(&schema, "Tile", &tile_samples());
+        assert_round_trips(&schema, "Object", &object_samples());
+        assert_round_trips(&schema, "PowerUp", &power_up_samples());
+        assert_round_trips(&schema, "Direction", &direction_samples());
+    }
+
+    #[test]
+    fn schema_lists_every_export() {
+        let schema: Value = serde_json::from_str(&export_schema().unwrap()).unwrap();
+        assert_eq!(schema["abi_version"], ABI_VERSION);
+        assert_eq!(schema["exports"], serde_json::json!(REQUIRED_EXPORTS));
+        for export in [
+            "__wasm_get_buffer_size",
+            "__wasm_shim_save_state",
+            "__wasm_shim_load_state_buffer",
+            "__wasm_shim_load_state",
+            "__wasm_shim_hill_hint",
+            "__wasm_shim_last_death_buffer",
+            "__wasm_shim_last_death",
+            "__wasm_shim_bomb_count",
+            "__wasm_shim_scored",
+        ] {
+            assert!(
+                schema["optional_exports"].as_array().unwrap().iter().any(|e| e == export),
+                "{} is missing from the schema",
+                export
+            );
+        }
+    }
+}
@@ -1,30 +1,162 @@
 use bevy::prelude::*;
-use bomber_lib::world::Tile;
 
-use crate::{game_map::TileLocation, player_behaviour::Player, tick::Tick, ExternalCrateComponent};
+use crate::{
+    game_map::{TileCache, TileLocation},
+    player::{PendingDeaths, Player},
+    player_hotswap::WasmPlayerAsset,
+    settings::GameSettings,
+    stats::RoundStats,
+    tick::{world_turn, TickStep, WorldTurnStep},
+};
 
 pub struct ScorePlugin;
 #[derive(Component, Debug, Copy, Clone)]
 pub struct Score(pub u32);
 
+/// Marks a leader so far ahead that their hill points are halved (`COMEBACK_THRESHOLD`).
+#[derive(Component, Debug, Copy, Clone, Default)]
+pub struct Throttled {
+    /// Ticks spent on the hill while throttled. Every other one of them scores.
+    hill_ticks: u32,
+}
+
+/// Most points a late joiner can be given as a head start.
+const MAX_HANDICAP: u32 = 60;
+
+/// Head start for a player joining a round already underway: half the median score of the
+/// players in the arena, which grows with the time they've had on the hill.
+pub fn late_joiner_handicap(scores: impl Iterator<Item = u32>) -> u32 {
+    median_score(scores).map_or(0, |median| (median / 2).min(MAX_HANDICAP))
+}
+
+/// The median of the given scores (the higher one for an even count), if there are any.
+pub fn median_score(scores: impl Iterator<Item = u32>) -> Option<u32> {
+    let mut scores = scores.collect::<Vec<_>>();
+    scores.sort_unstable();
+    scores.get(scores.len() / 2).copied()
+}
+
+/// The sole leader, if their score is more than `threshold` points over the median. A
+/// threshold of zero turns the comeback mechanic off.
+fn runaway_leader(threshold: u32, scores: &[(Entity, u32)]) -> Option<Entity> {
+    if threshold == 0 {
+        return None;
+    }
+    let median = median_score(scores.iter().map(|(_, score)| *score))?;
+    let &(leader, top) = scores.iter().max_by_key(|(_, score)| *score)?;
+    let sole_leader = scores.iter().filter(|(_, score)| *score == top).count() == 1;
+    (sole_leader && top > median + threshold).then_some(leader)
+}
+
 impl Plugin for ScorePlugin {
     fn build(&self, app: &mut App) {
-        app.add_system(hill_score_system);
+        app.add_system_set(
+            SystemSet::new()
+                .with_run_criteria(world_turn)
+                .label(WorldTurnStep::Scoring)
+                .label(TickStep::Score)
+                .after(WorldTurnStep::Effects)
+                .after(TickStep::ApplyKills)
+                .with_system(hill_score_system),
+        );
     }
 }
 
 fn hill_score_system(
-    mut player_query: Query<(&mut Score, &TileLocation), With<Player>>,
-    tile_query: Query<(&ExternalCrateComponent<Tile>, &TileLocation), Without<Player>>,
-    mut ticks: EventReader<Tick>,
+    settings: Res<GameSettings>,
+    mut player_query: Query<(
+        Entity,
+        &mut Player,
+        &mut Score,
+        &TileLocation,
+        Option<&Throttled>,
+        Option<&Handle<WasmPlayerAsset>>,
+    )>,
+    tile_cache: Res<TileCache>,
+    mut stats: ResMut<RoundStats>,
+    pending_deaths: Res<PendingDeaths>,
+    mut commands: Commands,
 ) {
-    for _ in ticks.iter().filter(|t| matches!(t, Tick::World)) {
-        for (mut score, location) in player_query.iter_mut() {
-            if let Some(Tile::Hill) =
-                tile_query.iter().find_map(|(t, l)| (l == location).then_some(**t))
-            {
-                score.0 += 1;
+    let scores =
+        player_query.iter().map(|(entity, _, score, ..)| (entity, score.0)).collect::<Vec<_>>();
+    let leader = runaway_leader(settings.rules.comeback_threshold, &scores);
+    for (entity, mut player, mut score, location, throttled, handle) in player_query.iter_mut() {
+        // Players killed this tick are still around until their despawn is applied, but
+        // don't score on their way out.
+        if pending_deaths.0.contains(&entity) {
+            continue;
+        }
+        let on_hill = tile_cache.is_hill(location);
+        if leader != Some(entity) {
+            if throttled.is_some() {
+                commands.entity(entity).remove::<Throttled>();
             }
+            score.0 += on_hill as u32;
+            player.record_points(on_hill as u32);
+            continue;
+        }
+
+        let mut throttled = throttled.copied().unwrap_or_default();
+        if on_hill {
+            throttled.hill_ticks += 1;
+            // Halved, rounding up: the first of every two hill ticks scores.
+            score.0 += throttled.hill_ticks % 2;
+            player.record_points(throttled.hill_ticks % 2);
         }
+        commands.entity(entity).insert(throttled);
+        if let Some(handle) = handle {
+            stats.0.entry(handle.id).or_default().throttled_ticks += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::schedule::Stage;
+
+    use super::*;
+    use crate::game_map::GameMap;
+
+    fn player(world: &mut World, location: TileLocation) -> Entity {
+        world.spawn().insert(Player::default()).insert(Score(0)).insert(location).id()
+    }
+
+    #[test]
+    fn players_killed_on_the_hill_score_nothing() {
+        let mut world = World::new();
+        let (_, rows) = GameMap::parse("test", "~s").unwrap();
+        world.insert_resource(TileCache::from_rows(&rows));
+        world.insert_resource(GameSettings::default());
+        world.insert_resource(RoundStats::default());
+        world.insert_resource(PendingDeaths::default());
+        let survivor = player(&mut world, TileLocation(0, 0));
+        let dying = player(&mut world, TileLocation(0, 0));
+        let off_hill = player(&mut world, TileLocation(1, 0));
+        world.resource_mut::<PendingDeaths>().0.insert(dying);
+
+        SystemStage::single_threaded().with_system(hill_score_system).run(&mut world);
+
+        let score = |entity| world.get::<Score>(entity).unwrap().0;
+        assert_eq!((score(survivor), score(dying), score(off_hill)), (1, 0, 0));
+    }
+
+    #[test]
+    fn points_are_kept_for_the_next_turn() {
+        let mut world = World::new();
+        let (_, rows) = GameMap::parse("test", "~s").unwrap();
+        world.insert_resource(TileCache::from_rows(&rows));
+        world.insert_resource(GameSettings::default());
+        world.insert_resource(RoundStats::default());
+        world.insert_resource(PendingDeaths::default());
+        let on_hill = player(&mut world, TileLocation(0, 0));
+        let off_hill = player(&mut world, TileLocation(1, 0));
+
+        // Points add up over world turns until the player's next turn takes them.
+        let mut stage = SystemStage::single_threaded().with_system(hill_score_system);
+        stage.run(&mut world);
+        stage.run(&mut world);
+        let mut take = |entity| world.get_mut::<Player>(entity).unwrap().take_points_scored();
+        assert_eq!((take(on_hill), take(off_hill)), (2, 0));
+        assert_eq!(take(on_hill), 0);
     }
 }
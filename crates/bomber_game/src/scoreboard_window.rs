@@ -0,0 +1,275 @@
+//! Defines a Bevy plugin that opens a second window (`--scoreboard-window`) with a large
+//! leaderboard, the round timer and a kill feed, so events can project the arena and the
+//! scores on separate screens. It's drawn with `Text2d` rather than egui, to stay readable
+//! from the back of the room.
+
+use std::collections::VecDeque;
+
+use bevy::{
+    app::AppExit,
+    ecs::system::EntityCommands,
+    prelude::*,
+    render::{camera::RenderTarget, view::RenderLayers},
+    window::{CreateWindow, WindowClosed, WindowId},
+};
+
+use crate::{
+    game_ui::HIDDEN_NAME,
+    native_player::HouseBot,
+    player::{HideNames, PlayerDespawnedEvent, PlayerName, SpawnPlayerEvent, Team},
+    score::{Score, Throttled},
+    stagnation::StalemateNudgeEvent,
+    state::{Round, RoundTimer},
+};
+
+pub struct ScoreboardWindowPlugin;
+
+/// Render layer of the scoreboard camera and its text, so neither window draws the entities
+/// meant for the other one.
+const SCOREBOARD_LAYER: u8 = 1;
+const WINDOW_WIDTH_PX: f32 = 1280.0;
+const WINDOW_HEIGHT_PX: f32 = 720.0;
+const MARGIN_PX: f32 = 40.0;
+const HEADER_FONT_SIZE: f32 = 56.0;
+const LEADERBOARD_FONT_SIZE: f32 = 44.0;
+const KILL_FEED_FONT_SIZE: f32 = 28.0;
+/// Rows that fit the window at the leaderboard font size, below the header.
+const LEADERBOARD_LENGTH: usize = 10;
+const KILL_FEED_LENGTH: usize = 12;
+
+struct Scoreboard {
+    window: WindowId,
+    font: Handle<Font>,
+}
+
+/// Most recent deaths, bans and late joiners given a handicap, newest first. Entries without
+/// a player are about the arena itself.
+#[derive(Default)]
+struct KillFeed(VecDeque<(Option<PlayerName>, String)>);
+
+/// Marks the camera rendering to the scoreboard window.
+#[derive(Component)]
+pub struct ScoreboardCamera;
+/// Marks every entity belonging to the scoreboard window, to despawn when it's closed.
+#[derive(Component)]
+struct ScoreboardEntity;
+#[derive(Component)]
+struct HeaderText;
+#[derive(Component)]
+struct LeaderboardText;
+#[derive(Component)]
+struct KillFeedText;
+
+impl Plugin for ScoreboardWindowPlugin {
+    fn build(&self, app: &mut App) {
+        let asset_server =
+            app.world.get_resource::<AssetServer>().expect("Failed to retrieve asset server");
+        let scoreboard = Scoreboard {
+            window: WindowId::new(),
+            font: asset_server.load("fonts/space_mono_400.ttf"),
+        };
+        app.insert_resource(scoreboard)
+            .init_resource::<KillFeed>()
+            .add_startup_system(setup)
+            .add_system(kill_feed_system)
+            .add_system(header_text_system)
+            .add_system(leaderboard_text_system)
+            .add_system(kill_feed_text_system)
+            .add_system(window_closed_system);
+    }
+}
+
+fn setup(
+    scoreboard: Res<Scoreboard>,
+    mut create_window_events: EventWriter<CreateWindow>,
+    mut commands: Commands,
+) {
+    create_window_events.send(CreateWindow {
+        id: scoreboard.window,
+        descriptor: WindowDescriptor {
+            width: WINDOW_WIDTH_PX,
+            height: WINDOW_HEIGHT_PX,
+            title: "Bomberman of the Hill — Scoreboard".into(),
+            ..default()
+        },
+    });
+    commands
+        .spawn_bundle(Camera2dBundle {
+            camera: Camera { target: RenderTarget::Window(scoreboard.window), ..default() },
+            ..default()
+        })
+        .insert(UiCameraConfig { show_ui: false })
+        .insert(RenderLayers::layer(SCOREBOARD_LAYER))
+        .insert(ScoreboardCamera)
+        .insert(ScoreboardEntity);
+
+    let top = WINDOW_HEIGHT_PX / 2.0 - MARGIN_PX;
+    let left = -WINDOW_WIDTH_PX / 2.0 + MARGIN_PX;
+    let below_header = top - HEADER_FONT_SIZE * 2.0 - MARGIN_PX;
+    spawn_text(&mut commands, HorizontalAlign::Center, Vec2::new(0.0, top)).insert(HeaderText);
+    spawn_text(&mut commands, HorizontalAlign::Left, Vec2::new(left, below_header))
+        .insert(LeaderboardText);
+    spawn_text(&mut commands, HorizontalAlign::Left, Vec2::new(MARGIN_PX, below_header))
+        .insert(KillFeedText);
+}
+
+/// Spawns an empty block of text hanging from `position`, only visible in the scoreboard
+/// window. Its sections are filled in every frame.
+fn spawn_text<'w, 's, 'a>(
+    commands: &'a mut Commands<'w, 's>,
+    horizontal: HorizontalAlign,
+    position: Vec2,
+) -> EntityCommands<'w, 's, 'a> {
+    let mut entity = commands.spawn();
+    entity
+        .insert_bundle(Text2dBundle {
+            text: Text::default()
+                .with_alignment(TextAlignment { vertical: VerticalAlign::Top, horizontal }),
+            transform: Transform::from_translation(position.extend(0.0)),
+            ..default()
+        })
+        .insert(RenderLayers::layer(SCOREBOARD_LAYER))
+        .insert(ScoreboardEntity);
+    entity
+}
+
+fn section(scoreboard: &Scoreboard, value: String, font_size: f32, color: Color) -> TextSection {
+    TextSection::new(value, TextStyle { font: scoreboard.font.clone(), font_size, color })
+}
+
+fn kill_feed_system(
+    mut spawn_events: EventReader<SpawnPlayerEvent>,
+    mut despawn_events: EventReader<PlayerDespawnedEvent>,
+    mut nudge_events: EventReader<StalemateNudgeEvent>,
+    mut kill_feed: ResMut<KillFeed>,
+) {
+    // Only late joiners start with points, and only on their first spawn.
+    let handicaps = spawn_events
+        .iter()
+        .filter(|SpawnPlayerEvent(.., Score(score))| *score > 0)
+        .map(|SpawnPlayerEvent(name, _, score)| {
+            (Some(name.clone()), format!("joined (+{} handicap)", score.0))
+        });
+    let despawns = despawn_events
+        .iter()
+        .map(|PlayerDespawnedEvent(name, _, reason, _)| (Some(name.clone()), reason.clone()));
+    let nudges =
+        nudge_events.iter().map(|_| (None, "Stalemate! Loot crates dropped near the hill".into()));
+    for entry in handicaps.chain(despawns).chain(nudges) {
+        kill_feed.0.push_front(entry);
+        kill_feed.0.truncate(KILL_FEED_LENGTH);
+    }
+}
+
+fn header_text_system(
+    scoreboard: Res<Scoreboard>,
+    round: Res<Round>,
+    round_timer_query: Query<&RoundTimer>,
+    mut text_query: Query<&mut Text, With<HeaderText>>,
+) {
+    let (timer, mut text) = match (round_timer_query.get_single(), text_query.get_single_mut()) {
+        (Ok(timer), Ok(text)) => (timer, text),
+        _ => return,
+    };
+    let remaining = timer.0.duration() - timer.0.elapsed();
+    let (minutes, seconds) = (remaining.as_secs() / 60, remaining.as_secs() % 60);
+    text.sections = vec![
+        section(
+            &scoreboard,
+            format!("Round {} ends in {minutes}:{seconds:02}\n", round.0),
+            HEADER_FONT_SIZE,
+            Color::WHITE,
+        ),
+        section(&scoreboard, round.name(), HEADER_FONT_SIZE / 2.0, Color::GRAY),
+    ];
+}
+
+fn leaderboard_text_system(
+    scoreboard: Res<Scoreboard>,
+    player_query: Query<(&PlayerName, &Team, &Score, Option<&Throttled>), Without<HouseBot>>,
+    hide_names: Res<HideNames>,
+    mut text_query: Query<&mut Text, With<LeaderboardText>>,
+) {
+    let mut text = if let Ok(text) = text_query.get_single_mut() { text } else { return };
+    let mut entries = player_query.iter().collect::<Vec<_>>();
+    entries.sort_by(|(_, _, Score(a), _), (_, _, Score(b), _)| b.cmp(a));
+    text.sections = entries
+        .into_iter()
+        .take(LEADERBOARD_LENGTH)
+        .enumerate()
+        .flat_map(|(rank, (PlayerName(name), team, score, throttled))| {
+            let name = if hide_names.0 { &team.name } else { name };
+            [
+                section(
+                    &scoreboard,
+                    format!("{:>2}. ", rank + 1),
+                    LEADERBOARD_FONT_SIZE,
+                    Color::GRAY,
+                ),
+                section(&scoreboard, name.clone(), LEADERBOARD_FONT_SIZE, team.color),
+                section(&scoreboard, format!(" {}", score.0), LEADERBOARD_FONT_SIZE, Color::WHITE),
+                // The font has no scales glyph, so an arrow marks halved hill points.
+                section(
+                    &scoreboard,
+                    if throttled.is_some() { " ↓\n" } else { "\n" }.to_owned(),
+                    LEADERBOARD_FONT_SIZE,
+                    Color::YELLOW,
+                ),
+            ]
+        })
+        .collect();
+}
+
+fn kill_feed_text_system(
+    scoreboard: Res<Scoreboard>,
+    kill_feed: Res<KillFeed>,
+    hide_names: Res<HideNames>,
+    mut text_query: Query<&mut Text, With<KillFeedText>>,
+) {
+    let mut text = if let Ok(text) = text_query.get_single_mut() { text } else { return };
+    text.sections = kill_feed
+        .0
+        .iter()
+        .flat_map(|(name, reason)| match name {
+            Some(PlayerName(name)) => {
+                let name = if hide_names.0 { HIDDEN_NAME } else { name.as_str() };
+                vec![
+                    section(&scoreboard, name.to_owned(), KILL_FEED_FONT_SIZE, Color::WHITE),
+                    section(
+                        &scoreboard,
+                        format!(" {}\n", reason),
+                        KILL_FEED_FONT_SIZE,
+                        Color::GRAY,
+                    ),
+                ]
+            },
+            None => vec![section(
+                &scoreboard,
+                format!("{}\n", reason),
+                KILL_FEED_FONT_SIZE,
+                Color::YELLOW,
+            )],
+        })
+        .collect();
+}
+
+/// Closing the scoreboard leaves the game running, while closing the arena ends the game
+/// rather than leave the scoreboard up on its own.
+fn window_closed_system(
+    mut closed_events: EventReader<WindowClosed>,
+    scoreboard: Res<Scoreboard>,
+    scoreboard_entities: Query<Entity, With<ScoreboardEntity>>,
+    mut exit_events: EventWriter<AppExit>,
+    mut commands: Commands,
+) {
+    for WindowClosed { id } in closed_events.iter() {
+        if *id == WindowId::primary() {
+            exit_events.send(AppExit);
+        } else if *id == scoreboard.window {
+            info!("Scoreboard window closed.");
+            for entity in scoreboard_entities.iter() {
+                commands.entity(entity).despawn_recursive();
+            }
+        }
+    }
+}
@@ -0,0 +1,378 @@
+//! Loads the game settings from the environment (or a `.env` file) and the command line,
+//! so organizers can configure a deployment without recompiling the game.
+
+use anyhow::{anyhow, Context, Result};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{env, fs, io::ErrorKind, path::Path, str::FromStr, time::Duration};
+
+use crate::schema::EXPORT_SCHEMA_FLAG;
+
+const DEFAULT_ROUND_DURATION: Duration = Duration::from_secs(3 * 60);
+
+/// Rules that can be made more or less generous, such as for beginner and advanced brackets.
+/// They are included in the round results, so the bracket can be audited.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct Rules {
+    /// WASM fuel each bot may spend per turn (`FUEL_PER_TICK`).
+    pub fuel_per_tick: u64,
+    /// Taxicab distance bots see before vision power-ups (`BASE_VISION`).
+    pub base_vision: u32,
+    /// Bomb range before range power-ups (`BASE_BOMB_RANGE`).
+    pub base_bomb_range: u32,
+    /// World ticks from a bomb being dropped to it exploding (`BOMB_FUSE_TICKS`).
+    pub bomb_fuse_ticks: u32,
+    /// World ticks dead players wait before respawning (`RESPAWN_TICKS`).
+    pub respawn_ticks: u32,
+    /// Gives players joining a round late a head start (`LATE_JOINER_HANDICAP`).
+    pub late_joiner_handicap: bool,
+    /// Whether bombs kill the owner's teammates (`FRIENDLY_FIRE`). Either way, they still
+    /// destroy crates and kill the owner.
+    pub friendly_fire: bool,
+    /// Drops loot crates near the hill when nobody has moved, bombed or scored for a while
+    /// (`BREAK_STALEMATES`).
+    pub break_stalemates: bool,
+    /// Halves the hill points of a leader more than this many points above the median score,
+    /// until the others catch up (`COMEBACK_THRESHOLD`). Zero turns it off.
+    pub comeback_threshold: u32,
+    /// Sends `.wasm` players to the back of the queue once they go this many turns without
+    /// moving, dropping a bomb or scoring (`IDLE_KICK_TICKS`). Zero turns it off.
+    pub idle_kick_ticks: u32,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Self {
+            fuel_per_tick: 1_000_000_000,
+            base_vision: 4,
+            base_bomb_range: 2,
+            bomb_fuse_ticks: 2,
+            respawn_ticks: 3,
+            late_joiner_handicap: false,
+            friendly_fire: true,
+            break_stalemates: false,
+            comeback_threshold: 0,
+            idle_kick_ticks: 0,
+        }
+    }
+}
+
+impl Rules {
+    /// Reads the rules from the environment, falling back to the defaults for those not set.
+    pub fn from_env() -> Result<Self> {
+        let defaults = Self::default();
+        Ok(Self {
+            fuel_per_tick: env_or("FUEL_PER_TICK", defaults.fuel_per_tick)?,
+            base_vision: env_or("BASE_VISION", defaults.base_vision)?,
+            base_bomb_range: env_or("BASE_BOMB_RANGE", defaults.base_bomb_range)?,
+            bomb_fuse_ticks: env_or("BOMB_FUSE_TICKS", defaults.bomb_fuse_ticks)?,
+            respawn_ticks: env_or("RESPAWN_TICKS", defaults.respawn_ticks)?,
+            late_joiner_handicap: env::var("LATE_JOINER_HANDICAP")
+                .map_or(defaults.late_joiner_handicap, |v| v == "1" || v == "true"),
+            friendly_fire: env::var("FRIENDLY_FIRE")
+                .map_or(defaults.friendly_fire, |v| v != "0" && v != "false"),
+            break_stalemates: env::var("BREAK_STALEMATES")
+                .map_or(defaults.break_stalemates, |v| v == "1" || v == "true"),
+            comeback_threshold: env_or("COMEBACK_THRESHOLD", defaults.comeback_threshold)?,
+            idle_kick_ticks: env_or("IDLE_KICK_TICKS", defaults.idle_kick_ticks)?,
+        })
+    }
+}
+
+/// Name of the optional file in a round folder that changes some of the rules for that round
+/// only, for gimmick rounds.
+pub const RULES_OVERRIDE_FILENAME: &str = "settings.override.toml";
+
+/// Rules set by a round's `RULES_OVERRIDE_FILENAME`, on top of the ones the game was started
+/// with. Keys are named after the fields of `Rules`.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RulesOverride {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fuel_per_tick: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub base_vision: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub base_bomb_range: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bomb_fuse_ticks: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub respawn_ticks: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub late_joiner_handicap: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub friendly_fire: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub break_stalemates: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comeback_threshold: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub idle_kick_ticks: Option<u32>,
+}
+
+impl RulesOverride {
+    /// Reads the override of the round folder, or `None` if it has none.
+    pub fn read(round_folder: &Path) -> Result<Option<Self>> {
+        let path = round_folder.join(RULES_OVERRIDE_FILENAME);
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("reading {:?}", path)),
+        };
+        toml::from_str(&text).map(Some).with_context(|| format!("parsing {:?}", path))
+    }
+
+    /// The rules with the overridden ones replaced.
+    pub fn apply(&self, base: Rules) -> Rules {
+        Rules {
+            fuel_per_tick: self.fuel_per_tick.unwrap_or(base.fuel_per_tick),
+            base_vision: self.base_vision.unwrap_or(base.base_vision),
+            base_bomb_range: self.base_bomb_range.unwrap_or(base.base_bomb_range),
+            bomb_fuse_ticks: self.bomb_fuse_ticks.unwrap_or(base.bomb_fuse_ticks),
+            respawn_ticks: self.respawn_ticks.unwrap_or(base.respawn_ticks),
+            late_joiner_handicap: self.late_joiner_handicap.unwrap_or(base.late_joiner_handicap),
+            friendly_fire: self.friendly_fire.unwrap_or(base.friendly_fire),
+            break_stalemates: self.break_stalemates.unwrap_or(base.break_stalemates),
+            comeback_threshold: self.comeback_threshold.unwrap_or(base.comeback_threshold),
+            idle_kick_ticks: self.idle_kick_ticks.unwrap_or(base.idle_kick_ticks),
+        }
+    }
+
+    /// Lists the overridden rules as `key=value`, such as `bomb_fuse_ticks=1, base_bomb_range=5`.
+    pub fn describe(&self) -> String {
+        let fields = match serde_json::to_value(self) {
+            Ok(serde_json::Value::Object(fields)) => fields,
+            _ => return String::new(),
+        };
+        fields
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct GameSettings {
+    /// Endpoint that receives a JSON announcement at the end of every round, if set.
+    pub webhook_url: Option<String>,
+    /// Address to serve the WebSocket live feed on (`LIVE_FEED_ADDRESS`), with the
+    /// `live-feed` feature.
+    pub live_feed_address: Option<String>,
+    /// Shows team names in place of player names (`HIDE_NAMES` env variable), which can
+    /// also be toggled in game.
+    pub hide_names: bool,
+    /// Skips rendering and the UI (`--headless`), for unattended runs.
+    pub headless: bool,
+    /// Number of rounds to play before stopping (`--rounds N`), or `None` to play forever.
+    pub round_limit: Option<u32>,
+    /// Exits once the round limit is reached (`--exit-when-done`), with a non-zero exit
+    /// code if any error was logged along the way.
+    pub exit_when_done: bool,
+    /// How long each round lasts (`--round-seconds N`), three minutes by default.
+    pub round_duration: Duration,
+    /// Rules of the round being played, which are `base_rules` with the round's override on
+    /// top, if any.
+    pub rules: Rules,
+    /// Rules the game was started with.
+    pub base_rules: Rules,
+    /// Rules changed for the round being played, from its `RULES_OVERRIDE_FILENAME`.
+    pub rules_override: Option<RulesOverride>,
+    /// Records the inputs of every `.wasm` player in the round folder (`RECORD_INPUTS`).
+    pub record_inputs: bool,
+    /// Skips screen shake and slow motion on kills (`REDUCED_MOTION`), for motion sensitive
+    /// audiences.
+    pub reduced_motion: bool,
+    /// Opens a second window with a large scoreboard (`--scoreboard-window`), for events
+    /// with a screen to spare.
+    pub scoreboard_window: bool,
+    /// Minimum number of players in the arena, made up with house bots while fewer teams
+    /// have uploaded (`FILL_WITH_HOUSE_BOTS`). Zero disables house bots.
+    pub house_bots: usize,
+    /// Seeds where players spawn every round (`RNG_SEED`), offset by the round number, so the
+    /// same uploads spawn the same way again. Crates, loot and colors are still random.
+    /// Rounds are seeded at random if unset.
+    pub rng_seed: Option<u64>,
+}
+
+/// Settings of a game started without any configuration.
+impl Default for GameSettings {
+    fn default() -> Self {
+        Self {
+            webhook_url: None,
+            live_feed_address: None,
+            hide_names: false,
+            headless: false,
+            round_limit: None,
+            exit_when_done: false,
+            round_duration: DEFAULT_ROUND_DURATION,
+            rules: Rules::default(),
+            base_rules: Rules::default(),
+            rules_override: None,
+            record_inputs: false,
+            reduced_motion: false,
+            scoreboard_window: false,
+            house_bots: 0,
+            rng_seed: None,
+        }
+    }
+}
+
+impl GameSettings {
+    /// Reads the settings, failing on unknown or malformed command line arguments.
+    pub fn load() -> Result<Self> {
+        if let Err(e) = dotenv::dotenv() {
+            info!("Not loading a .env file ({}).", e);
+        }
+
+        let rules = Rules::from_env()?;
+
+        let mut settings = Self {
+            webhook_url: env::var("WEBHOOK_URL").ok().filter(|url| !url.is_empty()),
+            live_feed_address: env::var("LIVE_FEED_ADDRESS").ok().filter(|a| !a.is_empty()),
+            hide_names: env::var("HIDE_NAMES").map_or(false, |v| v == "1" || v == "true"),
+            headless: false,
+            round_limit: None,
+            exit_when_done: false,
+            round_duration: DEFAULT_ROUND_DURATION,
+            rules,
+            base_rules: rules,
+            rules_override: None,
+            record_inputs: env::var("RECORD_INPUTS").map_or(false, |v| v == "1" || v == "true"),
+            reduced_motion: env::var("REDUCED_MOTION").map_or(false, |v| v == "1" || v == "true"),
+            scoreboard_window: false,
+            house_bots: env_or("FILL_WITH_HOUSE_BOTS", 0)?,
+            rng_seed: env::var("RNG_SEED")
+                .ok()
+                .filter(|seed| !seed.is_empty())
+                .map(|seed| seed.parse().with_context(|| format!("parsing RNG_SEED {:?}", seed)))
+                .transpose()?,
+        };
+
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--headless" => settings.headless = true,
+                "--rounds" => {
+                    let rounds = args.next().ok_or_else(|| anyhow!("--rounds expects a count"))?;
+                    settings.round_limit =
+                        Some(rounds.parse().with_context(|| format!("parsing {:?}", rounds))?);
+                },
+                "--exit-when-done" => settings.exit_when_done = true,
+                "--round-seconds" => {
+                    let seconds =
+                        args.next().ok_or_else(|| anyhow!("--round-seconds expects a count"))?;
+                    settings.round_duration = Duration::from_secs(
+                        seconds.parse().with_context(|| format!("parsing {:?}", seconds))?,
+                    );
+                },
+                "--scoreboard-window" => settings.scoreboard_window = true,
+                EXPORT_SCHEMA_FLAG => (),
+                other => return Err(anyhow!("Unknown argument {:?}", other)),
+            }
+        }
+        if settings.exit_when_done && settings.round_limit.is_none() {
+            return Err(anyhow!("--exit-when-done requires --rounds"));
+        }
+
+        Ok(settings)
+    }
+
+    /// Plays the next round with the given rules override, or with the base rules if `None`.
+    pub fn set_rules_override(&mut self, rules_override: Option<RulesOverride>) {
+        self.rules = rules_override.as_ref().map_or(self.base_rules, |o| o.apply(self.base_rules));
+        self.rules_override = rules_override;
+    }
+}
+
+/// Reads a numeric environment variable, falling back to `default` if it isn't set.
+fn env_or<T: FromStr>(name: &str, default: T) -> Result<T>
+where
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    match env::var(name) {
+        Ok(value) if !value.is_empty() => {
+            value.parse().with_context(|| format!("parsing {} {:?}", name, value))
+        },
+        _ => Ok(default),
+    }
+}
+
+/// Prints the effective rules once logging is set up.
+pub fn log_rules_system(settings: Res<GameSettings>) {
+    info!("Playing with {:?}.", settings.rules);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn gimmick() -> RulesOverride {
+        toml::from_str("bomb_fuse_ticks = 1\nbase_bomb_range = 5").unwrap()
+    }
+
+    /// Empty folder for a test to play in, named after it so tests don't step on each other.
+    fn test_folder(name: &str) -> PathBuf {
+        let folder = env::temp_dir().join(format!("bomber_game_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&folder);
+        fs::create_dir_all(&folder).unwrap();
+        folder
+    }
+
+    #[test]
+    fn override_replaces_only_the_rules_it_sets() {
+        let base = Rules { base_bomb_range: 3, friendly_fire: false, ..Rules::default() };
+        let rules = gimmick().apply(base);
+        assert_eq!((rules.bomb_fuse_ticks, rules.base_bomb_range), (1, 5));
+        assert_eq!(
+            format!("{:?}", Rules { bomb_fuse_ticks: 2, base_bomb_range: 3, ..rules }),
+            format!("{:?}", base)
+        );
+    }
+
+    #[test]
+    fn empty_override_changes_nothing() {
+        let rules = RulesOverride::default().apply(Rules::default());
+        assert_eq!(format!("{:?}", rules), format!("{:?}", Rules::default()));
+        assert_eq!(RulesOverride::default().describe(), "");
+    }
+
+    #[test]
+    fn override_lists_the_rules_it_sets() {
+        assert_eq!(gimmick().describe(), "base_bomb_range=5, bomb_fuse_ticks=1");
+    }
+
+    #[test]
+    fn override_with_unknown_rules_is_rejected() {
+        assert!(toml::from_str::<RulesOverride>("fuse = 1").is_err());
+        assert!(toml::from_str::<RulesOverride>("bomb_fuse_ticks = \"fast\"").is_err());
+    }
+
+    #[test]
+    fn override_is_read_from_the_round_folder() {
+        let folder = test_folder("override_is_read_from_the_round_folder");
+        assert!(RulesOverride::read(&folder).unwrap().is_none());
+
+        fs::write(folder.join(RULES_OVERRIDE_FILENAME), "respawn_ticks = 0\n").unwrap();
+        let rules_override = RulesOverride::read(&folder).unwrap().unwrap();
+        assert_eq!(rules_override.respawn_ticks, Some(0));
+
+        fs::write(folder.join(RULES_OVERRIDE_FILENAME), "respawn_ticks = \n").unwrap();
+        assert!(RulesOverride::read(&folder).is_err());
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn override_only_lasts_one_round() {
+        let mut settings = GameSettings::default();
+        settings.set_rules_override(Some(gimmick()));
+        assert_eq!(settings.rules.bomb_fuse_ticks, 1);
+        assert!(settings.rules_override.is_some());
+
+        settings.set_rules_override(None);
+        assert_eq!(format!("{:?}", settings.rules), format!("{:?}", settings.base_rules));
+        assert!(settings.rules_override.is_none());
+    }
+}
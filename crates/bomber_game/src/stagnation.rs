@@ -0,0 +1,139 @@
+//! Defines a Bevy plugin that breaks stalemates (`BREAK_STALEMATES`). When nobody moves,
+//! drops a bomb or scores for a while, bots are likely waiting each other out, so a few
+//! crates guaranteed to drop a power up are placed around the hill to give them something
+//! to fight over.
+
+use anyhow::Result;
+use bevy::prelude::*;
+use bomber_lib::world::Object;
+use rand::{seq::SliceRandom, thread_rng};
+
+use crate::{
+    game_map::{GameMap, Textures, TileCache, TileLocation},
+    log_recoverable_error,
+    object::{random_power_up, CrateLoot, SpawnBombEvent},
+    player::{Player, PlayerMovedEvent},
+    score::Score,
+    settings::GameSettings,
+    state::AppState,
+    tick::{RoundPhase, TurnPhase, WorldTurnStep},
+    ExternalCrateComponent,
+};
+
+pub struct StagnationPlugin;
+
+/// World ticks without any activity before loot crates are dropped.
+const QUIET_TICKS_BEFORE_NUDGE: u32 = 20;
+const LOOT_CRATES_PER_NUDGE: usize = 3;
+/// Loot crates are placed around the hill, within this taxicab distance of it.
+const LOOT_CRATE_MAX_HILL_DISTANCE: u32 = 2;
+
+/// Counts world ticks since anything last happened in the round.
+#[derive(Default)]
+pub struct StagnationWatcher {
+    pub quiet_ticks: u32,
+    /// Times loot crates were dropped this round.
+    pub nudges: u32,
+}
+
+/// Sent when loot crates are dropped to break a stalemate.
+pub struct StalemateNudgeEvent;
+
+impl Plugin for StagnationPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<StagnationWatcher>()
+            .add_event::<StalemateNudgeEvent>()
+            .add_system_set(SystemSet::on_enter(AppState::InGame).with_system(reset))
+            .add_system_set(SystemSet::on_update(AppState::InGame).with_system(
+                stagnation_system.chain(log_recoverable_error).after(WorldTurnStep::Scoring),
+            ));
+    }
+}
+
+fn reset(mut watcher: ResMut<StagnationWatcher>) {
+    *watcher = StagnationWatcher::default();
+}
+
+/// Runs on every frame so no event is missed, while ticks are only counted on world turns.
+fn stagnation_system(
+    settings: Res<GameSettings>,
+    turn_phase: Res<TurnPhase>,
+    round_phase: Res<RoundPhase>,
+    mut watcher: ResMut<StagnationWatcher>,
+    mut active: Local<bool>,
+    mut moved_events: EventReader<PlayerMovedEvent>,
+    mut bomb_events: EventReader<SpawnBombEvent>,
+    score_query: Query<(), (With<Player>, Changed<Score>)>,
+    // Grouped to stay within the lint on system parameters.
+    (player_query, object_query): (
+        Query<&TileLocation, With<Player>>,
+        Query<&TileLocation, With<ExternalCrateComponent<Object>>>,
+    ),
+    (game_map_query, tile_cache, textures): (
+        Query<(Entity, &GameMap)>,
+        Res<TileCache>,
+        Res<Textures>,
+    ),
+    mut nudge_events: EventWriter<StalemateNudgeEvent>,
+    mut commands: Commands,
+) -> Result<()> {
+    *active |= moved_events.iter().count() > 0
+        || bomb_events.iter().count() > 0
+        || !score_query.is_empty();
+    if !settings.rules.break_stalemates
+        || *turn_phase != TurnPhase::World
+        || !matches!(*round_phase, RoundPhase::Playing)
+    {
+        return Ok(());
+    }
+
+    // An empty arena isn't stalled, it's waiting for players.
+    if std::mem::take(&mut *active) || player_query.is_empty() {
+        watcher.quiet_ticks = 0;
+        return Ok(());
+    }
+    watcher.quiet_ticks += 1;
+    if watcher.quiet_ticks < QUIET_TICKS_BEFORE_NUDGE {
+        return Ok(());
+    }
+
+    let (map_entity, game_map) = game_map_query.get_single()?;
+    let taken = |location: &TileLocation| {
+        tile_cache.is_wall(location)
+            || tile_cache.is_hill(location)
+            || player_query.iter().chain(object_query.iter()).any(|l| l == location)
+    };
+    let mut candidates = (0..game_map.width())
+        .flat_map(|x| (0..game_map.height()).map(move |y| TileLocation(x, y)))
+        .filter(|location| {
+            location.taxicab_distance_to_closest(tile_cache.hills()) <= LOOT_CRATE_MAX_HILL_DISTANCE
+        })
+        .filter(|location| !taken(location))
+        .collect::<Vec<_>>();
+    candidates.shuffle(&mut thread_rng());
+    candidates.truncate(LOOT_CRATES_PER_NUDGE);
+
+    // Either way the count starts over. Nudging again later is the only escalation, as
+    // there is no sudden death to fall back on.
+    watcher.quiet_ticks = 0;
+    if candidates.is_empty() {
+        return Ok(());
+    }
+
+    let mut crates = Ok(Vec::new());
+    commands.entity(map_entity).with_children(|parent| {
+        crates = candidates
+            .iter()
+            .map(|&location| {
+                GameMap::spawn_object(parent, game_map, Object::Crate, None, location, &textures)
+            })
+            .collect::<Result<Vec<_>>>();
+    });
+    for entity in crates? {
+        commands.entity(entity).insert(CrateLoot(Some(random_power_up())));
+    }
+    info!("Stalemate in the arena, dropping {} loot crates near the hill.", candidates.len());
+    watcher.nudges += 1;
+    nudge_events.send(StalemateNudgeEvent);
+    Ok(())
+}
@@ -1,16 +1,29 @@
 //! Defines a Bevy plugin that manages transitions between the game states.
 
 use anyhow::{Context, Result};
-use bevy::prelude::*;
+use bevy::{app::AppExit, prelude::*};
+use bomber_shared::{
+    finished_round_marker, remove_stale_temp_files, round_folder, RoundConfig, STALE_TEMP_FILE_AGE,
+};
+use rand::{rngs::StdRng, SeedableRng};
 use std::{
     fs::{self, create_dir_all},
-    path::Path,
+    path::{Path, PathBuf},
     time::Duration,
 };
 
-use crate::log_unrecoverable_error_and_panic;
+use crate::{
+    log_unrecoverable_error_and_panic,
+    player_hotswap::{PlayerHandle, PlayerHandles, WasmPlayerAsset, MAX_PLAYERS},
+    settings::{GameSettings, Rules, RulesOverride},
+};
 
-pub struct AppStatePlugin;
+pub struct AppStatePlugin {
+    pub rounds_folder: PathBuf,
+    /// Whether to create the folder of the first round if it's missing, rather than
+    /// expecting it to be there.
+    pub create_missing_folders: bool,
+}
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
 pub enum AppState {
@@ -22,12 +35,59 @@ pub enum AppState {
 }
 
 pub struct Round(pub u32);
+/// Number of rounds played to completion since the game started.
+pub struct RoundsPlayed(pub u32);
+/// Folder holding a subfolder of `.wasm` players per round.
+pub struct RoundsFolder(pub PathBuf);
+/// Source of the randomness that decides where players spawn. It is reseeded every round from
+/// `RNG_SEED` when set, so spawns can be reproduced.
+pub struct MatchRng(pub StdRng);
+/// Why the round was cut short, shown on the victory screen instead of the winner. `None`
+/// when the round ran its full length.
+#[derive(Default)]
+pub struct RoundEndReason(pub Option<String>);
+
+const ROUND_NAME_ADJECTIVES: &[&str] = &[
+    "Blazing",
+    "Crumbling",
+    "Dizzy",
+    "Explosive",
+    "Fearless",
+    "Grumpy",
+    "Hasty",
+    "Icy",
+    "Jolly",
+    "Lucky",
+    "Mighty",
+    "Noisy",
+    "Restless",
+    "Sneaky",
+    "Thundering",
+    "Wobbly",
+];
+const ROUND_NAME_NOUNS: &[&str] = &[
+    "Badger", "Crab", "Dragon", "Falcon", "Gecko", "Hedgehog", "Koala", "Lobster", "Mole",
+    "Narwhal", "Otter", "Panda", "Raccoon", "Tortoise", "Walrus",
+];
 
-const GAME_DURATION: Duration = Duration::from_secs(3 * 60);
 const VICTORY_SCREEN_DURATION: Duration = Duration::from_secs(20);
-const FINISHED_ROUND_MARKER_FILENAME: &str = "round-finished.marker";
-const ROUNDS_FOLDER: &str = "rounds";
 const MAX_ROUNDS: u32 = 10_000;
+/// How long a round carries on with every player disqualified, giving their authors a chance
+/// to upload a fix before it ends.
+const DISQUALIFIED_GRACE_PERIOD: Duration = Duration::from_secs(10);
+
+impl Round {
+    /// Human readable name for the round, such as "Sneaky Otter". Names are derived from the
+    /// round number, so they are stable across game restarts and don't repeat for a long while.
+    pub fn name(&self) -> String {
+        let index = self.0 as usize;
+        format!(
+            "{} {}",
+            ROUND_NAME_ADJECTIVES[index % ROUND_NAME_ADJECTIVES.len()],
+            ROUND_NAME_NOUNS[index % ROUND_NAME_NOUNS.len()]
+        )
+    }
+}
 
 #[derive(Component)]
 pub struct RoundTimer(pub Timer);
@@ -35,28 +95,41 @@ pub struct RoundTimer(pub Timer);
 impl Plugin for AppStatePlugin {
     fn build(&self, app: &mut App) {
         let first_round = (1..MAX_ROUNDS)
-            .find(|r| {
-                !Path::new(ROUNDS_FOLDER)
-                    .join(r.to_string())
-                    .join(FINISHED_ROUND_MARKER_FILENAME)
-                    .exists()
-            })
+            .find(|r| !finished_round_marker(&self.rounds_folder, *r).exists())
             .expect("All possible round slots are full");
 
-        let round_folder = Path::new(ROUNDS_FOLDER).join(first_round.to_string());
+        let round_folder = round_folder(&self.rounds_folder, first_round);
         if !round_folder.exists() {
-            create_dir_all(round_folder).expect("Failed to create round folder");
+            assert!(self.create_missing_folders, "Round folder {:?} not found", round_folder);
         }
+        let rules = app.world.get_resource::<GameSettings>().map(|s| s.rules).unwrap_or_default();
+        prepare_round_folder(&self.rounds_folder, first_round, rules)
+            .expect("Failed to prepare round folder");
 
         app.add_startup_system(setup)
             .insert_resource(Round(first_round))
+            .insert_resource(RoundsPlayed(0))
+            .insert_resource(RoundsFolder(self.rounds_folder.clone()))
+            .init_resource::<RoundEndReason>()
+            .insert_resource(MatchRng(StdRng::from_entropy()))
+            .add_system_set(
+                SystemSet::on_enter(AppState::InGame)
+                    .with_system(reseed_match_rng_system)
+                    .with_system(rules_override_system),
+            )
             .add_system(app_state_system.chain(log_unrecoverable_error_and_panic))
+            .add_system_set(
+                SystemSet::on_update(AppState::InGame).with_system(all_disqualified_system),
+            )
+            .add_system_set(
+                SystemSet::on_update(AppState::VictoryScreen).with_system(exit_when_done_system),
+            )
             .add_state(AppState::InGame);
     }
 }
 
-fn setup(mut commands: Commands) {
-    commands.spawn().insert(RoundTimer(Timer::new(GAME_DURATION, false)));
+fn setup(settings: Res<GameSettings>, mut commands: Commands) {
+    commands.spawn().insert(RoundTimer(Timer::new(settings.round_duration, false)));
 }
 
 fn app_state_system(
@@ -64,6 +137,10 @@ fn app_state_system(
     time: Res<Time>,
     mut app_state: ResMut<State<AppState>>,
     mut round: ResMut<Round>,
+    mut rounds_played: ResMut<RoundsPlayed>,
+    rounds_folder: Res<RoundsFolder>,
+    settings: Res<GameSettings>,
+    mut end_reason: ResMut<RoundEndReason>,
     mut commands: Commands,
 ) -> Result<()> {
     let (timer_entity, mut timer) = timer_query.single_mut();
@@ -72,20 +149,28 @@ fn app_state_system(
     if timer.tick(time.delta()).just_finished() {
         let (next_state, next_duration) = match app_state.current() {
             AppState::InGame => {
-                let finished_round_path = Path::new(ROUNDS_FOLDER)
-                    .join(round.0.to_string())
-                    .join(FINISHED_ROUND_MARKER_FILENAME);
+                let finished_round_path = finished_round_marker(&rounds_folder.0, round.0);
 
                 fs::write(&finished_round_path, &[])
                     .with_context(|| format!("writing {:?}", finished_round_path))?;
                 round.0 += 1;
-                let round_folder = Path::new(ROUNDS_FOLDER).join(round.0.to_string());
-                if !round_folder.exists() {
-                    create_dir_all(round_folder).expect("Failed to create round folder");
+                rounds_played.0 += 1;
+                prepare_round_folder(&rounds_folder.0, round.0, settings.base_rules)?;
+                match remove_stale_temp_files(&rounds_folder.0, STALE_TEMP_FILE_AGE) {
+                    Ok(0) => {},
+                    Ok(removed) => info!("Removed {} stale upload temp files.", removed),
+                    Err(e) => warn!("Cleaning up stale upload temp files: {}", e),
                 }
                 (AppState::VictoryScreen, VICTORY_SCREEN_DURATION)
             },
-            AppState::VictoryScreen => (AppState::InGame, GAME_DURATION),
+            AppState::VictoryScreen => {
+                if settings.round_limit.map_or(false, |limit| rounds_played.0 >= limit) {
+                    // Stay on the final victory screen rather than starting another round.
+                    return Ok(());
+                }
+                end_reason.0 = None;
+                (AppState::InGame, settings.round_duration)
+            },
         };
         app_state.set(next_state)?;
         commands.entity(timer_entity).despawn();
@@ -94,3 +179,97 @@ fn app_state_system(
 
     Ok(())
 }
+
+fn reseed_match_rng_system(
+    settings: Res<GameSettings>,
+    round: Res<Round>,
+    mut match_rng: ResMut<MatchRng>,
+) {
+    match_rng.0 = match settings.rng_seed {
+        Some(seed) => StdRng::seed_from_u64(seed.wrapping_add(round.0 as u64)),
+        None => StdRng::from_entropy(),
+    };
+}
+
+/// Applies the rules override dropped in the folder of the round about to start, or goes back
+/// to the base rules if there is none. A broken override is ignored, rather than holding up
+/// the round.
+fn rules_override_system(
+    mut settings: ResMut<GameSettings>,
+    round: Res<Round>,
+    rounds_folder: Res<RoundsFolder>,
+) {
+    let rules_override = match RulesOverride::read(&round_folder(&rounds_folder.0, round.0)) {
+        Ok(rules_override) => rules_override,
+        Err(e) => {
+            error!("Playing round {} with the base rules: {:#}", round.0, e);
+            None
+        },
+    };
+    if let Some(rules_override) = &rules_override {
+        info!("Gimmick round {}: {}.", round.0, rules_override.describe());
+    }
+    settings.set_rules_override(rules_override);
+}
+
+/// Ends the round on the next frame, going through the same steps as when its time runs out.
+pub fn end_round_early(
+    RoundTimer(timer): &mut RoundTimer,
+    end_reason: &mut RoundEndReason,
+    reason: impl Into<String>,
+) {
+    let reason = reason.into();
+    info!("Ending the round early: {}", reason);
+    end_reason.0 = Some(reason);
+    let duration = timer.duration();
+    timer.set_elapsed(duration);
+}
+
+/// Ends the round once every uploaded player has been banned for misbehaving and stayed so for
+/// `DISQUALIFIED_GRACE_PERIOD`, as nothing would happen in the arena for the rest of it. A
+/// fixed upload lifts the ban, which resets the grace period.
+fn all_disqualified_system(
+    handles: Res<PlayerHandles>,
+    wasm_player_query: Query<(), With<Handle<WasmPlayerAsset>>>,
+    mut timer_query: Query<&mut RoundTimer>,
+    mut end_reason: ResMut<RoundEndReason>,
+    time: Res<Time>,
+    mut disqualified_for: Local<Duration>,
+) {
+    let all_disqualified = !handles.0.is_empty()
+        && handles.0.iter().all(|h| matches!(h, PlayerHandle::Misbehaved(..)))
+        && wasm_player_query.is_empty();
+    if !all_disqualified {
+        *disqualified_for = Duration::ZERO;
+        return;
+    }
+
+    let was_in_grace_period = *disqualified_for < DISQUALIFIED_GRACE_PERIOD;
+    *disqualified_for += time.delta();
+    if was_in_grace_period && *disqualified_for >= DISQUALIFIED_GRACE_PERIOD {
+        end_round_early(&mut timer_query.single_mut(), &mut end_reason, "All players disqualified");
+    }
+}
+
+/// Creates the folder of a round if the upload server hasn't already, and writes the config
+/// telling the upload server how many players the round takes.
+fn prepare_round_folder(rounds_folder: &Path, round: u32, rules: Rules) -> Result<()> {
+    let folder = round_folder(rounds_folder, round);
+    create_dir_all(&folder).with_context(|| format!("creating {:?}", folder))?;
+    RoundConfig { max_players: MAX_PLAYERS, rules: serde_json::to_value(rules)? }
+        .write(rounds_folder, round)
+}
+
+/// Exits the game once the round limit is reached, in unattended mode. This runs from the
+/// frame after entering the victory screen, so the results of the last round are written.
+fn exit_when_done_system(
+    settings: Res<GameSettings>,
+    rounds_played: Res<RoundsPlayed>,
+    mut exit: EventWriter<AppExit>,
+) {
+    let limit_reached = settings.round_limit.map_or(false, |limit| rounds_played.0 >= limit);
+    if settings.exit_when_done && limit_reached {
+        info!("Played {} rounds, exiting.", rounds_played.0);
+        exit.send(AppExit);
+    }
+}
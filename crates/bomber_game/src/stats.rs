@@ -0,0 +1,157 @@
+//! Defines a Bevy plugin that keeps track of per-player statistics throughout a round,
+//! so teams can find out how much headroom their bots have.
+
+use std::{collections::VecDeque, time::Duration};
+
+use bevy::{asset::HandleId, prelude::*, utils::HashMap};
+
+use crate::{
+    game_map::{GameMap, TileLocation},
+    player::{Player, PlayerMovedEvent},
+    player_hotswap::WasmPlayerAsset,
+    score::Score,
+    state::AppState,
+    tick::{world_turn, RoundPhase, WorldTurnStep},
+};
+
+pub struct StatsPlugin;
+
+/// Number of world ticks of score history kept per player.
+pub const SCORE_HISTORY_LENGTH: usize = 120;
+
+#[derive(Default, Debug, Clone)]
+pub struct PlayerStats {
+    pub name: String,
+    pub team_name: String,
+    pub turns: u32,
+    /// Actions that couldn't be applied, such as walking into a wall.
+    pub failed_actions: u32,
+    /// Actions the player couldn't take at all, such as dropping a bomb beyond its budget,
+    /// which were replaced with standing still.
+    pub invalid_actions: u32,
+    pub crates_destroyed: u32,
+    /// Teammates caught in this player's blasts. They only die of it with friendly fire on.
+    pub team_kills: u32,
+    /// Head start given for joining the round late.
+    pub handicap: u32,
+    /// World ticks spent as a runaway leader, with halved hill points.
+    pub throttled_ticks: u32,
+    /// Turns on which the farthest tiles were left out of the player's view, for its
+    /// surroundings not to overflow its buffer.
+    pub clipped_turns: u32,
+    pub min_fuel: u64,
+    pub max_fuel: u64,
+    pub total_fuel: u64,
+    pub max_latency: Duration,
+    pub total_latency: Duration,
+    pub score_history: ScoreHistory,
+    pub heatmap: Heatmap,
+}
+
+/// Score at each of the last `SCORE_HISTORY_LENGTH` world ticks, oldest first.
+#[derive(Default, Debug, Clone)]
+pub struct ScoreHistory(VecDeque<u32>);
+
+impl ScoreHistory {
+    pub fn record(&mut self, score: u32) {
+        if self.0.len() == SCORE_HISTORY_LENGTH {
+            self.0.pop_front();
+        }
+        self.0.push_back(score);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = u32> + '_ {
+        self.0.iter().copied()
+    }
+}
+
+/// Number of moves that ended on each tile, indexed as `[y][x]` in tile coordinates (so
+/// the bottom row comes first). Empty until the player first moves.
+#[derive(Default, Debug, Clone)]
+pub struct Heatmap(Vec<Vec<u32>>);
+
+impl Heatmap {
+    pub fn record(&mut self, location: TileLocation, game_map: &GameMap) {
+        if self.0.is_empty() {
+            self.0 = vec![vec![0; game_map.width()]; game_map.height()];
+        }
+        if let Some(visits) = self.0.get_mut(location.1).and_then(|row| row.get_mut(location.0)) {
+            *visits += 1;
+        }
+    }
+
+    pub fn rows(&self) -> &[Vec<u32>] {
+        &self.0
+    }
+}
+
+/// Statistics of every player in the current round. They are keyed by `.wasm`
+/// handle rather than entity, so they survive respawns.
+#[derive(Default)]
+pub struct RoundStats(pub HashMap<HandleId, PlayerStats>);
+
+impl Plugin for StatsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<RoundStats>()
+            .add_system_set(SystemSet::on_enter(AppState::InGame).with_system(reset))
+            .add_system_set(SystemSet::on_update(AppState::InGame).with_system(heatmap_system))
+            .add_system_set(
+                SystemSet::new()
+                    .with_run_criteria(world_turn)
+                    .after(WorldTurnStep::Scoring)
+                    .with_system(score_history_system),
+            );
+    }
+}
+
+impl PlayerStats {
+    pub fn record_turn(&mut self, fuel: u64, latency: Duration) {
+        self.min_fuel = if self.turns == 0 { fuel } else { self.min_fuel.min(fuel) };
+        self.max_fuel = self.max_fuel.max(fuel);
+        self.total_fuel += fuel;
+        self.max_latency = self.max_latency.max(latency);
+        self.total_latency += latency;
+        self.turns += 1;
+    }
+
+    pub fn mean_fuel(&self) -> u64 {
+        self.total_fuel.checked_div(self.turns as u64).unwrap_or_default()
+    }
+
+    pub fn mean_latency(&self) -> Duration {
+        self.total_latency.checked_div(self.turns).unwrap_or_default()
+    }
+}
+
+fn reset(mut stats: ResMut<RoundStats>) {
+    stats.0.clear();
+}
+
+/// Samples the score of every `.wasm` player on each world turn, once the round is underway.
+fn score_history_system(
+    phase: Res<RoundPhase>,
+    player_query: Query<(&Score, &Handle<WasmPlayerAsset>), With<Player>>,
+    mut stats: ResMut<RoundStats>,
+) {
+    if matches!(*phase, RoundPhase::GetReady(_)) {
+        return;
+    }
+    for (Score(score), handle) in player_query.iter() {
+        stats.0.entry(handle.id).or_default().score_history.record(*score);
+    }
+}
+
+/// Counts the tiles `.wasm` players move onto, to show where they spend the round.
+fn heatmap_system(
+    mut moved_events: EventReader<PlayerMovedEvent>,
+    player_query: Query<&Handle<WasmPlayerAsset>, With<Player>>,
+    game_map_query: Query<&GameMap>,
+    mut stats: ResMut<RoundStats>,
+) {
+    let game_map = if let Ok(game_map) = game_map_query.get_single() { game_map } else { return };
+    for PlayerMovedEvent { entity, to, .. } in moved_events.iter() {
+        if let Ok(handle) = player_query.get(*entity) {
+            stats.0.entry(handle.id).or_default().heatmap.record(*to, game_map);
+        }
+    }
+}
@@ -2,36 +2,124 @@ use anyhow::Result;
 use std::time::Duration;
 
 use crate::{log_unrecoverable_error_and_panic, state::AppState};
-use bevy::prelude::*;
-
-/// Helps keep game logic discrete by sending alternative world
-/// tick and player tick events. Player ticks sequence all player
-/// actions, and world ticks sequence all passive world effects
-/// like explosions, crate breakage and points. This ensures
-/// there are no race conditions (such as a player moving away from
-/// a bomb the same frame it explodes).
+use bevy::{ecs::schedule::ShouldRun, prelude::*};
+
+/// Helps keep game logic discrete by alternating world and player
+/// turns. Player turns sequence all player actions, and world turns
+/// sequence all passive world effects like explosions, crate breakage
+/// and points. This ensures there are no race conditions (such as a
+/// player moving away from a bomb the same frame it explodes).
 pub struct TickPlugin;
 
 #[derive(Component)]
 struct TickTimer(pub Timer);
 #[derive(Component)]
 struct TickCounter(u32);
+/// Ticks the timer fired that haven't been handled yet. A frame only handles one tick, so
+/// ticks fired during a long frame are handled over the following frames.
+#[derive(Component)]
+struct OwedTicks(u32);
 
 pub const TICK_PERIOD: Duration = Duration::from_millis(500);
 pub const WHOLE_TURN_PERIOD: Duration = Duration::from_millis(1000);
+pub const GET_READY_DURATION: Duration = Duration::from_secs(3);
+/// World turns are considered stalled once they're this late, which has happened when the
+/// tick timer was lost to a panic elsewhere.
+const WORLD_TURN_STALL: Duration = Duration::from_millis(3 * WHOLE_TURN_PERIOD.as_millis() as u64);
+/// Most ticks the game catches up on after a hiccup. Any more are dropped, so a game that
+/// can't keep up doesn't fall further and further behind.
+const MAX_OWED_TICKS: u32 = 4;
 
-pub enum Tick {
-    /// Player actions happen simultaneously during player ticks.
+/// Turn phase of the current frame. It is set before any game logic runs, and only lasts
+/// for the frame the tick fires on, so every tick is handled exactly once and none are
+/// handled once the round is over. The steps within a turn (deciding and applying actions,
+/// world effects, scoring) are ordered by `TickStep` rather than being phases of their own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TurnPhase {
+    /// Between ticks, nothing turn based happens.
+    Idle,
+    /// Players decide on their actions, which are then applied simultaneously.
     Player,
-    /// World reactions happen simultaneously during world ticks.
+    /// World reactions happen simultaneously, followed by scoring.
     World,
 }
 
+/// Ordering of the systems running during world turns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, SystemLabel)]
+pub enum WorldTurnStep {
+    /// Fuses, explosions, power ups and respawns.
+    Effects,
+    /// Points, awarded once the world has settled.
+    Scoring,
+}
+
+/// Order of the game logic within a frame. Every labelled system runs after the step right
+/// before its own, and the steps are:
+///
+/// - `DecideActions` and `ApplyMoves`: players pick and take their actions (player turns),
+///   both in the same system.
+/// - `SpawnBombs`: the bombs dropped by those actions are placed.
+/// - `ResolveExplosions`: fuses burn down and bombs go off (world turns), and flames set
+///   crates and players on fire.
+/// - `ApplyKills`: players killed or banned leave the arena.
+/// - `Score`: the survivors collect their points (world turns, `WorldTurnStep::Scoring`).
+/// - `Cleanup`: the remains of dead and banned players fade away.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, SystemLabel)]
+pub enum TickStep {
+    DecideActions,
+    ApplyMoves,
+    SpawnBombs,
+    ResolveExplosions,
+    ApplyKills,
+    Score,
+    Cleanup,
+}
+
+/// Speed at which ticks and animations run, to slow the game down for effect. The round
+/// timer always runs in real time.
+pub struct TimeScale(pub f32);
+
+impl TimeScale {
+    /// How much game time `time` advanced by on this frame.
+    pub fn delta(&self, time: &Time) -> Duration {
+        time.delta().mul_f32(self.0)
+    }
+}
+
+/// When the last world turn happened, as time since startup.
+pub struct LastWorldTurn(pub Duration);
+
+impl LastWorldTurn {
+    /// Whether world turns stopped coming, in which case systems that count world turns
+    /// should fall back to counting real time.
+    pub fn stalled(&self, time: &Time) -> bool {
+        time.time_since_startup().saturating_sub(self.0) > WORLD_TURN_STALL
+    }
+}
+
+/// Rounds start with a "get ready" phase, during which world ticks flow but players don't
+/// act yet. This gives every player time to spawn, so they all act for the first time on
+/// the same tick.
+pub enum RoundPhase {
+    GetReady(Timer),
+    Playing,
+}
+
 impl Plugin for TickPlugin {
     fn build(&self, app: &mut App) {
-        app.add_event::<Tick>()
+        app.insert_resource(TurnPhase::Idle)
+            .insert_resource(RoundPhase::Playing)
+            .insert_resource(TimeScale(1.0))
+            .insert_resource(LastWorldTurn(Duration::ZERO))
             .add_system_set(SystemSet::on_enter(AppState::InGame).with_system(setup))
-            .add_system_set(SystemSet::on_update(AppState::InGame).with_system(tick_system))
+            .add_system_set(
+                SystemSet::on_update(AppState::InGame).with_system(tick_watchdog_system),
+            )
+            .add_system_to_stage(
+                // Runs ahead of the game logic, so all of it sees the same phase.
+                CoreStage::PreUpdate,
+                tick_system,
+            )
             .add_system_set(
                 SystemSet::on_exit(AppState::InGame)
                     .with_system(cleanup.chain(log_unrecoverable_error_and_panic)),
@@ -39,21 +127,123 @@ impl Plugin for TickPlugin {
     }
 }
 
-fn setup(mut commands: Commands) {
-    commands.spawn().insert(TickTimer(Timer::new(TICK_PERIOD, true))).insert(TickCounter(0));
+fn setup(mut commands: Commands, time: Res<Time>, mut last_world_turn: ResMut<LastWorldTurn>) {
+    spawn_tick_timer(&mut commands);
+    commands.insert_resource(RoundPhase::GetReady(Timer::new(GET_READY_DURATION, false)));
+    // Updated right away rather than through commands, so the watchdog doesn't see the time
+    // spent on the victory screen as a stall.
+    last_world_turn.0 = time.time_since_startup();
+}
+
+fn spawn_tick_timer(commands: &mut Commands) {
+    commands
+        .spawn()
+        .insert(TickTimer(Timer::new(TICK_PERIOD, true)))
+        .insert(TickCounter(0))
+        .insert(OwedTicks(0));
 }
 
 fn tick_system(
-    mut timer_query: Query<(&mut TickTimer, &mut TickCounter)>,
+    mut timer_query: Query<(&mut TickTimer, &mut TickCounter, &mut OwedTicks)>,
     time: Res<Time>,
-    mut events: EventWriter<Tick>,
+    time_scale: Res<TimeScale>,
+    mut phase: ResMut<RoundPhase>,
+    mut turn_phase: ResMut<TurnPhase>,
+    mut last_world_turn: ResMut<LastWorldTurn>,
 ) {
-    let (mut timer, mut tick_counter) = timer_query.single_mut();
+    *turn_phase = TurnPhase::Idle;
+    // The timer only exists during rounds.
+    let (mut timer, mut tick_counter, mut owed_ticks) = match timer_query.get_single_mut() {
+        Ok(timer) => timer,
+        Err(_) => return,
+    };
     let TickTimer(ref mut timer) = *timer;
-    if timer.tick(time.delta()).just_finished() {
-        let event = if tick_counter.0 % 2 == 0 { Tick::Player } else { Tick::World };
-        events.send(event);
-        tick_counter.0 += 1;
+
+    if let RoundPhase::GetReady(ref mut get_ready_timer) = *phase {
+        if get_ready_timer.tick(time.delta()).just_finished() {
+            // Start the turn sequence afresh, so the first player turn happens right away.
+            *phase = RoundPhase::Playing;
+            timer.reset();
+            tick_counter.0 = 1;
+            owed_ticks.0 = 0;
+            *turn_phase = TurnPhase::Player;
+            return;
+        }
+    }
+
+    // A long frame (such as one compiling a large bot) can fire the timer several times.
+    let fired = timer.tick(time_scale.delta(&time)).times_finished();
+    let (next, dropped) = take_owed_tick(&mut owed_ticks, &mut tick_counter, fired);
+    if dropped > 0 {
+        warn!(
+            "The game fell {} ticks behind, dropping {} of them.",
+            MAX_OWED_TICKS + dropped,
+            dropped
+        );
+    }
+    if let Some(next) = next {
+        // Players sit out their turns until the round has properly started.
+        if next == TurnPhase::World || matches!(*phase, RoundPhase::Playing) {
+            *turn_phase = next;
+        }
+        if next == TurnPhase::World {
+            last_world_turn.0 = time.time_since_startup();
+        }
+    }
+}
+
+/// Adds the ticks the timer `fired` to those owed, keeping at most `MAX_OWED_TICKS`, and takes
+/// the one to handle this frame. Returns the phase of that tick, if any is owed, and how many
+/// ticks were dropped.
+fn take_owed_tick(
+    owed_ticks: &mut OwedTicks,
+    tick_counter: &mut TickCounter,
+    fired: u32,
+) -> (Option<TurnPhase>, u32) {
+    owed_ticks.0 += fired;
+    let dropped = owed_ticks.0.saturating_sub(MAX_OWED_TICKS);
+    owed_ticks.0 -= dropped;
+    if owed_ticks.0 == 0 {
+        return (None, dropped);
+    }
+    owed_ticks.0 -= 1;
+    let next = if tick_counter.0 % 2 == 0 { TurnPhase::Player } else { TurnPhase::World };
+    tick_counter.0 += 1;
+    (Some(next), dropped)
+}
+
+/// Notices when world turns stop coming during a round, and brings the tick timer back if
+/// it went missing.
+fn tick_watchdog_system(
+    time: Res<Time>,
+    last_world_turn: Res<LastWorldTurn>,
+    timer_query: Query<(), With<TickTimer>>,
+    mut commands: Commands,
+    mut reported: Local<bool>,
+) {
+    if !last_world_turn.stalled(&time) {
+        *reported = false;
+        return;
+    }
+    if !*reported {
+        error!(
+            "No world turn in the last {:?}, respawns are counted in real time until they resume.",
+            time.time_since_startup() - last_world_turn.0
+        );
+        *reported = true;
+    }
+    if timer_query.is_empty() {
+        error!("The tick timer is gone, starting a new one.");
+        spawn_tick_timer(&mut commands);
+    }
+}
+
+/// Run criteria for systems that only run during world turns.
+pub fn world_turn(turn_phase: Res<TurnPhase>) -> ShouldRun {
+    if *turn_phase == TurnPhase::World {
+        ShouldRun::Yes
+    } else {
+        ShouldRun::No
     }
 }
 
@@ -63,3 +253,172 @@ fn cleanup(timer_query: Query<Entity, With<TickTimer>>, mut commands: Commands)
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::schedule::Stage;
+
+    use super::*;
+
+    /// Phases handled over the given number of frames, the first of which lasted `delta`.
+    fn phases_after(delta: Duration, frames: usize) -> (Vec<Option<TurnPhase>>, u32) {
+        let mut timer = Timer::new(TICK_PERIOD, true);
+        let (mut owed_ticks, mut tick_counter) = (OwedTicks(0), TickCounter(0));
+        let mut fired = timer.tick(delta).times_finished();
+        let mut dropped = 0;
+        let phases = (0..frames)
+            .map(|_| {
+                let (next, d) = take_owed_tick(&mut owed_ticks, &mut tick_counter, fired);
+                fired = 0;
+                dropped += d;
+                next
+            })
+            .collect();
+        (phases, dropped)
+    }
+
+    #[test]
+    fn long_frames_are_caught_up_on_one_tick_per_frame() {
+        let (player, world) = (Some(TurnPhase::Player), Some(TurnPhase::World));
+        let (phases, dropped) = phases_after(Duration::from_millis(2300), 5);
+        assert_eq!(phases, vec![player, world, player, world, None]);
+        assert_eq!(dropped, 0);
+    }
+
+    #[test]
+    fn catching_up_is_capped() {
+        let (phases, dropped) = phases_after(Duration::from_millis(3100), 6);
+        assert_eq!(phases.iter().filter(|p| p.is_some()).count(), MAX_OWED_TICKS as usize);
+        assert_eq!(dropped, 2);
+    }
+
+    #[test]
+    fn short_frames_tick_on_time() {
+        let (phases, _) = phases_after(Duration::from_millis(400), 1);
+        assert_eq!(phases, vec![None]);
+        let (phases, _) = phases_after(Duration::from_millis(600), 2);
+        assert_eq!(phases, vec![Some(TurnPhase::Player), None]);
+    }
+
+    /// Moves `time` to `since_startup`, as if a frame started then.
+    fn set_time(time: &mut Time, since_startup: Duration) {
+        let startup = time.startup();
+        time.update_with_instant(startup + since_startup);
+    }
+
+    #[test]
+    fn world_turns_stall_once_late_enough() {
+        let ms = Duration::from_millis;
+        let mut time = Time::default();
+        let last_world_turn = LastWorldTurn(ms(1000));
+        set_time(&mut time, ms(1000) + WORLD_TURN_STALL);
+        assert!(!last_world_turn.stalled(&time));
+        set_time(&mut time, ms(1001) + WORLD_TURN_STALL);
+        assert!(last_world_turn.stalled(&time));
+    }
+
+    #[test]
+    fn watchdog_brings_back_a_lost_timer() {
+        let ms = Duration::from_millis;
+        let mut world = World::new();
+        world.insert_resource(Time::default());
+        world.insert_resource(LastWorldTurn(Duration::ZERO));
+        let mut stage = SystemStage::single_threaded().with_system(tick_watchdog_system);
+        let timers =
+            |world: &mut World| world.query_filtered::<(), With<TickTimer>>().iter(world).count();
+
+        // The timer is missing, but world turns aren't late yet.
+        set_time(&mut world.resource_mut::<Time>(), ms(2000));
+        stage.run(&mut world);
+        assert_eq!(timers(&mut world), 0);
+
+        set_time(&mut world.resource_mut::<Time>(), ms(3500));
+        stage.run(&mut world);
+        assert_eq!(timers(&mut world), 1);
+
+        // Still stalled, as the new timer hasn't fired yet, but it isn't replaced again.
+        set_time(&mut world.resource_mut::<Time>(), ms(3600));
+        stage.run(&mut world);
+        assert_eq!(timers(&mut world), 1);
+    }
+
+    /// A round that's just started, with its tick timer.
+    fn round_world() -> World {
+        let mut world = World::new();
+        let mut time = Time::default();
+        set_time(&mut time, Duration::ZERO);
+        world.insert_resource(time);
+        world.insert_resource(TimeScale(1.0));
+        world.insert_resource(TurnPhase::Idle);
+        world.insert_resource(RoundPhase::GetReady(Timer::new(GET_READY_DURATION, false)));
+        world.insert_resource(LastWorldTurn(Duration::ZERO));
+        world
+            .spawn()
+            .insert(TickTimer(Timer::new(TICK_PERIOD, true)))
+            .insert(TickCounter(0))
+            .insert(OwedTicks(0));
+        world
+    }
+
+    /// Runs `tick_system` over frames of the given lengths, and returns the phase of each.
+    fn phases_over(world: &mut World, frames: &[Duration]) -> Vec<TurnPhase> {
+        let mut stage = SystemStage::single_threaded().with_system(tick_system);
+        frames
+            .iter()
+            .map(|frame| {
+                let mut time = world.resource_mut::<Time>();
+                let now = time.time_since_startup() + *frame;
+                set_time(&mut time, now);
+                stage.run(world);
+                *world.resource::<TurnPhase>()
+            })
+            .collect()
+    }
+
+    /// Frames of uneven lengths, some long enough for the game to fall behind.
+    fn uneven_frames(count: usize) -> Vec<Duration> {
+        [16, 16, 100, 700, 16, 1600, 16, 16, 16, 900]
+            .into_iter()
+            .cycle()
+            .take(count)
+            .map(Duration::from_millis)
+            .collect()
+    }
+
+    #[test]
+    fn players_act_before_the_world_reacts() {
+        let mut world = round_world();
+        let mut frames = vec![Duration::from_millis(100); 30];
+        frames.extend(uneven_frames(100));
+        let phases = phases_over(&mut world, &frames);
+
+        // Players sit out the get ready phase. Once it's over, each of their turns comes
+        // before the world turn reacting to it, however far behind the game falls.
+        let first_player_turn = phases.iter().position(|p| *p == TurnPhase::Player).unwrap();
+        let turns = phases[first_player_turn..]
+            .iter()
+            .filter(|p| **p != TurnPhase::Idle)
+            .copied()
+            .collect::<Vec<_>>();
+        assert!(turns.len() > 20);
+        for (i, turn) in turns.into_iter().enumerate() {
+            let expected = if i % 2 == 0 { TurnPhase::Player } else { TurnPhase::World };
+            assert_eq!(turn, expected, "turn {i}");
+        }
+    }
+
+    #[test]
+    fn no_turns_once_the_round_is_over() {
+        let mut world = round_world();
+        let mut frames = vec![Duration::from_millis(100); 30];
+        frames.extend(uneven_frames(20));
+        let phases = phases_over(&mut world, &frames);
+        assert!(phases.contains(&TurnPhase::World));
+
+        // Ending the round takes the tick timer away, even with ticks still owed.
+        let timer = world.query_filtered::<Entity, With<TickTimer>>().single(&world);
+        world.despawn(timer);
+        let phases = phases_over(&mut world, &uneven_frames(20));
+        assert!(phases.iter().all(|p| *p == TurnPhase::Idle));
+    }
+}
@@ -0,0 +1,222 @@
+//! Defines a Bevy plugin that schedules round participants in tournament mode.
+//!
+//! Tournament mode is enabled by creating a `pool` folder next to the `rounds` folder and
+//! dropping every participant's `.wasm` file in it. At the start of each round, the participants
+//! that have met each other the least are copied into the round folder, so over many rounds
+//! every team gets to fight every other team.
+
+use anyhow::{anyhow, Context, Result};
+use bevy::{prelude::*, utils::HashMap};
+use bomber_shared::round_folder;
+use std::{ffi::OsStr, fs, path::Path};
+
+use crate::{
+    log_recoverable_error,
+    player_hotswap::MAX_PLAYERS,
+    state::{AppState, Round, RoundsFolder},
+};
+
+const POOL_FOLDER: &str = "pool";
+/// Persists the pairing counts across game restarts, one tab separated `name name count`
+/// triple per line. Tabs rather than spaces, as participant names may contain spaces.
+const PAIRINGS_FILENAME: &str = "pairings.txt";
+const PAIRINGS_DELIMITER: char = '\t';
+
+pub struct TournamentPlugin;
+
+/// Number of rounds each pair of participants has played together.
+#[derive(Default, Debug)]
+pub struct Pairings(HashMap<(String, String), u32>);
+
+impl Plugin for TournamentPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(
+            SystemSet::on_enter(AppState::InGame)
+                .with_system(schedule_round_system.chain(log_recoverable_error)),
+        );
+    }
+}
+
+impl Pairings {
+    fn key(a: &str, b: &str) -> (String, String) {
+        if a < b {
+            (a.to_owned(), b.to_owned())
+        } else {
+            (b.to_owned(), a.to_owned())
+        }
+    }
+
+    pub fn count(&self, a: &str, b: &str) -> u32 {
+        self.0.get(&Self::key(a, b)).copied().unwrap_or_default()
+    }
+
+    /// Total number of pairings a participant has been part of.
+    pub fn total(&self, participant: &str) -> u32 {
+        self.0
+            .iter()
+            .filter(|((a, b), _)| a == participant || b == participant)
+            .map(|(_, c)| c)
+            .sum()
+    }
+
+    /// Registers that all the given participants met each other in a round.
+    pub fn record(&mut self, participants: &[String]) {
+        for (i, a) in participants.iter().enumerate() {
+            for b in &participants[i + 1..] {
+                *self.0.entry(Self::key(a, b)).or_insert(0) += 1;
+            }
+        }
+    }
+
+    fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path).with_context(|| format!("reading {:?}", path))?;
+        let mut pairings = Self::default();
+        for line in text.lines().filter(|l| !l.trim().is_empty()) {
+            let mut fields = line.split(PAIRINGS_DELIMITER);
+            match (fields.next(), fields.next(), fields.next().map(|c| c.trim().parse::<u32>())) {
+                (Some(a), Some(b), Some(Ok(count))) => {
+                    pairings.0.insert(Self::key(a, b), count);
+                },
+                _ => return Err(anyhow!("Malformed line in {:?}: {}", path, line)),
+            }
+        }
+        Ok(pairings)
+    }
+
+    fn save(&self, path: &Path) -> Result<()> {
+        let mut entries = self.0.iter().collect::<Vec<_>>();
+        entries.sort();
+        let text: String = entries
+            .into_iter()
+            .map(|((a, b), count)| format!("{}{d}{}{d}{}\n", a, b, count, d = PAIRINGS_DELIMITER))
+            .collect();
+        fs::write(path, text).with_context(|| format!("writing {:?}", path))
+    }
+}
+
+/// Greedily picks up to `max_participants` from the pool, each time adding the participant
+/// that has met the already selected ones the least. Ties go to whoever has played the fewest
+/// pairings overall, and then to alphabetical order so the selection is deterministic.
+pub fn select_participants(
+    pool: &[String],
+    pairings: &Pairings,
+    max_participants: usize,
+) -> Vec<String> {
+    let mut candidates = pool.to_vec();
+    candidates.sort();
+    candidates.dedup();
+    let mut selected: Vec<String> = vec![];
+
+    while selected.len() < max_participants && !candidates.is_empty() {
+        let (index, _) = candidates
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, candidate)| {
+                let meetings: u32 = selected.iter().map(|s| pairings.count(s, candidate)).sum();
+                (meetings, pairings.total(candidate))
+            })
+            .expect("Candidates are not empty");
+        selected.push(candidates.remove(index));
+    }
+
+    selected
+}
+
+fn pool_participants(pool_path: &Path) -> Result<Vec<String>> {
+    let wasm_extension = OsStr::new("wasm");
+    let mut participants = vec![];
+    for file in fs::read_dir(pool_path).with_context(|| format!("reading {:?}", pool_path))? {
+        let path = file?.path();
+        if path.is_file() && path.extension() == Some(wasm_extension) {
+            match path.file_stem().and_then(OsStr::to_str) {
+                Some(stem) if !stem.contains(PAIRINGS_DELIMITER) && !stem.contains('\n') => {
+                    participants.push(stem.to_owned())
+                },
+                _ => warn!("Skipping {:?}, its name can't be recorded in the pairings.", path),
+            }
+        }
+    }
+    Ok(participants)
+}
+
+/// Copies the selected participants for the round that's about to start into its folder,
+/// where the hotswap system picks them up.
+fn schedule_round_system(round: Res<Round>, rounds_folder: Res<RoundsFolder>) -> Result<()> {
+    let pool_path = Path::new(POOL_FOLDER);
+    if !pool_path.is_dir() {
+        return Ok(());
+    }
+
+    let pairings_path = pool_path.join(PAIRINGS_FILENAME);
+    let mut pairings =
+        if pairings_path.exists() { Pairings::load(&pairings_path)? } else { Pairings::default() };
+    let participants = select_participants(&pool_participants(pool_path)?, &pairings, MAX_PLAYERS);
+
+    let round_folder = round_folder(&rounds_folder.0, round.0);
+    for participant in &participants {
+        let filename = format!("{}.wasm", participant);
+        let (from, to) = (pool_path.join(&filename), round_folder.join(&filename));
+        fs::copy(&from, &to).with_context(|| format!("copying {:?} to {:?}", from, to))?;
+    }
+    pairings.record(&participants);
+    pairings.save(&pairings_path)?;
+
+    info!("Scheduled {} for round {}.", participants.join(", "), round.0);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    fn pool(names: &[&str]) -> Vec<String> {
+        names.iter().map(|&n| n.to_owned()).collect()
+    }
+
+    #[test]
+    fn small_pools_are_selected_whole() {
+        let selected = select_participants(&pool(&["c", "a", "b", "a"]), &Pairings::default(), 8);
+        assert_eq!(selected, pool(&["a", "b", "c"]));
+    }
+
+    #[test]
+    fn participants_that_met_the_least_are_selected() {
+        let mut pairings = Pairings::default();
+        pairings.record(&pool(&["a", "b"]));
+        pairings.record(&pool(&["a", "c"]));
+        // `b` and `c` tie on the fewest pairings, so `b` goes first alphabetically, then `c`
+        // as it hasn't met `b` yet.
+        let selected = select_participants(&pool(&["a", "b", "c"]), &pairings, 2);
+        assert_eq!(selected, pool(&["b", "c"]));
+    }
+
+    #[test]
+    fn every_pair_eventually_meets() {
+        let names: Vec<String> = (0..16).map(|i| format!("team {:02}", i)).collect();
+        let mut pairings = Pairings::default();
+        for _ in 0..20 {
+            let selected = select_participants(&names, &pairings, 8);
+            assert_eq!(selected.len(), 8);
+            pairings.record(&selected);
+        }
+        for (i, a) in names.iter().enumerate() {
+            for b in &names[i + 1..] {
+                assert!(pairings.count(a, b) > 0, "{} never met {}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn pairings_survive_names_with_spaces() {
+        let path = env::temp_dir().join(format!("bomber_pairings_{}.txt", std::process::id()));
+        let mut pairings = Pairings::default();
+        pairings.record(&pool(&["tonari team", "other"]));
+        pairings.save(&path).unwrap();
+        let loaded = Pairings::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.count("other", "tonari team"), 1);
+        assert_eq!(loaded.0.len(), 1);
+    }
+}
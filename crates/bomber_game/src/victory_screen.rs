@@ -1,13 +1,22 @@
 use anyhow::Result;
-use bevy::prelude::*;
+use bevy::{prelude::*, utils::HashMap};
+use bevy_tweening::{lens::TransformPositionLens, *};
+use bomber_shared::Predictions;
+use std::{collections::VecDeque, time::Duration};
 
 use crate::{
     audio::SoundEffects,
+    game_map::{GameMap, TileLocation},
     log_unrecoverable_error_and_panic,
-    player_behaviour::{PlayerName, Team},
-    rendering::{PLAYER_HEIGHT_PX, PLAYER_WIDTH_PX, VICTORY_SCREEN_ITEMS_Z, VICTORY_SCREEN_Z},
+    native_player::HouseBot,
+    player::{HideNames, PlayerMovedEvent, PlayerName, Team},
+    rendering::{
+        PLAYER_HEIGHT_PX, PLAYER_VERTICAL_OFFSET_PX, PLAYER_WIDTH_PX, VICTORY_SCREEN_ITEMS_Z,
+        VICTORY_SCREEN_Z,
+    },
     score::Score,
-    state::{AppState, Round, RoundTimer},
+    state::{AppState, Round, RoundEndReason, RoundTimer, RoundsFolder},
+    tick::WHOLE_TURN_PERIOD,
 };
 
 pub struct VictoryScreenPlugin;
@@ -17,6 +26,34 @@ struct VictoryScreen;
 #[derive(Component)]
 struct CountdownText;
 
+/// Ghost of the winner retracing its last moves of the round behind the podium, over and over.
+#[derive(Component)]
+struct Highlight {
+    /// Moves as the time into the highlight they happened at, and the tiles moved between.
+    moves: Vec<(Duration, TileLocation, TileLocation)>,
+    next_move: usize,
+    elapsed: Duration,
+}
+
+/// Recent moves of every player, as the time since startup they happened at and the tiles
+/// moved between. Only the last `HIGHLIGHT_DURATION` is kept.
+#[derive(Default)]
+struct RecentMoves(HashMap<Entity, VecDeque<(Duration, TileLocation, TileLocation)>>);
+
+const PREDICTION_BAR_WIDTH_PX: f32 = 400.0;
+const PREDICTION_BAR_HEIGHT_PX: f32 = 12.0;
+/// Votes for teams that didn't show up in the round.
+const UNKNOWN_TEAM_COLOR: Color = Color::GRAY;
+/// How much of the end of the round the highlight replays.
+const HIGHLIGHT_DURATION: Duration = Duration::from_secs(10);
+/// Pause on the final position before the highlight starts over.
+const HIGHLIGHT_PAUSE: Duration = Duration::from_secs(1);
+/// Lets the arena and the highlight show through the background.
+const BACKGROUND_COLOR: Color = Color::rgba(0.0, 0.0, 0.0, 0.85);
+const GHOST_ALPHA: f32 = 0.6;
+/// The ghost goes over the background, but under the podium.
+const GHOST_Z: f32 = 0.5;
+
 struct Fonts {
     mono: Handle<Font>,
 }
@@ -27,10 +64,16 @@ impl Plugin for VictoryScreenPlugin {
 
         let fonts = Fonts { mono: asset_server.load("fonts/space_mono_400.ttf") };
         app.insert_resource(fonts);
-        app.add_system_set(SystemSet::on_enter(AppState::VictoryScreen).with_system(setup))
+        app.init_resource::<RecentMoves>()
+            .add_system_set(
+                SystemSet::on_enter(AppState::InGame).with_system(clear_recent_moves_system),
+            )
+            .add_system_set(SystemSet::on_update(AppState::InGame).with_system(record_moves_system))
+            .add_system_set(SystemSet::on_enter(AppState::VictoryScreen).with_system(setup))
             .add_system_set(
                 SystemSet::on_update(AppState::VictoryScreen)
-                    .with_system(countdown_text_system.chain(log_unrecoverable_error_and_panic)),
+                    .with_system(countdown_text_system.chain(log_unrecoverable_error_and_panic))
+                    .with_system(highlight_system),
             )
             .add_system_set(
                 SystemSet::on_exit(AppState::VictoryScreen)
@@ -39,27 +82,70 @@ impl Plugin for VictoryScreenPlugin {
     }
 }
 
+fn clear_recent_moves_system(mut recent_moves: ResMut<RecentMoves>) {
+    recent_moves.0.clear();
+}
+
+fn record_moves_system(
+    mut moved_events: EventReader<PlayerMovedEvent>,
+    time: Res<Time>,
+    mut recent_moves: ResMut<RecentMoves>,
+) {
+    let now = time.time_since_startup();
+    for PlayerMovedEvent { entity, from, to } in moved_events.iter() {
+        let moves = recent_moves.0.entry(*entity).or_default();
+        moves.push_back((now, *from, *to));
+        while moves.front().map_or(false, |(at, ..)| now - *at > HIGHLIGHT_DURATION) {
+            moves.pop_front();
+        }
+    }
+}
+
 fn setup(
-    player_query: Query<(&PlayerName, &Score, &Team)>,
+    player_query: Query<(Entity, &PlayerName, &Score, &Team), Without<HouseBot>>,
+    game_map_query: Query<&GameMap>,
+    // Grouped to stay within the lint on system parameters.
+    (recent_moves, time): (Res<RecentMoves>, Res<Time>),
+    hide_names: Res<HideNames>,
     asset_server: Res<AssetServer>,
     mut texture_atlases: ResMut<Assets<TextureAtlas>>,
     fonts: Res<Fonts>,
     windows: Res<Windows>,
     round: Res<Round>,
+    rounds_folder: Res<RoundsFolder>,
+    end_reason: Res<RoundEndReason>,
     audio: Res<Audio>,
     sound_effects: Res<SoundEffects>,
     mut commands: Commands,
 ) {
-    let window = windows.get_primary().unwrap();
+    // There is no window to show the podium in when running headless.
+    let window = if let Some(window) = windows.get_primary() { window } else { return };
     audio.play(sound_effects.win.clone());
 
+    // The round counter has already moved on to the next round.
+    let finished_round = round.0.saturating_sub(1);
+    let predictions = Predictions::read(&rounds_folder.0, finished_round).unwrap_or_else(|e| {
+        warn!("Not showing the predictions of round {}: {:#}", finished_round, e);
+        Predictions::default()
+    });
+    let team_colors = player_query
+        .iter()
+        .map(|(_, _, _, team)| (team.name.clone(), team.color))
+        .collect::<HashMap<_, _>>();
+    // TODO(ryo): Handle a tie.
+    let winner = player_query
+        .iter()
+        .filter(|(_, _, Score(point), _)| *point > 0)
+        .max_by_key(|(_, _, Score(point), _)| point);
+    let player_atlas = player_atlas(&asset_server, &mut texture_atlases);
+
     // Fill the background in a transparent black.
     commands
         .spawn()
         .insert(VictoryScreen)
         .insert_bundle(SpriteBundle {
             sprite: Sprite {
-                color: Color::rgba(0.0, 0.0, 0.0, 0.95),
+                color: BACKGROUND_COLOR,
                 custom_size: Some(Vec2::new(window.width(), window.height())),
                 ..Default::default()
             },
@@ -67,33 +153,71 @@ fn setup(
             ..Default::default()
         })
         .with_children(|parent| {
-            spawn_podium(parent, player_query, &asset_server, &mut texture_atlases, &fonts);
+            spawn_podium(
+                parent,
+                winner.map(|(_, name, score, team)| (name, score, team)),
+                hide_names.0,
+                end_reason.0.as_deref(),
+                &player_atlas,
+                &fonts,
+            );
+            if let (None, Some((entity, _, _, team)), Ok(game_map)) =
+                (&end_reason.0, winner, game_map_query.get_single())
+            {
+                let since = time.time_since_startup().saturating_sub(HIGHLIGHT_DURATION);
+                let moves = recent_moves.0.get(&entity).into_iter().flatten();
+                let moves = moves
+                    .filter(|(at, ..)| *at >= since)
+                    .map(|(at, from, to)| (*at - since, *from, *to))
+                    .collect();
+                spawn_highlight(parent, moves, team, game_map, &player_atlas);
+            }
+            spawn_prediction_bar(parent, &predictions, &team_colors, &fonts);
             spawn_countdown_text(parent, &fonts, &round);
         });
 }
 
-fn spawn_podium(
-    parent: &mut ChildBuilder,
-    player_query: Query<(&PlayerName, &Score, &Team)>,
+fn player_atlas(
     asset_server: &AssetServer,
     texture_atlases: &mut Assets<TextureAtlas>,
+) -> Handle<TextureAtlas> {
+    let texture_handle = asset_server.load("graphics/Sprites/Bomberman/sheet.png");
+    texture_atlases.add(TextureAtlas::from_grid(texture_handle, Vec2::new(21.0, 32.0), 5, 4))
+}
+
+fn spawn_podium(
+    parent: &mut ChildBuilder,
+    winner: Option<(&PlayerName, &Score, &Team)>,
+    hide_names: bool,
+    end_reason: Option<&str>,
+    player_atlas: &Handle<TextureAtlas>,
     fonts: &Fonts,
 ) {
-    // TODO(ryo): Handle a tie.
-    let no1_player = player_query
-        .iter()
-        .filter(|(_, Score(point), _)| *point > 0)
-        .max_by_key(|(_, Score(point), _)| point);
-    if let Some((PlayerName(name), Score(score), team)) = no1_player {
+    if let Some(reason) = end_reason {
         parent.spawn().insert_bundle(Text2dBundle {
-            text: mono_text(&format!("#1 {} from team {}", name, team.name), 60.0, fonts),
+            text: mono_text(reason, 60.0, fonts),
             transform: Transform::from_translation(Vec3::new(0.0, 80.0, VICTORY_SCREEN_ITEMS_Z)),
             ..Default::default()
         });
+        parent.spawn().insert_bundle(Text2dBundle {
+            text: mono_text("Upload a fix to get back in the game!", 30.0, fonts),
+            transform: Transform::from_translation(Vec3::new(0.0, -80.0, VICTORY_SCREEN_ITEMS_Z)),
+            ..Default::default()
+        });
+        return;
+    }
 
-        let texture_handle = asset_server.load("graphics/Sprites/Bomberman/sheet.png");
-        let texture_atlas = TextureAtlas::from_grid(texture_handle, Vec2::new(21.0, 32.0), 5, 4);
-        let texture_atlas_handle = texture_atlases.add(texture_atlas);
+    if let Some((PlayerName(name), Score(score), team)) = winner {
+        let headline = if hide_names {
+            format!("#1 from team {}", team.name)
+        } else {
+            format!("#1 {} from team {}", name, team.name)
+        };
+        parent.spawn().insert_bundle(Text2dBundle {
+            text: mono_text(&headline, 60.0, fonts),
+            transform: Transform::from_translation(Vec3::new(0.0, 80.0, VICTORY_SCREEN_ITEMS_Z)),
+            ..Default::default()
+        });
 
         // The player avatar doubled in size.
         parent.spawn().insert_bundle(SpriteSheetBundle {
@@ -103,7 +227,7 @@ fn spawn_podium(
                 custom_size: Some(Vec2::new(PLAYER_WIDTH_PX, PLAYER_HEIGHT_PX) * 2.0),
                 ..Default::default()
             },
-            texture_atlas: texture_atlas_handle,
+            texture_atlas: player_atlas.clone(),
             transform: Transform::from_translation(Vec3::new(0.0, 0.0, VICTORY_SCREEN_ITEMS_Z)),
             ..default()
         });
@@ -127,6 +251,119 @@ fn spawn_podium(
     }
 }
 
+/// Spawns the ghost of the winner, standing where its last recorded move started. Rounds won
+/// without moving in the end have nothing to replay.
+fn spawn_highlight(
+    parent: &mut ChildBuilder,
+    moves: Vec<(Duration, TileLocation, TileLocation)>,
+    team: &Team,
+    game_map: &GameMap,
+    player_atlas: &Handle<TextureAtlas>,
+) {
+    let start = if let Some((_, from, _)) = moves.first() { *from } else { return };
+    let mut color = team.color;
+    color.set_a(GHOST_ALPHA);
+    parent
+        .spawn()
+        .insert(Highlight { moves, next_move: 0, elapsed: Duration::ZERO })
+        .insert_bundle(SpriteSheetBundle {
+            sprite: TextureAtlasSprite {
+                index: 2,
+                color,
+                custom_size: Some(Vec2::new(PLAYER_WIDTH_PX, PLAYER_HEIGHT_PX)),
+                ..Default::default()
+            },
+            texture_atlas: player_atlas.clone(),
+            transform: Transform::from_translation(ghost_position(start, game_map)),
+            ..default()
+        });
+}
+
+fn ghost_position(location: TileLocation, game_map: &GameMap) -> Vec3 {
+    location.as_world_coordinates(game_map).extend(GHOST_Z)
+        + Vec3::new(0.0, PLAYER_VERTICAL_OFFSET_PX, 0.0)
+}
+
+/// Moves the ghost of the winner along its recorded moves, starting over once it's done.
+fn highlight_system(
+    mut highlight_query: Query<(Entity, &mut Highlight, &mut Transform)>,
+    game_map_query: Query<&GameMap>,
+    time: Res<Time>,
+    mut commands: Commands,
+) {
+    let game_map = if let Ok(game_map) = game_map_query.get_single() { game_map } else { return };
+    for (entity, mut highlight, mut transform) in highlight_query.iter_mut() {
+        highlight.elapsed += time.delta();
+        if highlight.elapsed > HIGHLIGHT_DURATION + HIGHLIGHT_PAUSE {
+            highlight.elapsed = Duration::ZERO;
+            highlight.next_move = 0;
+            commands.entity(entity).remove::<Animator<Transform>>();
+            transform.translation = ghost_position(highlight.moves[0].1, game_map);
+        }
+        while let Some(&(at, from, to)) = highlight.moves.get(highlight.next_move) {
+            if at > highlight.elapsed {
+                break;
+            }
+            highlight.next_move += 1;
+            commands.entity(entity).insert(Animator::new(Tween::new(
+                EaseMethod::Linear,
+                TweeningType::Once,
+                WHOLE_TURN_PERIOD,
+                TransformPositionLens {
+                    start: ghost_position(from, game_map),
+                    end: ghost_position(to, game_map),
+                },
+            )));
+        }
+    }
+}
+
+/// Shows how the audience voted on the winner, as a bar split between teams in their colors.
+fn spawn_prediction_bar(
+    parent: &mut ChildBuilder,
+    predictions: &Predictions,
+    team_colors: &HashMap<String, Color>,
+    fonts: &Fonts,
+) {
+    let total = predictions.total();
+    if total == 0 {
+        return;
+    }
+    let mut votes = predictions.0.iter().collect::<Vec<_>>();
+    votes.sort_by_key(|(_, count)| std::cmp::Reverse(**count));
+
+    let top_picks = votes
+        .iter()
+        .take(3)
+        .map(|(team, count)| format!("{} {}%", team, **count * 100 / total))
+        .collect::<Vec<_>>()
+        .join(", ");
+    parent.spawn().insert_bundle(Text2dBundle {
+        text: mono_text(&format!("Audience picks ({} votes): {}", total, top_picks), 20.0, fonts),
+        transform: Transform::from_translation(Vec3::new(0.0, -120.0, VICTORY_SCREEN_ITEMS_Z)),
+        ..Default::default()
+    });
+
+    let mut left = -PREDICTION_BAR_WIDTH_PX / 2.0;
+    for (team, count) in votes {
+        let width = PREDICTION_BAR_WIDTH_PX * *count as f32 / total as f32;
+        parent.spawn().insert_bundle(SpriteBundle {
+            sprite: Sprite {
+                color: team_colors.get(team).copied().unwrap_or(UNKNOWN_TEAM_COLOR),
+                custom_size: Some(Vec2::new(width, PREDICTION_BAR_HEIGHT_PX)),
+                ..Default::default()
+            },
+            transform: Transform::from_translation(Vec3::new(
+                left + width / 2.0,
+                -145.0,
+                VICTORY_SCREEN_ITEMS_Z,
+            )),
+            ..Default::default()
+        });
+        left += width;
+    }
+}
+
 fn spawn_countdown_text(parent: &mut ChildBuilder, fonts: &Fonts, round: &Round) {
     parent.spawn().insert_bundle(Text2dBundle {
         text: mono_text(&format!("Next round ({}) in...", round.0), 30.0, fonts),
@@ -0,0 +1,198 @@
+//! Defines a Bevy plugin that announces the results of each round to a webhook (such as a
+//! Discord channel's), when one is configured through `WEBHOOK_URL`.
+
+use bevy::prelude::*;
+use serde::Serialize;
+use std::{collections::BTreeMap, thread, time::Duration};
+
+use crate::{
+    game_map::{ForcedMap, GameMap},
+    player::Player,
+    player_hotswap::WasmPlayerAsset,
+    results::RoundResults,
+    score::Score,
+    settings::GameSettings,
+    stagnation::StagnationWatcher,
+    state::{AppState, Round},
+    stats::RoundStats,
+};
+
+const WEBHOOK_ATTEMPTS: u32 = 2;
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+const PODIUM_SIZE: usize = 3;
+
+pub struct WebhookPlugin;
+
+#[derive(Serialize, Debug)]
+struct Announcement {
+    /// Names of the best players of the round, best first.
+    podium: Vec<String>,
+    team_scores: BTreeMap<String, u32>,
+    #[serde(flatten)]
+    results: RoundResults,
+}
+
+impl Plugin for WebhookPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(
+            SystemSet::on_enter(AppState::VictoryScreen).with_system(announce_round_system),
+        );
+    }
+}
+
+impl From<RoundResults> for Announcement {
+    fn from(results: RoundResults) -> Self {
+        let podium = results
+            .players
+            .iter()
+            .filter(|p| p.score.is_some())
+            .take(PODIUM_SIZE)
+            .map(|p| p.name.clone())
+            .collect();
+        let mut team_scores = BTreeMap::new();
+        for player in &results.players {
+            *team_scores.entry(player.team_name.clone()).or_insert(0) +=
+                player.score.unwrap_or_default();
+        }
+        Self { podium, team_scores, results }
+    }
+}
+
+/// Posts the round announcement from a background thread, so a slow or unreachable webhook
+/// never stalls the game. Failures are only logged.
+fn announce_round_system(
+    settings: Res<GameSettings>,
+    player_query: Query<(&Handle<WasmPlayerAsset>, &Score), With<Player>>,
+    assets: Res<Assets<WasmPlayerAsset>>,
+    stats: Res<RoundStats>,
+    round: Res<Round>,
+    game_map_query: Query<(&GameMap, Option<&ForcedMap>)>,
+    watcher: Res<StagnationWatcher>,
+) {
+    let url = if let Some(url) = &settings.webhook_url { url.clone() } else { return };
+    let (game_map, forced) = game_map_query.single();
+    let announcement = Announcement::from(RoundResults::collect(
+        &player_query,
+        &assets,
+        &stats,
+        &round,
+        game_map,
+        forced.is_some(),
+        &settings,
+        watcher.nudges,
+    ));
+
+    thread::spawn(move || {
+        if !post_announcement(&url, &announcement) {
+            error!("Giving up on announcing round {}.", announcement.results.round);
+        }
+    });
+}
+
+/// Posts the announcement, trying again once if that fails. Returns whether it went through.
+fn post_announcement(url: &str, announcement: &Announcement) -> bool {
+    for attempt in 1..=WEBHOOK_ATTEMPTS {
+        match ureq::post(url).timeout(WEBHOOK_TIMEOUT).send_json(announcement) {
+            Ok(_) => {
+                info!("Round {} announced to the webhook.", announcement.results.round);
+                return true;
+            },
+            Err(e) => warn!(
+                "Failed to announce round {} (attempt {}/{}): {}",
+                announcement.results.round, attempt, WEBHOOK_ATTEMPTS, e
+            ),
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+        thread::JoinHandle,
+    };
+
+    use super::*;
+    use crate::settings::Rules;
+
+    fn announcement() -> Announcement {
+        Announcement::from(RoundResults {
+            round: 7,
+            name: "Test round".into(),
+            map: "test".into(),
+            map_forced: false,
+            rules: Rules::default(),
+            rules_override: None,
+            stalemate_nudges: 0,
+            players: vec![],
+        })
+    }
+
+    /// Serves one request with each of the given statuses, returning the webhook URL and a
+    /// handle yielding the bodies it received.
+    fn serve(statuses: Vec<u16>) -> (String, JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/webhook", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            statuses
+                .into_iter()
+                .map(|status| {
+                    let (stream, _) = listener.accept().unwrap();
+                    let mut reader = BufReader::new(stream);
+                    let mut content_length = 0;
+                    loop {
+                        let mut line = String::new();
+                        reader.read_line(&mut line).unwrap();
+                        if line == "\r\n" {
+                            break;
+                        }
+                        if let Some((name, value)) = line.split_once(':') {
+                            if name.eq_ignore_ascii_case("content-length") {
+                                content_length = value.trim().parse().unwrap();
+                            }
+                        }
+                    }
+                    let mut body = vec![0; content_length];
+                    reader.read_exact(&mut body).unwrap();
+                    write!(
+                        reader.get_mut(),
+                        "HTTP/1.1 {} Test\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                        status
+                    )
+                    .unwrap();
+                    String::from_utf8(body).unwrap()
+                })
+                .collect()
+        });
+        (url, server)
+    }
+
+    #[test]
+    fn announcement_is_posted_as_json() {
+        let (url, server) = serve(vec![200]);
+        assert!(post_announcement(&url, &announcement()));
+
+        let bodies = server.join().unwrap();
+        assert_eq!(bodies.len(), 1);
+        let json: serde_json::Value = serde_json::from_str(&bodies[0]).unwrap();
+        assert_eq!(json["round"], 7);
+        assert_eq!(json["name"], "Test round");
+        assert_eq!(json["podium"], serde_json::json!([]));
+    }
+
+    #[test]
+    fn failed_announcement_is_tried_again() {
+        let (url, server) = serve(vec![500, 200]);
+        assert!(post_announcement(&url, &announcement()));
+        assert_eq!(server.join().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn announcement_is_given_up_after_two_attempts() {
+        let (url, server) = serve(vec![500, 503]);
+        assert!(!post_announcement(&url, &announcement()));
+        assert_eq!(server.join().unwrap().len(), 2);
+    }
+}
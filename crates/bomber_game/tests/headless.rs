@@ -0,0 +1,25 @@
+//! Plays a short round without a display, the way unattended runs do in CI.
+
+use std::{env, fs, process::Command};
+
+#[test]
+fn plays_a_short_headless_round() {
+    let working_dir = env::temp_dir().join(format!("bomber_headless_{}", std::process::id()));
+    fs::create_dir_all(&working_dir).unwrap();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_bomber_game"))
+        .args(["--headless", "--rounds", "1", "--round-seconds", "2", "--exit-when-done"])
+        .current_dir(&working_dir)
+        .env(
+            // Assets are looked up next to the manifest rather than the working directory.
+            "CARGO_MANIFEST_DIR",
+            env!("CARGO_MANIFEST_DIR"),
+        )
+        .env("ROUNDS_FOLDER", working_dir.join("rounds"))
+        .status()
+        .unwrap();
+
+    assert!(status.success(), "the game exited with {}", status);
+    assert!(working_dir.join("rounds/leaderboard.json").exists());
+    fs::remove_dir_all(&working_dir).unwrap();
+}
@@ -1,4 +1,7 @@
-use std::convert::TryFrom;
+use std::{
+    convert::TryFrom,
+    sync::atomic::{AtomicU32, Ordering},
+};
 
 use bomber_lib::{
     self,
@@ -7,6 +10,15 @@ use bomber_lib::{
 };
 use bomber_macro::wasm_export;
 
+/// Which way the nearest hill is, as last told by the game (0 while a hill is in sight).
+static HILL_HINT: AtomicU32 = AtomicU32::new(0);
+
+/// Called by the game before each turn on maps that give hill hints.
+#[no_mangle]
+pub extern "C" fn __wasm_shim_hill_hint(hint: u32) {
+    HILL_HINT.store(hint, Ordering::Relaxed);
+}
+
 struct Wanderer {
     preferred_direction: Direction,
     bomb_ticks: u32,
@@ -26,6 +38,15 @@ impl Player for Wanderer {
         &mut self,
         surroundings: Vec<(Tile, Option<Object>, Option<Enemy>, bomber_lib::world::TileOffset)>,
     ) -> Action {
+        // Heads for the hill when the game tells them where it is.
+        match HILL_HINT.swap(0, Ordering::Relaxed) {
+            1 => self.preferred_direction = Direction::North,
+            2 => self.preferred_direction = Direction::East,
+            3 => self.preferred_direction = Direction::South,
+            4 => self.preferred_direction = Direction::West,
+            _ => {},
+        }
+
         // Drops a bomb every once in a while.
         if self.bomb_ticks >= 3 {
             self.bomb_ticks = 0;
@@ -39,7 +60,7 @@ impl Player for Wanderer {
             (o.is_none() && (*p == self.preferred_direction.extend(1))).then_some(t)
         });
         if matches!(preferred_tile, Some(Tile::Floor)) {
-            Action::Move(Direction::North)
+            Action::Move(self.preferred_direction)
         } else {
             surroundings
                 .iter()
@@ -0,0 +1,333 @@
+//! Conventions shared by the game and the upload server, which communicate through the
+//! rounds folder: the upload server drops `.wasm` files in the folder of the next unfinished
+//! round, and the game marks rounds as finished once played.
+
+use anyhow::{Context, Result};
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    env, fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+/// Environment variable that overrides where the rounds folder lives.
+pub const ROUNDS_FOLDER_ENV: &str = "ROUNDS_FOLDER";
+pub const DEFAULT_ROUNDS_FOLDER: &str = "rounds";
+/// Name of the file that the game uses to mark a finished round.
+pub const FINISHED_ROUND_MARKER_FILENAME: &str = "round-finished.marker";
+/// Name of the file in which the game describes a round as it creates its folder.
+pub const ROUND_CONFIG_FILENAME: &str = "round-config.json";
+/// Name of the file in which the upload server tallies the audience's predictions of a round.
+pub const PREDICTIONS_FILENAME: &str = "predictions.json";
+/// Uploads are written to a file with this in its extension, then renamed into place.
+pub const TEMP_FILE_EXTENSION_MARKER: &str = ".tmp";
+/// Temp files older than this were left behind by an upload that was interrupted.
+pub const STALE_TEMP_FILE_AGE: Duration = Duration::from_secs(60 * 60);
+/// Name of the optional custom section in which bots embed their `BuildMeta`, as JSON.
+pub const BUILD_META_SECTION: &str = "bomber.meta";
+/// Version of the interface between the game and `.wasm` players: the exports below and the
+/// encoding of what crosses the boundary. Bumped whenever a change breaks existing players.
+pub const ABI_VERSION: u32 = 1;
+/// Functions the game expects every `.wasm` player to export, as generated by the
+/// `bomber_macro::wasm_export` attribute for Rust bots.
+pub const REQUIRED_EXPORTS: &[&str] = &["__wasm_shim_act"];
+/// Optional export through which `.wasm` players report the size of the buffer their
+/// surroundings are decoded from.
+pub const BUFFER_SIZE_EXPORT: &str = "__wasm_get_buffer_size";
+/// Optional export through which `.wasm` players hand over their state before being reloaded,
+/// returning the pointer (high 32 bits) and length (low 32 bits) of the state in their memory.
+pub const SAVE_STATE_EXPORT: &str = "__wasm_shim_save_state";
+/// Optional export returning a pointer to as many bytes as requested, for the host to write a
+/// saved state into before calling `LOAD_STATE_EXPORT`.
+pub const LOAD_STATE_BUFFER_EXPORT: &str = "__wasm_shim_load_state_buffer";
+/// Optional export through which reloaded `.wasm` players pick up the state of their previous
+/// version, given its length.
+pub const LOAD_STATE_EXPORT: &str = "__wasm_shim_load_state";
+/// Optional export through which `.wasm` players are told, before each turn, which way the
+/// nearest hill is when none is in sight: 1 to 4 for north, east, south and west, or 0.
+pub const HILL_HINT_EXPORT: &str = "__wasm_shim_hill_hint";
+/// Optional export returning a pointer to as many bytes as requested, for the host to write
+/// the team name of a player's killer into before calling `LAST_DEATH_EXPORT`.
+pub const LAST_DEATH_BUFFER_EXPORT: &str = "__wasm_shim_last_death_buffer";
+/// Optional export through which respawned `.wasm` players are told how they last died: the
+/// length of the killer's team name (0 for their own bomb), and the offset of the bomb.
+pub const LAST_DEATH_EXPORT: &str = "__wasm_shim_last_death";
+/// Optional export through which `.wasm` players are told, before each turn, how many of their
+/// bombs are still on the map and how many they may have there at once.
+pub const BOMB_COUNT_EXPORT: &str = "__wasm_shim_bomb_count";
+/// Optional export through which `.wasm` players are told, before each turn, how many points
+/// they scored since their previous turn.
+pub const SCORED_EXPORT: &str = "__wasm_shim_scored";
+/// Functions `.wasm` players may export to opt into the features above.
+pub const OPTIONAL_EXPORTS: &[&str] = &[
+    BUFFER_SIZE_EXPORT,
+    SAVE_STATE_EXPORT,
+    LOAD_STATE_BUFFER_EXPORT,
+    LOAD_STATE_EXPORT,
+    HILL_HINT_EXPORT,
+    LAST_DEATH_BUFFER_EXPORT,
+    LAST_DEATH_EXPORT,
+    BOMB_COUNT_EXPORT,
+    SCORED_EXPORT,
+];
+const SHORT_HASH_LENGTH: usize = 7;
+const WASM_BINARY_PREFIX: &[u8] = b"\0asm";
+
+/// Describes which build of a bot a `.wasm` file is, so teams can tell what's live.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct BuildMeta {
+    pub git_hash: Option<String>,
+    /// Seconds since the Unix epoch.
+    pub build_time: Option<u64>,
+}
+
+impl BuildMeta {
+    /// Abbreviated git hash, as shown next to player names.
+    pub fn short_hash(&self) -> Option<&str> {
+        self.git_hash.as_deref().map(|h| h.get(..SHORT_HASH_LENGTH).unwrap_or(h))
+    }
+
+    /// Reads the build metadata embedded in a binary `.wasm` module. Returns `None` if the
+    /// module has none (which includes modules in text format), and an error if the module or
+    /// the metadata are malformed.
+    pub fn read(wasm: &[u8]) -> Result<Option<Self>> {
+        if !wasm.starts_with(WASM_BINARY_PREFIX) {
+            return Ok(None);
+        }
+        for payload in wasmparser::Parser::new(0).parse_all(wasm) {
+            if let wasmparser::Payload::CustomSection { name, data, .. } =
+                payload.context("parsing the module")?
+            {
+                if name == BUILD_META_SECTION {
+                    return serde_json::from_slice(data)
+                        .with_context(|| format!("parsing the {} section", BUILD_META_SECTION))
+                        .map(Some);
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// How the game is set to play a round, so the upload server fills rounds up to the number of
+/// players the game takes rather than keeping its own count.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RoundConfig {
+    pub max_players: usize,
+    /// Rules of the round, recorded for reference. The upload server doesn't need them.
+    #[serde(default)]
+    pub rules: serde_json::Value,
+}
+
+impl RoundConfig {
+    /// Reads the config of the given round, or `None` if the game hasn't written one.
+    pub fn read(rounds_folder: &Path, round: u32) -> Result<Option<Self>> {
+        let path = round_config_path(rounds_folder, round);
+        let json = match fs::read(&path) {
+            Ok(json) => json,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("reading {:?}", path)),
+        };
+        serde_json::from_slice(&json).with_context(|| format!("parsing {:?}", path)).map(Some)
+    }
+
+    /// Writes the config through a temp file, so the upload server never reads a partial file.
+    pub fn write(&self, rounds_folder: &Path, round: u32) -> Result<()> {
+        let path = round_config_path(rounds_folder, round);
+        let temp_path = path.with_extension(format!("json{}", TEMP_FILE_EXTENSION_MARKER));
+        fs::write(&temp_path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("writing {:?}", temp_path))?;
+        fs::rename(&temp_path, &path).with_context(|| format!("renaming {:?}", temp_path))
+    }
+}
+
+/// Votes of the audience on which team wins a round, by team name.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Predictions(pub BTreeMap<String, u32>);
+
+impl Predictions {
+    /// Reads the predictions of the given round, which has none until somebody votes.
+    pub fn read(rounds_folder: &Path, round: u32) -> Result<Self> {
+        let path = predictions_path(rounds_folder, round);
+        let json = match fs::read(&path) {
+            Ok(json) => json,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e).with_context(|| format!("reading {:?}", path)),
+        };
+        serde_json::from_slice(&json).with_context(|| format!("parsing {:?}", path))
+    }
+
+    /// Writes the predictions through a temp file, so the game never reads a partial file.
+    pub fn write(&self, rounds_folder: &Path, round: u32) -> Result<()> {
+        let path = predictions_path(rounds_folder, round);
+        let temp_path = path.with_extension(format!("json{}", TEMP_FILE_EXTENSION_MARKER));
+        fs::write(&temp_path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("writing {:?}", temp_path))?;
+        fs::rename(&temp_path, &path).with_context(|| format!("renaming {:?}", temp_path))
+    }
+
+    pub fn total(&self) -> u32 {
+        self.0.values().sum()
+    }
+}
+
+/// The rounds folder, as configured through `ROUNDS_FOLDER_ENV`.
+pub fn rounds_folder() -> PathBuf {
+    env::var_os(ROUNDS_FOLDER_ENV).map_or_else(|| DEFAULT_ROUNDS_FOLDER.into(), PathBuf::from)
+}
+
+/// Folder holding the players and results of the given round.
+pub fn round_folder(rounds_folder: &Path, round: u32) -> PathBuf {
+    rounds_folder.join(round.to_string())
+}
+
+/// Path of the marker file that exists once the given round is finished.
+pub fn finished_round_marker(rounds_folder: &Path, round: u32) -> PathBuf {
+    round_folder(rounds_folder, round).join(FINISHED_ROUND_MARKER_FILENAME)
+}
+
+/// Path of the file holding the audience's predictions of the given round.
+pub fn predictions_path(rounds_folder: &Path, round: u32) -> PathBuf {
+    round_folder(rounds_folder, round).join(PREDICTIONS_FILENAME)
+}
+
+/// Path of the file describing how the game plays the given round.
+pub fn round_config_path(rounds_folder: &Path, round: u32) -> PathBuf {
+    round_folder(rounds_folder, round).join(ROUND_CONFIG_FILENAME)
+}
+
+/// Deletes temp files older than `max_age` from every round folder. These are left behind if
+/// the upload server dies between writing an upload and renaming it into place. Returns how
+/// many files were deleted. Files that can't be looked at or deleted are skipped with a
+/// warning, as they may be renamed into place or cleaned up by someone else meanwhile.
+pub fn remove_stale_temp_files(rounds_folder: &Path, max_age: Duration) -> io::Result<usize> {
+    let now = SystemTime::now();
+    let mut removed = 0;
+    for round in fs::read_dir(rounds_folder)? {
+        let round_path = round?.path();
+        if !round_path.is_dir() {
+            continue;
+        }
+        let files = match fs::read_dir(&round_path) {
+            Ok(files) => files,
+            Err(e) => {
+                warn!("Skipping stale temp files in {:?}: {}", round_path, e);
+                continue;
+            },
+        };
+        for file in files {
+            let file = match file {
+                Ok(file) => file,
+                Err(e) => {
+                    warn!("Skipping a file in {:?}: {}", round_path, e);
+                    continue;
+                },
+            };
+            if !file.file_name().to_string_lossy().contains(TEMP_FILE_EXTENSION_MARKER) {
+                continue;
+            }
+            let modified = match file.metadata().and_then(|m| m.modified()) {
+                Ok(modified) => modified,
+                // Renamed into place since it was listed.
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => {
+                    warn!("Skipping {:?}: {}", file.path(), e);
+                    continue;
+                },
+            };
+            if now.duration_since(modified).map_or(true, |age| age <= max_age) {
+                continue;
+            }
+            match fs::remove_file(file.path()) {
+                Ok(()) => removed += 1,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {},
+                Err(e) => warn!("Couldn't remove {:?}: {}", file.path(), e),
+            }
+        }
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Empty folder for a test to play in, named after it so tests don't step on each other.
+    fn test_folder(name: &str) -> PathBuf {
+        let folder = env::temp_dir().join(format!("bomber_shared_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&folder);
+        fs::create_dir_all(&folder).unwrap();
+        folder
+    }
+
+    /// The only test touching `ROUNDS_FOLDER_ENV`, as the environment is shared by all tests.
+    #[test]
+    fn game_and_server_agree_on_paths_under_the_configured_folder() {
+        let rounds = test_folder("configured_rounds_folder");
+        env::set_var(ROUNDS_FOLDER_ENV, &rounds);
+        // The game makes the folder absolute from its working directory, and the upload server
+        // uses it as is from its own, so they only agree when the folder is absolute.
+        let game_rounds = env::current_dir().unwrap().join(rounds_folder());
+        let server_rounds = rounds_folder();
+        env::remove_var(ROUNDS_FOLDER_ENV);
+
+        assert_eq!(server_rounds, rounds);
+        assert_eq!(game_rounds, server_rounds);
+        assert_eq!(round_folder(&server_rounds, 12), rounds.join("12"));
+        assert_eq!(
+            finished_round_marker(&game_rounds, 12),
+            rounds.join("12").join(FINISHED_ROUND_MARKER_FILENAME)
+        );
+        assert_eq!(rounds_folder(), PathBuf::from(DEFAULT_ROUNDS_FOLDER));
+        fs::remove_dir_all(&rounds).unwrap();
+    }
+
+    #[test]
+    fn round_config_round_trips() {
+        let rounds = test_folder("round_config_round_trips");
+        fs::create_dir_all(round_folder(&rounds, 3)).unwrap();
+        let config = RoundConfig { max_players: 7, rules: serde_json::json!({ "base_vision": 5 }) };
+
+        assert_eq!(RoundConfig::read(&rounds, 3).unwrap(), None);
+        config.write(&rounds, 3).unwrap();
+        assert_eq!(RoundConfig::read(&rounds, 3).unwrap(), Some(config));
+        // Nothing is left behind next to the config.
+        assert_eq!(fs::read_dir(round_folder(&rounds, 3)).unwrap().count(), 1);
+        fs::remove_dir_all(&rounds).unwrap();
+    }
+
+    #[test]
+    fn round_config_without_rules_reads() {
+        let rounds = test_folder("round_config_without_rules_reads");
+        fs::create_dir_all(round_folder(&rounds, 1)).unwrap();
+        fs::write(round_config_path(&rounds, 1), r#"{ "max_players": 4 }"#).unwrap();
+
+        let config = RoundConfig::read(&rounds, 1).unwrap().unwrap();
+        assert_eq!(config.max_players, 4);
+        assert_eq!(config.rules, serde_json::Value::Null);
+        fs::remove_dir_all(&rounds).unwrap();
+    }
+
+    #[test]
+    fn only_stale_temp_files_are_removed() {
+        let rounds = test_folder("only_stale_temp_files_are_removed");
+        let round = round_folder(&rounds, 1);
+        fs::create_dir_all(&round).unwrap();
+        fs::write(rounds.join("audit.log"), "").unwrap();
+        fs::write(round.join("key.wasm"), "").unwrap();
+        fs::write(round.join(format!("key.wasm{}123", TEMP_FILE_EXTENSION_MARKER)), "").unwrap();
+
+        // Nothing is old enough yet.
+        assert_eq!(remove_stale_temp_files(&rounds, STALE_TEMP_FILE_AGE).unwrap(), 0);
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(remove_stale_temp_files(&rounds, Duration::from_millis(10)).unwrap(), 1);
+
+        let left =
+            fs::read_dir(&round).unwrap().map(|f| f.unwrap().file_name()).collect::<Vec<_>>();
+        assert_eq!(left, vec!["key.wasm"]);
+        assert!(rounds.join("audit.log").exists());
+        fs::remove_dir_all(&rounds).unwrap();
+    }
+}
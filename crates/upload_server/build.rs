@@ -0,0 +1,24 @@
+//! Bakes the `bomber_lib` version the workspace is locked to into the binary, so the upload
+//! server can tell teams which version to build against.
+
+use std::fs;
+use toml::Value;
+
+const LOCK_FILE: &str = "../../Cargo.lock";
+
+fn main() {
+    println!("cargo:rerun-if-changed={}", LOCK_FILE);
+    let lock = fs::read_to_string(LOCK_FILE)
+        .ok()
+        .and_then(|text| text.parse::<Value>().ok())
+        .unwrap_or_else(|| Value::Table(Default::default()));
+
+    let package = lock.get("package").and_then(Value::as_array).and_then(|packages| {
+        packages.iter().find(|p| p.get("name").and_then(Value::as_str) == Some("bomber_lib"))
+    });
+    let field =
+        |key: &str| package.and_then(|p| p.get(key)).and_then(Value::as_str).unwrap_or("unknown");
+
+    println!("cargo:rustc-env=BOMBER_LIB_VERSION={}", field("version"));
+    println!("cargo:rustc-env=BOMBER_LIB_SOURCE={}", field("source"));
+}
@@ -0,0 +1,25 @@
+use bomber_shared::{ABI_VERSION, OPTIONAL_EXPORTS, REQUIRED_EXPORTS};
+use rouille::{Request, Response};
+use serde_json::json;
+
+use crate::upload_handler::{text_response, METHOD_NOT_ALLOWED};
+
+/// `bomber_lib` version and source (git revision) from the workspace `Cargo.lock`.
+pub const BOMBER_LIB_VERSION: &str = env!("BOMBER_LIB_VERSION");
+pub const BOMBER_LIB_SOURCE: &str = env!("BOMBER_LIB_SOURCE");
+
+/// Handles `GET /abi`, describing the `bomber_lib` the game is built with and the interface it
+/// expects, so tooling can check a bot is compatible before uploading it.
+pub fn abi_handler(request: &Request) -> Response {
+    if request.method() != "GET" {
+        return text_response("We only accept HTTP GET.\n").with_status_code(METHOD_NOT_ALLOWED);
+    }
+
+    Response::json(&json!({
+        "bomber_lib_version": BOMBER_LIB_VERSION,
+        "bomber_lib_source": BOMBER_LIB_SOURCE,
+        "abi_version": ABI_VERSION,
+        "required_exports": REQUIRED_EXPORTS,
+        "optional_exports": OPTIONAL_EXPORTS,
+    }))
+}
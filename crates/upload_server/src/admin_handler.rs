@@ -0,0 +1,80 @@
+use crate::{
+    api_keys::{generate_api_keys, ApiKeys},
+    audit_log::AuditLog,
+    upload_handler::{
+        text_response, BAD_REQUEST, INTERNAL_SERVER_ERROR, METHOD_NOT_ALLOWED, UNAUTHORIZED,
+    },
+};
+use rouille::{Request, Response};
+
+/// Upper bound on keys generated per request, to catch typos in `count`.
+const MAX_GENERATED_KEYS: usize = 100;
+
+/// Handles `POST /admin/keys/generate?count=N`, authenticated with the `ADMIN_KEY` in the
+/// Api-Key header. The endpoint is disabled when no admin key is configured.
+pub fn generate_keys_handler(
+    request: &Request,
+    api_keys: &ApiKeys,
+    admin_key: Option<&str>,
+) -> Response {
+    if request.method() != "POST" {
+        return text_response("We only accept HTTP POST.\n").with_status_code(METHOD_NOT_ALLOWED);
+    }
+
+    if !is_admin(request, admin_key) {
+        return text_response("Admin key required.\n").with_status_code(UNAUTHORIZED);
+    }
+
+    let count = match request.get_param("count").map(|count| count.parse::<usize>()) {
+        Some(Ok(count)) if (1..=MAX_GENERATED_KEYS).contains(&count) => count,
+        _ => {
+            return text_response(format!(
+                "Query parameter count must be a number between 1 and {}.\n",
+                MAX_GENERATED_KEYS
+            ))
+            .with_status_code(BAD_REQUEST)
+        },
+    };
+
+    match generate_api_keys(api_keys, count) {
+        Ok(keys) => text_response(keys.join("\n") + "\n"),
+        Err(e) => text_response(format!("Error generating keys: {:#}\n", e))
+            .with_status_code(INTERNAL_SERVER_ERROR),
+    }
+}
+
+/// Handles `GET /admin/audit?round=N`, returning the audit log entries of the accepted uploads
+/// as a JSON array, of round N or of all rounds without the parameter. Authenticated like
+/// [`generate_keys_handler`].
+pub fn audit_handler(request: &Request, audit_log: &AuditLog, admin_key: Option<&str>) -> Response {
+    if request.method() != "GET" {
+        return text_response("We only accept HTTP GET.\n").with_status_code(METHOD_NOT_ALLOWED);
+    }
+    if !is_admin(request, admin_key) {
+        return text_response("Admin key required.\n").with_status_code(UNAUTHORIZED);
+    }
+
+    let round = match request.get_param("round").map(|round| round.parse::<u32>()) {
+        Some(Ok(round)) => Some(round),
+        Some(Err(_)) => {
+            return text_response("Query parameter round must be a round number.\n")
+                .with_status_code(BAD_REQUEST)
+        },
+        None => None,
+    };
+
+    match audit_log.entries(round) {
+        Ok(entries) => Response::json(&entries),
+        Err(e) => text_response(format!("Error reading the audit log: {:#}\n", e))
+            .with_status_code(INTERNAL_SERVER_ERROR),
+    }
+}
+
+/// Whether the request carries the `ADMIN_KEY` in its Api-Key header. Admin endpoints are
+/// disabled when no admin key is configured.
+fn is_admin(request: &Request, admin_key: Option<&str>) -> bool {
+    matches!(
+        (admin_key, request.header("Api-Key")),
+        (Some(admin_key), Some(api_key)) if admin_key == api_key
+    )
+}
@@ -3,11 +3,19 @@ use log::*;
 use rand::Rng;
 use std::{
     cmp::Ordering,
-    fs::OpenOptions,
+    fs::{self, File, OpenOptions},
     io::{BufRead, BufReader, Seek, Write},
+    sync::{Arc, RwLock},
+    thread,
+    time::{Duration, SystemTime},
 };
 
 const API_KEYS_FILE: &str = "api_keys.txt";
+/// How often `api_keys.txt` is checked for changes.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(3);
+
+/// Currently valid API keys, swapped whenever `api_keys.txt` changes.
+pub type ApiKeys = Arc<RwLock<Vec<String>>>;
 
 /// Reads or creates file with API keys for players, returns a slice of valid keys.
 pub fn init_api_keys(key_count: usize) -> Result<Vec<String>, Error> {
@@ -39,10 +47,8 @@ pub fn init_api_keys(key_count: usize) -> Result<Vec<String>, Error> {
                 API_KEYS_FILE,
                 key_count
             );
-            let mut random_generator = rand::thread_rng();
             while keys.len() < key_count {
-                let random: u64 = random_generator.gen();
-                keys.push(format!("{:x}", random));
+                keys.push(random_key());
             }
 
             // Truncate the file and overwrite.
@@ -57,3 +63,69 @@ pub fn init_api_keys(key_count: usize) -> Result<Vec<String>, Error> {
 
     Ok(keys)
 }
+
+/// Polls `api_keys.txt` in a background thread and swaps in its contents when it changes, so
+/// keys can be added or revoked without restarting the server.
+pub fn watch_api_keys(api_keys: ApiKeys) {
+    thread::spawn(move || {
+        let mut last_modified = modified_time();
+        loop {
+            thread::sleep(RELOAD_INTERVAL);
+            let modified = modified_time();
+            if modified == last_modified {
+                continue;
+            }
+            last_modified = modified;
+
+            match read_api_keys() {
+                Ok(keys) => {
+                    info!("{} changed, now accepting {} keys.", API_KEYS_FILE, keys.len());
+                    *api_keys.write().unwrap() = keys;
+                },
+                Err(e) => error!("Reloading {}, keeping the previous keys: {:#}", API_KEYS_FILE, e),
+            }
+        }
+    });
+}
+
+/// Appends `count` new random keys to `api_keys.txt`, starts accepting them, and returns them.
+pub fn generate_api_keys(api_keys: &ApiKeys, count: usize) -> Result<Vec<String>, Error> {
+    // Hold the lock while writing so concurrent requests don't interleave their lines.
+    let mut keys = api_keys.write().unwrap();
+    let new_keys: Vec<String> = (0..count).map(|_| random_key()).collect();
+
+    let missing_newline = fs::read_to_string(API_KEYS_FILE)
+        .map(|text| !text.is_empty() && !text.ends_with('\n'))
+        .unwrap_or(false);
+    let mut file = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(API_KEYS_FILE)
+        .with_context(|| format!("Opening {} for appending.", API_KEYS_FILE))?;
+    if missing_newline {
+        writeln!(&mut file)?;
+    }
+    for key in &new_keys {
+        writeln!(&mut file, "{}", key)?;
+    }
+
+    keys.extend(new_keys.iter().cloned());
+    info!("Generated {} new API keys.", count);
+    Ok(new_keys)
+}
+
+fn read_api_keys() -> Result<Vec<String>, Error> {
+    let file = File::open(API_KEYS_FILE)
+        .with_context(|| format!("Opening {} for reading.", API_KEYS_FILE))?;
+    let keys: Result<Vec<_>, _> = BufReader::new(file).lines().collect();
+    Ok(keys?.into_iter().map(|key| key.trim().to_owned()).filter(|key| !key.is_empty()).collect())
+}
+
+fn modified_time() -> Option<SystemTime> {
+    fs::metadata(API_KEYS_FILE).and_then(|metadata| metadata.modified()).ok()
+}
+
+fn random_key() -> String {
+    let random: u64 = rand::thread_rng().gen();
+    format!("{:x}", random)
+}
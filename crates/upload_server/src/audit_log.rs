@@ -0,0 +1,365 @@
+use anyhow::{bail, Context, Error};
+use bomber_shared::rounds_folder;
+use log::*;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    env,
+    fs::{self, OpenOptions},
+    io::{ErrorKind, Read, Seek, SeekFrom, Write},
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const AUDIT_LOG_FILENAME: &str = "audit.log";
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+/// Hex digits of the SHA-256 of an API key kept to tell keys apart in the log.
+const KEY_FINGERPRINT_LENGTH: usize = 16;
+/// Megabytes each API key may upload per day by default.
+const DEFAULT_MAX_UPLOAD_MB_PER_DAY: u64 = 50;
+
+/// Append-only record of accepted uploads, one JSON object per line under the rounds folder,
+/// for settling disputes about who uploaded what and when. API keys are recorded by their
+/// fingerprint, so the log doesn't hand out upload credentials.
+///
+/// Entries are appended with a single write to a file opened in append mode, so they don't
+/// interleave with other processes appending small lines. The lock also keeps the workers of
+/// this server from racing to repair a truncated last line.
+///
+/// The bytes each API key uploaded on the current (UTC) day are kept in memory alongside, so
+/// the daily quota can be enforced without reading the whole log on every upload.
+pub struct AuditLog {
+    path: PathBuf,
+    /// Keyed by API key fingerprint.
+    usage: Mutex<HashMap<String, DailyUsage>>,
+    max_bytes_per_day: u64,
+}
+
+/// Bytes uploaded with an API key on a given day, counted in days since the Unix epoch.
+#[derive(Default, Clone, Copy)]
+struct DailyUsage {
+    day: u64,
+    bytes: u64,
+}
+
+impl AuditLog {
+    /// Picks up the uploads already made today from the log on disk, with a quota of
+    /// `MAX_UPLOAD_MB_PER_DAY` megabytes per API key.
+    pub fn load() -> Result<Self, Error> {
+        let max_upload_mb = match env::var("MAX_UPLOAD_MB_PER_DAY") {
+            Ok(mb) => mb.parse().context("parsing ${MAX_UPLOAD_MB_PER_DAY} as u64")?,
+            Err(_) => DEFAULT_MAX_UPLOAD_MB_PER_DAY,
+        };
+        Self::open(rounds_folder().join(AUDIT_LOG_FILENAME), max_upload_mb * 1_000_000)
+    }
+
+    fn open(path: PathBuf, max_bytes_per_day: u64) -> Result<Self, Error> {
+        let today = today()?;
+        let mut usage = HashMap::<String, DailyUsage>::new();
+        for entry in read_entries(&path, None)? {
+            let day = entry["timestamp"].as_u64().map(|timestamp| timestamp / SECONDS_PER_DAY);
+            if let (Some(fingerprint), Some(size), true) =
+                (entry["key_fingerprint"].as_str(), entry["size"].as_u64(), day == Some(today))
+            {
+                let key_usage = usage.entry(fingerprint.to_owned()).or_default();
+                key_usage.day = today;
+                key_usage.bytes += size;
+            }
+        }
+        Ok(Self { path, usage: Mutex::new(usage), max_bytes_per_day })
+    }
+
+    /// Sets `size` bytes of today's quota of `api_key` aside for an upload, or fails if that
+    /// would go over it. Checking and setting aside under one lock keeps concurrent uploads
+    /// with the same key from going over the quota together.
+    pub fn reserve_quota(&self, api_key: &str, size: usize) -> Result<QuotaReservation<'_>, Error> {
+        let today = today()?;
+        let fingerprint = key_fingerprint(api_key);
+        let mut usage = self.usage.lock().unwrap();
+        let key_usage = usage.entry(fingerprint.clone()).or_default();
+        if key_usage.day != today {
+            *key_usage = DailyUsage { day: today, bytes: 0 };
+        }
+        if key_usage.bytes + size as u64 > self.max_bytes_per_day {
+            bail!(
+                "this API key already uploaded {} of its {} bytes today (UTC), try again tomorrow",
+                key_usage.bytes,
+                self.max_bytes_per_day
+            );
+        }
+        key_usage.bytes += size as u64;
+        Ok(QuotaReservation { usage: &self.usage, fingerprint, day: today, bytes: size as u64 })
+    }
+
+    /// Appends an entry for an upload that has been saved to a round. The upload counts
+    /// against the quota through its reservation, which is only kept once this succeeds.
+    pub fn record_upload(
+        &self,
+        api_key: &str,
+        data: &[u8],
+        round: u32,
+        address: IpAddr,
+    ) -> Result<(), Error> {
+        let timestamp = now()?;
+        let fingerprint = key_fingerprint(api_key);
+        let entry = json!({
+            "timestamp": timestamp,
+            "key_fingerprint": fingerprint,
+            "sha256": hex(&Sha256::digest(data)),
+            "size": data.len(),
+            "round": round,
+            "client_ip": address.to_string(),
+        });
+        let mut line = entry.to_string();
+        line.push('\n');
+
+        let _lock = self.usage.lock().unwrap();
+        let path = &self.path;
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)
+            .with_context(|| format!("opening {:?}", path))?;
+        // A write cut short (by a crash or a full disk) leaves a line without its newline,
+        // which would swallow this entry too.
+        if file.metadata()?.len() > 0 {
+            let mut last_byte = [0];
+            file.seek(SeekFrom::End(-1))?;
+            file.read_exact(&mut last_byte)?;
+            if last_byte[0] != b'\n' {
+                line.insert(0, '\n');
+            }
+        }
+        file.write_all(line.as_bytes()).with_context(|| format!("writing {:?}", path))?;
+        Ok(())
+    }
+
+    /// Entries of the uploads to the given round, or to all rounds. Lines that can't be parsed,
+    /// such as one cut short by a crash, are skipped.
+    pub fn entries(&self, round: Option<u32>) -> Result<Vec<Value>, Error> {
+        read_entries(&self.path, round)
+    }
+}
+
+/// Bytes set aside from an API key's daily quota for an upload in progress. They are given back
+/// when the reservation is dropped, unless it was kept because the upload was recorded.
+pub struct QuotaReservation<'a> {
+    usage: &'a Mutex<HashMap<String, DailyUsage>>,
+    fingerprint: String,
+    day: u64,
+    bytes: u64,
+}
+
+impl QuotaReservation<'_> {
+    /// Counts the bytes against the quota for good.
+    pub fn keep(mut self) {
+        self.bytes = 0;
+    }
+}
+
+impl Drop for QuotaReservation<'_> {
+    fn drop(&mut self) {
+        if self.bytes == 0 {
+            return;
+        }
+        let mut usage = self.usage.lock().unwrap();
+        // A reservation made yesterday has nothing to give back to today's quota.
+        if let Some(key_usage) = usage.get_mut(&self.fingerprint).filter(|u| u.day == self.day) {
+            key_usage.bytes = key_usage.bytes.saturating_sub(self.bytes);
+        }
+    }
+}
+
+/// Reads the entries of the log at `path`. Entries written before keys were fingerprinted have
+/// their key replaced by its fingerprint.
+fn read_entries(path: &Path, round: Option<u32>) -> Result<Vec<Value>, Error> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e).with_context(|| format!("reading {:?}", path)),
+    };
+
+    let mut entries = vec![];
+    for (number, line) in text.lines().enumerate().filter(|(_, l)| !l.is_empty()) {
+        match serde_json::from_str::<Value>(line) {
+            Ok(mut entry) if round.map_or(true, |r| entry["round"].as_u64() == Some(r.into())) => {
+                if let Some(Value::String(api_key)) =
+                    entry.as_object_mut().and_then(|e| e.remove("api_key"))
+                {
+                    entry["key_fingerprint"] = key_fingerprint(&api_key).into();
+                }
+                entries.push(entry)
+            },
+            Ok(_) => {},
+            Err(e) => warn!("Skipping line {} of {:?}: {}", number + 1, path, e),
+        }
+    }
+    Ok(entries)
+}
+
+fn now() -> Result<u64, Error> {
+    Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs())
+}
+
+fn today() -> Result<u64, Error> {
+    Ok(now()? / SECONDS_PER_DAY)
+}
+
+/// Short digest of an API key, enough to tell keys apart without revealing them.
+fn key_fingerprint(api_key: &str) -> String {
+    let mut fingerprint = hex(&Sha256::digest(api_key.as_bytes()));
+    fingerprint.truncate(KEY_FINGERPRINT_LENGTH);
+    fingerprint
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_folder;
+    use std::{
+        sync::{Arc, Barrier},
+        thread,
+    };
+
+    const ADDRESS: [u8; 4] = [127, 0, 0, 1];
+
+    #[test]
+    fn keys_are_fingerprinted() {
+        let folder = test_folder("keys_are_fingerprinted");
+        let audit_log = AuditLog::open(folder.join(AUDIT_LOG_FILENAME), u64::MAX).unwrap();
+        audit_log.record_upload("secret-key", b"\0asm", 2, ADDRESS.into()).unwrap();
+
+        let text = fs::read_to_string(folder.join(AUDIT_LOG_FILENAME)).unwrap();
+        assert!(!text.contains("secret-key"));
+        let entries = audit_log.entries(Some(2)).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0]["key_fingerprint"], key_fingerprint("secret-key"));
+        assert_eq!(entries[0]["size"], 4);
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn entries_with_raw_keys_are_fingerprinted_on_read() {
+        let folder = test_folder("entries_with_raw_keys_are_fingerprinted_on_read");
+        let path = folder.join(AUDIT_LOG_FILENAME);
+        fs::write(&path, "{\"api_key\":\"old-key\",\"round\":1,\"size\":4}\n").unwrap();
+
+        let entries = read_entries(&path, None).unwrap();
+        assert_eq!(entries[0]["key_fingerprint"], key_fingerprint("old-key"));
+        assert!(entries[0].get("api_key").is_none());
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn concurrent_appends_stay_whole() {
+        let folder = test_folder("concurrent_appends_stay_whole");
+        let audit_log =
+            Arc::new(AuditLog::open(folder.join(AUDIT_LOG_FILENAME), u64::MAX).unwrap());
+        let writers = (0..8)
+            .map(|writer| {
+                let audit_log = Arc::clone(&audit_log);
+                thread::spawn(move || {
+                    for _ in 0..50 {
+                        let key = format!("key-{}", writer);
+                        audit_log.record_upload(&key, &[0; 100], writer, ADDRESS.into()).unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let text = fs::read_to_string(folder.join(AUDIT_LOG_FILENAME)).unwrap();
+        assert_eq!(text.lines().count(), 400);
+        assert!(text.lines().all(|line| serde_json::from_str::<Value>(line).is_ok()));
+        for writer in 0..8 {
+            assert_eq!(audit_log.entries(Some(writer)).unwrap().len(), 50);
+        }
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn truncated_last_line_is_skipped_without_losing_the_next_entry() {
+        let folder = test_folder("truncated_last_line_is_skipped_without_losing_the_next_entry");
+        let path = folder.join(AUDIT_LOG_FILENAME);
+        fs::write(&path, "{\"timestamp\":1,\"round\":1,\"si").unwrap();
+        let audit_log = AuditLog::open(path, u64::MAX).unwrap();
+        audit_log.record_upload("key", b"\0asm", 1, ADDRESS.into()).unwrap();
+
+        let entries = audit_log.entries(None).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0]["key_fingerprint"], key_fingerprint("key"));
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn uploads_over_the_daily_quota_are_refused() {
+        let folder = test_folder("uploads_over_the_daily_quota_are_refused");
+        let audit_log = AuditLog::open(folder.join(AUDIT_LOG_FILENAME), 1000).unwrap();
+
+        audit_log.reserve_quota("key", 600).unwrap().keep();
+        assert!(audit_log.reserve_quota("key", 500).is_err());
+        // Other keys have their own quota.
+        audit_log.reserve_quota("other-key", 500).unwrap().keep();
+        audit_log.reserve_quota("key", 400).unwrap().keep();
+        assert!(audit_log.reserve_quota("key", 1).is_err());
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn failed_uploads_give_their_quota_back() {
+        let folder = test_folder("failed_uploads_give_their_quota_back");
+        let audit_log = AuditLog::open(folder.join(AUDIT_LOG_FILENAME), 1000).unwrap();
+
+        let reservation = audit_log.reserve_quota("key", 800).unwrap();
+        assert!(audit_log.reserve_quota("key", 800).is_err());
+        drop(reservation);
+        audit_log.reserve_quota("key", 800).unwrap().keep();
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn concurrent_uploads_share_one_quota() {
+        let folder = test_folder("concurrent_uploads_share_one_quota");
+        let audit_log = Arc::new(AuditLog::open(folder.join(AUDIT_LOG_FILENAME), 1000).unwrap());
+        // Every upload holds its reservation until all of them asked, as if still writing.
+        let barrier = Arc::new(Barrier::new(10));
+        let uploads = (0..10)
+            .map(|_| {
+                let (audit_log, barrier) = (Arc::clone(&audit_log), Arc::clone(&barrier));
+                thread::spawn(move || {
+                    let reservation = audit_log.reserve_quota("key", 300);
+                    barrier.wait();
+                    reservation.map(QuotaReservation::keep).is_ok()
+                })
+            })
+            .collect::<Vec<_>>();
+        let accepted = uploads.into_iter().filter(|upload| upload.join().unwrap()).count();
+        assert_eq!(accepted, 3);
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn quota_is_picked_up_from_the_log() {
+        let folder = test_folder("quota_is_picked_up_from_the_log");
+        let path = folder.join(AUDIT_LOG_FILENAME);
+        let audit_log = AuditLog::open(path.clone(), 1000).unwrap();
+        let reservation = audit_log.reserve_quota("key", 700).unwrap();
+        audit_log.record_upload("key", &[0; 700], 1, ADDRESS.into()).unwrap();
+        reservation.keep();
+
+        let reopened = AuditLog::open(path, 1000).unwrap();
+        assert!(reopened.reserve_quota("key", 400).is_err());
+        reopened.reserve_quota("key", 300).unwrap().keep();
+        fs::remove_dir_all(&folder).unwrap();
+    }
+}
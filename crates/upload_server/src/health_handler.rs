@@ -0,0 +1,135 @@
+use anyhow::{bail, Context, Error};
+use bomber_shared::{rounds_folder, TEMP_FILE_EXTENSION_MARKER};
+use log::*;
+use rand::Rng;
+use rouille::{Request, Response};
+use serde_json::json;
+use std::{env, fs};
+
+use crate::{
+    api_keys::ApiKeys,
+    upload_handler::{text_response, METHOD_NOT_ALLOWED},
+};
+
+pub const SERVICE_UNAVAILABLE: u16 = 503;
+pub const INSUFFICIENT_STORAGE: u16 = 507;
+
+/// Free disk space the server keeps in reserve by default, in megabytes.
+const DEFAULT_MIN_FREE_DISK_MB: u64 = 100;
+
+/// Keeps uploads from filling the disk the rounds folder is on.
+pub struct DiskSpace {
+    min_free_bytes: u64,
+    /// Looks up the free space of the disk, in bytes.
+    free_bytes: fn() -> Result<u64, Error>,
+}
+
+impl DiskSpace {
+    /// Reserves `MIN_FREE_DISK_MB` megabytes of the disk.
+    pub fn from_env() -> Result<Self, Error> {
+        let min_free_mb = match env::var("MIN_FREE_DISK_MB") {
+            Ok(mb) => mb.parse().context("parsing ${MIN_FREE_DISK_MB} as u64")?,
+            Err(_) => DEFAULT_MIN_FREE_DISK_MB,
+        };
+        Ok(Self { min_free_bytes: min_free_mb * 1_000_000, free_bytes })
+    }
+
+    /// Fails if writing `size` more bytes would eat into the reserve. Uploads aren't held up
+    /// when free space can't be checked, as writing them reports any actual problem.
+    pub fn check_room_for(&self, size: usize) -> Result<(), Error> {
+        let free = match (self.free_bytes)() {
+            Ok(free) => free,
+            Err(e) => {
+                warn!("{:#}", e);
+                return Ok(());
+            },
+        };
+        if free.saturating_sub(size as u64) < self.min_free_bytes {
+            bail!("the server is running out of disk space, please let the organizers know");
+        }
+        Ok(())
+    }
+}
+
+/// Handles `GET /healthz`, describing whether uploads can be accepted. Responds with 503 when
+/// disk space is below the reserve or the rounds folder can't be written to.
+pub fn health_handler(request: &Request, disk_space: &DiskSpace, api_keys: &ApiKeys) -> Response {
+    if request.method() != "GET" {
+        return text_response("We only accept HTTP GET.\n").with_status_code(METHOD_NOT_ALLOWED);
+    }
+
+    let disk_free_bytes =
+        (disk_space.free_bytes)().map_err(|e| warn!("Checking free disk space: {:#}", e)).ok();
+    let rounds_writable = check_writable().map_err(|e| warn!("{:#}", e)).is_ok();
+    let healthy =
+        rounds_writable && disk_free_bytes.map_or(false, |free| free >= disk_space.min_free_bytes);
+    let response = Response::json(&json!({
+        "disk_free_bytes": disk_free_bytes,
+        "rounds_writable": rounds_writable,
+        "keys_loaded": api_keys.read().unwrap().len(),
+    }));
+    if healthy {
+        response
+    } else {
+        response.with_status_code(SERVICE_UNAVAILABLE)
+    }
+}
+
+fn free_bytes() -> Result<u64, Error> {
+    let path = rounds_folder();
+    fs2::available_space(&path).with_context(|| format!("checking free space of {:?}", path))
+}
+
+/// Writes and removes a small file in the rounds folder. Its name marks it as a temp file, so
+/// nothing mistakes it for a player while it's there.
+fn check_writable() -> Result<(), Error> {
+    let random: u32 = rand::thread_rng().gen();
+    let path = rounds_folder().join(format!("healthz{}{}", TEMP_FILE_EXTENSION_MARKER, random));
+    fs::write(&path, b"ok").with_context(|| format!("writing {:?}", path))?;
+    fs::remove_file(&path).with_context(|| format!("removing {:?}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+    use std::sync::{Arc, RwLock};
+
+    use super::*;
+
+    const MB: u64 = 1_000_000;
+
+    fn disk_space(free_bytes: fn() -> Result<u64, Error>) -> DiskSpace {
+        DiskSpace { min_free_bytes: 100 * MB, free_bytes }
+    }
+
+    fn health_status(disk_space: &DiskSpace) -> u16 {
+        let request = Request::fake_http("GET", "/healthz", vec![], vec![]);
+        health_handler(&request, disk_space, &Arc::new(RwLock::new(vec![]))).status_code
+    }
+
+    #[test]
+    fn uploads_must_leave_the_reserve_free() {
+        let disk_space = disk_space(|| Ok(101 * MB));
+        assert!(disk_space.check_room_for(MB as usize).is_ok());
+        assert!(disk_space.check_room_for(2 * MB as usize).is_err());
+    }
+
+    #[test]
+    fn uploads_go_ahead_when_free_space_is_unknown() {
+        let disk_space = disk_space(|| Err(anyhow!("no statvfs here")));
+        assert!(disk_space.check_room_for(usize::MAX).is_ok());
+    }
+
+    #[test]
+    fn unhealthy_when_low_on_disk_space() {
+        assert_eq!(health_status(&disk_space(|| Ok(99 * MB))), SERVICE_UNAVAILABLE);
+    }
+
+    #[test]
+    fn unhealthy_when_free_space_is_unknown() {
+        assert_eq!(
+            health_status(&disk_space(|| Err(anyhow!("no statvfs here")))),
+            SERVICE_UNAVAILABLE
+        );
+    }
+}
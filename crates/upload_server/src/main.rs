@@ -1,14 +1,39 @@
 #![warn(clippy::all)]
 
-use crate::{api_keys::init_api_keys, upload_handler::handler};
-use anyhow::{Context, Error};
+use crate::{
+    abi_handler::abi_handler,
+    admin_handler::{audit_handler, generate_keys_handler},
+    api_keys::{init_api_keys, watch_api_keys},
+    audit_log::AuditLog,
+    health_handler::{health_handler, DiskSpace},
+    predict_handler::{predict_handler, tally_handler, VoteLimiter},
+    upload_handler::handler,
+};
+use anyhow::{anyhow, Context, Error};
+use bomber_shared::{remove_stale_temp_files, rounds_folder, STALE_TEMP_FILE_AGE};
 use log::*;
 use rouille::{Request, Response};
-use std::{env, time::Duration};
+use std::{
+    env,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, RwLock,
+    },
+    thread,
+    time::Duration,
+};
 
+mod abi_handler;
+mod admin_handler;
 mod api_keys;
+mod audit_log;
+mod health_handler;
+mod predict_handler;
 mod upload_handler;
 
+/// How often the server checks whether it has been asked to shut down.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
 fn main() -> Result<(), Error> {
     let dotenv_result = dotenv::dotenv();
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("debug")).init();
@@ -24,7 +49,18 @@ fn main() -> Result<(), Error> {
         .unwrap_or_else(|_| "20".to_owned())
         .parse()
         .context("parsing ${API_KEY_COUNT} as usize")?;
-    let api_keys = init_api_keys(key_count)?;
+    let api_keys = Arc::new(RwLock::new(init_api_keys(key_count)?));
+    watch_api_keys(Arc::clone(&api_keys));
+    let admin_key = env::var("ADMIN_KEY").ok().filter(|key| !key.is_empty());
+    if admin_key.is_none() {
+        info!("ADMIN_KEY not set, admin endpoints are disabled.");
+    }
+
+    match remove_stale_temp_files(&rounds_folder(), STALE_TEMP_FILE_AGE) {
+        Ok(0) => {},
+        Ok(removed) => info!("Removed {} stale temp files from interrupted uploads.", removed),
+        Err(e) => warn!("Cleaning up stale temp files: {}", e),
+    }
 
     let bind_addr = env::var("UPLOAD_SERVER_ADDRESS").unwrap_or_else(|_| "0.0.0.0:8765".to_owned());
 
@@ -48,7 +84,120 @@ fn main() -> Result<(), Error> {
         );
     };
 
-    rouille::start_server(bind_addr, move |req| {
-        rouille::log_custom(req, log_ok, log_err, || handler(req, &api_keys))
-    });
+    // Lets a reverse proxy mount the server under a sub-path, e.g. `/bomber/upload`.
+    let base_path = env::var("BASE_PATH").unwrap_or_default().trim_end_matches('/').to_owned();
+    if !base_path.is_empty() {
+        info!("Serving under {}.", base_path);
+    }
+
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let shutdown_flag = Arc::clone(&shutdown);
+    ctrlc::set_handler(move || {
+        info!("Shutdown requested, finishing in-flight uploads.");
+        shutdown_flag.store(true, Ordering::SeqCst);
+    })
+    .context("installing SIGTERM/SIGINT handler")?;
+
+    let vote_limiter = VoteLimiter::default();
+    let audit_log = AuditLog::load()?;
+    let disk_space = DiskSpace::from_env()?;
+    let in_flight = Arc::new(AtomicUsize::new(0));
+    let in_flight_counter = Arc::clone(&in_flight);
+    let server = rouille::Server::new(&bind_addr, move |req| {
+        let _guard = InFlightGuard::new(&in_flight_counter);
+        rouille::log_custom(req, log_ok, log_err, || match remove_base_path(req, &base_path) {
+            Some(req) => match req.url().as_str() {
+                "/abi" => abi_handler(&req),
+                "/healthz" => health_handler(&req, &disk_space, &api_keys),
+                "/admin/keys/generate" => {
+                    generate_keys_handler(&req, &api_keys, admin_key.as_deref())
+                },
+                "/admin/audit" => audit_handler(&req, &audit_log, admin_key.as_deref()),
+                "/predict" => predict_handler(&req, &vote_limiter),
+                url if url.starts_with("/predict/") => {
+                    tally_handler(&req, &url["/predict/".len()..])
+                },
+                _ => handler(&req, &api_keys, &audit_log, &disk_space),
+            },
+            None => Response::empty_404(),
+        })
+    })
+    .map_err(|e| anyhow!("binding {}: {}", bind_addr, e))?;
+    info!("Listening on {}.", server.server_addr());
+
+    while !shutdown.load(Ordering::SeqCst) {
+        server.poll_timeout(POLL_INTERVAL);
+    }
+
+    // Stop accepting connections, then wait for the requests already being handled.
+    drop(server);
+    while in_flight.load(Ordering::SeqCst) > 0 {
+        thread::sleep(POLL_INTERVAL);
+    }
+    info!("Shut down cleanly.");
+    Ok(())
+}
+
+/// The request with the base path taken off its URL, if it was sent under the base path. The
+/// base path must be followed by a `/` or nothing, so `/bomber` doesn't serve `/bomberman`.
+fn remove_base_path(req: &Request, base_path: &str) -> Option<Request> {
+    let rest = req.url().strip_prefix(base_path)?.to_owned();
+    if !rest.is_empty() && !rest.starts_with('/') {
+        return None;
+    }
+    req.remove_prefix(base_path)
+}
+
+/// Counts a request as in flight for as long as it is alive, even if the handler panics.
+struct InFlightGuard<'a>(&'a AtomicUsize);
+
+impl<'a> InFlightGuard<'a> {
+    fn new(counter: &'a AtomicUsize) -> Self {
+        counter.fetch_add(1, Ordering::SeqCst);
+        Self(counter)
+    }
+}
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Empty folder for a test to play in, named after it so tests don't step on each other.
+#[cfg(test)]
+fn test_folder(name: &str) -> std::path::PathBuf {
+    let folder = env::temp_dir().join(format!("upload_server_{}_{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&folder);
+    std::fs::create_dir_all(&folder).unwrap();
+    folder
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url_under(url: &str, base_path: &str) -> Option<String> {
+        let req = Request::fake_http("GET", url, vec![], vec![]);
+        remove_base_path(&req, base_path).map(|req| req.url())
+    }
+
+    #[test]
+    fn base_path_is_removed() {
+        assert_eq!(url_under("/bomber/abi", "/bomber").as_deref(), Some("/abi"));
+        assert_eq!(url_under("/bomber/predict/3", "/bomber").as_deref(), Some("/predict/3"));
+    }
+
+    #[test]
+    fn base_path_must_end_at_a_segment() {
+        assert_eq!(url_under("/bomberman/abi", "/bomber"), None);
+        assert_eq!(url_under("/bomber2", "/bomber"), None);
+        assert_eq!(url_under("/other/abi", "/bomber"), None);
+    }
+
+    #[test]
+    fn everything_is_served_without_base_path() {
+        assert_eq!(url_under("/abi", "").as_deref(), Some("/abi"));
+        assert_eq!(url_under("/", "").as_deref(), Some("/"));
+    }
 }
@@ -0,0 +1,127 @@
+use anyhow::{anyhow, bail, Context, Error};
+use bomber_shared::{finished_round_marker, round_folder, rounds_folder, Predictions};
+use log::*;
+use rouille::{Request, Response};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    io::Read,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::upload_handler::{
+    text_response, BAD_REQUEST, INTERNAL_SERVER_ERROR, METHOD_NOT_ALLOWED,
+};
+
+pub const TOO_MANY_REQUESTS: u16 = 429;
+
+/// How long a spectator has to wait between votes.
+const VOTE_INTERVAL: Duration = Duration::from_secs(30);
+const MAX_BODY_SIZE: u64 = 1024;
+/// Longer than the game allows team names to be, so any of them can be voted for.
+const MAX_TEAM_NAME_LENGTH: usize = 64;
+/// Keeps made up team names from growing the tally file without bounds.
+const MAX_TEAMS_PER_ROUND: usize = 100;
+
+/// When each address last voted. Also serializes updates to the predictions files.
+#[derive(Default)]
+pub struct VoteLimiter(Mutex<HashMap<IpAddr, Instant>>);
+
+/// Handles `POST /predict` with a `{"round": 3, "team": "..."}` body, counting a vote for the
+/// team a spectator expects to win a round that isn't over yet.
+pub fn predict_handler(request: &Request, limiter: &VoteLimiter) -> Response {
+    if request.method() != "POST" {
+        return text_response("We only accept HTTP POST.\n").with_status_code(METHOD_NOT_ALLOWED);
+    }
+
+    let (round, team) = match parse_vote(request) {
+        Ok(vote) => vote,
+        Err(e) => {
+            return text_response(format!("Invalid prediction: {:#}\n", e))
+                .with_status_code(BAD_REQUEST)
+        },
+    };
+
+    let mut last_votes = limiter.0.lock().unwrap();
+    last_votes.retain(|_, voted_at| voted_at.elapsed() < VOTE_INTERVAL);
+    let address = request.remote_addr().ip();
+    if let Some(voted_at) = last_votes.get(&address) {
+        let wait = VOTE_INTERVAL - voted_at.elapsed();
+        return text_response(format!(
+            "Please wait {} seconds before voting again.\n",
+            wait.as_secs() + 1
+        ))
+        .with_status_code(TOO_MANY_REQUESTS);
+    }
+
+    match record_vote(round, &team) {
+        Ok(()) => {
+            last_votes.insert(address, Instant::now());
+            text_response(format!("Your prediction of {} winning round {} is in.\n", team, round))
+        },
+        Err(e) => text_response(format!("Error recording your prediction: {:#}\n", e))
+            .with_status_code(BAD_REQUEST),
+    }
+}
+
+/// Handles `GET /predict/{round}`, returning the votes of the round by team.
+pub fn tally_handler(request: &Request, round: &str) -> Response {
+    if request.method() != "GET" {
+        return text_response("We only accept HTTP GET.\n").with_status_code(METHOD_NOT_ALLOWED);
+    }
+    let round = match round.parse() {
+        Ok(round) => round,
+        Err(_) => {
+            return text_response(format!("Invalid round {:?}.\n", round))
+                .with_status_code(BAD_REQUEST)
+        },
+    };
+
+    match Predictions::read(&rounds_folder(), round) {
+        Ok(predictions) => Response::json(&predictions),
+        Err(e) => text_response(format!("Error reading predictions: {:#}\n", e))
+            .with_status_code(INTERNAL_SERVER_ERROR),
+    }
+}
+
+fn parse_vote(request: &Request) -> Result<(u32, String), Error> {
+    let body = request.data().ok_or_else(|| anyhow!("please submit request with body"))?;
+    let mut data = Vec::new();
+    body.take(MAX_BODY_SIZE + 1).read_to_end(&mut data)?;
+    if data.len() as u64 > MAX_BODY_SIZE {
+        bail!("maximum size of {} exceeded", MAX_BODY_SIZE);
+    }
+
+    let vote: Value = serde_json::from_slice(&data).context("parsing body as JSON")?;
+    let round = vote["round"]
+        .as_u64()
+        .and_then(|round| u32::try_from(round).ok())
+        .ok_or_else(|| anyhow!("\"round\" must be a round number"))?;
+    let team = vote["team"].as_str().map(str::trim).unwrap_or_default();
+    if team.is_empty() || team.chars().count() > MAX_TEAM_NAME_LENGTH {
+        bail!("\"team\" must be a team name of up to {} characters", MAX_TEAM_NAME_LENGTH);
+    }
+    Ok((round, team.to_owned()))
+}
+
+/// Adds a vote to the predictions file of the round. Callers hold the [`VoteLimiter`] lock.
+fn record_vote(round: u32, team: &str) -> Result<(), Error> {
+    let rounds_path = rounds_folder();
+    if !round_folder(&rounds_path, round).is_dir() {
+        bail!("there is no round {}", round);
+    }
+    if finished_round_marker(&rounds_path, round).exists() {
+        bail!("round {} is already over", round);
+    }
+
+    let mut predictions = Predictions::read(&rounds_path, round)?;
+    if !predictions.0.contains_key(team) && predictions.0.len() >= MAX_TEAMS_PER_ROUND {
+        bail!("round {} has too many teams voted for already", round);
+    }
+    *predictions.0.entry(team.to_owned()).or_default() += 1;
+    predictions.write(&rounds_path, round)?;
+    debug!("Prediction of {} winning round {} recorded.", team, round);
+    Ok(())
+}
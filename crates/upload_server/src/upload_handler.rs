@@ -1,38 +1,52 @@
+use crate::{
+    api_keys::ApiKeys,
+    audit_log::AuditLog,
+    health_handler::{DiskSpace, INSUFFICIENT_STORAGE},
+    predict_handler::TOO_MANY_REQUESTS,
+};
 use anyhow::{anyhow, bail, Context, Error};
+use bomber_shared::{
+    finished_round_marker, round_folder, rounds_folder, BuildMeta, RoundConfig,
+    TEMP_FILE_EXTENSION_MARKER,
+};
 use log::*;
 use rand::Rng;
 use rouille::{Request, Response};
 use std::{
+    env,
     ffi::OsStr,
     fs::{self, create_dir_all},
-    io::Read,
+    io::{ErrorKind, Read},
     path::{Path, PathBuf},
 };
 
-const ROUNDS_FOLDER: &str = "rounds";
 /// Max number of rounds the upload server will attempt to create.
-const MAX_ROUNDS: usize = 10_000;
-/// Maximum number of players in a round before upload server starts putting them into next one.
-const MAX_PLAYERS_PER_ROUND: usize = 12;
-/// Name of the file that the game engine uses to mark a finished round.
-const FINISHED_ROUND_MARKER_FILENAME: &str = "round-finished.marker";
+const MAX_ROUNDS: u32 = 10_000;
+/// Maximum number of players in a round before upload server starts putting them into next one,
+/// for rounds the game hasn't written a config for yet. Overridden by `MAX_PLAYERS_PER_ROUND`.
+const DEFAULT_MAX_PLAYERS_PER_ROUND: usize = 12;
 
 const MAX_WASM_SIZE: usize = 10_000_000;
 const WASM_FILE_PREFIX: &[u8] = b"\0asm";
 
-const BAD_REQUEST: u16 = 400;
-const UNAUTHORIZED: u16 = 401;
-const METHOD_NOT_ALLOWED: u16 = 405;
-const INTERNAL_SERVER_ERROR: u16 = 500;
+pub const BAD_REQUEST: u16 = 400;
+pub const UNAUTHORIZED: u16 = 401;
+pub const METHOD_NOT_ALLOWED: u16 = 405;
+pub const INTERNAL_SERVER_ERROR: u16 = 500;
 
-pub fn handler(request: &Request, api_keys: &[String]) -> Response {
+pub fn handler(
+    request: &Request,
+    api_keys: &ApiKeys,
+    audit_log: &AuditLog,
+    disk_space: &DiskSpace,
+) -> Response {
     if request.method() != "POST" {
         return text_response("We only accept HTTP POST.\n").with_status_code(METHOD_NOT_ALLOWED);
     }
 
     let api_key = match request.header("Api-Key") {
         Some(api_key) => {
-            if api_keys.iter().all(|allowed_key| allowed_key != api_key) {
+            if api_keys.read().unwrap().iter().all(|allowed_key| allowed_key != api_key) {
                 return text_response(format!("HTTP header Api-Key \"{}\" not valid.\n", api_key))
                     .with_status_code(UNAUTHORIZED);
             }
@@ -57,10 +71,45 @@ pub fn handler(request: &Request, api_keys: &[String]) -> Response {
         if !data.starts_with(WASM_FILE_PREFIX) {
             return text_response("Uploaded data not a WASM file.\n").with_status_code(BAD_REQUEST);
         }
-        match handle_upload(api_key, &data) {
-            Ok(round_number) => text_response(format!(
-                "Your submission has been accepted to round {round_number}.\n"
-            )),
+        // Build metadata is optional, and a malformed one is only worth a warning.
+        let build = match BuildMeta::read(&data) {
+            Ok(build) => build,
+            Err(e) => {
+                warn!("Ignoring the build metadata uploaded with key {}: {:#}", api_key, e);
+                None
+            },
+        };
+        if let Err(e) = disk_space.check_room_for(data.len()) {
+            error!("Rejecting the upload with key {}: {:#}", api_key, e);
+            return text_response(format!("Error accepting your submission: {:#}\n", e))
+                .with_status_code(INSUFFICIENT_STORAGE);
+        }
+        let reservation = match audit_log.reserve_quota(api_key, data.len()) {
+            Ok(reservation) => reservation,
+            Err(e) => {
+                warn!("Rejecting the upload with key {}: {:#}", api_key, e);
+                return text_response(format!("Error accepting your submission: {:#}\n", e))
+                    .with_status_code(TOO_MANY_REQUESTS);
+            },
+        };
+        let result = handle_upload(api_key, &data);
+        // The quota set aside is given back if the upload fails or can't be audited.
+        if let Ok(round_number) = result {
+            let address = request.remote_addr().ip();
+            match audit_log.record_upload(api_key, &data, round_number, address) {
+                Ok(()) => reservation.keep(),
+                Err(e) => error!("Failed to audit the upload with key {}: {:#}", api_key, e),
+            }
+        }
+        match result {
+            Ok(round_number) => match build.as_ref().and_then(BuildMeta::short_hash) {
+                Some(hash) => text_response(format!(
+                    "Your submission (build {hash}) has been accepted to round {round_number}.\n"
+                )),
+                None => text_response(format!(
+                    "Your submission has been accepted to round {round_number}.\n"
+                )),
+            },
             Err(e) => text_response(format!("Error accepting your submission: {:#}\n", e))
                 .with_status_code(INTERNAL_SERVER_ERROR),
         }
@@ -69,49 +118,85 @@ pub fn handler(request: &Request, api_keys: &[String]) -> Response {
     }
 }
 
-fn handle_upload(api_key: &str, data: &[u8]) -> Result<usize, Error> {
+fn handle_upload(api_key: &str, data: &[u8]) -> Result<u32, Error> {
     let filename = format!("{}.wasm", api_key);
-    let (round_number, path) = get_upload_round_and_path_for(&filename)?;
+    let (round_number, path) = get_upload_round_and_path_for(&rounds_folder(), &filename)?;
 
     let random: u32 = rand::thread_rng().gen();
-    let temp_path = path.with_extension(format!("wasm.tmp{}", random));
+    let temp_file = TempFile {
+        path: path.with_extension(format!("wasm{}{}", TEMP_FILE_EXTENSION_MARKER, random)),
+        persisted: false,
+    };
 
     // Writing is not atomic, so write to temp file and then rename.
-    fs::write(&temp_path, data).with_context(|| format!("writing {:?}", temp_path))?;
-    fs::rename(&temp_path, &path)?;
+    fs::write(&temp_file.path, data).with_context(|| format!("writing {:?}", temp_file.path))?;
+    temp_file.persist(&path)?;
     info!("{:?} saved.", path);
     Ok(round_number)
 }
 
+/// Upload being written next to its final path. It is deleted when dropped unless it made it
+/// into place, so failed uploads don't leave it behind until the stale file cleanup.
+struct TempFile {
+    path: PathBuf,
+    persisted: bool,
+}
+
+impl TempFile {
+    fn persist(mut self, path: &Path) -> Result<(), Error> {
+        fs::rename(&self.path, path)
+            .with_context(|| format!("renaming {:?} to {:?}", self.path, path))?;
+        self.persisted = true;
+        Ok(())
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if self.persisted {
+            return;
+        }
+        match fs::remove_file(&self.path) {
+            Ok(()) => debug!("Removed {:?} after a failed upload.", self.path),
+            Err(e) if e.kind() == ErrorKind::NotFound => {},
+            Err(e) => warn!("Removing {:?} after a failed upload: {}", self.path, e),
+        }
+    }
+}
+
 /// Return a path to upload `filename` player to, creating folders as necessary.
-fn get_upload_round_and_path_for(filename: &str) -> Result<(usize, PathBuf), Error> {
-    let rounds_path = Path::new(ROUNDS_FOLDER);
+fn get_upload_round_and_path_for(
+    rounds_path: &Path,
+    filename: &str,
+) -> Result<(u32, PathBuf), Error> {
     if !rounds_path.is_dir() {
         bail!("{:?} must be a directory.", rounds_path);
     }
 
+    // Each key gets a single slot per round, and only in one upcoming round at a time: if this
+    // player is already in some non-past round, overwrite it. This has to look through all of
+    // them before picking one with room, which may come before the one the player is in.
+    if let Some(existing) = find_existing_upload(rounds_path, filename) {
+        return Ok(existing);
+    }
+
     for round in 1..MAX_ROUNDS {
-        let round_path = rounds_path.join(round.to_string());
+        let round_path = round_folder(rounds_path, round);
 
         // Skip finished rounds.
-        if round_path.join(FINISHED_ROUND_MARKER_FILENAME).exists() {
+        if finished_round_marker(rounds_path, round).exists() {
             continue;
         }
 
         let player_in_round_path = round_path.join(filename);
 
-        // If this player is already in some non-past round, overwrite it.
-        if player_in_round_path.exists() {
-            return Ok((round, player_in_round_path));
-        }
-
         // The round folder may not exist, ensure it does.
         if !round_path.exists() {
             create_dir_all(&round_path)?;
         }
 
         // Skip full rounds.
-        if count_players_in_dir(&round_path)? >= MAX_PLAYERS_PER_ROUND {
+        if count_players_in_dir(&round_path)? >= max_players_in_round(rounds_path, round)? {
             continue;
         }
 
@@ -121,13 +206,40 @@ fn get_upload_round_and_path_for(filename: &str) -> Result<(usize, PathBuf), Err
     Err(anyhow!("Couldn't find a round to add player to."))
 }
 
+/// Round and path of `filename` player in a non-past round, if it was uploaded to one. Round
+/// folders are created in order, so the search stops at the first one missing.
+fn find_existing_upload(rounds_path: &Path, filename: &str) -> Option<(u32, PathBuf)> {
+    (1..MAX_ROUNDS)
+        .take_while(|round| round_folder(rounds_path, *round).is_dir())
+        .filter(|round| !finished_round_marker(rounds_path, *round).exists())
+        .map(|round| (round, round_folder(rounds_path, round).join(filename)))
+        .find(|(_, path)| path.exists())
+}
+
+/// Number of players the game takes in the given round, as it wrote in the round folder. Rounds
+/// the game hasn't reached yet fall back to the configured default.
+fn max_players_in_round(rounds_path: &Path, round: u32) -> Result<usize, Error> {
+    match RoundConfig::read(rounds_path, round) {
+        Ok(Some(config)) => return Ok(config.max_players),
+        Ok(None) => {},
+        Err(e) => warn!("Ignoring the config of round {}: {:#}", round, e),
+    }
+    env::var("MAX_PLAYERS_PER_ROUND").map_or(Ok(DEFAULT_MAX_PLAYERS_PER_ROUND), |max| {
+        max.parse().context("parsing ${MAX_PLAYERS_PER_ROUND} as usize")
+    })
+}
+
 fn count_players_in_dir(path: &Path) -> Result<usize, Error> {
     let wasm_extension = OsStr::new("wasm");
 
     let mut count = 0;
     for file in path.read_dir().context(format!("reading {path:?}"))? {
         let path = file?.path();
-        if path.is_file() && path.extension() == Some(wasm_extension) {
+        // Uploads still being written (or left behind by a crash) aren't players yet.
+        let is_temp = path
+            .file_name()
+            .map_or(false, |name| name.to_string_lossy().contains(TEMP_FILE_EXTENSION_MARKER));
+        if path.is_file() && path.extension() == Some(wasm_extension) && !is_temp {
             count += 1;
         }
     }
@@ -136,8 +248,116 @@ fn count_players_in_dir(path: &Path) -> Result<usize, Error> {
 
 /// Create a text response and log it. Work-around for the fact that response body can be read only
 /// once from [rouille::Response]. Use instead of text_response(...).
-fn text_response(text: impl Into<String>) -> Response {
+pub fn text_response(text: impl Into<String>) -> Response {
     let text: String = text.into();
     debug!("Responding with: {}", text.trim());
     Response::text(text)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_folder;
+
+    #[test]
+    fn round_takes_as_many_players_as_the_game_wrote() {
+        let rounds = test_folder("round_takes_as_many_players_as_the_game_wrote");
+        fs::create_dir_all(round_folder(&rounds, 1)).unwrap();
+        RoundConfig { max_players: 2, rules: serde_json::Value::Null }.write(&rounds, 1).unwrap();
+
+        assert_eq!(max_players_in_round(&rounds, 1).unwrap(), 2);
+        fs::remove_dir_all(&rounds).unwrap();
+    }
+
+    #[test]
+    fn new_player_takes_the_first_round_with_room() {
+        let rounds = test_folder("new_player_takes_the_first_round_with_room");
+        fs::create_dir_all(round_folder(&rounds, 1)).unwrap();
+        RoundConfig { max_players: 1, rules: serde_json::Value::Null }.write(&rounds, 1).unwrap();
+        fs::write(round_folder(&rounds, 1).join("other.wasm"), b"").unwrap();
+
+        let (round, path) = get_upload_round_and_path_for(&rounds, "key.wasm").unwrap();
+        assert_eq!(round, 2);
+        assert_eq!(path, round_folder(&rounds, 2).join("key.wasm"));
+        fs::remove_dir_all(&rounds).unwrap();
+    }
+
+    #[test]
+    fn player_keeps_its_single_slot() {
+        let rounds = test_folder("player_keeps_its_single_slot");
+        fs::create_dir_all(round_folder(&rounds, 1)).unwrap();
+        fs::create_dir_all(round_folder(&rounds, 2)).unwrap();
+        RoundConfig { max_players: 1, rules: serde_json::Value::Null }.write(&rounds, 1).unwrap();
+        fs::write(round_folder(&rounds, 2).join("key.wasm"), b"").unwrap();
+
+        // Round 1 has room, yet uploading again replaces the upload in round 2 rather than
+        // taking a second slot.
+        let (round, path) = get_upload_round_and_path_for(&rounds, "key.wasm").unwrap();
+        assert_eq!(round, 2);
+        assert_eq!(path, round_folder(&rounds, 2).join("key.wasm"));
+        assert_eq!(count_players_in_dir(&round_folder(&rounds, 1)).unwrap(), 0);
+        fs::remove_dir_all(&rounds).unwrap();
+    }
+
+    #[test]
+    fn player_in_a_finished_round_gets_a_new_slot() {
+        let rounds = test_folder("player_in_a_finished_round_gets_a_new_slot");
+        fs::create_dir_all(round_folder(&rounds, 1)).unwrap();
+        fs::write(round_folder(&rounds, 1).join("key.wasm"), b"").unwrap();
+        fs::write(finished_round_marker(&rounds, 1), b"").unwrap();
+
+        let (round, _) = get_upload_round_and_path_for(&rounds, "key.wasm").unwrap();
+        assert_eq!(round, 2);
+        fs::remove_dir_all(&rounds).unwrap();
+    }
+
+    fn temp_file(folder: &Path) -> TempFile {
+        let path = folder.join(format!("key.wasm{}123", TEMP_FILE_EXTENSION_MARKER));
+        fs::write(&path, b"\0asm").unwrap();
+        TempFile { path, persisted: false }
+    }
+
+    #[test]
+    fn temp_file_is_moved_into_place() {
+        let folder = test_folder("temp_file_is_moved_into_place");
+        let temp_file = temp_file(&folder);
+        let temp_path = temp_file.path.clone();
+
+        temp_file.persist(&folder.join("key.wasm")).unwrap();
+        assert!(!temp_path.exists());
+        assert_eq!(fs::read(folder.join("key.wasm")).unwrap(), b"\0asm");
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn temp_file_is_removed_when_the_rename_fails() {
+        let folder = test_folder("temp_file_is_removed_when_the_rename_fails");
+        let temp_file = temp_file(&folder);
+        let temp_path = temp_file.path.clone();
+
+        // A read-only folder doesn't stop tests running as root, but a missing one does.
+        assert!(temp_file.persist(&folder.join("missing/key.wasm")).is_err());
+        assert!(!temp_path.exists());
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn temp_file_is_removed_when_dropped() {
+        let folder = test_folder("temp_file_is_removed_when_dropped");
+        let temp_path = temp_file(&folder).path.clone();
+
+        assert!(!temp_path.exists());
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn temp_files_are_not_players() {
+        let folder = test_folder("temp_files_are_not_players");
+        let temp_file = temp_file(&folder);
+        fs::write(folder.join("other.wasm"), b"").unwrap();
+
+        assert_eq!(count_players_in_dir(&folder).unwrap(), 1);
+        drop(temp_file);
+        fs::remove_dir_all(&folder).unwrap();
+    }
+}
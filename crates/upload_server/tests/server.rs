@@ -0,0 +1,83 @@
+//! Runs the upload server on an ephemeral port, the way it's deployed behind a reverse proxy.
+
+use std::{
+    env, fs,
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpStream,
+    process::{Command, Stdio},
+    sync::mpsc,
+    thread,
+    time::Duration,
+};
+
+const API_KEY: &str = "test-key";
+/// The smallest valid wasm module: the magic number and version.
+const EMPTY_MODULE: &[u8] = b"\0asm\x01\0\0\0";
+
+/// Sends a raw HTTP request and returns the response, status line included.
+fn send(address: &str, request_head: &str, body: &[u8]) -> String {
+    let mut stream = TcpStream::connect(address).unwrap();
+    write!(stream, "{}Content-Length: {}\r\nConnection: close\r\n\r\n", request_head, body.len())
+        .unwrap();
+    stream.write_all(body).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+#[cfg(unix)]
+#[test]
+fn uploads_under_the_base_path_and_shuts_down_cleanly() {
+    let working_dir = env::temp_dir().join(format!("upload_server_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&working_dir);
+    fs::create_dir_all(working_dir.join("rounds")).unwrap();
+    fs::write(working_dir.join("api_keys.txt"), format!("{}\n", API_KEY)).unwrap();
+
+    let mut server = Command::new(env!("CARGO_BIN_EXE_upload_server"))
+        .current_dir(&working_dir)
+        .env("UPLOAD_SERVER_ADDRESS", "127.0.0.1:0")
+        .env("BASE_PATH", "/bomber/")
+        .env("API_KEY_COUNT", "1")
+        .env("ROUNDS_FOLDER", working_dir.join("rounds"))
+        .env("MIN_FREE_DISK_MB", "0")
+        .env("RUST_LOG", "info")
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    // The server logs the address it's bound to, and how it shut down.
+    let (log_sender, log) = mpsc::channel();
+    let stderr = BufReader::new(server.stderr.take().unwrap());
+    thread::spawn(move || {
+        for line in stderr.lines().map_while(Result::ok) {
+            let _ = log_sender.send(line);
+        }
+    });
+    let timeout = Duration::from_secs(30);
+    let address = loop {
+        let line = log.recv_timeout(timeout).expect("the server never started listening");
+        if let Some((_, rest)) = line.split_once("Listening on ") {
+            break rest.trim_end_matches('.').to_owned();
+        }
+    };
+
+    let head = format!("POST /bomber/ HTTP/1.1\r\nHost: {}\r\nApi-Key: {}\r\n", address, API_KEY);
+    let response = send(&address, &head, EMPTY_MODULE);
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    assert!(response.contains("accepted to round 1"), "{}", response);
+    assert_eq!(
+        fs::read(working_dir.join("rounds/1").join(API_KEY).with_extension("wasm")).unwrap(),
+        EMPTY_MODULE
+    );
+    // Paths outside of the base path aren't served.
+    let head = format!("POST /other/ HTTP/1.1\r\nHost: {}\r\nApi-Key: {}\r\n", address, API_KEY);
+    assert!(send(&address, &head, EMPTY_MODULE).starts_with("HTTP/1.1 404"));
+
+    let killed = Command::new("kill").args(["-TERM", &server.id().to_string()]).status().unwrap();
+    assert!(killed.success());
+    let status = server.wait().unwrap();
+    assert!(status.success(), "the server exited with {}", status);
+    let shut_down_cleanly = log.iter().any(|line| line.contains("Shut down cleanly."));
+    assert!(shut_down_cleanly);
+    fs::remove_dir_all(&working_dir).unwrap();
+}